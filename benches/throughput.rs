@@ -0,0 +1,134 @@
+use std::{path::{Path, PathBuf}, time::{Duration, Instant}};
+
+use bogger::{BlockInfo, Config, EntryWriter, ForwardConfig, Forwarder, LogReader, NullSink, Receiver};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::{fs, runtime::Runtime, time::timeout};
+
+const ENTRY_SIZES: &[usize] = &[64, 1024, 16 * 1024];
+
+/// Number of entries per read and forward iteration.
+const ENTRIES: u64 = 10_000;
+
+fn append(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut g = c.benchmark_group("append");
+    for &size in ENTRY_SIZES {
+        g.throughput(Throughput::Bytes(size as u64));
+        g.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.to_async(&rt).iter_custom(|iters| async move {
+                let dir = recreate(&format!("append-{size}")).await;
+                let mut w = EntryWriter::open(&dir, config()).await.unwrap();
+                let entry = vec![0x2a; size];
+                let start = Instant::now();
+                for _ in 0 .. iters {
+                    w.append(&entry).await.unwrap()
+                }
+                w.sync().await.unwrap();
+                start.elapsed()
+            })
+        });
+        report()
+    }
+    g.finish()
+}
+
+fn read(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut g = c.benchmark_group("read");
+    for &size in ENTRY_SIZES {
+        let dir = rt.block_on(async {
+            let dir = recreate(&format!("read-{size}")).await;
+            write_entries(&dir, size).await;
+            dir
+        });
+        g.throughput(Throughput::Bytes(ENTRIES * size as u64));
+        g.bench_with_input(BenchmarkId::from_parameter(size), &dir, |b, dir| {
+            b.to_async(&rt).iter(|| async {
+                let mut r = LogReader::new(dir, BlockInfo::zero());
+                let mut n = 0;
+                while r.next_entry().await.unwrap().is_some() {
+                    n += 1
+                }
+                assert_eq!(ENTRIES, n)
+            })
+        });
+        report()
+    }
+    g.finish()
+}
+
+/// Forward entries over loopback to a receiver which discards them.
+fn forward(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut g = c.benchmark_group("forward");
+    g.sample_size(10);
+    let size = 1024;
+    g.throughput(Throughput::Bytes(ENTRIES * size as u64));
+    g.bench_function(BenchmarkId::from_parameter(size), |b| {
+        b.to_async(&rt).iter_custom(|iters| async move {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0 .. iters {
+                let dir = recreate("forward").await;
+                let last = write_entries(&dir, size).await;
+                let receiver = Receiver::new("127.0.0.1:0", NullSink::new()).await.unwrap();
+                let address = receiver.local_addr().unwrap().to_string();
+                let receiver = tokio::spawn(receiver.go());
+                let forwarder = Forwarder::new("bench", &dir, &address).await.unwrap()
+                    .with_config(ForwardConfig::default().with_poll_interval(Duration::from_millis(10)));
+                let mut progress = forwarder.progress();
+                let start = Instant::now();
+                let forwarder = tokio::spawn(forwarder.go());
+                timeout(Duration::from_secs(60), progress.wait_for(|p| p.acked() >= last))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                elapsed += start.elapsed();
+                forwarder.abort();
+                receiver.abort()
+            }
+            elapsed
+        })
+    });
+    g.finish()
+}
+
+/// Write `ENTRIES` entries and return the position of the last one.
+async fn write_entries(dir: &Path, size: usize) -> BlockInfo {
+    let mut w = EntryWriter::open(dir, config()).await.unwrap();
+    let entry = vec![0x2a; size];
+    let mut last = BlockInfo::zero();
+    for _ in 0 .. ENTRIES {
+        last = w.position();
+        w.append(&entry).await.unwrap();
+        if w.position().number() != last.number() {
+            last = BlockInfo::zero().with_number(w.position().number()).with_offset(8u8)
+        }
+    }
+    w.sync().await.unwrap();
+    last
+}
+
+fn config() -> Config {
+    Config::default().with_max_entry_len(64 * 1024)
+}
+
+async fn recreate(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bogger-bench-{name}"));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).await.unwrap()
+    }
+    fs::create_dir_all(&dir).await.unwrap();
+    dir
+}
+
+/// Print and reset the storage counters.
+fn report() {
+    #[cfg(feature = "bench")]
+    {
+        eprintln!("{:?}", bogger::counters());
+        bogger::reset_counters()
+    }
+}
+
+criterion_group!(benches, append, read, forward);
+criterion_main!(benches);
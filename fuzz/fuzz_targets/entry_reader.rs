@@ -0,0 +1,26 @@
+#![no_main]
+
+use bogger::{BlockInfo, EntryReader};
+use libfuzzer_sys::fuzz_target;
+
+// Interprets the input as the contents of a block file and reads
+// entries from it, both strictly and skipping corrupt frames.
+fuzz_target!(|data: &[u8]| {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+        let dir = std::env::temp_dir().join(format!("bogger-fuzz-{}", std::process::id()));
+        let _ = tokio::fs::create_dir_all(&dir).await;
+        tokio::fs::write(dir.join("block.1"), data).await.unwrap();
+        for skip in [false, true] {
+            let start = BlockInfo::zero().with_number(1);
+            let Ok(r) = EntryReader::open(&dir, start).await else { return };
+            let mut r = r.with_skip_corrupt(skip);
+            for _ in 0 ..= data.len() {
+                match r.next_entry().await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) | Err(_) => break
+                }
+            }
+        }
+    })
+});
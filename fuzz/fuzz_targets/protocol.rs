@@ -0,0 +1,12 @@
+#![no_main]
+
+use bogger::{Ack, Handshake, HandshakeResponse, Record};
+use libfuzzer_sys::fuzz_target;
+
+// Decodes the input as every message of the forwarding protocol.
+fuzz_target!(|data: &[u8]| {
+    let _ = minicbor::decode::<Handshake>(data);
+    let _ = minicbor::decode::<HandshakeResponse>(data);
+    let _ = minicbor::decode::<Record>(data);
+    let _ = minicbor::decode::<Ack>(data);
+});
@@ -1,28 +1,446 @@
 use clap::Parser;
-use bogger::Forwarder;
-use std::{error::Error, path::PathBuf};
-use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+use bogger::{ArchiveAction, Forwarder, ForwarderHandle, ForwardProgress, Identity, Metrics, MultiForwarder, RateLimit, Retention, Settings, TimeWindow, available_space};
+use std::{error::Error, num::NonZeroU64, path::PathBuf, time::{Duration, Instant}};
+use tokio::sync::watch;
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::{select, signal::unix::{signal, SignalKind}};
+use tracing_subscriber::{EnvFilter, Registry, filter::ParseError, fmt, prelude::*, reload};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Settings file with storage, forwarding and retention settings.
+    ///
+    /// Command line options take precedence over the file. On SIGHUP the
+    /// retention, rate limit and log settings are reloaded from it.
+    #[arg(short, long, env = "BOGGER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Id of this forwarder, under which destinations keep its cursor.
+    ///
+    /// Defaults to the `id` of the settings file or the hostname.
+    #[arg(long, env = "BOGGER_ID")]
+    id: Option<String>,
+
     /// Directory path containing blocks.
-    #[arg(short, long)]
+    #[arg(short, long, env = "BOGGER_DIRECTORY")]
     directory: PathBuf,
 
-    /// Network address of the destination.
-    #[arg(short, long)]
-    address: String
+    /// Network address of a destination (may be given multiple times).
+    #[arg(short, long, required = true, env = "BOGGER_ADDRESS", value_delimiter = ',')]
+    address: Vec<String>,
+
+    /// Resume from the local cursor if a destination has no state.
+    #[arg(long)]
+    client_cursor: bool,
+
+    /// Token to authenticate with.
+    #[arg(long, env = "BOGGER_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Name of the stream the directory contains.
+    #[arg(long, env = "BOGGER_STREAM")]
+    stream: Option<String>,
+
+    /// Forward another directory as a stream, as `name=directory` (may be given multiple times).
+    #[arg(long, value_parser = parse_stream)]
+    add_stream: Vec<(String, PathBuf)>,
+
+    /// Forward added streams over the connections of the first one.
+    ///
+    /// Destinations need to support multiplexing. Otherwise every stream
+    /// has its own connections.
+    #[arg(long)]
+    multiplex: bool,
+
+    /// Do not release any blocks, only log which would be released.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Let other forwarders forward the same directories at the same time.
+    #[arg(long)]
+    shared_directory: bool,
+
+    /// Skip corrupt entries instead of stopping at the first one.
+    #[arg(long)]
+    skip_corrupt: bool,
+
+    /// Move blocks which can not be read into the quarantine directory.
+    #[arg(long)]
+    quarantine: bool,
+
+    /// Send the hostname and process id to destinations.
+    #[arg(long)]
+    identity: bool,
+
+    /// Label to send with the identity, as `key=value` (may be given multiple times).
+    #[arg(long, value_parser = parse_label)]
+    label: Vec<(String, String)>,
+
+    /// Interval in milliseconds at which to look for new entries.
+    #[arg(long)]
+    poll_interval: Option<u64>,
+
+    /// Reconnect if sent records are not acknowledged within this many seconds.
+    #[arg(long)]
+    ack_timeout: Option<u64>,
+
+    /// Report a stall if acknowledgements do not advance for this many seconds.
+    #[arg(long)]
+    stall_timeout: Option<u64>,
+
+    /// Reconnect when acknowledgements stall.
+    #[arg(long)]
+    stall_reconnect: bool,
+
+    /// Initial delay in milliseconds before reconnecting to a destination.
+    #[arg(long)]
+    min_reconnect_delay: Option<u64>,
+
+    /// Maximum delay in milliseconds before reconnecting to a destination.
+    #[arg(long)]
+    max_reconnect_delay: Option<u64>,
+
+    /// Read up to this many entries ahead while records are being sent.
+    #[arg(long)]
+    read_ahead: Option<usize>,
+
+    /// Read up to this many completed blocks concurrently when catching up.
+    #[arg(long)]
+    parallel_blocks: Option<usize>,
+
+    /// Only forward during this daily window, as `HH:MM-HH:MM` in UTC (may be given multiple times).
+    #[arg(long)]
+    window: Vec<TimeWindow>,
+
+    /// Stop forwarding once this many payload bytes have been sent within 24 hours.
+    #[arg(long)]
+    max_bytes_per_day: Option<NonZeroU64>,
+
+    /// Keep acknowledged blocks for this many seconds.
+    #[arg(long)]
+    keep_max_age: Option<u64>,
+
+    /// Keep acknowledged blocks up to this many bytes in total.
+    #[arg(long)]
+    keep_max_bytes: Option<u64>,
+
+    /// Move released blocks into this directory instead of deleting them.
+    #[arg(long, conflicts_with = "archive_suffix")]
+    archive_dir: Option<PathBuf>,
+
+    /// Rename released blocks by appending this suffix instead of deleting them.
+    #[arg(long)]
+    archive_suffix: Option<String>,
+
+    /// Serve Prometheus metrics at `/metrics` on this address.
+    #[arg(long, env = "BOGGER_METRICS_ADDR")]
+    metrics_addr: Option<String>,
+
+    /// Serve health checks at `/health` on this address.
+    #[arg(long, env = "BOGGER_HEALTH_ADDR")]
+    health_addr: Option<String>,
+
+    /// Report unhealthy if sent records are not acknowledged for this many seconds.
+    #[arg(long, default_value_t = 300)]
+    max_ack_age: u64,
+
+    /// Report unhealthy if less than this many bytes are available on disk.
+    #[arg(long, default_value_t = 0)]
+    min_free_space: u64,
+
+    /// Prefix of block file names.
+    #[arg(long)]
+    prefix: Option<String>,
+
+    /// Suffix of block file names.
+    #[arg(long)]
+    suffix: Option<String>
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let args = Args::parse();
 
+    let settings = match &args.config {
+        Some(path) => Settings::from_path(path).await?,
+        None       => Settings::default()
+    };
+
+    let (filter, log) = reload::Layer::new(log_filter(settings.log.as_deref())?);
     tracing_subscriber::registry()
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| "bogger=debug".into()))
+        .with(filter)
         .with(fmt::layer())
         .init();
 
-    Forwarder::new("test", &args.directory, &args.address).await?.go().await
+    let mut config = settings.forward.clone();
+    if let Some(n) = args.poll_interval {
+        config = config.with_poll_interval(Duration::from_millis(n))
+    }
+    if let Some(n) = args.ack_timeout {
+        config = config.with_ack_timeout(Some(Duration::from_secs(n)))
+    }
+    if let Some(n) = args.stall_timeout {
+        config = config.with_stall_timeout(Some(Duration::from_secs(n)))
+    }
+    if args.stall_reconnect {
+        config = config.with_stall_reconnect(true)
+    }
+    if args.min_reconnect_delay.is_some() || args.max_reconnect_delay.is_some() {
+        let min = args.min_reconnect_delay.map(Duration::from_millis).unwrap_or(config.min_reconnect_delay());
+        let max = args.max_reconnect_delay.map(Duration::from_millis).unwrap_or(config.max_reconnect_delay());
+        config = config.with_reconnect_delay(min, max)
+    }
+
+    if let Some(n) = args.read_ahead {
+        config = config.with_read_ahead(n)
+    }
+    if let Some(n) = args.parallel_blocks {
+        config = config.with_parallel_blocks(n)
+    }
+    for w in &args.window {
+        config = config.with_window(*w)
+    }
+
+    let overrides = Overrides {
+        keep_max_age: args.keep_max_age,
+        keep_max_bytes: args.keep_max_bytes,
+        max_bytes_per_day: args.max_bytes_per_day
+    };
+    let retention = overrides.retention(&settings);
+    let rate_limit = overrides.rate_limit(&settings);
+
+    let mut archive = settings.archive;
+    if let Some(d) = args.archive_dir {
+        archive = ArchiveAction::MoveTo(d)
+    }
+    if let Some(s) = args.archive_suffix {
+        archive = ArchiveAction::RenameSuffix(s)
+    }
+
+    let id = args.id
+        .or(settings.id)
+        .unwrap_or_else(|| Identity::local().hostname().to_string());
+
+    let mut names = settings.storage.block_names().clone();
+    if let Some(p) = args.prefix {
+        names = names.with_prefix(p)
+    }
+    if let Some(s) = args.suffix {
+        names = names.with_suffix(s)
+    }
+
+    let identity = (args.identity || !args.label.is_empty())
+        .then(|| args.label.into_iter().fold(Identity::local(), |i, (k, v)| i.with_label(k, v)));
+    let metrics = (args.metrics_addr.is_some() || args.health_addr.is_some()).then(Metrics::new);
+
+    let configure = |mut f: Forwarder| {
+        f = f.with_client_cursor(args.client_cursor)
+            .with_dry_run(args.dry_run)
+            .with_shared_directory(args.shared_directory)
+            .with_skip_corrupt(args.skip_corrupt)
+            .with_quarantine(args.quarantine)
+            .with_config(config.clone())
+            .with_retention(retention.clone())
+            .with_rate_limit(rate_limit)
+            .with_archive_action(archive.clone())
+            .with_block_names(names.clone());
+        if let Some(t) = &args.token {
+            f = f.with_token(t)
+        }
+        for a in &args.address[1 ..] {
+            f = f.with_destination(a)
+        }
+        if let Some(m) = &metrics {
+            f = f.with_metrics(m)
+        }
+        if let Some(i) = &identity {
+            f = f.with_identity(i.clone())
+        }
+        f
+    };
+
+    let mut forwarder = configure(Forwarder::new(&id, &args.directory, &args.address[0]).await?);
+    if let Some(s) = &args.stream {
+        forwarder = forwarder.with_stream(s)
+    }
+    let mut forwarders = MultiForwarder::new();
+    if args.multiplex {
+        for (name, dir) in &args.add_stream {
+            forwarder = forwarder.with_multiplexed(name, dir)
+        }
+        forwarders = forwarders.with_forwarder(forwarder)
+    } else {
+        forwarders = forwarders.with_forwarder(forwarder);
+        for (name, dir) in &args.add_stream {
+            let f = Forwarder::new(&id, dir, &args.address[0]).await?.with_stream(name);
+            forwarders = forwarders.with_forwarder(configure(f))
+        }
+    }
+
+    if let Some(m) = &metrics {
+        let max_ack_age = Duration::from_secs(args.max_ack_age);
+        let dirs = std::iter::once(&args.directory).chain(args.add_stream.iter().map(|s| &s.1));
+        add_checks(m, forwarders.progress(), max_ack_age, dirs, args.min_free_space);
+        for a in args.metrics_addr.iter().chain(&args.health_addr) {
+            m.serve(a).await?;
+        }
+    }
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(args.config.clone(), overrides, forwarders.handles(), log));
+    #[cfg(not(unix))]
+    let _ = log;
+    #[cfg(all(feature = "systemd", unix))]
+    started()?;
+    forwarders.go_until(shutdown()).await;
+    Ok(())
+}
+
+/// Command line options which take precedence over reloadable settings.
+#[derive(Debug, Clone, Copy)]
+struct Overrides {
+    keep_max_age: Option<u64>,
+    keep_max_bytes: Option<u64>,
+    max_bytes_per_day: Option<NonZeroU64>
+}
+
+impl Overrides {
+    fn retention(&self, settings: &Settings) -> Retention {
+        if self.keep_max_age.is_none() && self.keep_max_bytes.is_none() {
+            return settings.retention.clone()
+        }
+        Retention::AfterAck {
+            max_age: self.keep_max_age.map(Duration::from_secs),
+            max_bytes: self.keep_max_bytes
+        }
+    }
+
+    fn rate_limit(&self, settings: &Settings) -> RateLimit {
+        match self.max_bytes_per_day {
+            Some(n) => settings.rate_limit.with_bytes_per_day(n),
+            None    => settings.rate_limit
+        }
+    }
+}
+
+/// The log filter of `RUST_LOG`, or else of the settings, or the default.
+fn log_filter(setting: Option<&str>) -> Result<EnvFilter, ParseError> {
+    match EnvFilter::try_from_default_env() {
+        Ok(f)  => Ok(f),
+        Err(_) => EnvFilter::try_new(setting.unwrap_or("bogger=debug"))
+    }
+}
+
+/// Apply retention, rate limit and log settings of the settings file
+/// whenever SIGHUP is received.
+#[cfg(unix)]
+async fn reload_on_hangup
+    ( path: Option<PathBuf>
+    , overrides: Overrides
+    , handles: Vec<ForwarderHandle>
+    , log: reload::Handle<EnvFilter, Registry>
+    )
+{
+    let mut hangup = signal(SignalKind::hangup()).expect("SIGHUP handler can be installed");
+    while hangup.recv().await.is_some() {
+        let Some(path) = &path else {
+            tracing::warn!("no settings file to reload");
+            continue
+        };
+        let settings = match Settings::from_path(path).await {
+            Ok(s)    => s,
+            Err(err) => {
+                tracing::error!(?path, %err, "failed to reload settings");
+                continue
+            }
+        };
+        match log_filter(settings.log.as_deref()) {
+            Ok(f)    => if let Err(err) = log.reload(f) {
+                tracing::error!(%err, "failed to reload log filter")
+            },
+            Err(err) => tracing::error!(%err, "invalid log filter")
+        }
+        let (retention, rate_limit) = (overrides.retention(&settings), overrides.rate_limit(&settings));
+        for h in &handles {
+            h.update_retention(retention.clone());
+            h.update_rate_limit(rate_limit)
+        }
+        tracing::info!(?path, ?retention, ?rate_limit, "reloaded settings")
+    }
+}
+
+fn add_checks<'a, I>
+    ( metrics: &Metrics
+    , progress: Vec<watch::Receiver<ForwardProgress>>
+    , max_ack_age: Duration
+    , dirs: I
+    , min_free: u64
+    )
+where
+    I: IntoIterator<Item = &'a PathBuf>
+{
+    let p = progress.clone();
+    metrics.add_check("connection", move || {
+        if p.iter().any(|p| p.borrow().connections() == 0) {
+            return Err("not connected to any destination".into())
+        }
+        Ok(())
+    });
+    let start = Instant::now();
+    metrics.add_check("ack", move || {
+        for p in &progress {
+            let p = p.borrow();
+            let age = p.acked_at().unwrap_or(start).elapsed();
+            if p.is_pending() && age > max_ack_age {
+                return Err(format!("no acknowledgement for {}s", age.as_secs()))
+            }
+        }
+        Ok(())
+    });
+    let dirs: Vec<PathBuf> = dirs.into_iter().cloned().collect();
+    metrics.add_check("disk", move || {
+        for d in &dirs {
+            match available_space(d) {
+                Ok(Some(n)) if n < min_free => return Err(format!("only {n} bytes available in {d:?}")),
+                Ok(_)                       => {}
+                Err(err)                    => return Err(format!("failed to determine available space: {err}"))
+            }
+        }
+        Ok(())
+    })
+}
+
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (k, v) = s.split_once('=').ok_or_else(|| format!("invalid label {s:?}, expected key=value"))?;
+    Ok((k.to_string(), v.to_string()))
+}
+
+fn parse_stream(s: &str) -> Result<(String, PathBuf), String> {
+    let (k, v) = s.split_once('=').ok_or_else(|| format!("invalid stream {s:?}, expected name=directory"))?;
+    Ok((k.to_string(), PathBuf::from(v)))
+}
+
+/// Completes on SIGINT or SIGTERM.
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        let mut term = signal(SignalKind::terminate()).expect("SIGTERM handler can be installed");
+        select! {
+            _ = ctrl_c()    => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c().await;
+    #[cfg(all(feature = "systemd", unix))]
+    let _ = bogger::systemd::notify_stopping();
+}
+
+/// Tell systemd that start-up is complete and keep its watchdog happy.
+#[cfg(all(feature = "systemd", unix))]
+fn started() -> std::io::Result<()> {
+    bogger::systemd::notify_ready()?;
+    bogger::systemd::spawn_watchdog();
+    Ok(())
 }
@@ -1,6 +1,6 @@
 use clap::Parser;
-use bogger::{BlockInfo, EntryReader};
-use std::{error::Error, path::PathBuf};
+use bogger::{BlockInfo, BlockNames, EntryReader, LogRecord, Schemas, SyslogFormat};
+use std::{error::Error, io, path::PathBuf};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -11,21 +11,78 @@ struct Args {
 
     /// Block to read.
     #[arg(short, long)]
-    block_num: u64
+    block_num: u64,
+
+    /// Print all entries in CBOR diagnostic notation.
+    #[arg(short, long)]
+    raw: bool,
+
+    /// Schema ID of entries which are log records. Entries without schema
+    /// ID are always considered log records.
+    #[arg(long = "log-schema")]
+    log_schemas: Vec<u32>,
+
+    /// Prefix of block file names.
+    #[arg(long, default_value = "block.")]
+    prefix: String,
+
+    /// Suffix of block file names.
+    #[arg(long, default_value = "")]
+    suffix: String,
+
+    /// Print log records as RFC 5424 syslog messages.
+    #[arg(long)]
+    syslog: bool,
+
+    /// Send syslog messages to this unix datagram socket (e.g. `/dev/log`)
+    /// instead of printing them.
+    #[cfg(unix)]
+    #[arg(long)]
+    syslog_socket: Option<PathBuf>,
+
+    /// Syslog facility code.
+    #[arg(long, default_value_t = 1)]
+    facility: u8,
+
+    /// Syslog application name.
+    #[arg(long, default_value = "bogger")]
+    app_name: String
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let args = Args::parse();
 
+    let output = Output::new(&args)?;
+
+    let syslog = (args.syslog || !matches!(output, Output::Stdout)).then(|| {
+        SyslogFormat::new().with_facility(args.facility).with_app_name(&args.app_name)
+    });
+
+    let schemas = args.log_schemas.iter().fold(
+        Schemas::new().with_default(|b| minicbor::decode::<LogRecord>(b)),
+        |s, id| s.with_cbor::<LogRecord>(*id)
+    );
+
     let mut reader = {
         let b = BlockInfo::zero().with_number(args.block_num);
-        EntryReader::open(&args.directory, b).await?
+        let n = BlockNames::new().with_prefix(args.prefix).with_suffix(args.suffix);
+        EntryReader::open_with(&args.directory, b, &n).await?
     };
 
     loop {
         match reader.next_entry().await {
-            Ok(Some((b, _crc))) => println!("{}", minicbor::display(&b)),
+            Ok(Some((b, _crc))) =>
+                match (schemas.decode(reader.schema(), &b), &syslog) {
+                    (Ok(r), Some(f)) => output.write(&f.format(&r)).await?,
+                    (Ok(r), None) if !args.raw => println!("{r}"),
+                    // Entries which are no log records can not be exported.
+                    (Err(_), Some(_)) => eprintln!("not a log record: {}", minicbor::display(&b)),
+                    _ => match reader.schema() {
+                        Some(id) => println!("schema {id}: {}", minicbor::display(&b)),
+                        None     => println!("{}", minicbor::display(&b))
+                    }
+                }
             Ok(None)   => break,
             Err(error) => eprintln!("{error}")
         }
@@ -33,3 +90,36 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     Ok(())
 }
+
+/// Where syslog messages are written to.
+enum Output {
+    Stdout,
+    #[cfg(unix)]
+    Socket(tokio::net::UnixDatagram)
+}
+
+impl Output {
+    #[cfg(unix)]
+    fn new(args: &Args) -> io::Result<Self> {
+        let Some(path) = &args.syslog_socket else {
+            return Ok(Output::Stdout)
+        };
+        let s = tokio::net::UnixDatagram::unbound()?;
+        s.connect(path)?;
+        Ok(Output::Socket(s))
+    }
+
+    #[cfg(not(unix))]
+    fn new(_: &Args) -> io::Result<Self> {
+        Ok(Output::Stdout)
+    }
+
+    async fn write(&self, line: &str) -> io::Result<()> {
+        match self {
+            Output::Stdout => println!("{line}"),
+            #[cfg(unix)]
+            Output::Socket(s) => { s.send(line.as_bytes()).await?; }
+        }
+        Ok(())
+    }
+}
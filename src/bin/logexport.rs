@@ -0,0 +1,96 @@
+use clap::{Parser, ValueEnum};
+use bogger::{BlockInfo, BlockNames, LogReader};
+use bogger::export::{Column, Mapping, ParquetExport};
+use std::{error::Error, fs::File, io::{BufWriter, Write}, path::PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory path containing blocks.
+    #[arg(short, long)]
+    directory: PathBuf,
+
+    /// File to write.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = Format::Parquet)]
+    format: Format,
+
+    /// Map entries to columns instead of reading them as log records,
+    /// e.g. `--column host=host:text --column latency=2:float`.
+    #[arg(long = "column")]
+    columns: Vec<Column>,
+
+    /// Only export entries with this schema ID.
+    #[arg(long)]
+    schema: Option<u32>,
+
+    /// First block to export.
+    #[arg(long, default_value_t = 0)]
+    from: u64,
+
+    /// Skip entries which can not be decoded.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    /// Number of rows per row group.
+    #[arg(long, default_value_t = 64 * 1024)]
+    row_group_len: usize,
+
+    /// Prefix of block file names.
+    #[arg(long, default_value = "block.")]
+    prefix: String,
+
+    /// Suffix of block file names.
+    #[arg(long, default_value = "")]
+    suffix: String
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Parquet
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+
+    let output = BufWriter::new(File::create(&args.output)?);
+    let mut export = match args.format {
+        Format::Parquet if args.columns.is_empty() => ParquetExport::log_records(output)?,
+        Format::Parquet => {
+            let m = args.columns.into_iter().fold(Mapping::new(), Mapping::with_column);
+            ParquetExport::with_mapping(output, m)?
+        }
+    };
+    export = export.with_row_group_len(args.row_group_len);
+
+    let mut reader = {
+        let b = BlockInfo::zero().with_number(args.from);
+        let n = BlockNames::new().with_prefix(args.prefix).with_suffix(args.suffix);
+        LogReader::new_with(&args.directory, b, n)
+    };
+
+    let (mut entries, mut skipped) = (0, 0);
+    while let Some((pos, entry)) = reader.next_entry().await? {
+        entries += 1;
+        if args.schema.is_some() && reader.schema() != args.schema {
+            skipped += 1;
+            continue
+        }
+        if let Err(e) = export.write(&entry) {
+            if !args.skip_invalid {
+                return Err(format!("entry at {pos}: {e}").into())
+            }
+            eprintln!("skipping entry at {pos}: {e}");
+            skipped += 1
+        }
+    }
+
+    let (rows, mut output) = export.finish()?;
+    output.flush()?;
+    eprintln!("{entries} entries, {rows} exported, {skipped} skipped");
+    Ok(())
+}
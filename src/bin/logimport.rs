@@ -0,0 +1,60 @@
+use clap::Parser;
+use bogger::{BlockNames, Config, EntryWriter};
+use bogger::import::{Importer, LineFormat};
+use std::{error::Error, path::PathBuf};
+use tokio::io::{self, BufReader};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory path to append blocks to.
+    #[arg(short, long)]
+    directory: PathBuf,
+
+    /// File to import (default: stdin).
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// Lines are JSON objects instead of plain text.
+    #[arg(long)]
+    json: bool,
+
+    /// Target of records without one.
+    #[arg(long, default_value = "import")]
+    target: String,
+
+    /// Skip lines which can not be parsed.
+    #[arg(long)]
+    skip_invalid: bool,
+
+    /// Prefix of block file names.
+    #[arg(long, default_value = "block.")]
+    prefix: String,
+
+    /// Suffix of block file names.
+    #[arg(long, default_value = "")]
+    suffix: String
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+
+    let importer = Importer::new(if args.json { LineFormat::Json } else { LineFormat::Text })
+        .with_target(args.target)
+        .with_skip_invalid(args.skip_invalid);
+
+    let mut writer = {
+        let n = BlockNames::new().with_prefix(args.prefix).with_suffix(args.suffix);
+        EntryWriter::open(&args.directory, Config::default().with_block_names(n)).await?
+    };
+
+    let summary = match &args.input {
+        Some(path) => importer.import(BufReader::new(tokio::fs::File::open(path).await?), &mut writer).await?,
+        None       => importer.import(BufReader::new(io::stdin()), &mut writer).await?
+    };
+    writer.close().await?;
+
+    eprintln!("{} lines, {} imported, {} skipped", summary.lines(), summary.imported(), summary.skipped());
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use clap::{Parser, ValueEnum};
+use bogger::{BlockNames, Config, DigestKind, MigrateOptions, migrate_blocks_with};
+use std::{error::Error, path::PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory path containing blocks.
+    #[arg(short, long)]
+    directory: PathBuf,
+
+    /// Directory to write migrated blocks to (default: migrate in place).
+    #[arg(short, long)]
+    target: Option<PathBuf>,
+
+    /// Minimum block format version, e.g. 2 for 32-bit entry lengths.
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1 ..= 3))]
+    format_version: u8,
+
+    /// Add a trailer to every block.
+    #[arg(long)]
+    trailer: bool,
+
+    /// Allow entries to span several frames.
+    #[arg(long)]
+    chunking: bool,
+
+    /// Add a digest to every entry.
+    #[arg(long, value_enum)]
+    digest: Option<Digest>,
+
+    /// Add (empty) metadata to every entry.
+    #[arg(long)]
+    entry_metadata: bool,
+
+    /// Also migrate blocks which have not been forwarded yet, invalidating
+    /// the positions of forwarders and receivers in them.
+    #[arg(long)]
+    force: bool,
+
+    /// Sync every migrated block.
+    #[arg(long)]
+    durable: bool,
+
+    /// Prefix of block file names.
+    #[arg(long, default_value = "block.")]
+    prefix: String,
+
+    /// Suffix of block file names.
+    #[arg(long, default_value = "")]
+    suffix: String
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Digest {
+    Xxh64,
+    Blake3
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+
+    let cfg = Config::default()
+        .with_block_names(BlockNames::new().with_prefix(args.prefix).with_suffix(args.suffix))
+        .with_trailer(args.trailer)
+        .with_chunking(args.chunking)
+        .with_digest(args.digest.map(|d| match d {
+            Digest::Xxh64  => DigestKind::Xxh64,
+            Digest::Blake3 => DigestKind::Blake3
+        }))
+        .with_entry_metadata(args.entry_metadata)
+        .with_durable_metadata(args.durable);
+
+    let opts = MigrateOptions::new()
+        .with_config(cfg)
+        .with_min_version(args.format_version)
+        .with_target_dir(args.target.as_ref())
+        .with_force(args.force);
+
+    let summary = migrate_blocks_with(&args.directory, &opts).await?;
+    eprintln!("{} blocks migrated, {} unchanged, {} not forwarded yet",
+        summary.migrated().len(),
+        summary.skipped().len(),
+        summary.pending().len());
+    Ok(())
+}
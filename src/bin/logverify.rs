@@ -0,0 +1,53 @@
+use clap::Parser;
+use bogger::{BlockNames, BlockNum, BlockStatus, list_blocks_with, verify_block_with};
+use std::{error::Error, path::PathBuf, process::ExitCode};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Directory path containing blocks.
+    #[arg(short, long)]
+    directory: PathBuf,
+
+    /// Blocks to verify (default: all blocks in the directory).
+    #[arg(short, long)]
+    block_num: Vec<u64>,
+
+    /// Prefix of block file names.
+    #[arg(long, default_value = "block.")]
+    prefix: String,
+
+    /// Suffix of block file names.
+    #[arg(long, default_value = "")]
+    suffix: String
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+
+    let names = BlockNames::new().with_prefix(args.prefix).with_suffix(args.suffix);
+    let mut blocks = args.block_num;
+    if blocks.is_empty() {
+        blocks = list_blocks_with(&args.directory, &names).await?.iter().map(|b| b.number().value()).collect()
+    }
+
+    let mut failed = false;
+    for n in blocks {
+        let name = names.file_name(BlockNum::from(n));
+        match verify_block_with(&args.directory, BlockNum::from(n), &names).await {
+            Ok(BlockStatus::Complete { entries }) => println!("{name}: ok ({entries} entries, trailer)"),
+            Ok(BlockStatus::Valid { entries })    => println!("{name}: ok ({entries} entries)"),
+            Ok(BlockStatus::Corrupt { entries })  => {
+                println!("{name}: corrupt after {entries} entries");
+                failed = true
+            }
+            Err(err) => {
+                println!("{name}: {err}");
+                failed = true
+            }
+        }
+    }
+
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
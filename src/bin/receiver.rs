@@ -0,0 +1,235 @@
+use clap::Parser;
+use bogger::{AckCadence, Receiver, FileSink, Metrics, OwnRecords, RelayAck, RelaySink, Settings, Sink, Tokens, Window, available_space};
+use std::{error::Error, path::PathBuf, time::Duration};
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::{select, signal::unix::{signal, SignalKind}};
+use tracing_subscriber::{EnvFilter, Registry, filter::ParseError, fmt, prelude::*, reload};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Settings file with storage settings and, when relaying, forwarding
+    /// and retention settings.
+    ///
+    /// On SIGHUP the log settings are reloaded from it.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Directory path to store received blocks in.
+    #[arg(short, long)]
+    directory: PathBuf,
+
+    /// Network address to listen on.
+    #[arg(short, long)]
+    address: String,
+
+    /// Accepted client token as `<id>=<token>` (may be given multiple times).
+    #[arg(long)]
+    token: Vec<String>,
+
+    /// Acknowledge after this many records.
+    #[arg(long)]
+    ack_records: Option<u64>,
+
+    /// Acknowledge after records with this many bytes.
+    #[arg(long)]
+    ack_bytes: Option<u64>,
+
+    /// Acknowledge at least every this many milliseconds.
+    #[arg(long)]
+    ack_interval: Option<u64>,
+
+    /// Flow control window as `<records>,<bytes>`.
+    #[arg(long)]
+    window: Option<String>,
+
+    /// Forward received records to the receiver at this address.
+    #[arg(long)]
+    relay: Option<String>,
+
+    /// Acknowledge relayed records only once the upstream receiver did.
+    #[arg(long)]
+    relay_upstream_ack: bool,
+
+    /// Let clients replay the records they have forwarded.
+    #[arg(long)]
+    replay: bool,
+
+    /// Number of sinks storing records of different clients concurrently.
+    #[arg(long, default_value_t = 1)]
+    workers: usize,
+
+    /// Serve Prometheus metrics at `/metrics` on this address.
+    #[arg(long)]
+    metrics_addr: Option<String>,
+
+    /// Serve health checks at `/health` on this address.
+    #[arg(long)]
+    health_addr: Option<String>,
+
+    /// Report unhealthy if less than this many bytes are available on disk.
+    #[arg(long, default_value_t = 0)]
+    min_free_space: u64
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+
+    let settings = match &args.config {
+        Some(path) => Some(Settings::from_path(path).await?),
+        None       => None
+    };
+
+    let (filter, log) = reload::Layer::new(log_filter(settings.as_ref().and_then(|s| s.log.as_deref()))?);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(args.config.clone(), log));
+    #[cfg(not(unix))]
+    let _ = log;
+
+    match &args.relay {
+        Some(upstream) => {
+            let policy = if args.relay_upstream_ack { RelayAck::Upstream } else { RelayAck::Durable };
+            run(&args, || {
+                let mut sink = RelaySink::new(&args.directory, upstream).with_policy(policy);
+                if let Some(s) = &settings {
+                    sink = sink.with_config(s.storage.clone())
+                        .with_forward_config(s.forward.clone())
+                        .with_retention(s.retention.clone())
+                }
+                sink
+            }).await
+        }
+        None => {
+            run(&args, || {
+                let mut sink = FileSink::new(&args.directory);
+                if let Some(s) = &settings {
+                    sink = sink.with_config(s.storage.clone())
+                }
+                sink
+            }).await
+        }
+    }
+}
+
+async fn run<S, F>(args: &Args, make: F) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: Sink,
+    F: FnMut() -> S
+{
+    let mut cadence = AckCadence::new();
+    if let Some(n) = args.ack_records {
+        cadence = cadence.with_records(n)
+    }
+    if let Some(n) = args.ack_bytes {
+        cadence = cadence.with_bytes(n)
+    }
+    if let Some(n) = args.ack_interval {
+        cadence = cadence.with_interval(Duration::from_millis(n))
+    }
+
+    let mut receiver = Receiver::new_with_workers(&args.address, args.workers, make)
+        .await?
+        .with_ack_cadence(cadence);
+    if let Some(w) = &args.window {
+        let Some((records, bytes)) = w.split_once(',') else {
+            return Err(format!("invalid window argument: {w}").into())
+        };
+        receiver = receiver.with_window(Window::new(records.parse()?, bytes.parse()?))
+    }
+    if args.metrics_addr.is_some() || args.health_addr.is_some() {
+        let metrics = Metrics::new();
+        let (dir, min_free) = (args.directory.clone(), args.min_free_space);
+        metrics.add_check("disk", move || {
+            match available_space(&dir) {
+                Ok(Some(n)) if n < min_free => Err(format!("only {n} bytes available")),
+                Ok(_)                       => Ok(()),
+                Err(err)                    => Err(format!("failed to determine available space: {err}"))
+            }
+        });
+        for a in args.metrics_addr.iter().chain(&args.health_addr) {
+            metrics.serve(a).await?;
+        }
+        receiver = receiver.with_metrics(&metrics)
+    }
+    if !args.token.is_empty() {
+        let mut tokens = Tokens::new();
+        for t in &args.token {
+            let Some((id, token)) = t.split_once('=') else {
+                return Err(format!("invalid token argument: {t}").into())
+            };
+            tokens = tokens.with_token(id, token)
+        }
+        receiver = receiver.with_authenticator(tokens)
+    }
+    if args.replay {
+        receiver = receiver.with_replay(OwnRecords)
+    }
+    #[cfg(all(feature = "systemd", unix))]
+    started()?;
+    receiver.go_until(shutdown()).await;
+    Ok(())
+}
+
+/// The log filter of `RUST_LOG`, or else of the settings, or the default.
+fn log_filter(setting: Option<&str>) -> Result<EnvFilter, ParseError> {
+    match EnvFilter::try_from_default_env() {
+        Ok(f)  => Ok(f),
+        Err(_) => EnvFilter::try_new(setting.unwrap_or("bogger=debug"))
+    }
+}
+
+/// Apply the log settings of the settings file whenever SIGHUP is received.
+#[cfg(unix)]
+async fn reload_on_hangup(path: Option<PathBuf>, log: reload::Handle<EnvFilter, Registry>) {
+    let mut hangup = signal(SignalKind::hangup()).expect("SIGHUP handler can be installed");
+    while hangup.recv().await.is_some() {
+        let Some(path) = &path else {
+            tracing::warn!("no settings file to reload");
+            continue
+        };
+        let settings = match Settings::from_path(path).await {
+            Ok(s)    => s,
+            Err(err) => {
+                tracing::error!(?path, %err, "failed to reload settings");
+                continue
+            }
+        };
+        match log_filter(settings.log.as_deref()) {
+            Ok(f)    => if let Err(err) = log.reload(f) {
+                tracing::error!(%err, "failed to reload log filter")
+            },
+            Err(err) => tracing::error!(%err, "invalid log filter")
+        }
+        tracing::info!(?path, "reloaded settings")
+    }
+}
+
+/// Completes on SIGINT or SIGTERM.
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        let mut term = signal(SignalKind::terminate()).expect("SIGTERM handler can be installed");
+        select! {
+            _ = ctrl_c()    => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c().await;
+    #[cfg(all(feature = "systemd", unix))]
+    let _ = bogger::systemd::notify_stopping();
+}
+
+/// Tell systemd that start-up is complete and keep its watchdog happy.
+#[cfg(all(feature = "systemd", unix))]
+fn started() -> std::io::Result<()> {
+    bogger::systemd::notify_ready()?;
+    bogger::systemd::spawn_watchdog();
+    Ok(())
+}
@@ -0,0 +1,530 @@
+//! Export entries to Parquet files, e.g. to query archived logs with
+//! DuckDB or Spark.
+
+use std::{fmt, io::Write, str::FromStr, sync::Arc, time::UNIX_EPOCH};
+
+use minicbor::{data::Type, decode, Decoder};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::{properties::WriterProperties, writer::SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+
+use crate::LogRecord;
+
+/// Writes entries as rows of a Parquet file.
+///
+/// Entries are either `LogRecord`s, which become rows with the columns
+/// `timestamp`, `level`, `target`, `message` and `fields` (a map of
+/// strings), or CBOR arrays or maps whose items are mapped to columns
+/// by a `Mapping`.
+pub struct ParquetExport<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    mapping: Option<Mapping>,
+    columns: Vec<Buffer>,
+    rows: usize,
+    total: u64,
+    row_group_len: usize
+}
+
+impl<W: Write + Send> fmt::Debug for ParquetExport<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParquetExport")
+            .field("mapping", &self.mapping)
+            .field("rows", &self.rows)
+            .field("total", &self.total)
+            .field("row_group_len", &self.row_group_len)
+            .finish()
+    }
+}
+
+impl<W: Write + Send> ParquetExport<W> {
+    /// Export entries which are `LogRecord`s.
+    pub fn log_records(w: W) -> Result<Self, ExportError> {
+        let schema = "
+            message log_record {
+                required int64 timestamp (TIMESTAMP(NANOS, true));
+                required binary level (STRING);
+                required binary target (STRING);
+                required binary message (STRING);
+                required group fields (MAP) {
+                    repeated group key_value {
+                        required binary key (STRING);
+                        required binary value (STRING);
+                    }
+                }
+            }";
+        let columns = vec![
+            Buffer::new(Values::Int(Vec::new())),
+            Buffer::new(Values::Bytes(Vec::new())),
+            Buffer::new(Values::Bytes(Vec::new())),
+            Buffer::new(Values::Bytes(Vec::new())),
+            Buffer::new(Values::Bytes(Vec::new())),
+            Buffer::new(Values::Bytes(Vec::new()))
+        ];
+        Self::new(w, schema, None, columns)
+    }
+
+    /// Export entries with the columns of the given mapping.
+    pub fn with_mapping(w: W, m: Mapping) -> Result<Self, ExportError> {
+        if m.columns.is_empty() {
+            return Err(ExportError::NoColumns)
+        }
+        let mut schema = String::from("message entry {\n");
+        for c in &m.columns {
+            if m.columns.iter().filter(|x| x.name == c.name).count() > 1 {
+                return Err(ExportError::InvalidColumn(c.name.clone()))
+            }
+            let t = match c.kind {
+                Kind::Int                             => "int64",
+                Kind::Float                           => "double",
+                Kind::Bool                            => "boolean",
+                Kind::Bytes | Kind::Text | Kind::Cbor => "binary"
+            };
+            let logical = if matches!(c.kind, Kind::Text | Kind::Cbor) { " (STRING)" } else { "" };
+            schema.push_str(&format!("optional {t} {}{logical};\n", c.name))
+        }
+        schema.push('}');
+        let columns = m.columns.iter()
+            .map(|c| Buffer::new(match c.kind {
+                Kind::Int   => Values::Int(Vec::new()),
+                Kind::Float => Values::Float(Vec::new()),
+                Kind::Bool  => Values::Bool(Vec::new()),
+                _           => Values::Bytes(Vec::new())
+            }))
+            .collect();
+        Self::new(w, &schema, Some(m), columns)
+    }
+
+    fn new(w: W, schema: &str, mapping: Option<Mapping>, columns: Vec<Buffer>) -> Result<Self, ExportError> {
+        let schema = Arc::new(parse_message_type(schema)?);
+        let props = Arc::new(WriterProperties::builder().build());
+        Ok(Self {
+            writer: SerializedFileWriter::new(w, schema, props)?,
+            mapping,
+            columns,
+            rows: 0,
+            total: 0,
+            row_group_len: 64 * 1024
+        })
+    }
+
+    /// The number of rows buffered before they are written as a row group.
+    pub fn with_row_group_len(mut self, n: usize) -> Self {
+        self.row_group_len = n.max(1);
+        self
+    }
+
+    /// Add an entry as a row.
+    ///
+    /// Entries which can not be decoded are rejected without adding a row.
+    pub fn write(&mut self, entry: &[u8]) -> Result<(), ExportError> {
+        match &self.mapping {
+            None => {
+                let r = minicbor::decode::<LogRecord>(entry)?;
+                push_log_record(&mut self.columns, &r)
+            }
+            Some(m) => {
+                let cells = m.cells(entry)?;
+                for (b, c) in self.columns.iter_mut().zip(cells) {
+                    b.push_optional(c)
+                }
+            }
+        }
+        self.rows += 1;
+        self.total += 1;
+        if self.rows >= self.row_group_len {
+            self.flush()?
+        }
+        Ok(())
+    }
+
+    /// Write the buffered rows as a row group.
+    pub fn flush(&mut self) -> Result<(), ExportError> {
+        if self.rows == 0 {
+            return Ok(())
+        }
+        let mut rg = self.writer.next_row_group()?;
+        for b in &mut self.columns {
+            let Some(mut col) = rg.next_column()? else {
+                return Err(ParquetError::General("missing column".into()).into())
+            };
+            let defs = (!b.defs.is_empty()).then_some(&b.defs[..]);
+            let reps = (!b.reps.is_empty()).then_some(&b.reps[..]);
+            match &b.values {
+                Values::Int(v)   => col.typed::<Int64Type>().write_batch(v, defs, reps)?,
+                Values::Float(v) => col.typed::<DoubleType>().write_batch(v, defs, reps)?,
+                Values::Bool(v)  => col.typed::<BoolType>().write_batch(v, defs, reps)?,
+                Values::Bytes(v) => col.typed::<ByteArrayType>().write_batch(v, defs, reps)?
+            };
+            col.close()?;
+            b.clear()
+        }
+        rg.close()?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Write the remaining rows and the file footer.
+    ///
+    /// Returns the number of rows written and the sink.
+    pub fn finish(mut self) -> Result<(u64, W), ExportError> {
+        self.flush()?;
+        Ok((self.total, self.writer.into_inner()?))
+    }
+}
+
+fn push_log_record(columns: &mut [Buffer], r: &LogRecord) {
+    let nanos = r.timestamp().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    columns[0].push(Cell::Int(i64::try_from(nanos).unwrap_or(i64::MAX)));
+    columns[1].push(Cell::Bytes(r.level().to_string().into_bytes()));
+    columns[2].push(Cell::Bytes(r.target().as_bytes().to_vec()));
+    columns[3].push(Cell::Bytes(r.message().as_bytes().to_vec()));
+    // An empty map has a single level without value, every other
+    // key-value pair after the first one repeats the map.
+    let mut rep = 0;
+    for (k, v) in r.fields() {
+        let v = match v {
+            crate::Value::Str(s) => s.clone(),
+            other                => other.to_string()
+        };
+        let [keys, vals] = &mut columns[4 ..] else {
+            unreachable!("key and value columns")
+        };
+        for (b, x) in [(keys, k.as_bytes().to_vec()), (vals, v.into_bytes())] {
+            b.push(Cell::Bytes(x));
+            b.defs.push(1);
+            b.reps.push(rep)
+        }
+        rep = 1
+    }
+    if rep == 0 {
+        for b in &mut columns[4 ..] {
+            b.defs.push(0);
+            b.reps.push(0)
+        }
+    }
+}
+
+/// How the items of CBOR arrays or maps are mapped to columns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mapping {
+    columns: Vec<Column>
+}
+
+impl Mapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_column(mut self, c: Column) -> Self {
+        self.columns.push(c);
+        self
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Get the cell of every column from an entry.
+    fn cells(&self, entry: &[u8]) -> Result<Vec<Option<Cell>>, ExportError> {
+        let items = items(entry)?;
+        let mut cells = Vec::with_capacity(self.columns.len());
+        for c in &self.columns {
+            let item = items.iter().find(|(k, _)| match (&c.field, k) {
+                (Field::Index(i), Key::Index(j)) => i == j,
+                (Field::Key(a), Key::Name(b))    => a == b,
+                _                                => false
+            });
+            let cell = match item {
+                Some((_, b)) => cell(c.kind, b).map_err(|e| ExportError::Column(c.name.clone(), e))?,
+                None         => None
+            };
+            cells.push(cell)
+        }
+        Ok(cells)
+    }
+}
+
+/// A column of a `Mapping`.
+///
+/// Columns are parsed from `<name>=<field>:<kind>`, e.g. `host=host:text`
+/// or `latency=2:float`. Fields consisting of digits are indexes, others
+/// are map keys. Kinds are `int`, `float`, `bool`, `text`, `bytes` and
+/// `cbor`, the latter being CBOR diagnostic notation of any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    name: String,
+    field: Field,
+    kind: Kind
+}
+
+impl Column {
+    pub fn new<N: Into<String>>(name: N, field: Field, kind: Kind) -> Result<Self, ExportError> {
+        let name = name.into();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(ExportError::InvalidColumn(name))
+        }
+        Ok(Self { name, field, kind })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn field(&self) -> &Field {
+        &self.field
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+}
+
+impl FromStr for Column {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ExportError::InvalidColumn(s.to_string());
+        let (name, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (field, kind) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        let field = match field.parse() {
+            Ok(i)  => Field::Index(i),
+            Err(_) => Field::Key(field.to_string())
+        };
+        let kind = match kind {
+            "int"   => Kind::Int,
+            "float" => Kind::Float,
+            "bool"  => Kind::Bool,
+            "text"  => Kind::Text,
+            "bytes" => Kind::Bytes,
+            "cbor"  => Kind::Cbor,
+            _       => return Err(invalid())
+        };
+        Self::new(name, field, kind)
+    }
+}
+
+/// Where the value of a column is found in an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// An array index or integer map key, e.g. of `#[n(..)]` fields.
+    Index(u64),
+    /// A text map key.
+    Key(String)
+}
+
+/// The type of column values.
+///
+/// Null, undefined and missing values are stored as null.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Int,
+    Float,
+    Bool,
+    Text,
+    Bytes,
+    Cbor
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("parquet error: {0}")]
+    Parquet(#[from] ParquetError),
+
+    #[error("decode error: {0}")]
+    Decode(#[from] decode::Error),
+
+    #[error("column {0}: {1}")]
+    Column(String, #[source] decode::Error),
+
+    #[error("invalid column: {0}")]
+    InvalidColumn(String),
+
+    #[error("mapping has no columns")]
+    NoColumns
+}
+
+enum Key<'b> {
+    Index(u64),
+    Name(&'b str)
+}
+
+/// The top-level items of a CBOR array or map and their keys.
+fn items(b: &[u8]) -> Result<Vec<(Key<'_>, &[u8])>, decode::Error> {
+    let mut d = Decoder::new(b);
+    let mut items = Vec::new();
+    match d.datatype()? {
+        Type::Array | Type::ArrayIndef => {
+            let len = d.array()?;
+            let mut i = 0;
+            while len.is_none_or(|n| i < n) {
+                if len.is_none() && d.datatype()? == Type::Break {
+                    break
+                }
+                let start = d.position();
+                d.skip()?;
+                items.push((Key::Index(i), &b[start .. d.position()]));
+                i += 1
+            }
+        }
+        Type::Map | Type::MapIndef => {
+            let len = d.map()?;
+            let mut i = 0;
+            while len.is_none_or(|n| i < n) {
+                if len.is_none() && d.datatype()? == Type::Break {
+                    break
+                }
+                let key = match d.datatype()? {
+                    Type::String => Some(Key::Name(d.str()?)),
+                    Type::U8 | Type::U16 | Type::U32 | Type::U64 => Some(Key::Index(d.u64()?)),
+                    _ => {
+                        d.skip()?;
+                        None
+                    }
+                };
+                let start = d.position();
+                d.skip()?;
+                if let Some(k) = key {
+                    items.push((k, &b[start .. d.position()]))
+                }
+                i += 1
+            }
+        }
+        t => return Err(decode::Error::type_mismatch(t).with_message("expected array or map"))
+    }
+    Ok(items)
+}
+
+enum Cell {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Bytes(Vec<u8>)
+}
+
+fn cell(kind: Kind, b: &[u8]) -> Result<Option<Cell>, decode::Error> {
+    let mut d = Decoder::new(b);
+    let t = d.datatype()?;
+    if matches!(t, Type::Null | Type::Undefined) {
+        return Ok(None)
+    }
+    let c = match kind {
+        Kind::Int   => Cell::Int(d.i64()?),
+        Kind::Float => match t {
+            Type::F16 | Type::F32 | Type::F64 => Cell::Float(d.f64()?),
+            _                                 => Cell::Float(d.i64()? as f64)
+        },
+        Kind::Bool  => Cell::Bool(d.bool()?),
+        Kind::Text  => Cell::Bytes(d.str()?.as_bytes().to_vec()),
+        Kind::Bytes => Cell::Bytes(d.bytes()?.to_vec()),
+        Kind::Cbor  => Cell::Bytes(minicbor::display(b).to_string().into_bytes())
+    };
+    Ok(Some(c))
+}
+
+/// The values and levels of a column not yet written.
+struct Buffer {
+    values: Values,
+    defs: Vec<i16>,
+    reps: Vec<i16>
+}
+
+enum Values {
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+    Bool(Vec<bool>),
+    Bytes(Vec<ByteArray>)
+}
+
+impl Buffer {
+    fn new(values: Values) -> Self {
+        Self { values, defs: Vec::new(), reps: Vec::new() }
+    }
+
+    fn push(&mut self, c: Cell) {
+        match (&mut self.values, c) {
+            (Values::Int(v), Cell::Int(x))     => v.push(x),
+            (Values::Float(v), Cell::Float(x)) => v.push(x),
+            (Values::Bool(v), Cell::Bool(x))   => v.push(x),
+            (Values::Bytes(v), Cell::Bytes(x)) => v.push(ByteArray::from(x)),
+            _                                  => unreachable!("cell matches column kind")
+        }
+    }
+
+    fn push_optional(&mut self, c: Option<Cell>) {
+        self.defs.push(i16::from(c.is_some()));
+        if let Some(c) = c {
+            self.push(c)
+        }
+    }
+
+    fn clear(&mut self) {
+        match &mut self.values {
+            Values::Int(v)   => v.clear(),
+            Values::Float(v) => v.clear(),
+            Values::Bool(v)  => v.clear(),
+            Values::Bytes(v) => v.clear()
+        }
+        self.defs.clear();
+        self.reps.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field as Value;
+
+    use crate::LogRecord;
+    use super::{Column, Mapping, ParquetExport};
+
+    #[test]
+    fn log_records() {
+        let mut x = ParquetExport::log_records(Vec::new()).unwrap().with_row_group_len(2);
+        for r in [
+            LogRecord::info("a", "first").with_field("n", 1i64).with_field("s", "x"),
+            LogRecord::warn("b", "second"),
+            LogRecord::error("c", "third").with_field("ok", false)
+        ] {
+            x.write(&minicbor::to_vec(&r).unwrap()).unwrap()
+        }
+        assert!(x.write(b"\x01").is_err());
+        let (n, file) = x.finish().unwrap();
+        assert_eq!(3, n);
+
+        let r = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        assert_eq!(2, r.metadata().num_row_groups());
+        let rows = r.get_row_iter(None).unwrap().map(|r| r.unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(3, rows.len());
+        assert!(rows[0].contains("level: \"INFO\"") && rows[0].contains("fields: {\"n\" -> \"1\", \"s\" -> \"x\"}"));
+        assert!(rows[1].contains("message: \"second\"") && rows[1].contains("fields: {}"));
+        assert!(rows[2].contains("fields: {\"ok\" -> \"false\"}"))
+    }
+
+    #[test]
+    fn mapping() {
+        let m = Mapping::new()
+            .with_column("n=0:int".parse().unwrap())
+            .with_column("name=name:text".parse().unwrap())
+            .with_column("rest=2:cbor".parse().unwrap());
+        assert!("bad name=0:int".parse::<Column>().is_err());
+        assert!("n=0:decimal".parse::<Column>().is_err());
+
+        let mut x = ParquetExport::with_mapping(Vec::new(), m).unwrap();
+        x.write(&minicbor::to_vec((7u8, "ignored", [1, 2])).unwrap()).unwrap();
+        let mut e = minicbor::Encoder::new(Vec::new());
+        e.map(2).unwrap().u8(0).unwrap().i32(-1).unwrap().str("name").unwrap().str("x").unwrap();
+        x.write(e.writer()).unwrap();
+        // Rows are added completely or not at all.
+        assert!(x.write(&minicbor::to_vec(("no int",)).unwrap()).is_err());
+        let (n, file) = x.finish().unwrap();
+        assert_eq!(2, n);
+
+        let r = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        let rows = r.get_row_iter(None).unwrap().map(|r| r.unwrap()).collect::<Vec<_>>();
+        let cols = |i: usize| rows[i].get_column_iter().map(|(_, v)| v.clone()).collect::<Vec<_>>();
+        assert_eq!(vec![Value::Long(7), Value::Null, Value::Str("[1, 2]".into())], cols(0));
+        assert_eq!(vec![Value::Long(-1), Value::Str("x".into()), Value::Null], cols(1))
+    }
+}
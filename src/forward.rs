@@ -1,23 +1,95 @@
-use std::{path::{PathBuf, Path}, time::Duration, io, fmt, convert::Infallible, iter::repeat};
+use std::{path::{PathBuf, Path}, future::Future, io, fmt, convert::Infallible, net::SocketAddr, pin::pin, sync::Arc, time::{Duration, SystemTime}};
+use std::ops::{BitAnd, BitOr};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use bytes::Bytes;
-use futures_util::future::{self, Either};
+use futures_util::future;
 use minicbor::{Encode, Decode, Encoder, encode::{self, Write}, Decoder, decode};
 use minicbor_io::{AsyncWriter, AsyncReader};
-use tokio::{net::{TcpStream, tcp::{OwnedWriteHalf, OwnedReadHalf}}, time::sleep, fs, spawn};
-use tokio_util::compat::{TokioAsyncWriteCompatExt, Compat, TokioAsyncReadCompatExt};
-use tracing::{debug, error, trace, warn};
+use tokio::{io::AsyncWriteExt, net::{TcpStream, tcp::{OwnedWriteHalf, OwnedReadHalf}}, select, spawn, task::JoinHandle};
+use tokio::{time::{sleep, timeout}, sync::{watch, Mutex}};
+use tokio_util::{compat::{TokioAsyncWriteCompatExt, Compat, TokioAsyncReadCompatExt}, sync::CancellationToken};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::{BlockInfo, fs::{read_block_num, latest_block_number}, EntryReader, ReadError, BLOCK_FILENAME_PREFIX, delete_blocks, CRC32C, BlockNum};
+use crate::{BlockInfo, BlockNames, Digest, Metadata, fs::{latest_block_number, portable::{self, FileLock}}, ReadError, CRC32C, BlockNum};
+use crate::retention::{Retention, ArchiveAction};
+use crate::metrics::{Counter, Metrics};
+
+mod cursor;
+mod compression;
+mod config;
+mod control;
+mod credit;
+mod cursors;
+#[cfg(feature = "http")]
+mod http;
+mod identity;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod limit;
+mod multi;
+mod progress;
+mod quarantine;
+#[cfg(feature = "s3")]
+mod s3;
+mod schedule;
+mod stall;
+
+pub use compression::Compression;
+pub use config::ForwardConfig;
+pub use control::ForwarderHandle;
+pub use credit::Window;
+#[cfg(feature = "http")]
+pub use http::{HttpForwarder, HttpFormat};
+pub use identity::Identity;
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaForwarder;
+pub use limit::RateLimit;
+pub use multi::MultiForwarder;
+pub use progress::ForwardProgress;
+pub use quarantine::QUARANTINE_DIR;
+#[cfg(feature = "s3")]
+pub use s3::{S3Forwarder, Manifest, MANIFEST_SUFFIX};
+pub use schedule::{InvalidTimeWindow, TimeWindow};
+
+use credit::Credits;
+use cursor::{Cursor, Entry};
+use cursors::Cursors;
+use limit::{DailyCap, Limiter, Pause};
+use progress::{ForwardMetrics, Progress};
+use stall::AckWatch;
 
 type Reader = AsyncReader<Compat<OwnedReadHalf>>;
 type Writer = AsyncWriter<Compat<OwnedWriteHalf>>;
 
+/// The version of the forwarding protocol implemented by this crate.
+///
+/// Peers which do not send a version are assumed to speak version 0.
+pub const PROTOCOL_VERSION: u8 = 2;
+
 #[derive(Debug)]
 pub struct Forwarder {
     id: String,
     directory: PathBuf,
-    address: String
+    destinations: Vec<String>,
+    archive: ArchiveAction,
+    strategy: Strategy,
+    client_cursor: bool,
+    compression: Compression,
+    token: Option<String>,
+    stream: Option<String>,
+    /// Further streams forwarded over the same connections.
+    multiplexed: Vec<(String, PathBuf)>,
+    dry_run: bool,
+    progress: Arc<Progress>,
+    block_names: BlockNames,
+    skip_corrupt: bool,
+    quarantine: bool,
+    identity: Option<Identity>,
+    config: ForwardConfig,
+    metrics: Option<ForwardMetrics>,
+    control: ForwarderHandle,
+    shared: bool
 }
 
 impl Forwarder {
@@ -33,202 +105,986 @@ impl Forwarder {
         Ok(Self {
             id: id.to_string(),
             directory: path,
-            address: address.to_string()
+            destinations: vec![address.to_string()],
+            archive: ArchiveAction::default(),
+            strategy: Strategy::default(),
+            client_cursor: false,
+            compression: Compression::default(),
+            token: None,
+            stream: None,
+            multiplexed: Vec::new(),
+            dry_run: false,
+            progress: Arc::new(Progress::new()),
+            block_names: BlockNames::default(),
+            skip_corrupt: false,
+            quarantine: false,
+            identity: None,
+            config: ForwardConfig::default(),
+            metrics: None,
+            control: ForwarderHandle::new(),
+            shared: false
         })
     }
 
+    /// Cf. `ForwarderHandle::update_retention` to change it while running.
+    pub fn with_retention(self, r: Retention) -> Self {
+        self.control.update_retention(r);
+        self
+    }
+
+    pub fn with_archive_action(mut self, a: ArchiveAction) -> Self {
+        self.archive = a;
+        self
+    }
+
+    /// Cf. `ForwarderHandle::update_rate_limit` to change it while running.
+    pub fn with_rate_limit(self, r: RateLimit) -> Self {
+        self.control.update_rate_limit(r);
+        self
+    }
+
+    pub fn with_strategy(mut self, s: Strategy) -> Self {
+        self.strategy = s;
+        self
+    }
+
+    /// Resume from the locally persisted cursor if a destination replies
+    /// with `BlockInfo::zero`, e.g. because it lost its state.
+    pub fn with_client_cursor(mut self, val: bool) -> Self {
+        self.client_cursor = val;
+        self
+    }
+
+    /// Compress records if the destination supports it.
+    pub fn with_compression(mut self, c: Compression) -> Self {
+        self.compression = c;
+        self
+    }
+
+    /// Authenticate with the given token.
+    pub fn with_token<T: Into<String>>(mut self, t: T) -> Self {
+        self.token = Some(t.into());
+        self
+    }
+
+    /// Name the stream this forwarder forwards, cf. `LogSet`.
+    pub fn with_stream<T: Into<String>>(mut self, name: T) -> Self {
+        self.stream = Some(name.into());
+        self
+    }
+
+    /// Forward another stream over the same connections.
+    ///
+    /// Records of each stream are interleaved and every stream keeps its
+    /// own cursors. Destinations which do not support multiplexing only
+    /// receive this forwarder's own stream.
+    pub fn with_multiplexed<N, P>(mut self, name: N, dir: P) -> Self
+    where
+        N: Into<String>,
+        P: AsRef<Path>
+    {
+        self.multiplexed.push((name.into(), dir.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Never release blocks, but log which blocks would be released.
+    ///
+    /// Useful to validate a new destination before relying on it.
+    pub fn with_dry_run(mut self, val: bool) -> Self {
+        self.dry_run = val;
+        self
+    }
+
+    /// Forward block files named according to `names`.
+    pub fn with_block_names(mut self, names: BlockNames) -> Self {
+        self.block_names = names;
+        self
+    }
+
+    /// Skip corrupt entries instead of stopping at the first one.
+    ///
+    /// Cf. `EntryReader::recover_next_entry`.
+    pub fn with_skip_corrupt(mut self, val: bool) -> Self {
+        self.skip_corrupt = val;
+        self
+    }
+
+    /// Move blocks which can not be read into `QUARANTINE_DIR` and
+    /// continue with the next block.
+    ///
+    /// Without quarantine, forwarding stops at corrupt entries (unless they
+    /// are skipped) and blocks which can not be opened are skipped.
+    pub fn with_quarantine(mut self, val: bool) -> Self {
+        self.quarantine = val;
+        self
+    }
+
+    pub fn with_config(mut self, c: ForwardConfig) -> Self {
+        self.config = c;
+        self
+    }
+
+    /// Send the given identity to receivers, cf. `Handshake::identity`.
+    pub fn with_identity(mut self, i: Identity) -> Self {
+        self.identity = Some(i);
+        self
+    }
+
+    /// Forward to an additional destination.
+    ///
+    /// Every destination is forwarded to independently. Blocks are released
+    /// only after all destinations have acknowledged them.
+    pub fn with_destination(mut self, address: &str) -> Self {
+        if !self.destinations.iter().any(|d| d == address) {
+            self.destinations.push(address.to_string())
+        }
+        self
+    }
+
+    /// Allow other forwarders to forward the same directories.
+    ///
+    /// By default a forwarder locks its directories while running and
+    /// waits for other forwarders of them to stop first, since two
+    /// forwarders would send every record twice and release blocks behind
+    /// each other's backs.
+    pub fn with_shared_directory(mut self, val: bool) -> Self {
+        self.shared = val;
+        self
+    }
+
+    /// Record metrics of this forwarder in the given registry.
+    ///
+    /// Besides counters of records sent and reconnects, the lag of and
+    /// disk space used by this forwarder's own stream are reported, labeled
+    /// with the forwarder ID and stream.
+    pub fn with_metrics(mut self, m: &Metrics) -> Self {
+        self.metrics = Some(ForwardMetrics::new(m));
+        self
+    }
+
+    /// Watch the progress of forwarding this forwarder's own stream.
+    pub fn progress(&self) -> watch::Receiver<ForwardProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Get a handle to pause, resume and reconfigure forwarding while running.
+    pub fn handle(&self) -> ForwarderHandle {
+        self.control.clone()
+    }
+
     pub async fn go(self) -> ! {
-        loop {
-            let latest = match latest_block_number(&self.directory).await {
-                Ok(number) => {
-                    debug!(%number, "latest block number");
-                    number
-                }
-                Err(err) => {
-                    error!(path = ?self.directory, %err, "failed to read latest block number");
-                    sleep(Duration::from_secs(5)).await;
-                    continue
-                }
-            };
-            let (r, w, s) = self.connect(latest).await;
-            let forwarder = spawn(forward(self.directory.clone(), w, s));
-            let receiver  = spawn(handle_acks(self.directory.clone(), r));
-            match future::select(forwarder, receiver).await {
-                Either::Right((Ok(Ok(())), f)) => {
-                    warn!("connection to remote lost");
-                    f.abort()
-                }
-                Either::Left((Ok(Ok(_)), _)) => {
-                    unreachable!("forwarder never returns an ok value")
-                }
-                Either::Left((Ok(Err(err)), r)) => {
-                    error!(%err, "forwarder error");
-                    r.abort()
-                }
-                Either::Right((Ok(Err(err)), f)) => {
-                    error!(%err, "receiver error");
-                    f.abort()
-                }
-                Either::Left((Err(err), r)) => {
-                    error!(%err, "receiver task error");
-                    r.abort()
+        self.go_until(future::pending()).await;
+        unreachable!("forwarder only stops when signalled")
+    }
+
+    /// Forward until `signal` completes, then shut down gracefully.
+    ///
+    /// On shutdown no more records are sent and every connection is closed
+    /// after the destination acknowledged the records it received, or
+    /// after a timeout.
+    ///
+    /// Events are recorded in a `forwarder` span with the forwarder `id`,
+    /// a `destination` span per destination, a `connection` span with the
+    /// `peer` address per connection and a `block` span while reading a
+    /// block.
+    pub async fn go_until<F: Future<Output = ()>>(self, signal: F) {
+        let span = info_span!("forwarder", id = %self.id, stream = ?self.stream);
+        self.forward_until(signal).instrument(span).await
+    }
+
+    async fn forward_until<F: Future<Output = ()>>(self, signal: F) {
+        let mut signal = pin!(signal);
+        let locks = select! {
+            locks = self.lock_directories() => locks,
+            _ = &mut signal => return
+        };
+        let dirs = std::iter::once(&self.directory).chain(self.multiplexed.iter().map(|m| &m.1));
+        let mut cursors = Vec::new();
+        for dir in dirs {
+            loop {
+                match Cursors::load(dir, &self.block_names, self.destinations.iter().map(String::as_str)).await {
+                    Ok(c) => break cursors.push(c),
+                    Err(err) => {
+                        error!(path = ?dir, %err, "failed to load cursors");
+                        select! {
+                            _ = sleep(self.config.error_delay()) => {}
+                            _ = &mut signal => return
+                        }
+                    }
                 }
-                Either::Right((Err(err), f)) => {
-                    error!(%err, "forwarder task error");
-                    f.abort()
+            }
+        }
+        self.progress.acked(cursors[0].acked_by_all().await);
+        let report = self.metrics.as_ref().map(|m| {
+            let report = m.clone().report(self.id.clone(), self.stream.clone(), self.directory.clone(), self.block_names.clone(), self.progress());
+            spawn(report.in_current_span())
+        });
+        let cursors = Arc::new(cursors);
+        let cap = Arc::new(DailyCap::new(self.progress.clone()));
+        let stop = CancellationToken::new();
+        let this = Arc::new(self);
+        let runs: Vec<_> = this.destinations.iter()
+            .map(|a| {
+                let span = info_span!("destination", dest = %a);
+                spawn(this.clone().run(a.clone(), cursors.clone(), cap.clone(), stop.clone()).instrument(span))
+            })
+            .collect();
+        signal.await;
+        info!("shutting down");
+        stop.cancel();
+        for r in runs {
+            if let Err(err) = r.await {
+                error!(%err, "forwarder task error")
+            }
+        }
+        if let Some(r) = report {
+            r.abort()
+        }
+        drop(locks)
+    }
+
+    /// Lock all directories, unless they are shared.
+    ///
+    /// Directories are locked in the order of their canonical paths, so that
+    /// forwarders sharing some of their directories can not deadlock.
+    async fn lock_directories(&self) -> Vec<FileLock> {
+        let mut locks = Vec::new();
+        if self.shared {
+            return locks
+        }
+        let mut dirs = Vec::new();
+        for dir in std::iter::once(&self.directory).chain(self.multiplexed.iter().map(|m| &m.1)) {
+            dirs.push(tokio::fs::canonicalize(dir).await.unwrap_or_else(|_| dir.clone()))
+        }
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            let path = dir.join(self.block_names.forwarder_lock_file_name());
+            loop {
+                match portable::try_lock(&path).await {
+                    Ok(Some(lock)) => break locks.push(lock),
+                    Ok(None)       => warn!(?path, "directory locked by another forwarder, waiting"),
+                    Err(err)       => error!(?path, %err, "failed to lock directory")
                 }
+                sleep(self.config.error_delay()).await
             }
         }
+        locks
     }
 
-    async fn connect(&self, latest: BlockNum) -> (Reader, Writer, BlockInfo) {
-        let mut delays = [1, 1, 1, 1, 1, 5, 5, 5, 5, 5].into_iter().chain(repeat(10));
-        loop {
-            debug!(addr = %self.address, "connecting...");
-            match TcpStream::connect(&self.address).await {
-                Ok(s) => {
-                    let addr = s.peer_addr().ok();
-                    debug!(remote = ?addr, "connected");
-                    let (r, w) = s.into_split();
-                    let mut r = AsyncReader::new(r.compat());
-                    let mut w = AsyncWriter::new(w.compat_write());
-                    if let Err(err) = w.write(Handshake::new(&self.id, latest)).await {
-                        error!(%err, remote = ?addr, "failed to send handshake");
-                        continue
+    async fn run
+        ( self: Arc<Self>
+        , address: String
+        , cursors: Arc<Vec<Cursors>>
+        , cap: Arc<DailyCap>
+        , stop: CancellationToken
+        )
+    {
+        let mut connected = false;
+        'main: loop {
+            let mut latest = Vec::with_capacity(cursors.len());
+            for c in cursors.iter() {
+                match latest_block_number(c.dir(), c.names()).await {
+                    Ok(number) => {
+                        debug!(path = ?c.dir(), %number, "latest block number");
+                        latest.push(number)
                     }
-                    match r.read::<HandshakeResponse>().await {
-                        Ok(Some(HandshakeResponse::Go { start })) => {
-                            debug! {
-                                remote = ?addr,
-                                start  = %start,
-                                "received handshake response"
-                            }
-                            return (r, w, start)
+                    Err(err) => {
+                        error!(path = ?c.dir(), %err, "failed to read latest block number");
+                        self.progress.failed(ForwardErrorKind::Storage);
+                        select! {
+                            _ = sleep(self.config.error_delay()) => continue 'main,
+                            _ = stop.cancelled() => return
                         }
-                        Ok(Some(HandshakeResponse::Abort { message })) => {
-                            error! {
-                                remote  = ?addr,
-                                message = %message,
-                                "server sent abort response"
-                            }
-                            panic!("server sent abort message")
+                    }
+                }
+            }
+            self.progress.latest(latest[0]);
+            let (r, w, Session { starts, caps, window, peer }) = select! {
+                s = self.connect(&address, &latest) => match s {
+                    Ok(s) => s,
+                    Err(err) => {
+                        error!(%err, retryable = %err.is_retryable(), "failed to connect");
+                        self.progress.failed(err.kind());
+                        select! {
+                            _ = sleep(self.config.error_delay()) => continue,
+                            _ = stop.cancelled() => return
                         }
-                        Ok(None) => error! {
-                            remote = ?addr, "remote closed connection after handshake"
-                        },
-                        Err(err) => error! {
-                            %err, remote = ?addr, "failed to receive handshake response"
+                    }
+                },
+                _ = stop.cancelled() => return
+            };
+            let conn = info_span!("connection", ?peer);
+            self.progress.connected();
+            if connected {
+                self.progress.reconnected();
+                if let Some(m) = &self.metrics {
+                    m.reconnects.inc()
+                }
+            }
+            connected = true;
+            if self.strategy == Strategy::NewestFirstWithBackfill && !caps.contains(Capabilities::BACKFILL) {
+                warn!("remote does not support backfill, forwarding oldest first")
+            }
+            let c = if caps.contains(self.compression.capability()) {
+                self.compression
+            } else {
+                warn!(compression = ?self.compression, "remote does not support compression");
+                Compression::None
+            };
+            let credits = window.filter(|_| caps.contains(Capabilities::FLOW_CONTROL)).map(|w| {
+                debug!(window = ?w, "using flow control");
+                Arc::new(Credits::new(w))
+            });
+            let w = Arc::new(Mutex::new(w));
+            let sent = Arc::new(AtomicU64::new(0));
+            let pause = Arc::new(Pause::default());
+            let mut forwarders = Vec::with_capacity(starts.len());
+            let mut rewinds = Vec::with_capacity(starts.len());
+            let mut backfills = Vec::with_capacity(starts.len());
+            for (i, (s, b)) in starts.into_iter().enumerate() {
+                let (tx, rewind) = watch::channel(None);
+                rewinds.push(tx);
+                let s = self.check_start(&address, &cursors[i], latest[i], s).await;
+                let b = b.filter(|_| caps.contains(Capabilities::BACKFILL));
+                let (backfilled, rx) = watch::channel(b.filter(Backfill::is_pending).map(|b| b.cursor()));
+                backfills.push(rx);
+                let out = Outgoing {
+                    dir: cursors[i].dir().to_path_buf(),
+                    names: cursors[i].names().clone(),
+                    stream: i as u32,
+                    start: s,
+                    backfill: b,
+                    progress: (i == 0).then(|| self.progress.clone()),
+                    credits: credits.clone(),
+                    skip_corrupt: self.skip_corrupt,
+                    quarantine: self.quarantine,
+                    config: self.config.clone(),
+                    sent: sent.clone(),
+                    pause: pause.clone(),
+                    rewind,
+                    backfilled,
+                    control: self.control.clone(),
+                    cap: cap.clone(),
+                    records_sent: self.metrics.as_ref().map(|m| m.records_sent.clone()).unwrap_or_default()
+                };
+                forwarders.push(spawn(forward(out, w.clone(), c).instrument(conn.clone())))
+            }
+            let incoming = Incoming {
+                dest: address.clone(),
+                cursors: cursors.clone(),
+                control: self.control.clone(),
+                archive: self.archive.clone(),
+                dry_run: self.dry_run,
+                progress: self.progress.clone(),
+                credits,
+                pause,
+                rewinds,
+                backfills,
+                config: self.config.clone(),
+                receiver_errors: self.metrics.as_ref().map(|m| m.receiver_errors.clone()).unwrap_or_default(),
+                watch: AckWatch::new(sent, self.progress.clone(), &self.config)
+            };
+            let mut receiver = spawn(handle_acks(incoming, r).instrument(conn.clone()));
+            let mut forwarders = future::select_all(forwarders);
+            select! {
+                (result, _, f) = &mut forwarders => {
+                    match result {
+                        Ok(Ok(_))    => unreachable!("forwarder never returns an ok value"),
+                        Ok(Err(err)) => {
+                            error!(%err, retryable = %err.is_retryable(), "forwarder error");
+                            self.progress.failed(err.kind())
+                        }
+                        Err(err)     => error!(%err, "forwarder task error")
+                    }
+                    f.iter().for_each(|f| f.abort());
+                    receiver.abort()
+                }
+                result = &mut receiver => {
+                    match result {
+                        Ok(Ok(()))   => warn!("connection to remote lost"),
+                        Ok(Err(err)) => {
+                            error!(%err, retryable = %err.is_retryable(), "receiver error");
+                            self.progress.failed(err.kind())
                         }
+                        Err(err)     => error!(%err, "receiver task error")
                     }
+                    forwarders.into_inner().iter().for_each(|f| f.abort())
                 }
-                Err(err) => {
-                    error!(%err, addr = %self.address, "failed to connect");
-                    sleep(Duration::from_secs(delays.next().unwrap_or(10))).await
+                _ = stop.cancelled() => {
+                    forwarders.into_inner().iter().for_each(|f| f.abort());
+                    close(w, receiver).instrument(conn).await;
+                    self.progress.disconnected();
+                    return
                 }
             }
+            self.progress.disconnected()
         }
     }
-}
 
-async fn handle_acks(dir: PathBuf, mut rsock: Reader) -> Result<(), ForwardError> {
-    let mut prev = Ack::zero();
-    while let Some(ack) = rsock.read::<Ack>().await? {
-        if ack.info.number() > prev.info.number() {
-            prev = ack;
-            delete_blocks(&dir, ack.info.number()).await?;
+    /// Connect to a destination and perform the handshake.
+    ///
+    /// Returns the start position and backfill of every stream the
+    /// destination accepted, starting with this forwarder's own stream.
+    /// Fails only if the destination aborts the handshake. Every failed
+    /// attempt doubles the delay before the next one.
+    async fn connect(&self, address: &str, latest: &[BlockNum]) -> Result<(Reader, Writer, Session), ForwardError> {
+        let mut delay = self.config.min_reconnect_delay();
+        loop {
+            debug!("connecting...");
+            match TcpStream::connect(address).await {
+                Ok(s) => if let Some(connection) = self.handshake(s, latest).await? {
+                    return Ok(connection)
+                }
+                Err(err) => error!(%err, "failed to connect")
+            }
+            debug!(?delay, "waiting before reconnecting");
+            sleep(delay).await;
+            delay = delay.saturating_mul(2).min(self.config.max_reconnect_delay())
         }
     }
-    Ok(())
-}
 
-async fn forward(dir: PathBuf, mut wsock: Writer, start: BlockInfo) -> Result<Infallible, ForwardError> {
-    let (mut info, mut size) = (start, 0);
-
-    'main: loop {
-        (info, size) = updated_block(&dir, info, size).await;
-        let mut errors = 0;
-        let mut reader = loop {
-            match EntryReader::open(&dir, info).await {
-                Ok(reader) => break reader,
-                Err(err) => {
-                    error!(%info, %err, "error opening block");
-                    sleep(Duration::from_secs(5)).await
+    /// Perform the handshake over a new connection.
+    ///
+    /// Returns `None` if the connection failed and should be retried.
+    async fn handshake(&self, s: TcpStream, latest: &[BlockNum]) -> Result<Option<(Reader, Writer, Session)>, ForwardError> {
+        let addr = s.peer_addr().ok();
+        debug!(remote = ?addr, "connected");
+        let (r, w) = s.into_split();
+        let mut r = AsyncReader::new(r.compat());
+        let mut w = AsyncWriter::new(w.compat_write());
+        let multiplexed = self.multiplexed.iter()
+            .zip(&latest[1 ..])
+            .map(|((name, _), n)| Multiplexed::new(name, *n))
+            .collect::<Vec<_>>();
+        let hs = Handshake::new(&self.id, latest[0])
+            .with_strategy(self.strategy)
+            .with_client_cursor(self.client_cursor)
+            .with_token(self.token.as_deref())
+            .with_stream(self.stream.as_deref())
+            .with_identity(self.identity.clone())
+            .with_multiplexed(multiplexed);
+        if let Err(err) = w.write(&hs).await {
+            error!(%err, remote = ?addr, "failed to send handshake");
+            return Ok(None)
+        }
+        match r.read::<HandshakeResponse>().await {
+            Ok(Some(rsp @ HandshakeResponse::Go { start, backfill, .. })) => {
+                // Features the remote does not know about are not used.
+                let caps = hs.capabilities() & rsp.capabilities();
+                debug! {
+                    remote   = ?addr,
+                    start    = %start,
+                    backfill = ?backfill,
+                    version  = %rsp.version(),
+                    caps     = ?caps,
+                    "received handshake response"
                 }
+                let mut starts = vec![(start, backfill)];
+                if !self.multiplexed.is_empty() {
+                    if caps.contains(Capabilities::MULTIPLEX) && rsp.multiplexed().len() == self.multiplexed.len() {
+                        starts.extend(rsp.multiplexed().iter().map(|s| (s.start(), s.backfill())))
+                    } else {
+                        warn!(remote = ?addr, "remote does not support multiplexing, forwarding a single stream")
+                    }
+                }
+                let window = rsp.window();
+                Ok(Some((r, w, Session { starts, caps, window, peer: addr })))
             }
-            if errors < 3 {
-                errors += 1;
-                sleep(Duration::from_secs(1)).await
-            } else {
-                error!(%info, "moving to next block");
-                info.add_number(1);
-                size = 0;
-                continue 'main
+            Ok(Some(HandshakeResponse::Abort { message })) => {
+                Err(ForwardError::Aborted(message.to_string()))
+            }
+            Ok(None) => {
+                error!(remote = ?addr, "remote closed connection after handshake");
+                Ok(None)
             }
+            Err(err) => {
+                error!(%err, remote = ?addr, "failed to receive handshake response");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Validate the start position a destination sent against the local cursor.
+    async fn check_start
+        ( &self
+        , dest: &str
+        , cursors: &Cursors
+        , latest: BlockNum
+        , start: BlockInfo
+        ) -> BlockInfo
+    {
+        let Some(acked) = cursors.get(dest).await else {
+            return start
         };
-        while let Some((bytes, crc)) = reader.next_entry().await? {
-            let r = Record { info, item: Binary(bytes), crc };
-            wsock.write(&r).await?;
-            info = reader.block_info()
+        if start.is_zero() && self.client_cursor {
+            debug!(%acked, "resuming from client cursor");
+            return acked
+        }
+        if start.number() > latest {
+            warn!(%start, %latest, %acked, "remote start beyond latest block, using client cursor");
+            return acked
+        }
+        if start < acked {
+            warn!(%start, %acked, "remote start before acknowledged position")
+        }
+        start
+    }
+}
+
+/// How long to wait for final acknowledgements when shutting down.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Close a connection after the remote acknowledged the records it received.
+///
+/// The remote sees the end of the stream, flushes and acknowledges what it
+/// has received and closes its side, which ends `handle_acks`.
+async fn close(w: Arc<Mutex<Writer>>, acks: JoinHandle<Result<(), ForwardError>>) {
+    if let Err(err) = w.lock().await.writer_mut().get_mut().shutdown().await {
+        debug!(%err, "failed to shut down connection")
+    }
+    let abort = acks.abort_handle();
+    match timeout(CLOSE_TIMEOUT, acks).await {
+        Ok(Ok(Ok(()))) => debug!("connection closed"),
+        Ok(Ok(Err(err))) => warn!(%err, "error while closing connection"),
+        Ok(Err(err)) => error!(%err, "receiver task error"),
+        Err(_) => {
+            warn!("timeout waiting for final acknowledgements");
+            abort.abort()
         }
     }
 }
 
-async fn updated_block(dir: &Path, info: BlockInfo, size: u64) -> (BlockInfo, u64) {
-    async fn find_updated_block(dir: &Path, info: BlockInfo, size: u64) -> io::Result<Option<(BlockInfo, u64)>> {
-        trace!(?dir, %info, "looking for block updates");
-        let mut dir = fs::read_dir(dir).await?;
-        let mut closest: Option<(BlockInfo, u64)> = None;
-        while let Some(e) = dir.next_entry().await? {
-            if !e.file_name().to_str().map(|n| n.starts_with(BLOCK_FILENAME_PREFIX)).unwrap_or(false) {
-                continue
+/// The outcome of a handshake.
+struct Session {
+    /// Start position and backfill of every stream.
+    starts: Vec<(BlockInfo, Option<Backfill>)>,
+    caps: Capabilities,
+    window: Option<Window>,
+    peer: Option<SocketAddr>
+}
+
+/// The acknowledgements of a connection.
+struct Incoming {
+    dest: String,
+    cursors: Arc<Vec<Cursors>>,
+    control: ForwarderHandle,
+    archive: ArchiveAction,
+    dry_run: bool,
+    progress: Arc<Progress>,
+    credits: Option<Arc<Credits>>,
+    pause: Arc<Pause>,
+    rewinds: Vec<watch::Sender<Option<BlockInfo>>>,
+    /// Position of every pending backfill lane.
+    backfills: Vec<watch::Receiver<Option<BlockInfo>>>,
+    config: ForwardConfig,
+    receiver_errors: Counter,
+    watch: AckWatch
+}
+
+async fn handle_acks(incoming: Incoming, mut rsock: Reader) -> Result<(), ForwardError> {
+    let Incoming { dest, cursors, control, archive, dry_run, progress, credits, pause, rewinds, backfills, config, receiver_errors, mut watch } = incoming;
+    let mut prev = vec![BlockInfo::zero(); cursors.len()];
+    loop {
+        let reply = match watch.interval() {
+            Some(t) => match timeout(t, rsock.read::<Reply>()).await {
+                Ok(r) => r?,
+                Err(_) => {
+                    watch.check()?;
+                    continue
+                }
+            },
+            None => rsock.read::<Reply>().await?
+        };
+        let Some(reply) = reply else {
+            break
+        };
+        let ack = reply.ack();
+        let i = ack.stream() as usize;
+        watch.on_ack(prev.get(i).map(|p| ack.info > *p).unwrap_or(false));
+        watch.check()?;
+        if let (Some(c), Some(w)) = (&credits, ack.credit()) {
+            c.grant(w)
+        }
+        match ack.status() {
+            AckStatus::Accepted => {}
+            AckStatus::Throttle => {
+                debug!(stream = %i, message = ?reply.message(), "destination asks to slow down");
+                pause.extend(config.throttle_delay())
             }
-            if !e.file_type().await?.is_file() {
-                continue
+            AckStatus::StorageError => {
+                error!(stream = %i, message = ?reply.message(), "destination failed to store records");
+                receiver_errors.inc();
+                pause.extend(config.error_delay())
             }
-            let n = read_block_num(e.path());
-            if n == info.number() {
-                let s = e.metadata().await?.len();
-                if s > size {
-                    return Ok(Some((info, s)))
-                }
+        }
+        let Some(c) = cursors.get(i) else {
+            warn!(stream = %i, "ack of unknown stream");
+            continue
+        };
+        if let Some(to) = ack.rewind_to() {
+            info!(stream = %i, %to, "destination asks to rewind");
+            // Acknowledged blocks may have been released already.
+            let to = if to < prev[i] {
+                warn!(stream = %i, %to, acked = %prev[i], "rewind below acknowledged position");
+                prev[i]
+            } else {
+                to
+            };
+            rewinds[i].send_replace(Some(to));
+        }
+        // Blocks the backfill lane has not sent yet must not be released.
+        let info = match *backfills[i].borrow() {
+            Some(b) if b < ack.info => b,
+            _                       => ack.info
+        };
+        if info > prev[i] {
+            prev[i] = info;
+            let retention = control.retention();
+            let summary = c.acknowledge(&dest, info, &retention, &archive, dry_run).await?;
+            if i == 0 {
+                progress.acked(c.acked_by_all().await)
             }
-            if n > info.number() && closest.map(|(c, _)| n < c.number()).unwrap_or(true) {
-                let s = e.metadata().await?.len();
-                if s > 0 {
-                    closest = Some((BlockInfo::zero().with_number(n), s))
-                }
+            if dry_run && !summary.deleted().is_empty() {
+                info!(blocks = ?summary.deleted(), "dry run, not releasing blocks")
+            } else if !summary.deleted().is_empty() {
+                debug!(released = summary.deleted().len(), "released blocks")
+            }
+            for (number, err) in summary.failed() {
+                error!(%number, %err, "failed to release block")
             }
         }
-        Ok(closest)
     }
+    Ok(())
+}
+
+/// A stream to forward over a connection.
+struct Outgoing {
+    dir: PathBuf,
+    names: BlockNames,
+    stream: u32,
+    start: BlockInfo,
+    backfill: Option<Backfill>,
+    progress: Option<Arc<Progress>>,
+    credits: Option<Arc<Credits>>,
+    skip_corrupt: bool,
+    quarantine: bool,
+    config: ForwardConfig,
+    sent: Arc<AtomicU64>,
+    pause: Arc<Pause>,
+    rewind: watch::Receiver<Option<BlockInfo>>,
+    backfilled: watch::Sender<Option<BlockInfo>>,
+    control: ForwarderHandle,
+    cap: Arc<DailyCap>,
+    records_sent: Counter
+}
+
+async fn forward
+    ( out: Outgoing
+    , wsock: Arc<Mutex<Writer>>
+    , compression: Compression
+    ) -> Result<Infallible, ForwardError>
+{
+    let Outgoing { dir, names, stream, start, backfill, progress, credits, skip_corrupt, quarantine, config, sent, pause, mut rewind, backfilled, control, cap, records_sent } = out;
+    let cursor = |dir, names, info| {
+        Cursor::new(dir, names, info)
+            .with_skip_corrupt(skip_corrupt)
+            .with_quarantine(quarantine)
+            .with_open_retries(config.open_retries(), config.open_retry_delay())
+    };
+    let live_cursor = |info| {
+        cursor(dir.clone(), names.clone(), info)
+            .with_read_ahead(config.read_ahead())
+            .with_parallel_blocks(config.parallel_blocks())
+    };
+    let mut live = live_cursor(start);
+    let mut backfill = backfill
+        .filter(Backfill::is_pending)
+        .map(|b| (cursor(dir.clone(), names.clone(), b.cursor()), b.until()));
+    let mut limit = control.watch_rate_limit();
+    let mut limiter = Limiter::new(*limit.borrow_and_update());
 
     loop {
-        match find_updated_block(dir, info, size).await {
-            Ok(Some(val)) => return val,
-            Ok(None) => sleep(Duration::from_secs(1)).await,
-            Err(err) => {
-                error!{
-                    path  = ?dir,
-                    size  = %size,
-                    info  = %info,
-                    err   = %err,
-                    "failed to find updated block"
+        // Rewinds only apply to the live lane.
+        if rewind.has_changed().unwrap_or(false) {
+            if let Some(to) = *rewind.borrow_and_update() {
+                debug!(%stream, %to, "rewinding");
+                live = live_cursor(to)
+            }
+        }
+        if limit.has_changed().unwrap_or(false) {
+            limiter = Limiter::new(*limit.borrow_and_update())
+        }
+        let idle = schedule::until_open(config.windows(), SystemTime::now());
+        if !idle.is_zero() {
+            debug!(%stream, wait = ?idle, "outside of forwarding windows");
+            sleep(idle).await;
+            continue
+        }
+        if let Some(e) = live.next().await? {
+            let info = e.info;
+            control.resumed().await;
+            pause.wait().await;
+            limiter.acquire(e.bytes.len()).await;
+            let r = Record::from_entry(e, Lane::Live)
+                .with_stream(stream)
+                .compress(compression)?;
+            cap.acquire(&limit, r.item_bytes().len()).await;
+            r.acquire(credits.as_deref()).await;
+            wsock.lock().await.write(&r).await?;
+            sent.fetch_add(1, Ordering::Relaxed);
+            records_sent.inc();
+            if let Some(p) = &progress {
+                p.sent(info)
+            }
+            continue
+        }
+        // Older entries are only sent while there is nothing new to forward.
+        if let Some((cursor, until)) = &mut backfill {
+            if cursor.position() < *until {
+                if let Some(e) = cursor.next().await? {
+                    if e.info < *until {
+                        control.resumed().await;
+                        pause.wait().await;
+                        limiter.acquire(e.bytes.len()).await;
+                        let r = Record::from_entry(e, Lane::Backfill)
+                            .with_stream(stream)
+                            .compress(compression)?;
+                        cap.acquire(&limit, r.item_bytes().len()).await;
+                        r.acquire(credits.as_deref()).await;
+                        wsock.lock().await.write(&r).await?;
+                        backfilled.send_replace(Some(cursor.position()));
+                        sent.fetch_add(1, Ordering::Relaxed);
+                        records_sent.inc();
+                        continue
+                    }
+                } else {
+                    control.poll(config.poll_interval()).await;
+                    continue
                 }
-                sleep(Duration::from_secs(5)).await
             }
+            debug!(%stream, %until, "backfill complete");
+            // A backfill record at `until` tells the receiver the backfill is done.
+            let r = Record::new(*until, Bytes::new(), CRC32C.checksum(&[]), Lane::Backfill).with_stream(stream);
+            control.resumed().await;
+            r.acquire(credits.as_deref()).await;
+            wsock.lock().await.write(&r).await?;
+            backfilled.send_replace(None);
+            backfill = None;
+            continue
         }
+        control.poll(config.poll_interval()).await
     }
 }
 
-#[derive(Debug, Encode, Decode)]
+/// The order in which a backlog of blocks is forwarded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum Strategy {
+    /// Forward blocks strictly in order.
+    #[default]
+    #[n(0)] OldestFirst,
+
+    /// Forward the latest block first and send older blocks whenever
+    /// there is nothing new to forward.
+    #[n(1)] NewestFirstWithBackfill
+}
+
+/// The lane a record is forwarded in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum Lane {
+    #[default]
+    #[n(0)] Live,
+    #[n(1)] Backfill
+}
+
+/// The backfill cursor of a receiver.
+///
+/// Records from `cursor` up to (but excluding) `until` still need to be
+/// sent in the backfill lane. Receivers should not acknowledge blocks
+/// beyond `cursor` while a backfill is pending. Once all records have been
+/// sent, the forwarder sends an empty backfill record positioned at `until`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Backfill {
+    #[n(0)] cursor: BlockInfo,
+    #[n(1)] until: BlockInfo
+}
+
+impl Backfill {
+    pub fn new(cursor: BlockInfo, until: BlockInfo) -> Self {
+        Self { cursor, until }
+    }
+
+    pub fn none() -> Self {
+        Self::new(BlockInfo::zero(), BlockInfo::zero())
+    }
+
+    pub fn cursor(&self) -> BlockInfo {
+        self.cursor
+    }
+
+    pub fn until(&self) -> BlockInfo {
+        self.until
+    }
+
+    pub fn with_cursor(mut self, c: BlockInfo) -> Self {
+        self.cursor = c;
+        self
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.cursor < self.until
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Handshake<'a> {
     #[n(0)] id: &'a str,
-    #[n(1)] latest: BlockNum
+    #[n(1)] latest: BlockNum,
+    #[n(2)] strategy: Option<Strategy>,
+    #[n(3)] client_cursor: Option<bool>,
+    #[n(4)] version: Option<u8>,
+    #[n(5)] capabilities: Option<Capabilities>,
+    #[n(6)] token: Option<Token<'a>>,
+    #[n(7)] stream: Option<&'a str>,
+    #[b(8)] multiplexed: Option<Vec<Multiplexed<'a>>>,
+    #[n(9)] identity: Option<Identity>,
+    #[b(10)] replay: Option<Replay<'a>>
 }
 
 impl<'a> Handshake<'a> {
     pub fn new(id: &'a str, latest: BlockNum) -> Self {
-        Self { id, latest }
+        Self {
+            id,
+            latest,
+            strategy: None,
+            client_cursor: None,
+            version: Some(PROTOCOL_VERSION),
+            capabilities: Some(Capabilities::supported()),
+            token: None,
+            stream: None,
+            multiplexed: None,
+            identity: None,
+            replay: None
+        }
+    }
+
+    pub fn with_strategy(mut self, s: Strategy) -> Self {
+        self.strategy = Some(s);
+        self
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy.unwrap_or_default()
+    }
+
+    /// Signal that the forwarder resumes from its own cursor if the
+    /// receiver replies with `BlockInfo::zero`.
+    pub fn with_client_cursor(mut self, val: bool) -> Self {
+        self.client_cursor = Some(val);
+        self
+    }
+
+    pub fn uses_client_cursor(&self) -> bool {
+        self.client_cursor.unwrap_or(false)
+    }
+
+    /// Announce other capabilities than `Capabilities::supported`.
+    pub fn with_capabilities(mut self, c: Capabilities) -> Self {
+        self.capabilities = Some(c);
+        self
+    }
+
+    pub fn with_token(mut self, t: Option<&'a str>) -> Self {
+        self.token = t.map(Token);
+        self
+    }
+
+    pub fn token(&self) -> Option<&'a str> {
+        self.token.map(|t| t.0)
+    }
+
+    pub fn with_stream(mut self, s: Option<&'a str>) -> Self {
+        self.stream = s;
+        self
+    }
+
+    /// The name of the stream being forwarded, if any.
+    pub fn stream(&self) -> Option<&'a str> {
+        self.stream
+    }
+
+    pub fn with_identity(mut self, i: Option<Identity>) -> Self {
+        self.identity = i;
+        self
+    }
+
+    /// The host and process of the forwarder, if it sent its identity.
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Ask a receiver to send back records it has stored instead of
+    /// forwarding records to it.
+    pub fn with_replay(mut self, r: Option<Replay<'a>>) -> Self {
+        self.replay = r;
+        self
+    }
+
+    pub fn replay(&self) -> Option<Replay<'a>> {
+        self.replay
+    }
+
+    /// Announce further streams, which are identified by their position
+    /// in the list, starting at 1. Stream 0 is the handshake's own stream.
+    pub fn with_multiplexed(mut self, m: Vec<Multiplexed<'a>>) -> Self {
+        self.multiplexed = (!m.is_empty()).then_some(m);
+        self
+    }
+
+    pub fn multiplexed(&self) -> &[Multiplexed<'a>] {
+        self.multiplexed.as_deref().unwrap_or_default()
+    }
+
+    /// The handshake of a single stream.
+    ///
+    /// Stream 0 is this handshake's own stream, further streams are the
+    /// multiplexed ones.
+    pub fn for_stream(&self, id: u32) -> Option<Self> {
+        let mut hs = self.clone();
+        hs.multiplexed = None;
+        if id > 0 {
+            let m = self.multiplexed().get(id as usize - 1)?;
+            hs.stream = Some(m.name);
+            hs.latest = m.latest
+        }
+        Some(hs)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version.unwrap_or(0)
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities.unwrap_or_default()
+    }
+
+    /// Determine where to resume forwarding, given a receiver's cursors.
+    ///
+    /// If the forwarder asked for `Strategy::NewestFirstWithBackfill`, no
+    /// backfill is pending and the forwarder is behind by at least one block,
+    /// it is told to start with its latest block and backfill the rest.
+    pub fn resume_point(&self, live: BlockInfo, backfill: Option<Backfill>) -> (BlockInfo, Backfill) {
+        let pending = backfill.filter(Backfill::is_pending);
+        if pending.is_none()
+            && self.strategy() == Strategy::NewestFirstWithBackfill
+            && self.latest() > live.number()
+        {
+            let start = BlockInfo::zero().with_number(self.latest());
+            return (start, Backfill::new(live, start))
+        }
+        (live, pending.unwrap_or_else(Backfill::none))
     }
 
     pub fn id(&self) -> &'a str {
@@ -240,10 +1096,104 @@ impl<'a> Handshake<'a> {
     }
 }
 
+/// A stream forwarded in addition to the handshake's own stream.
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct Multiplexed<'a> {
+    #[b(0)] name: &'a str,
+    #[n(1)] latest: BlockNum
+}
+
+impl<'a> Multiplexed<'a> {
+    pub fn new(name: &'a str, latest: BlockNum) -> Self {
+        Self { name, latest }
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn latest(&self) -> BlockNum {
+        self.latest
+    }
+}
+
+/// A request to replay records stored by a receiver, cf. `Replayer`.
+///
+/// Positions refer to the receiver's storage of the given client and stream.
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct Replay<'a> {
+    #[b(0)] client: &'a str,
+    #[b(1)] stream: Option<&'a str>,
+    #[n(2)] from: BlockInfo,
+    #[n(3)] until: Option<BlockInfo>
+}
+
+impl<'a> Replay<'a> {
+    /// Replay the records of a client, starting at the given position.
+    ///
+    /// Without an end position, records received later are sent as well.
+    pub fn new(client: &'a str, from: BlockInfo) -> Self {
+        Self { client, stream: None, from, until: None }
+    }
+
+    pub fn with_stream(mut self, s: Option<&'a str>) -> Self {
+        self.stream = s;
+        self
+    }
+
+    /// Stop before the given position.
+    pub fn with_until(mut self, until: Option<BlockInfo>) -> Self {
+        self.until = until;
+        self
+    }
+
+    pub fn client(&self) -> &'a str {
+        self.client
+    }
+
+    pub fn stream(&self) -> Option<&'a str> {
+        self.stream
+    }
+
+    pub fn from(&self) -> BlockInfo {
+        self.from
+    }
+
+    pub fn until(&self) -> Option<BlockInfo> {
+        self.until
+    }
+}
+
+/// Where to resume forwarding a multiplexed stream.
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+pub struct Resume {
+    #[n(0)] start: BlockInfo,
+    #[n(1)] backfill: Option<Backfill>
+}
+
+impl Resume {
+    pub fn new(start: BlockInfo, backfill: Option<Backfill>) -> Self {
+        Self { start, backfill }
+    }
+
+    pub fn start(&self) -> BlockInfo {
+        self.start
+    }
+
+    pub fn backfill(&self) -> Option<Backfill> {
+        self.backfill
+    }
+}
+
 #[derive(Debug, Encode, Decode)]
 pub enum HandshakeResponse<'a> {
     #[n(0)] Go {
-        #[n(0)] start: BlockInfo
+        #[n(0)] start: BlockInfo,
+        #[n(1)] backfill: Option<Backfill>,
+        #[n(2)] version: Option<u8>,
+        #[n(3)] capabilities: Option<Capabilities>,
+        #[n(4)] multiplexed: Option<Vec<Resume>>,
+        #[n(5)] window: Option<Window>
     },
     #[n(1)] Abort {
         #[n(0)] message: &'a str
@@ -252,7 +1202,92 @@ pub enum HandshakeResponse<'a> {
 
 impl<'a> HandshakeResponse<'a> {
     pub fn go(start: BlockInfo) -> Self {
-        Self::Go { start }
+        Self::Go {
+            start,
+            backfill: None,
+            version: Some(PROTOCOL_VERSION),
+            capabilities: Some(Capabilities::empty()),
+            multiplexed: None,
+            window: None
+        }
+    }
+
+    /// Create a `Go` response from a receiver's live and backfill cursors.
+    ///
+    /// Cf. `Handshake::resume_point` and `HandshakeResponse::negotiate`.
+    pub fn resume(hs: &Handshake, live: BlockInfo, backfill: Option<Backfill>) -> Self {
+        let (start, backfill) = hs.resume_point(live, backfill);
+        Self::go(start).with_backfill(backfill).negotiate(hs)
+    }
+
+    pub fn with_backfill(mut self, b: Backfill) -> Self {
+        if let Self::Go { backfill, .. } = &mut self {
+            *backfill = Some(b)
+        }
+        self
+    }
+
+    pub fn with_capabilities(mut self, c: Capabilities) -> Self {
+        if let Self::Go { capabilities, .. } = &mut self {
+            *capabilities = Some(c)
+        }
+        self
+    }
+
+    /// Resume points of the multiplexed streams, in handshake order.
+    pub fn with_multiplexed(mut self, m: Vec<Resume>) -> Self {
+        if let Self::Go { multiplexed, .. } = &mut self {
+            *multiplexed = Some(m)
+        }
+        self
+    }
+
+    pub fn multiplexed(&self) -> &[Resume] {
+        match self {
+            Self::Go { multiplexed, .. } => multiplexed.as_deref().unwrap_or_default(),
+            Self::Abort { .. }           => &[]
+        }
+    }
+
+    /// The initial flow control window, cf. `Capabilities::FLOW_CONTROL`.
+    pub fn with_window(mut self, w: Window) -> Self {
+        if let Self::Go { window, .. } = &mut self {
+            *window = Some(w)
+        }
+        self
+    }
+
+    pub fn window(&self) -> Option<Window> {
+        match self {
+            Self::Go { window, .. } => *window,
+            Self::Abort { .. }      => None
+        }
+    }
+
+    /// Agree on the protocol version and capabilities with a forwarder.
+    ///
+    /// The response contains the lower of both protocol versions and the
+    /// capabilities supported by both sides.
+    pub fn negotiate(mut self, hs: &Handshake) -> Self {
+        if let Self::Go { version, capabilities, .. } = &mut self {
+            *version = Some(hs.version().min(PROTOCOL_VERSION));
+            *capabilities = Some(hs.capabilities() & Capabilities::supported())
+        }
+        self
+    }
+
+    pub fn version(&self) -> u8 {
+        match self {
+            Self::Go { version, .. } => version.unwrap_or(0),
+            Self::Abort { .. }       => 0
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match self {
+            Self::Go { capabilities, .. } => capabilities.unwrap_or_default(),
+            Self::Abort { .. }            => Capabilities::empty()
+        }
     }
 
     pub fn abort(msg: &'a str) -> Self {
@@ -260,14 +1295,177 @@ impl<'a> HandshakeResponse<'a> {
     }
 }
 
-#[derive(Debug, Encode, Decode)]
+/// An authentication token which is not shown in debug output.
+#[derive(Clone, Copy, Encode, Decode)]
+#[cbor(transparent)]
+struct Token<'a>(#[b(0)] &'a str);
+
+impl fmt::Debug for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+/// Optional protocol features.
+///
+/// Forwarders announce the capabilities they support in their `Handshake`,
+/// receivers reply with the subset they support as well. Peers without a
+/// capability set support none of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(transparent)]
+pub struct Capabilities(#[n(0)] u64);
+
+impl Capabilities {
+    /// Backfill lane records, cf. `Strategy::NewestFirstWithBackfill`.
+    pub const BACKFILL: Self = Self(0x1);
+
+    /// Records compressed with `Compression::Lz4`.
+    pub const LZ4: Self = Self(0x2);
+
+    /// Records compressed with `Compression::Zstd`.
+    pub const ZSTD: Self = Self(0x4);
+
+    /// Several streams per connection, cf. `Forwarder::with_multiplexed`.
+    pub const MULTIPLEX: Self = Self(0x8);
+
+    /// Credit based flow control, cf. `Window`.
+    pub const FLOW_CONTROL: Self = Self(0x10);
+
+    /// Replay of stored records, cf. `Replay`.
+    pub const REPLAY: Self = Self(0x20);
+
+    /// Rewinds requested by receivers, cf. `Ack::rewind`.
+    pub const REWIND: Self = Self(0x40);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// All capabilities this crate supports.
+    pub const fn supported() -> Self {
+        let mut bits = Self::BACKFILL.0 | Self::MULTIPLEX.0 | Self::FLOW_CONTROL.0 | Self::REPLAY.0 | Self::REWIND.0;
+        if cfg!(feature = "lz4") {
+            bits |= Self::LZ4.0
+        }
+        if cfg!(feature = "zstd") {
+            bits |= Self::ZSTD.0
+        }
+        Self(bits)
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
 pub struct Record {
     #[n(0)] info: BlockInfo,
     #[n(1)] item: Binary,
-    #[n(2)] crc: u32
+    #[n(2)] crc: u32,
+    #[n(3)] lane: Option<Lane>,
+    #[n(4)] compression: Option<Compression>,
+    #[n(5)] stream: Option<u32>,
+    #[n(6)] digest: Option<Digest>,
+    #[n(7)] metadata: Option<Metadata>,
+    #[n(8)] schema: Option<u32>
 }
 
 impl Record {
+    pub(crate) fn new(info: BlockInfo, item: Bytes, crc: u32, lane: Lane) -> Self {
+        let lane = (lane != Lane::Live).then_some(lane);
+        Self {
+            info,
+            item: Binary(item),
+            crc,
+            lane,
+            compression: None,
+            stream: None,
+            digest: None,
+            metadata: None,
+            schema: None
+        }
+    }
+
+    pub(crate) fn with_metadata(mut self, m: Option<Metadata>) -> Self {
+        self.metadata = m.filter(|m| !m.is_empty());
+        self
+    }
+
+    pub(crate) fn with_digest(mut self, d: Option<Digest>) -> Self {
+        self.digest = d;
+        self
+    }
+
+    pub(crate) fn with_schema(mut self, s: Option<u32>) -> Self {
+        self.schema = s;
+        self
+    }
+
+    fn from_entry(e: Entry, lane: Lane) -> Self {
+        Self::new(e.info, e.bytes, e.crc, lane)
+            .with_metadata(e.metadata)
+            .with_digest(e.digest)
+            .with_schema(e.schema)
+    }
+
+    pub(crate) fn with_stream(mut self, id: u32) -> Self {
+        self.stream = (id != 0).then_some(id);
+        self
+    }
+
+    /// Compress the item, unless that does not make it smaller.
+    pub(crate) fn compress(mut self, c: Compression) -> io::Result<Self> {
+        if let Some(item) = c.compress(self.item.as_ref())? {
+            self.item = Binary(item);
+            self.compression = Some(c)
+        }
+        Ok(self)
+    }
+
+    /// Wait for the credit to send this record, if flow control is used.
+    async fn acquire(&self, credits: Option<&Credits>) {
+        if let Some(c) = credits {
+            c.acquire(self.item.as_ref().len()).await
+        }
+    }
+
+    /// Decompress the item, which must not expand beyond `max` bytes.
+    pub fn decompress(mut self, max: usize) -> io::Result<Self> {
+        if let Some(c) = self.compression.take() {
+            self.item = Binary(c.decompress(self.item.as_ref(), max)?)
+        }
+        Ok(self)
+    }
+
     pub fn info(&self) -> BlockInfo {
         self.info
     }
@@ -276,53 +1474,441 @@ impl Record {
         self.item.clone()
     }
 
+    /// The item, without cloning it, cf. `Record::into_item`.
+    pub fn item_bytes(&self) -> &[u8] {
+        self.item.as_ref()
+    }
+
+    /// Take ownership of the item.
+    pub fn into_item(self) -> Bytes {
+        self.item.0
+    }
+
     pub fn crc(&self) -> u32 {
         self.crc
     }
 
+    pub fn lane(&self) -> Lane {
+        self.lane.unwrap_or_default()
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression.unwrap_or_default()
+    }
+
+    /// The stream this record belongs to, cf. `Handshake::for_stream`.
+    pub fn stream(&self) -> u32 {
+        self.stream.unwrap_or(0)
+    }
+
+    /// The digest of the item, if the forwarded block has digests.
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
+    /// The metadata of the item, if any, cf. `Config::with_entry_metadata`.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The metadata of the item, to be modified before the record is stored.
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        self.metadata.get_or_insert_with(Metadata::new)
+    }
+
+    /// The schema ID of the item, if the forwarded block has schema IDs.
+    pub fn schema(&self) -> Option<u32> {
+        self.schema
+    }
+
+    /// Replace the uncompressed item, updating its CRC and digest.
+    pub fn with_item(mut self, item: Bytes) -> Self {
+        self.crc = CRC32C.checksum(&item);
+        self.digest = self.digest.and_then(|d| d.kind().compute(&item));
+        self.item = Binary(item);
+        self
+    }
+
+    /// Check the CRC and digest of an uncompressed item, cf. `Record::decompress`.
+    ///
+    /// Digests are only checked if their algorithm is enabled.
     pub fn is_valid(&self) -> bool {
         self.crc == CRC32C.checksum(self.item.as_ref())
+            && self.digest.and_then(|d| d.verify(self.item.as_ref())).unwrap_or(true)
+    }
+}
+
+/// A `Record` which borrows its item from the bytes it has been decoded from.
+///
+/// Unlike `Record`, decoding a `RecordRef` does not copy the item.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct RecordRef<'b> {
+    #[n(0)] info: BlockInfo,
+    #[cbor(b(1), with = "minicbor::bytes")] item: &'b [u8],
+    #[n(2)] crc: u32,
+    #[n(3)] lane: Option<Lane>,
+    #[n(4)] compression: Option<Compression>,
+    #[n(5)] stream: Option<u32>,
+    #[n(6)] digest: Option<Digest>,
+    #[n(7)] metadata: Option<Metadata>,
+    #[n(8)] schema: Option<u32>
+}
+
+impl<'b> RecordRef<'b> {
+    pub fn info(&self) -> BlockInfo {
+        self.info
+    }
+
+    pub fn item(&self) -> &'b [u8] {
+        self.item
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    pub fn lane(&self) -> Lane {
+        self.lane.unwrap_or_default()
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression.unwrap_or_default()
+    }
+
+    pub fn stream(&self) -> u32 {
+        self.stream.unwrap_or(0)
+    }
+
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn schema(&self) -> Option<u32> {
+        self.schema
+    }
+
+    /// Like `Record::is_valid`.
+    pub fn is_valid(&self) -> bool {
+        self.crc == CRC32C.checksum(self.item)
+            && self.digest.and_then(|d| d.verify(self.item)).unwrap_or(true)
+    }
+
+    /// Copy the item into an owned `Record`.
+    pub fn to_record(&self) -> Record {
+        self.clone().into_record_with(Bytes::copy_from_slice(self.item))
+    }
+
+    /// Turn this into an owned `Record` which shares the item with `frame`.
+    ///
+    /// `frame` should be the bytes this record has been decoded from,
+    /// otherwise the item is copied.
+    pub fn into_record(self, frame: &Bytes) -> Record {
+        let (outer, inner) = (frame.as_ptr_range(), self.item.as_ptr_range());
+        let item = if outer.start <= inner.start && inner.end <= outer.end {
+            frame.slice_ref(self.item)
+        } else {
+            Bytes::copy_from_slice(self.item)
+        };
+        self.into_record_with(item)
+    }
+
+    fn into_record_with(self, item: Bytes) -> Record {
+        Record {
+            info: self.info,
+            item: Binary(item),
+            crc: self.crc,
+            lane: self.lane,
+            compression: self.compression,
+            stream: self.stream,
+            digest: self.digest,
+            metadata: self.metadata,
+            schema: self.schema
+        }
     }
 }
 
 #[derive(Debug, Clone, Copy, Encode, Decode)]
 pub struct Ack {
-    #[n(0)] info: BlockInfo
+    #[n(0)] info: BlockInfo,
+    #[n(1)] stream: Option<u32>,
+    #[n(2)] credit: Option<Window>,
+    #[n(3)] status: Option<AckStatus>,
+    // #[n(4)] is the message of a `Reply::Explained`.
+    #[n(5)] rewind: Option<BlockInfo>
+}
+
+/// An `Ack` as read by forwarders, with the receiver's explanation of its
+/// status if one was given (since protocol version 2).
+///
+/// Both variants are encoded as an `Ack`, the message being an optional
+/// field of it, so receivers may send plain `Ack`s as well.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Ack(Ack),
+    Explained(Ack, Arc<str>)
+}
+
+/// The wire format of a `Reply`.
+#[derive(Encode, Decode)]
+struct ReplyRepr<'a> {
+    #[n(0)] info: BlockInfo,
+    #[n(1)] stream: Option<u32>,
+    #[n(2)] credit: Option<Window>,
+    #[n(3)] status: Option<AckStatus>,
+    #[b(4)] message: Option<&'a str>,
+    #[n(5)] rewind: Option<BlockInfo>
+}
+
+/// The state of a receiver, as reported in an `Ack` (since protocol version 2).
+///
+/// Receivers which do not report a status have accepted the records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum AckStatus {
+    #[default]
+    #[n(0)] Accepted,
+
+    /// The receiver is overloaded and asks the forwarder to slow down.
+    #[n(1)] Throttle,
+
+    /// The receiver failed to store records and will not acknowledge them.
+    #[n(2)] StorageError
 }
 
 impl Ack {
     pub fn new(info: BlockInfo) -> Self {
-        Self { info }
+        Self { info, stream: None, credit: None, status: None, rewind: None }
+    }
+
+    /// An ack which asks the forwarder to resend records from the given
+    /// position on, cf. `Capabilities::REWIND`.
+    pub fn rewind(to: BlockInfo) -> Self {
+        let mut a = Self::zero();
+        a.rewind = Some(to);
+        a
+    }
+
+    /// An ack which only grants further credit, cf. `Window`.
+    pub fn grant(w: Window) -> Self {
+        Self::zero().with_credit(w)
     }
 
     pub fn zero() -> Self {
-        Ack { info: BlockInfo::zero() }
+        Ack::new(BlockInfo::zero())
+    }
+
+    pub fn with_stream(mut self, id: u32) -> Self {
+        self.stream = (id != 0).then_some(id);
+        self
+    }
+
+    pub fn with_credit(mut self, w: Window) -> Self {
+        self.credit = Some(w);
+        self
     }
 
     pub fn info(&self) -> BlockInfo {
         self.info
     }
+
+    pub fn stream(&self) -> u32 {
+        self.stream.unwrap_or(0)
+    }
+
+    /// Report the receiver's state, cf. `Reply::explained` to give a reason.
+    pub fn with_status(mut self, s: AckStatus) -> Self {
+        self.status = Some(s);
+        self
+    }
+
+    pub fn credit(&self) -> Option<Window> {
+        self.credit
+    }
+
+    pub fn status(&self) -> AckStatus {
+        self.status.unwrap_or_default()
+    }
+
+    pub fn rewind_to(&self) -> Option<BlockInfo> {
+        self.rewind
+    }
 }
 
 impl fmt::Display for Ack {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{{info: {}}}", self.info)
+        match &self.status {
+            Some(s) => write!(f, "{{info: {}, status: {s:?}}}", self.info),
+            None    => write!(f, "{{info: {}}}", self.info)
+        }
+    }
+}
+
+impl Reply {
+    /// Explain the status of `ack` with a human-readable message.
+    pub fn explained(ack: Ack, message: impl Into<Arc<str>>) -> Self {
+        Reply::Explained(ack, message.into())
+    }
+
+    pub fn ack(&self) -> Ack {
+        match self {
+            Reply::Ack(a) | Reply::Explained(a, _) => *a
+        }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Reply::Ack(_)          => None,
+            Reply::Explained(_, m) => Some(m)
+        }
     }
 }
 
+impl From<Ack> for Reply {
+    fn from(a: Ack) -> Self {
+        Reply::Ack(a)
+    }
+}
+
+impl<C> Encode<C> for Reply {
+    fn encode<W>(&self, e: &mut Encoder<W>, c: &mut C) -> Result<(), encode::Error<W::Error>>
+    where
+        W: Write
+    {
+        let Ack { info, stream, credit, status, rewind } = self.ack();
+        ReplyRepr { info, stream, credit, status, message: self.message(), rewind }.encode(e, c)
+    }
+}
+
+impl<'b, C> Decode<'b, C> for Reply {
+    fn decode(d: &mut Decoder<'b>, c: &mut C) -> Result<Self, decode::Error> {
+        let ReplyRepr { info, stream, credit, status, message, rewind } = ReplyRepr::decode(d, c)?;
+        let ack = Ack { info, stream, credit, status, rewind };
+        Ok(match message {
+            Some(m) => Reply::explained(ack, m),
+            None    => Reply::Ack(ack)
+        })
+    }
+}
+
+/// Errors of forwarding, cf. `ForwardError::kind` for how to react to them.
 #[derive(Debug, thiserror::Error)]
 pub enum ForwardError {
     #[error("not a directory: {0:?}")]
     NoDir(PathBuf),
 
+    /// A local i/o error, e.g. while reading blocks or updating cursors.
     #[error("i/o error: {0}")]
     Io(#[from] io::Error),
 
     #[error("read error: {0}")]
     Read(#[from] ReadError),
 
-    #[error("send error: {0}")]
-    Send(#[from] minicbor_io::Error)
+    /// The connection to a destination failed.
+    #[error("network error: {0}")]
+    Network(io::Error),
+
+    /// A destination sent a message which could not be decoded.
+    #[error("protocol violation: {0}")]
+    Protocol(String),
+
+    /// A destination refused the connection, e.g. because of a wrong token.
+    #[error("aborted by remote: {0}")]
+    Aborted(String),
+
+    #[error("no acknowledgement received in time")]
+    AckTimeout,
+
+    #[error("acknowledgements stalled")]
+    Stalled,
+
+    #[cfg(feature = "http")]
+    #[error("invalid url: {0}")]
+    Url(String),
+
+    #[cfg(feature = "http")]
+    #[error("http error: {0}")]
+    Http(#[from] hyper::Error),
+
+    #[cfg(feature = "http")]
+    #[error("unexpected http status: {0}")]
+    Status(u16),
+
+    #[cfg(feature = "s3")]
+    #[error("object store error: {0}")]
+    Store(#[from] object_store::Error),
+
+    #[cfg(feature = "kafka")]
+    #[error("kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError)
+}
+
+impl ForwardError {
+    pub fn kind(&self) -> ForwardErrorKind {
+        match self {
+            Self::NoDir(_)     => ForwardErrorKind::Storage,
+            Self::Io(_)        => ForwardErrorKind::Storage,
+            Self::Read(_)      => ForwardErrorKind::Storage,
+            Self::Network(_)   => ForwardErrorKind::Network,
+            Self::Protocol(_)  => ForwardErrorKind::Protocol,
+            Self::Aborted(_)   => ForwardErrorKind::Aborted,
+            Self::AckTimeout   => ForwardErrorKind::Network,
+            Self::Stalled      => ForwardErrorKind::Network,
+            #[cfg(feature = "http")]
+            Self::Url(_)       => ForwardErrorKind::Config,
+            #[cfg(feature = "http")]
+            Self::Http(_)      => ForwardErrorKind::Network,
+            #[cfg(feature = "http")]
+            Self::Status(s)    => match s {
+                408 | 429 | 500 ..= 599 => ForwardErrorKind::Network,
+                _                       => ForwardErrorKind::Aborted
+            },
+            #[cfg(feature = "s3")]
+            Self::Store(_)     => ForwardErrorKind::Network,
+            #[cfg(feature = "kafka")]
+            Self::Kafka(_)     => ForwardErrorKind::Network
+        }
+    }
+
+    /// Check if trying again later may succeed, cf. `ForwardErrorKind::is_retryable`.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
+impl From<minicbor_io::Error> for ForwardError {
+    fn from(e: minicbor_io::Error) -> Self {
+        match e {
+            minicbor_io::Error::Io(e) => Self::Network(e),
+            e                         => Self::Protocol(e.to_string())
+        }
+    }
+}
+
+/// The class of a `ForwardError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardErrorKind {
+    /// Connection problems and timeouts, which are usually transient.
+    Network,
+    /// A destination does not speak the protocol as expected.
+    Protocol,
+    /// Local blocks or cursors can not be read or written.
+    Storage,
+    /// A destination refused to accept records.
+    Aborted,
+    /// The forwarder is set up incorrectly.
+    Config
+}
+
+impl ForwardErrorKind {
+    /// Only network errors are retryable. Other errors persist until
+    /// someone intervenes, even though forwarders keep trying.
+    pub fn is_retryable(self) -> bool {
+        self == Self::Network
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,79 @@
+use std::io;
+
+use bytes::Bytes;
+use minicbor::{Encode, Decode};
+
+use super::Capabilities;
+
+/// Compression of forwarded records.
+///
+/// Each algorithm requires the corresponding crate feature (`lz4` or `zstd`)
+/// and is only used if the receiver supports it as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum Compression {
+    #[default]
+    #[n(0)] None,
+    #[n(1)] Lz4,
+    #[n(2)] Zstd
+}
+
+impl Compression {
+    /// The capability a receiver needs to accept this compression.
+    pub fn capability(self) -> Capabilities {
+        match self {
+            Self::None => Capabilities::empty(),
+            Self::Lz4  => Capabilities::LZ4,
+            Self::Zstd => Capabilities::ZSTD
+        }
+    }
+
+    /// Compress the given data.
+    ///
+    /// Returns `None` if the compressed data would not be smaller.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unreachable_code, unused_variables))]
+    pub(crate) fn compress(self, data: &[u8]) -> io::Result<Option<Bytes>> {
+        let c: Vec<u8> = match self {
+            Self::None => return Ok(None),
+            #[cfg(feature = "lz4")]
+            Self::Lz4  => lz4_flex::compress_prepend_size(data),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::compress(data, 0)?,
+            #[allow(unreachable_patterns)]
+            _ => return Err(unsupported(self))
+        };
+        Ok((c.len() < data.len()).then(|| Bytes::from(c)))
+    }
+
+    /// Decompress the given data, which must not expand beyond `max` bytes.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn decompress(self, data: &[u8], max: usize) -> io::Result<Bytes> {
+        match self {
+            Self::None => Ok(Bytes::copy_from_slice(data)),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                let (n, _) = lz4_flex::block::uncompressed_size(data).map_err(invalid)?;
+                if n > max {
+                    return Err(invalid("decompressed record too large"))
+                }
+                Ok(lz4_flex::decompress_size_prepended(data).map_err(invalid)?.into())
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::bulk::decompress(data, max)?.into()),
+            #[allow(unreachable_patterns)]
+            _ => Err(unsupported(self))
+        }
+    }
+}
+
+fn unsupported(c: Compression) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("{c:?} compression is not enabled"))
+}
+
+#[cfg(feature = "lz4")]
+fn invalid<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use super::TimeWindow;
+
+/// Lower bound of the reconnect delay, so that reconnects never spin.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(1);
+
+/// Timing parameters of a `Forwarder`, cf. `Forwarder::with_config`.
+///
+/// Shorter intervals reduce forwarding latency at the cost of more
+/// frequent directory scans.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ForwardConfig {
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration"))]
+    poll_interval: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration"))]
+    error_delay: Duration,
+    open_retries: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration"))]
+    open_retry_delay: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration::option"))]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    ack_timeout: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration::option"))]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    stall_timeout: Option<Duration>,
+    stall_reconnect: bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration"))]
+    min_reconnect_delay: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration"))]
+    max_reconnect_delay: Duration,
+    read_ahead: usize,
+    parallel_blocks: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::settings::duration"))]
+    throttle_delay: Duration,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    windows: Vec<TimeWindow>
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            error_delay: Duration::from_secs(5),
+            open_retries: 3,
+            open_retry_delay: Duration::from_secs(1),
+            ack_timeout: None,
+            stall_timeout: None,
+            stall_reconnect: false,
+            min_reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(10),
+            read_ahead: 0,
+            parallel_blocks: 1,
+            throttle_delay: Duration::from_secs(1),
+            windows: Vec::new()
+        }
+    }
+}
+
+impl ForwardConfig {
+    /// How long to wait before looking for new entries once everything
+    /// available has been forwarded.
+    pub fn with_poll_interval(mut self, d: Duration) -> Self {
+        self.poll_interval = d;
+        self
+    }
+
+    /// How long to wait before retrying after failing to read cursors or
+    /// the block directory.
+    pub fn with_error_delay(mut self, d: Duration) -> Self {
+        self.error_delay = d;
+        self
+    }
+
+    /// How often to retry opening a block before giving up on it.
+    ///
+    /// Blocks which can not be opened are skipped or quarantined, cf.
+    /// `Forwarder::with_quarantine`.
+    pub fn with_open_retries(mut self, n: u8) -> Self {
+        self.open_retries = n;
+        self
+    }
+
+    /// How long to wait between attempts to open a block.
+    pub fn with_open_retry_delay(mut self, d: Duration) -> Self {
+        self.open_retry_delay = d;
+        self
+    }
+
+    /// Reconnect if records sent at least this long ago have not been
+    /// followed by any acknowledgement.
+    ///
+    /// By default a connection is kept as long as it is open.
+    pub fn with_ack_timeout(mut self, d: Option<Duration>) -> Self {
+        self.ack_timeout = d;
+        self
+    }
+
+    /// Report a stall if records have been sent, but the acknowledged
+    /// position has not advanced for this long.
+    ///
+    /// Stalls are logged and reported by `ForwardProgress::is_stalled`.
+    pub fn with_stall_timeout(mut self, d: Option<Duration>) -> Self {
+        self.stall_timeout = d;
+        self
+    }
+
+    /// Reconnect when a stall is detected.
+    pub fn with_stall_reconnect(mut self, val: bool) -> Self {
+        self.stall_reconnect = val;
+        self
+    }
+
+    /// Bounds of the delay between attempts to connect to a destination.
+    ///
+    /// The delay starts at `min` and doubles after every failed attempt
+    /// until it reaches `max`. `min` is at least one millisecond.
+    pub fn with_reconnect_delay(mut self, min: Duration, max: Duration) -> Self {
+        let min = min.max(MIN_RECONNECT_DELAY);
+        self.min_reconnect_delay = min;
+        self.max_reconnect_delay = max.max(min);
+        self
+    }
+
+    /// Read up to `n` entries ahead while records are being sent.
+    ///
+    /// Reading ahead speeds up catching up with a backlog on storage with
+    /// high latency. It is disabled by default, i.e. with `n = 0`.
+    pub fn with_read_ahead(mut self, n: usize) -> Self {
+        self.read_ahead = n;
+        self
+    }
+
+    /// Read up to `n` completed blocks concurrently when catching up
+    /// with a backlog.
+    ///
+    /// Records are still sent in order, so destinations acknowledge them
+    /// as usual. By default blocks are read one after another.
+    pub fn with_parallel_blocks(mut self, n: usize) -> Self {
+        self.parallel_blocks = n.max(1);
+        self
+    }
+
+    /// How long to pause sending when a destination asks to slow down,
+    /// cf. `AckStatus::Throttle`.
+    pub fn with_throttle_delay(mut self, d: Duration) -> Self {
+        self.throttle_delay = d;
+        self
+    }
+
+    /// Only forward records during the given time window.
+    ///
+    /// If windows are added, forwarding idles outside of all of them.
+    /// Entries are written to blocks as usual and forwarded once the next
+    /// window opens.
+    pub fn with_window(mut self, w: TimeWindow) -> Self {
+        self.windows.push(w);
+        self
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    pub fn error_delay(&self) -> Duration {
+        self.error_delay
+    }
+
+    pub fn open_retries(&self) -> u8 {
+        self.open_retries
+    }
+
+    pub fn open_retry_delay(&self) -> Duration {
+        self.open_retry_delay
+    }
+
+    pub fn ack_timeout(&self) -> Option<Duration> {
+        self.ack_timeout
+    }
+
+    pub fn stall_timeout(&self) -> Option<Duration> {
+        self.stall_timeout
+    }
+
+    pub fn stall_reconnect(&self) -> bool {
+        self.stall_reconnect
+    }
+
+    pub fn min_reconnect_delay(&self) -> Duration {
+        self.min_reconnect_delay.max(MIN_RECONNECT_DELAY)
+    }
+
+    pub fn max_reconnect_delay(&self) -> Duration {
+        self.max_reconnect_delay.max(self.min_reconnect_delay())
+    }
+
+    pub fn read_ahead(&self) -> usize {
+        self.read_ahead
+    }
+
+    pub fn parallel_blocks(&self) -> usize {
+        self.parallel_blocks
+    }
+
+    pub fn throttle_delay(&self) -> Duration {
+        self.throttle_delay
+    }
+
+    pub fn windows(&self) -> &[TimeWindow] {
+        &self.windows
+    }
+}
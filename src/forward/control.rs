@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use tokio::{select, sync::{watch, Notify}, time::{sleep, Duration}};
+
+use crate::retention::Retention;
+
+use super::RateLimit;
+
+/// Controls a running `Forwarder`, cf. `Forwarder::handle`.
+///
+/// While paused, connections stay open and acknowledgements are still
+/// processed, but no records are sent. Retention and rate limits can be
+/// changed without restarting the forwarder.
+#[derive(Debug, Clone)]
+pub struct ForwarderHandle {
+    inner: Arc<Control>
+}
+
+#[derive(Debug)]
+struct Control {
+    paused: watch::Sender<bool>,
+    flush: Notify,
+    retention: watch::Sender<Retention>,
+    rate_limit: watch::Sender<RateLimit>
+}
+
+impl ForwarderHandle {
+    pub(crate) fn new() -> Self {
+        let control = Control {
+            paused: watch::Sender::new(false),
+            flush: Notify::new(),
+            retention: watch::Sender::new(Retention::default()),
+            rate_limit: watch::Sender::new(RateLimit::default())
+        };
+        Self { inner: Arc::new(control) }
+    }
+
+    /// Stop sending records until `ForwarderHandle::resume` is called.
+    pub fn pause(&self) {
+        self.inner.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.inner.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.inner.paused.borrow()
+    }
+
+    /// Look for new entries right away instead of at the next poll interval.
+    ///
+    /// Has no effect while paused.
+    pub fn flush_now(&self) {
+        self.inner.flush.notify_waiters()
+    }
+
+    /// Replace the retention of acknowledged blocks.
+    ///
+    /// Applies from the next acknowledgement on.
+    pub fn update_retention(&self, r: Retention) {
+        self.inner.retention.send_replace(r);
+    }
+
+    /// Replace the rate limits, which apply from the next record on.
+    pub fn update_rate_limit(&self, r: RateLimit) {
+        self.inner.rate_limit.send_replace(r);
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.inner.retention.borrow().clone()
+    }
+
+    pub fn rate_limit(&self) -> RateLimit {
+        *self.inner.rate_limit.borrow()
+    }
+
+    pub(crate) fn watch_rate_limit(&self) -> watch::Receiver<RateLimit> {
+        self.inner.rate_limit.subscribe()
+    }
+
+    /// Wait until forwarding is not paused.
+    pub(crate) async fn resumed(&self) {
+        let mut rx = self.inner.paused.subscribe();
+        let _ = rx.wait_for(|p| !p).await;
+    }
+
+    /// Wait for the poll interval to pass or for `ForwarderHandle::flush_now`.
+    pub(crate) async fn poll(&self, interval: Duration) {
+        select! {
+            _ = sleep(interval)              => {}
+            _ = self.inner.flush.notified() => {}
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use minicbor::{Encode, Decode};
+use tokio::sync::Semaphore;
+
+/// An amount of records and item bytes a forwarder may send.
+///
+/// If flow control has been negotiated (cf. `Capabilities::FLOW_CONTROL`),
+/// the receiver's `HandshakeResponse` contains the initial window and
+/// further credit is granted in `Ack`s as records are processed. Item bytes
+/// are counted as sent, i.e. after compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Window {
+    #[n(0)] records: u32,
+    #[n(1)] bytes: u64
+}
+
+impl Window {
+    pub fn new(records: u32, bytes: u64) -> Self {
+        Self { records, bytes }
+    }
+
+    pub fn records(&self) -> u32 {
+        self.records
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0 && self.bytes == 0
+    }
+
+    /// Account for a record with an item of the given length.
+    ///
+    /// Items larger than `max` bytes count as `max` bytes, so that a window
+    /// never needs to be larger than its initial size.
+    pub(crate) fn add(&mut self, len: usize, max: u64) {
+        self.records = self.records.saturating_add(1);
+        self.bytes = self.bytes.saturating_add((len as u64).min(max))
+    }
+}
+
+/// The credit available to the forwarders of a connection.
+#[derive(Debug)]
+pub(crate) struct Credits {
+    records: Semaphore,
+    bytes: Semaphore,
+    max_bytes: u64
+}
+
+impl Credits {
+    pub(crate) fn new(w: Window) -> Self {
+        let max_bytes = w.bytes.min(Semaphore::MAX_PERMITS as u64).min(u32::MAX.into());
+        Self {
+            records: Semaphore::new((w.records as usize).clamp(1, Semaphore::MAX_PERMITS)),
+            bytes: Semaphore::new(max_bytes as usize),
+            max_bytes
+        }
+    }
+
+    /// Wait until a record with an item of the given length may be sent.
+    pub(crate) async fn acquire(&self, len: usize) {
+        if let Ok(p) = self.records.acquire().await {
+            p.forget()
+        }
+        let n = (len as u64).min(self.max_bytes) as u32;
+        if let Ok(p) = self.bytes.acquire_many(n).await {
+            p.forget()
+        }
+    }
+
+    pub(crate) fn grant(&self, w: Window) {
+        grant(&self.records, w.records as usize);
+        grant(&self.bytes, w.bytes.min(Semaphore::MAX_PERMITS as u64) as usize)
+    }
+}
+
+fn grant(s: &Semaphore, n: usize) {
+    s.add_permits(n.min(Semaphore::MAX_PERMITS - s.available_permits()))
+}
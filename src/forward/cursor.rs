@@ -0,0 +1,356 @@
+use std::{collections::VecDeque, path::{Path, PathBuf}, io, time::Duration};
+
+use bytes::Bytes;
+use tokio::time::Instant;
+use tracing::{debug_span, error, trace, warn, Instrument, Span};
+
+use crate::{BlockInfo, BlockNames, BlockNum, Digest, EntryReader, Metadata, ReadAhead, ReadError, list_blocks_with};
+
+use super::quarantine::quarantine;
+
+/// A read position in a block directory.
+///
+/// In contrast to `EntryReader`, a cursor follows the block sequence and
+/// never waits for data to become available.
+#[derive(Debug)]
+pub(crate) struct Cursor {
+    dir: PathBuf,
+    names: BlockNames,
+    info: BlockInfo,
+    reader: Option<Reader>,
+    errors: u8,
+    /// No attempt to open a block is made before this time.
+    retry_at: Option<Instant>,
+    open_retries: u8,
+    open_retry_delay: Duration,
+    skip_corrupt: bool,
+    quarantine: bool,
+    read_ahead: usize,
+    parallel: usize,
+    /// Completed blocks after the current one, being read concurrently.
+    upcoming: VecDeque<ReadAhead>,
+    /// The span in which the cursor was created.
+    parent: Span,
+    /// The span of the current block.
+    span: (BlockNum, Span)
+}
+
+/// The number of entries of upcoming blocks to read ahead at least.
+const PREFETCH_LEN: usize = 256;
+
+/// An entry read by a `Cursor`.
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) info: BlockInfo,
+    pub(crate) bytes: Bytes,
+    pub(crate) crc: u32,
+    pub(crate) digest: Option<Digest>,
+    pub(crate) metadata: Option<Metadata>,
+    pub(crate) schema: Option<u32>
+}
+
+impl Cursor {
+    pub(crate) fn new(dir: PathBuf, names: BlockNames, info: BlockInfo) -> Self {
+        Self {
+            dir,
+            names,
+            info,
+            reader: None,
+            errors: 0,
+            retry_at: None,
+            open_retries: 3,
+            open_retry_delay: Duration::ZERO,
+            skip_corrupt: false,
+            quarantine: false,
+            read_ahead: 0,
+            parallel: 1,
+            upcoming: VecDeque::new(),
+            span: (info.number(), block_span(&Span::current(), info.number())),
+            parent: Span::current()
+        }
+    }
+
+    /// Retry opening a block `n` times, waiting `delay` between attempts.
+    pub(crate) fn with_open_retries(mut self, n: u8, delay: Duration) -> Self {
+        self.open_retries = n;
+        self.open_retry_delay = delay;
+        self
+    }
+
+    /// Skip corrupt entries, cf. `EntryReader::with_skip_corrupt`.
+    pub(crate) fn with_skip_corrupt(mut self, val: bool) -> Self {
+        self.skip_corrupt = val;
+        self
+    }
+
+    /// Move blocks which can not be read into the quarantine directory.
+    pub(crate) fn with_quarantine(mut self, val: bool) -> Self {
+        self.quarantine = val;
+        self
+    }
+
+    /// Read up to `n` entries ahead, cf. `EntryReader::read_ahead`.
+    pub(crate) fn with_read_ahead(mut self, n: usize) -> Self {
+        self.read_ahead = n;
+        self
+    }
+
+    /// Read up to `n` blocks concurrently, the current one included.
+    ///
+    /// Entries are still returned in order.
+    pub(crate) fn with_parallel_blocks(mut self, n: usize) -> Self {
+        self.parallel = n.max(1);
+        self
+    }
+
+    pub(crate) fn position(&self) -> BlockInfo {
+        self.info
+    }
+
+    /// Read the next entry together with its position, if one is available.
+    ///
+    /// Events while reading belong to a `block` span of the current block.
+    pub(crate) async fn next(&mut self) -> Result<Option<Entry>, ReadError> {
+        if self.span.0 != self.info.number() {
+            self.span = (self.info.number(), block_span(&self.parent, self.info.number()))
+        }
+        let span = self.span.1.clone();
+        self.advance().instrument(span).await
+    }
+
+    async fn advance(&mut self) -> Result<Option<Entry>, ReadError> {
+        if let Some(e) = self.read().await? {
+            return Ok(Some(e))
+        }
+        if self.retry_at.map(|t| t > Instant::now()).unwrap_or(false) {
+            return Ok(None)
+        }
+        let info = match find_updated_block(&self.dir, &self.names, self.info).await {
+            Ok(Some(info)) => info,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                error!(path = ?self.dir, info = %self.info, %err, "failed to find updated block");
+                return Ok(None)
+            }
+        };
+        if self.reader.is_none() || info.number() != self.info.number() {
+            self.reader = None;
+            self.info = info;
+            if let Some(r) = self.take_upcoming(info) {
+                self.reader = Some(Reader::Ahead(r));
+                self.errors = 0;
+                self.prefetch(info.number()).await;
+                return self.read().await
+            }
+            match EntryReader::open_with(&self.dir, info, &self.names).await {
+                Ok(r) => {
+                    let r = r.with_skip_corrupt(self.skip_corrupt);
+                    self.reader = Some(if self.read_ahead > 0 {
+                        Reader::Ahead(r.read_ahead(self.read_ahead))
+                    } else {
+                        Reader::Direct(r)
+                    });
+                    self.errors = 0;
+                    self.prefetch(info.number()).await
+                }
+                Err(err) => {
+                    error!(%info, %err, "error opening block");
+                    self.retry_at = None;
+                    if self.errors < self.open_retries {
+                        self.errors += 1;
+                        self.retry_at = Some(Instant::now() + self.open_retry_delay)
+                    } else if self.quarantine && !is_not_found(&err) && self.quarantine(&err).await {
+                        // continue with the next block
+                    } else {
+                        if let Some(n) = info.number().next() {
+                            error!(%info, "moving to next block");
+                            self.info = BlockInfo::zero().with_number(n)
+                        }
+                        self.errors = 0
+                    }
+                    return Ok(None)
+                }
+            }
+        }
+        self.read().await
+    }
+
+    async fn read(&mut self) -> Result<Option<Entry>, ReadError> {
+        let Some(r) = &mut self.reader else {
+            return Ok(None)
+        };
+        let pos = r.block_info();
+        match r.next_entry().await {
+            Ok(entry) => {
+                self.info = r.block_info();
+                Ok(entry.map(|(bytes, crc)| Entry {
+                    info: pos,
+                    bytes,
+                    crc,
+                    digest: r.digest(),
+                    metadata: r.take_metadata(),
+                    schema: r.schema()
+                }))
+            }
+            // Corrupt data does not go away by reading it again.
+            Err(err) if self.quarantine && (err.position().is_some() || matches!(err, ReadError::Header(_) | ReadError::Flags(..))) => {
+                if self.quarantine(&err).await {
+                    Ok(None)
+                } else {
+                    Err(err)
+                }
+            }
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Take the reader of an upcoming block if it starts at `info`.
+    fn take_upcoming(&mut self, info: BlockInfo) -> Option<ReadAhead> {
+        while self.upcoming.front().map(|r| r.block_info().number() < info.number()).unwrap_or(false) {
+            self.upcoming.pop_front();
+        }
+        let r = self.upcoming.front()?;
+        if r.block_info().number() != info.number() || info.offset() != 0 {
+            return None
+        }
+        self.upcoming.pop_front()
+    }
+
+    /// Start reading completed blocks after `current` concurrently.
+    ///
+    /// The latest block may still be written to and is not read ahead.
+    async fn prefetch(&mut self, current: BlockNum) {
+        if self.parallel < 2 {
+            return
+        }
+        let blocks = match list_blocks_with(&self.dir, &self.names).await {
+            Ok(blocks) => blocks,
+            Err(err)   => {
+                error!(path = ?self.dir, %err, "failed to list blocks");
+                return
+            }
+        };
+        let after = self.upcoming.back().map(|r| r.block_info().number()).unwrap_or(current);
+        let Some((_, completed)) = blocks.split_last() else {
+            return
+        };
+        for b in completed.iter().filter(|b| b.number() > after && b.size() > 0) {
+            if self.upcoming.len() + 1 >= self.parallel {
+                break
+            }
+            let info = BlockInfo::zero().with_number(b.number());
+            match EntryReader::open_with(&self.dir, info, &self.names).await {
+                Ok(r) => {
+                    let r = r.with_skip_corrupt(self.skip_corrupt);
+                    self.upcoming.push_back(r.read_ahead(self.read_ahead.max(PREFETCH_LEN)))
+                }
+                // The block is opened again once it is current.
+                Err(_) => break
+            }
+        }
+    }
+
+    /// Move the current block into quarantine and continue with the next one.
+    ///
+    /// The latest block may still be written to and is never quarantined.
+    /// Returns `false` if the block has not been quarantined.
+    async fn quarantine(&mut self, reason: &ReadError) -> bool {
+        let n = self.info.number();
+        match list_blocks_with(&self.dir, &self.names).await {
+            Ok(blocks) if blocks.last().map(|b| b.number() > n).unwrap_or(false) => {}
+            Ok(_) => {
+                warn!(block = %n, %reason, "not quarantining the latest block");
+                return false
+            }
+            Err(err) => {
+                error!(path = ?self.dir, %err, "failed to list blocks");
+                return false
+            }
+        }
+        match quarantine(&self.dir, &self.names, n, &reason.to_string()).await {
+            Ok(path) => error!(block = %n, ?path, %reason, "quarantined block"),
+            Err(err) => {
+                error!(block = %n, %err, %reason, "failed to quarantine block");
+                return false
+            }
+        }
+        self.reader = None;
+        self.errors = 0;
+        if let Some(n) = n.next() {
+            self.info = BlockInfo::zero().with_number(n)
+        }
+        true
+    }
+}
+
+/// The reader of the current block.
+#[derive(Debug)]
+enum Reader {
+    Direct(EntryReader),
+    Ahead(ReadAhead)
+}
+
+impl Reader {
+    fn block_info(&self) -> BlockInfo {
+        match self {
+            Self::Direct(r) => r.block_info(),
+            Self::Ahead(r)  => r.block_info()
+        }
+    }
+
+    fn digest(&self) -> Option<Digest> {
+        match self {
+            Self::Direct(r) => r.digest(),
+            Self::Ahead(r)  => r.digest()
+        }
+    }
+
+    fn schema(&self) -> Option<u32> {
+        match self {
+            Self::Direct(r) => r.schema(),
+            Self::Ahead(r)  => r.schema()
+        }
+    }
+
+    fn take_metadata(&mut self) -> Option<Metadata> {
+        match self {
+            Self::Direct(r) => r.take_metadata(),
+            Self::Ahead(r)  => r.take_metadata()
+        }
+    }
+
+    async fn next_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        match self {
+            Self::Direct(r) => r.next_entry().await,
+            Self::Ahead(r)  => r.next_entry().await
+        }
+    }
+}
+
+fn block_span(parent: &Span, number: BlockNum) -> Span {
+    debug_span!(parent: parent, "block", %number)
+}
+
+fn is_not_found(e: &ReadError) -> bool {
+    matches!(e, ReadError::Io(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Find something to read at or after the given position.
+///
+/// If the block of `info` has unread data, `info` is returned unchanged, i.e.
+/// reading continues at the exact offset. Otherwise the next existing block
+/// is returned, positioned at its start.
+async fn find_updated_block(dir: &Path, names: &BlockNames, info: BlockInfo) -> io::Result<Option<BlockInfo>> {
+    trace!(?dir, %info, "looking for block updates");
+    let mut closest: Option<BlockInfo> = None;
+    for b in list_blocks_with(dir, names).await? {
+        let n = b.number();
+        if n == info.number() && b.size() > info.offset() {
+            return Ok(Some(info))
+        }
+        if n > info.number() && closest.map(|c| n < c.number()).unwrap_or(true) && b.size() > 0 {
+            closest = Some(BlockInfo::zero().with_number(n))
+        }
+    }
+    Ok(closest)
+}
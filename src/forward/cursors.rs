@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, io, path::{Path, PathBuf}};
+
+use tokio::{fs, sync::Mutex};
+use tracing::{debug, warn};
+
+use crate::fs::replace_file;
+use crate::{BlockInfo, BlockNames, BlockNum, DeleteSummary};
+use crate::retention::{Retention, ArchiveAction, forwarded_with, release_blocks};
+
+/// The name of the cursors file before it was derived from `BlockNames`.
+const LEGACY_CURSORS_FILENAME: &str = "cursors";
+
+/// The acknowledged positions of all destinations of a block directory.
+///
+/// The positions are persisted in a `<prefix>cursors<suffix>` file and blocks are only
+/// released once every destination has acknowledged them.
+#[derive(Debug)]
+pub(crate) struct Cursors {
+    dir: PathBuf,
+    names: BlockNames,
+    acked: Mutex<BTreeMap<String, BlockInfo>>
+}
+
+impl Cursors {
+    /// Load the cursors of the given destinations.
+    ///
+    /// Destinations without a persisted cursor start at `BlockInfo::zero`.
+    /// Cursors of destinations not in the list are dropped. If the cursors
+    /// file can not be decoded, all destinations start after the blocks
+    /// marked as forwarded.
+    pub(crate) async fn load<'a, I>(dir: &Path, names: &BlockNames, destinations: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>
+    {
+        let (mut persisted, start) = match read_cursors(dir, names).await {
+            Ok(p) => (p, BlockInfo::zero()),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                warn!(path = ?dir, %err, "invalid cursors file, starting after the forwarded blocks");
+                (BTreeMap::new(), forwarded_position(dir, names).await?)
+            }
+            Err(err) => return Err(err)
+        };
+        let acked = destinations.into_iter()
+            .map(|d| (d.to_string(), persisted.remove(d).unwrap_or(start)))
+            .collect();
+        Ok(Self { dir: dir.to_path_buf(), names: names.clone(), acked: Mutex::new(acked) })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn names(&self) -> &BlockNames {
+        &self.names
+    }
+
+    /// The last position the given destination acknowledged.
+    pub(crate) async fn get(&self, dest: &str) -> Option<BlockInfo> {
+        self.acked.lock().await.get(dest).copied()
+    }
+
+    /// The position every destination has acknowledged.
+    pub(crate) async fn acked_by_all(&self) -> BlockInfo {
+        self.acked.lock().await.values().min().copied().unwrap_or_else(BlockInfo::zero)
+    }
+
+    /// Record an acknowledgement of a destination and release all blocks
+    /// acknowledged by every destination (unless this is a dry run).
+    pub(crate) async fn acknowledge
+        ( &self
+        , dest: &str
+        , info: BlockInfo
+        , r: &Retention
+        , a: &ArchiveAction
+        , dry_run: bool
+        ) -> io::Result<DeleteSummary>
+    {
+        let mut acked = self.acked.lock().await;
+        let before = min_number(&acked);
+        acked.insert(dest.to_string(), info);
+        write_cursors(&self.dir, &self.names, &acked).await?;
+        let after = min_number(&acked);
+        if after > before {
+            debug!(%dest, %after, "all destinations acknowledged");
+            return release_blocks(&self.dir, &self.names, after, r, a, dry_run).await
+        }
+        Ok(DeleteSummary::default())
+    }
+}
+
+fn min_number(acked: &BTreeMap<String, BlockInfo>) -> BlockNum {
+    acked.values().map(BlockInfo::number).min().unwrap_or_else(BlockNum::zero)
+}
+
+/// Read the cursors file, or the legacy one if it does not exist yet.
+async fn read_cursors(dir: &Path, names: &BlockNames) -> io::Result<BTreeMap<String, BlockInfo>> {
+    for name in [names.cursors_file_name().as_str(), LEGACY_CURSORS_FILENAME] {
+        match fs::read(dir.join(name)).await {
+            Ok(bytes) => return minicbor::decode(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(BTreeMap::new())
+}
+
+/// The position after the blocks marked as forwarded, or `BlockInfo::zero`.
+async fn forwarded_position(dir: &Path, names: &BlockNames) -> io::Result<BlockInfo> {
+    match forwarded_with(dir, names).await {
+        Ok(n) => Ok(BlockInfo::zero().with_number(n.unwrap_or_else(BlockNum::zero))),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            warn!(path = ?dir, %err, "invalid forwarded mark, starting at the first block");
+            Ok(BlockInfo::zero())
+        }
+        Err(err) => Err(err)
+    }
+}
+
+async fn write_cursors(dir: &Path, names: &BlockNames, acked: &BTreeMap<String, BlockInfo>) -> io::Result<()> {
+    let bytes = minicbor::to_vec(acked).map_err(io::Error::other)?;
+    replace_file(dir, &names.cursors_file_name(), &bytes).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::fs;
+
+    use crate::{ArchiveAction, BlockInfo, BlockNames, BlockNum, Config, EntryWriter, Retention, list_blocks};
+    use super::Cursors;
+
+    #[tokio::test]
+    async fn cursors_per_log() {
+        let dir = Path::new("/tmp/logs-test-cursors-per-log");
+        if dir.is_dir() {
+            fs::remove_dir_all(dir).await.unwrap();
+        }
+        fs::create_dir(dir).await.unwrap();
+
+        let (a, b) = (BlockNames::new().with_prefix("a."), BlockNames::new().with_prefix("b."));
+        let (r, x) = (Retention::UntilAck, ArchiveAction::Delete);
+        let pos = BlockInfo::zero().with_number(3).with_offset(8u8);
+        Cursors::load(dir, &a, ["d"]).await.unwrap().acknowledge("d", pos, &r, &x, false).await.unwrap();
+        Cursors::load(dir, &b, ["d"]).await.unwrap().acknowledge("d", pos.with_number(5), &r, &x, false).await.unwrap();
+
+        assert_eq!(Some(pos), Cursors::load(dir, &a, ["d"]).await.unwrap().get("d").await);
+        assert_eq!(Some(pos.with_number(5)), Cursors::load(dir, &b, ["d"]).await.unwrap().get("d").await)
+    }
+
+    #[tokio::test]
+    async fn invalid_cursors_start_after_forwarded() {
+        let dir = Path::new("/tmp/logs-test-invalid-cursors");
+        if dir.is_dir() {
+            fs::remove_dir_all(dir).await.unwrap();
+        }
+        fs::create_dir(dir).await.unwrap();
+
+        let names = BlockNames::new();
+        fs::write(dir.join(names.cursors_file_name()), b"").await.unwrap();
+        let c = Cursors::load(dir, &names, ["d"]).await.unwrap();
+        assert_eq!(Some(BlockInfo::zero()), c.get("d").await);
+
+        let mark = minicbor::to_vec(BlockNum::from(4u64)).unwrap();
+        fs::write(dir.join(names.forwarded_file_name()), mark).await.unwrap();
+        let c = Cursors::load(dir, &names, ["d", "e"]).await.unwrap();
+        assert_eq!(Some(BlockInfo::zero().with_number(4)), c.get("d").await);
+        assert_eq!(Some(BlockInfo::zero().with_number(4)), c.get("e").await)
+    }
+
+    #[tokio::test]
+    async fn dry_run_keeps_blocks() {
+        let dir = Path::new("/tmp/logs-test-cursors-dry-run");
+        if dir.is_dir() {
+            fs::remove_dir_all(dir).await.unwrap();
+        }
+        fs::create_dir(dir).await.unwrap();
+
+        let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(16).with_max_entry_len(16)).await.unwrap();
+        for e in [b"e1", b"e2", b"e3"] {
+            w.append(e).await.unwrap()
+        }
+        w.sync().await.unwrap();
+
+        let (r, x) = (Retention::UntilAck, ArchiveAction::Delete);
+        let c = Cursors::load(dir, &BlockNames::new(), ["d"]).await.unwrap();
+        let s = c.acknowledge("d", BlockInfo::zero().with_number(3), &r, &x, true).await.unwrap();
+        assert_eq!([BlockNum::from(1u64), BlockNum::from(2u64)], s.deleted());
+        assert_eq!(3, list_blocks(dir).await.unwrap().len())
+    }
+}
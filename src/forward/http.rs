@@ -0,0 +1,256 @@
+use std::{convert::Infallible, path::{Path, PathBuf}, sync::Arc};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{Method, Request, Uri, client::conn::http1::{self, SendRequest}, header};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use tokio::{net::TcpStream, spawn, sync::watch, time::sleep};
+use tracing::{debug, error, info_span, warn, Instrument};
+
+use crate::{BlockInfo, BlockNames, CRC32C, LogReader};
+use crate::retention::{Retention, ArchiveAction};
+
+use super::{ForwardConfig, ForwardError, ForwardProgress, Lane, Record};
+use super::cursors::Cursors;
+use super::progress::Progress;
+
+/// How record batches are encoded in request bodies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpFormat {
+    /// A CBOR array of `Record`s, as sent to receivers.
+    #[default]
+    Cbor,
+    /// A JSON array of objects with `block`, `offset`, `crc`, `metadata`
+    /// and the base64-encoded `item`.
+    Json
+}
+
+impl HttpFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Cbor => "application/cbor",
+            Self::Json => "application/json"
+        }
+    }
+}
+
+/// Forwards the entries of a block directory to an HTTP endpoint.
+///
+/// Entries are read like a `Forwarder` does and POSTed in batches. A batch
+/// is acknowledged if the endpoint responds with a success status, after
+/// which the cursor of the endpoint is updated and blocks are released
+/// according to the retention policy. Failed batches are retried.
+///
+/// Only plain `http` URLs are supported.
+#[derive(Debug)]
+pub struct HttpForwarder {
+    id: String,
+    directory: PathBuf,
+    url: Uri,
+    format: HttpFormat,
+    batch_len: usize,
+    token: Option<String>,
+    stream: Option<String>,
+    retention: Retention,
+    archive: ArchiveAction,
+    block_names: BlockNames,
+    progress: Arc<Progress>,
+    config: ForwardConfig
+}
+
+impl HttpForwarder {
+    pub async fn new<P, S>(id: S, dir: P, url: &str) -> Result<Self, ForwardError>
+    where
+        P: AsRef<Path>,
+        S: ToString
+    {
+        let path = dir.as_ref().to_path_buf();
+        if !path.is_dir() {
+            return Err(ForwardError::NoDir(path))
+        }
+        let url: Uri = url.parse().map_err(|_| ForwardError::Url(url.to_string()))?;
+        if url.scheme_str() != Some("http") || url.host().is_none() {
+            return Err(ForwardError::Url(url.to_string()))
+        }
+        Ok(Self {
+            id: id.to_string(),
+            directory: path,
+            url,
+            format: HttpFormat::default(),
+            batch_len: 256,
+            token: None,
+            stream: None,
+            retention: Retention::default(),
+            archive: ArchiveAction::default(),
+            block_names: BlockNames::default(),
+            progress: Arc::new(Progress::new()),
+            config: ForwardConfig::default()
+        })
+    }
+
+    pub fn with_format(mut self, f: HttpFormat) -> Self {
+        self.format = f;
+        self
+    }
+
+    /// The maximum number of records per request.
+    pub fn with_batch_len(mut self, n: usize) -> Self {
+        self.batch_len = n.max(1);
+        self
+    }
+
+    /// Send the token as bearer token in the `authorization` header.
+    pub fn with_token<T: Into<String>>(mut self, t: T) -> Self {
+        self.token = Some(t.into());
+        self
+    }
+
+    /// Send the stream name in the `x-bogger-stream` header.
+    pub fn with_stream<T: Into<String>>(mut self, name: T) -> Self {
+        self.stream = Some(name.into());
+        self
+    }
+
+    pub fn with_retention(mut self, r: Retention) -> Self {
+        self.retention = r;
+        self
+    }
+
+    pub fn with_archive_action(mut self, a: ArchiveAction) -> Self {
+        self.archive = a;
+        self
+    }
+
+    pub fn with_block_names(mut self, names: BlockNames) -> Self {
+        self.block_names = names;
+        self
+    }
+
+    pub fn with_config(mut self, c: ForwardConfig) -> Self {
+        self.config = c;
+        self
+    }
+
+    pub fn progress(&self) -> watch::Receiver<ForwardProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Events are recorded in a `forwarder` span with the forwarder `id`.
+    pub async fn go(self) -> ! {
+        let dest = self.url.to_string();
+        let span = info_span!("forwarder", id = %self.id, %dest);
+        self.forward(dest).instrument(span).await
+    }
+
+    async fn forward(self, dest: String) -> ! {
+        let cursors = loop {
+            match Cursors::load(&self.directory, &self.block_names, [dest.as_str()]).await {
+                Ok(c) => break c,
+                Err(err) => {
+                    error!(path = ?self.directory, %err, "failed to load cursors");
+                    sleep(self.config.error_delay()).await
+                }
+            }
+        };
+        loop {
+            let Err(err) = self.run(&dest, &cursors).await;
+            error!(%err, retryable = %err.is_retryable(), "http forwarder error");
+            sleep(self.config.error_delay()).await
+        }
+    }
+
+    /// Post batches of entries after the acknowledged position.
+    async fn run(&self, dest: &str, cursors: &Cursors) -> Result<Infallible, ForwardError> {
+        let mut acked = cursors.get(dest).await.unwrap_or_else(BlockInfo::zero);
+        self.progress.acked(acked);
+        let mut log = LogReader::new_with(&self.directory, acked, self.block_names.clone());
+        let mut sender = None;
+        loop {
+            let mut batch = Vec::new();
+            while batch.len() < self.batch_len {
+                let Some((info, bytes)) = log.next_entry().await? else {
+                    break
+                };
+                if info <= acked {
+                    continue
+                }
+                let crc = CRC32C.checksum(&bytes);
+                batch.push(Record::new(info, bytes, crc, Lane::Live).with_metadata(log.metadata().cloned()))
+            }
+            let Some(last) = batch.last().map(Record::info) else {
+                sleep(self.config.poll_interval()).await;
+                continue
+            };
+            self.progress.sent(last);
+            self.post(&mut sender, &batch).await?;
+            debug!(records = %batch.len(), %last, "batch acknowledged");
+            cursors.acknowledge(dest, last, &self.retention, &self.archive, false).await?;
+            self.progress.acked(last);
+            acked = last
+        }
+    }
+
+    async fn post(&self, sender: &mut Option<SendRequest<Full<Bytes>>>, batch: &[Record]) -> Result<(), ForwardError> {
+        let body = match self.format {
+            HttpFormat::Cbor => minicbor::to_vec(batch).map_err(|e| ForwardError::Io(std::io::Error::other(e)))?,
+            HttpFormat::Json => serde_json::to_vec(&batch.iter().map(to_json).collect::<Vec<_>>())
+                .map_err(|e| ForwardError::Io(e.into()))?
+        };
+        let path = self.url.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        let host = self.url.authority().map(|a| a.as_str()).unwrap_or_default();
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(path)
+            .header(header::HOST, host)
+            .header(header::CONTENT_TYPE, self.format.content_type())
+            .header("x-bogger-client", &self.id);
+        if let Some(s) = &self.stream {
+            request = request.header("x-bogger-stream", s)
+        }
+        if let Some(t) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {t}"))
+        }
+        let request = request.body(Full::new(Bytes::from(body)))
+            .map_err(|_| ForwardError::Url(self.url.to_string()))?;
+        let s = match sender {
+            Some(s) if !s.is_closed() => s,
+            _ => sender.insert(self.connect().await?)
+        };
+        s.ready().await?;
+        let response = s.send_request(request).await.inspect_err(|_| *sender = None)?;
+        let status = response.status();
+        if !status.is_success() {
+            warn!(url = %self.url, %status, "batch rejected");
+            return Err(ForwardError::Status(status.as_u16()))
+        }
+        Ok(())
+    }
+
+    async fn connect(&self) -> Result<SendRequest<Full<Bytes>>, ForwardError> {
+        let host = self.url.host().unwrap_or_default();
+        let port = self.url.port_u16().unwrap_or(80);
+        let sock = TcpStream::connect((host, port)).await.map_err(ForwardError::Network)?;
+        let (sender, conn) = http1::handshake(TokioIo::new(sock)).await?;
+        let url = self.url.clone();
+        spawn(async move {
+            if let Err(err) = conn.await {
+                debug!(%url, %err, "http connection closed")
+            }
+        }.in_current_span());
+        Ok(sender)
+    }
+}
+
+fn to_json(r: &Record) -> serde_json::Value {
+    json!({
+        "block": r.info().number().value(),
+        "offset": r.info().offset(),
+        "crc": r.crc(),
+        "metadata": r.metadata().map(|m| {
+            m.iter().map(|(k, v)| (k.to_string(), v.into())).collect::<serde_json::Map<_, _>>()
+        }),
+        "item": BASE64.encode(r.item_bytes())
+    })
+}
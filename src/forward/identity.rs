@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use minicbor::{Encode, Decode};
+
+/// The host and process a forwarder runs on, plus user-supplied labels.
+///
+/// A forwarder sends its identity with the handshake (cf.
+/// `Forwarder::with_identity`), so that receivers can attribute the
+/// records of a connection without applications embedding that data.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Identity {
+    #[n(0)] hostname: String,
+    #[n(1)] pid: u32,
+    #[n(2)] labels: BTreeMap<String, String>
+}
+
+impl Identity {
+    /// The identity of the current process on the given host.
+    pub fn new<H: Into<String>>(hostname: H) -> Self {
+        Self {
+            hostname: hostname.into(),
+            pid: std::process::id(),
+            labels: BTreeMap::new()
+        }
+    }
+
+    /// The identity of the current process on the local host.
+    #[cfg(feature = "gethostname")]
+    pub fn local() -> Self {
+        Self::new(gethostname::gethostname().to_string_lossy())
+    }
+
+    pub fn with_hostname<H: Into<String>>(mut self, h: H) -> Self {
+        self.hostname = h.into();
+        self
+    }
+
+    pub fn with_label<K: Into<String>, V: Into<String>>(mut self, key: K, val: V) -> Self {
+        self.labels.insert(key.into(), val.into());
+        self
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(String::as_str)
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.labels.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
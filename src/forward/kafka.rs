@@ -0,0 +1,184 @@
+use std::{convert::Infallible, fmt, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use futures_util::future::try_join_all;
+use rdkafka::{ClientConfig, message::{Header, OwnedHeaders}};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use tokio::{sync::watch, time::sleep};
+use tracing::{debug, error, info_span, Instrument};
+
+use crate::{BlockInfo, BlockNames, LogReader, Metadata};
+use crate::retention::{Retention, ArchiveAction};
+
+use super::{ForwardConfig, ForwardError, ForwardProgress};
+use super::cursors::Cursors;
+use super::progress::Progress;
+
+/// Publishes the entries of a block directory to a Kafka topic.
+///
+/// Every entry becomes one message keyed by the forwarder id, with its
+/// position in the `bogger-block` and `bogger-offset` headers and its
+/// metadata (if any) as further headers. Entries are published in batches
+/// and the cursor is only advanced once every message of a batch has been
+/// delivered. Failed batches are published again, so messages may be
+/// delivered more than once.
+pub struct KafkaForwarder {
+    id: String,
+    directory: PathBuf,
+    topic: String,
+    producer: FutureProducer,
+    batch_len: usize,
+    retention: Retention,
+    archive: ArchiveAction,
+    block_names: BlockNames,
+    progress: Arc<Progress>,
+    config: ForwardConfig
+}
+
+impl fmt::Debug for KafkaForwarder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaForwarder")
+            .field("id", &self.id)
+            .field("directory", &self.directory)
+            .field("topic", &self.topic)
+            .field("batch_len", &self.batch_len)
+            .finish()
+    }
+}
+
+impl KafkaForwarder {
+    /// Publish to a topic of the given, comma-separated bootstrap brokers.
+    pub async fn new<P, S>(id: S, dir: P, brokers: &str, topic: &str) -> Result<Self, ForwardError>
+    where
+        P: AsRef<Path>,
+        S: ToString
+    {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers).set("enable.idempotence", "true");
+        Self::new_with(id, dir, &config, topic).await
+    }
+
+    /// Like `KafkaForwarder::new` with a custom producer configuration.
+    pub async fn new_with<P, S>(id: S, dir: P, config: &ClientConfig, topic: &str) -> Result<Self, ForwardError>
+    where
+        P: AsRef<Path>,
+        S: ToString
+    {
+        let path = dir.as_ref().to_path_buf();
+        if !path.is_dir() {
+            return Err(ForwardError::NoDir(path))
+        }
+        Ok(Self {
+            id: id.to_string(),
+            directory: path,
+            topic: topic.to_string(),
+            producer: config.create()?,
+            batch_len: 256,
+            retention: Retention::default(),
+            archive: ArchiveAction::default(),
+            block_names: BlockNames::default(),
+            progress: Arc::new(Progress::new()),
+            config: ForwardConfig::default()
+        })
+    }
+
+    /// The maximum number of messages awaiting delivery.
+    pub fn with_batch_len(mut self, n: usize) -> Self {
+        self.batch_len = n.max(1);
+        self
+    }
+
+    pub fn with_retention(mut self, r: Retention) -> Self {
+        self.retention = r;
+        self
+    }
+
+    pub fn with_archive_action(mut self, a: ArchiveAction) -> Self {
+        self.archive = a;
+        self
+    }
+
+    pub fn with_block_names(mut self, names: BlockNames) -> Self {
+        self.block_names = names;
+        self
+    }
+
+    pub fn with_config(mut self, c: ForwardConfig) -> Self {
+        self.config = c;
+        self
+    }
+
+    pub fn progress(&self) -> watch::Receiver<ForwardProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Events are recorded in a `forwarder` span with the forwarder `id`.
+    pub async fn go(self) -> ! {
+        let dest = format!("kafka:{}", self.topic);
+        let span = info_span!("forwarder", id = %self.id, %dest);
+        self.forward(dest).instrument(span).await
+    }
+
+    async fn forward(self, dest: String) -> ! {
+        let cursors = loop {
+            match Cursors::load(&self.directory, &self.block_names, [dest.as_str()]).await {
+                Ok(c) => break c,
+                Err(err) => {
+                    error!(path = ?self.directory, %err, "failed to load cursors");
+                    sleep(self.config.error_delay()).await
+                }
+            }
+        };
+        loop {
+            let Err(err) = self.run(&dest, &cursors).await;
+            error!(%err, retryable = %err.is_retryable(), "kafka forwarder error");
+            sleep(self.config.error_delay()).await
+        }
+    }
+
+    /// Publish batches of entries after the acknowledged position.
+    async fn run(&self, dest: &str, cursors: &Cursors) -> Result<Infallible, ForwardError> {
+        let mut acked = cursors.get(dest).await.unwrap_or_else(BlockInfo::zero);
+        self.progress.acked(acked);
+        let mut log = LogReader::new_with(&self.directory, acked, self.block_names.clone());
+        loop {
+            let mut batch = Vec::new();
+            while batch.len() < self.batch_len {
+                let Some((info, bytes)) = log.next_entry().await? else {
+                    break
+                };
+                if info > acked {
+                    batch.push((info, bytes, headers(info, log.metadata())))
+                }
+            }
+            let Some(last) = batch.last().map(|b| b.0) else {
+                sleep(self.config.poll_interval()).await;
+                continue
+            };
+            self.progress.sent(last);
+            let deliveries = batch.iter().map(|(_, bytes, headers)| {
+                let record = FutureRecord::to(&self.topic)
+                    .key(&self.id)
+                    .payload(bytes.as_ref())
+                    .headers(headers.clone());
+                self.producer.send(record, Duration::from_secs(5))
+            });
+            try_join_all(deliveries).await.map_err(|(e, _)| e)?;
+            debug!(messages = %batch.len(), %last, "batch delivered");
+            cursors.acknowledge(dest, last, &self.retention, &self.archive, false).await?;
+            self.progress.acked(last);
+            acked = last
+        }
+    }
+}
+
+fn headers(info: BlockInfo, meta: Option<&Metadata>) -> OwnedHeaders {
+    let number = info.number().value().to_string();
+    let offset = info.offset().to_string();
+    let mut h = OwnedHeaders::new()
+        .insert(Header { key: "bogger-block", value: Some(&number) })
+        .insert(Header { key: "bogger-offset", value: Some(&offset) });
+    for (k, v) in meta.into_iter().flat_map(|m| m.iter()) {
+        h = h.insert(Header { key: k, value: Some(v) })
+    }
+    h
+}
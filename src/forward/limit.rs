@@ -0,0 +1,198 @@
+use std::{collections::VecDeque, num::{NonZeroU32, NonZeroU64}, sync::{Arc, Mutex}};
+
+use tokio::{select, sync::watch, time::{sleep, sleep_until, Duration, Instant}};
+use tracing::warn;
+
+use super::progress::Progress;
+
+/// Upper bounds on the rate at which records are forwarded.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct RateLimit {
+    #[cfg_attr(feature = "serde", serde(rename = "records_per_second", skip_serializing_if = "Option::is_none"))]
+    records: Option<NonZeroU32>,
+    #[cfg_attr(feature = "serde", serde(rename = "bytes_per_second", skip_serializing_if = "Option::is_none"))]
+    bytes: Option<NonZeroU64>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    bytes_per_day: Option<NonZeroU64>
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of records sent per second.
+    pub fn with_records_per_second(mut self, n: NonZeroU32) -> Self {
+        self.records = Some(n);
+        self
+    }
+
+    /// Limit the number of payload bytes sent per second.
+    pub fn with_bytes_per_second(mut self, n: NonZeroU64) -> Self {
+        self.bytes = Some(n);
+        self
+    }
+
+    /// Limit the number of payload bytes sent within any 24 hours.
+    ///
+    /// Unlike the other limits, this cap applies to all streams and
+    /// destinations of a forwarder together. When it is reached, forwarding
+    /// stops until enough earlier transfers are older than a day, which
+    /// `ForwardProgress::is_capped` reports. Payloads are counted after
+    /// compression.
+    pub fn with_bytes_per_day(mut self, n: NonZeroU64) -> Self {
+        self.bytes_per_day = Some(n);
+        self
+    }
+
+    pub fn records_per_second(&self) -> Option<NonZeroU32> {
+        self.records
+    }
+
+    pub fn bytes_per_second(&self) -> Option<NonZeroU64> {
+        self.bytes
+    }
+
+    pub fn bytes_per_day(&self) -> Option<NonZeroU64> {
+        self.bytes_per_day
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.records.is_none() && self.bytes.is_none() && self.bytes_per_day.is_none()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    records: Option<TokenBucket>,
+    bytes: Option<TokenBucket>
+}
+
+impl Limiter {
+    pub(crate) fn new(r: RateLimit) -> Self {
+        Self {
+            records: r.records.map(|n| TokenBucket::new(n.get() as f64)),
+            bytes: r.bytes.map(|n| TokenBucket::new(n.get() as f64))
+        }
+    }
+
+    /// Wait until a record of the given length may be sent.
+    pub(crate) async fn acquire(&mut self, len: usize) {
+        if let Some(b) = &mut self.records {
+            b.acquire(1.0).await
+        }
+        if let Some(b) = &mut self.bytes {
+            b.acquire(len as f64).await
+        }
+    }
+}
+
+/// A pause in sending requested by a destination, cf. `AckStatus::Throttle`.
+#[derive(Debug, Default)]
+pub(crate) struct Pause(Mutex<Option<Instant>>);
+
+impl Pause {
+    /// Pause sending for (at least) the given duration.
+    pub(crate) fn extend(&self, d: Duration) {
+        let until = Instant::now() + d;
+        let mut p = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        *p = Some(p.map_or(until, |t| t.max(until)))
+    }
+
+    /// Wait until the current pause, if any, is over.
+    pub(crate) async fn wait(&self) {
+        let until = *self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(t) = until {
+            sleep_until(t).await
+        }
+    }
+}
+
+/// The bytes sent within the last day, counted in hourly buckets.
+///
+/// Bytes are counted even without a cap, so that a cap set at runtime
+/// takes earlier transfers into account.
+#[derive(Debug)]
+pub(crate) struct DailyCap {
+    start: Instant,
+    /// Hours since `start` and the bytes sent during them, oldest first.
+    hours: Mutex<VecDeque<(u64, u64)>>,
+    progress: Arc<Progress>
+}
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+impl DailyCap {
+    pub(crate) fn new(progress: Arc<Progress>) -> Self {
+        Self { start: Instant::now(), hours: Mutex::new(VecDeque::new()), progress }
+    }
+
+    /// Wait until `n` more bytes may be sent under the current limit and
+    /// count them as sent.
+    pub(crate) async fn acquire(&self, limit: &watch::Receiver<RateLimit>, n: usize) {
+        let mut limit = limit.clone();
+        let mut warned = false;
+        loop {
+            let cap = limit.borrow().bytes_per_day().map_or(u64::MAX, NonZeroU64::get);
+            let wait = {
+                let mut hours = self.hours.lock().unwrap_or_else(|e| e.into_inner());
+                let now = self.start.elapsed().as_secs() / HOUR.as_secs();
+                while hours.front().is_some_and(|(h, _)| h + 24 <= now) {
+                    hours.pop_front();
+                }
+                let total: u64 = hours.iter().map(|(_, b)| b).sum();
+                // A payload larger than the cap is sent on its own.
+                if total == 0 || total.saturating_add(n as u64) <= cap {
+                    match hours.back_mut() {
+                        Some((h, b)) if *h == now => *b += n as u64,
+                        _                         => hours.push_back((now, n as u64))
+                    }
+                    self.progress.sent_bytes(total + n as u64, false);
+                    return
+                }
+                self.progress.sent_bytes(total, true);
+                let oldest = hours.front().map(|(h, _)| *h).unwrap_or(now);
+                self.start + HOUR * (oldest + 24) as u32
+            };
+            if !warned {
+                warn!(%cap, "daily transfer cap reached, pausing");
+                warned = true
+            }
+            // The cap may be changed in the meantime, cf. `ForwarderHandle::update_rate_limit`.
+            select! {
+                _ = sleep_until(wait)  => {}
+                _ = limit.changed()    => {}
+            }
+        }
+    }
+}
+
+/// A token bucket which refills at `rate` tokens per second up to a
+/// capacity of one second worth of tokens.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        Self { rate, tokens: rate, last: Instant::now() }
+    }
+
+    async fn acquire(&mut self, n: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        // Requests larger than the bucket capacity are allowed to go into
+        // debt, which is paid back by waiting.
+        self.tokens -= n;
+        if self.tokens < 0.0 {
+            sleep(Duration::from_secs_f64(-self.tokens / self.rate)).await
+        }
+    }
+}
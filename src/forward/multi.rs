@@ -0,0 +1,67 @@
+use std::future::Future;
+
+use tokio::{spawn, sync::watch};
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use super::{Forwarder, ForwarderHandle, ForwardProgress};
+
+/// Runs several forwarders in one process.
+///
+/// Every forwarder has its own connections and cursors, so unlike
+/// `Forwarder::with_multiplexed` this works with any destination. Give
+/// each forwarder a distinct stream name, cf. `Forwarder::with_stream`.
+#[derive(Debug, Default)]
+pub struct MultiForwarder {
+    forwarders: Vec<Forwarder>
+}
+
+impl MultiForwarder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_forwarder(mut self, f: Forwarder) -> Self {
+        self.forwarders.push(f);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.forwarders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forwarders.is_empty()
+    }
+
+    /// Watch the progress of every forwarder, in the order they were added.
+    pub fn progress(&self) -> Vec<watch::Receiver<ForwardProgress>> {
+        self.forwarders.iter().map(Forwarder::progress).collect()
+    }
+
+    /// Handles of every forwarder, in the order they were added.
+    pub fn handles(&self) -> Vec<ForwarderHandle> {
+        self.forwarders.iter().map(Forwarder::handle).collect()
+    }
+
+    pub async fn go(self) -> ! {
+        self.go_until(std::future::pending()).await;
+        unreachable!("forwarders only stop when signalled")
+    }
+
+    /// Forward until `signal` completes, then shut down every forwarder
+    /// gracefully, cf. `Forwarder::go_until`.
+    pub async fn go_until<F: Future<Output = ()>>(self, signal: F) {
+        let stop = CancellationToken::new();
+        let tasks: Vec<_> = self.forwarders.into_iter()
+            .map(|f| spawn(f.go_until(stop.clone().cancelled_owned())))
+            .collect();
+        signal.await;
+        stop.cancel();
+        for t in tasks {
+            if let Err(err) = t.await {
+                error!(%err, "forwarder task error")
+            }
+        }
+    }
+}
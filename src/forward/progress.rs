@@ -0,0 +1,250 @@
+use std::{path::PathBuf, time::{Duration, Instant}};
+
+use tokio::{sync::watch, time::sleep};
+use tracing::debug;
+
+use crate::{BlockInfo, BlockMeta, BlockNames, BlockNum, ForwardErrorKind, list_blocks_with};
+use crate::metrics::{Counter, Metrics};
+
+/// The progress of a forwarder's own stream, cf. `Forwarder::progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardProgress {
+    position: BlockInfo,
+    latest: BlockNum,
+    acked: BlockInfo,
+    acked_at: Option<Instant>,
+    connections: u32,
+    reconnects: u64,
+    stalled: u32,
+    stalls: u64,
+    bytes_per_day: u64,
+    capped: bool,
+    last_error: Option<ForwardErrorKind>
+}
+
+impl ForwardProgress {
+    fn new() -> Self {
+        Self {
+            position: BlockInfo::zero(),
+            latest: BlockNum::zero(),
+            acked: BlockInfo::zero(),
+            acked_at: None,
+            connections: 0,
+            reconnects: 0,
+            stalled: 0,
+            stalls: 0,
+            bytes_per_day: 0,
+            capped: false,
+            last_error: None
+        }
+    }
+
+    /// The position of the last record sent to any destination.
+    pub fn position(&self) -> BlockInfo {
+        self.position
+    }
+
+    /// The latest local block.
+    pub fn latest(&self) -> BlockNum {
+        self.latest
+    }
+
+    /// The position acknowledged by every destination.
+    pub fn acked(&self) -> BlockInfo {
+        self.acked
+    }
+
+    /// When the acknowledged position last advanced.
+    pub fn acked_at(&self) -> Option<Instant> {
+        self.acked_at
+    }
+
+    /// Check if records have been sent which are not yet acknowledged.
+    pub fn is_pending(&self) -> bool {
+        self.position > self.acked
+    }
+
+    /// The number of destinations currently connected.
+    pub fn connections(&self) -> u32 {
+        self.connections
+    }
+
+    /// How often connections to destinations have been re-established.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Check if acknowledgements of some destination are currently stalled,
+    /// cf. `ForwardConfig::with_stall_timeout`.
+    pub fn is_stalled(&self) -> bool {
+        self.stalled > 0
+    }
+
+    /// How often acknowledgements have stalled.
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    /// The payload bytes sent within the last 24 hours, cf.
+    /// `RateLimit::with_bytes_per_day`.
+    pub fn bytes_per_day(&self) -> u64 {
+        self.bytes_per_day
+    }
+
+    /// Check if forwarding is paused because the daily cap has been reached.
+    pub fn is_capped(&self) -> bool {
+        self.capped
+    }
+
+    /// The kind of the latest error of forwarding to any destination.
+    ///
+    /// Errors which are not retryable usually need attention, cf.
+    /// `ForwardErrorKind::is_retryable`.
+    pub fn last_error(&self) -> Option<ForwardErrorKind> {
+        self.last_error
+    }
+
+    /// The number of local blocks not yet acknowledged by every destination.
+    pub fn lag(&self) -> u64 {
+        self.latest.value().saturating_sub(self.acked.number().value())
+    }
+}
+
+/// Updates of a forwarder's progress.
+#[derive(Debug)]
+pub(crate) struct Progress(watch::Sender<ForwardProgress>);
+
+impl Progress {
+    pub(crate) fn new() -> Self {
+        Self(watch::channel(ForwardProgress::new()).0)
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<ForwardProgress> {
+        self.0.subscribe()
+    }
+
+    pub(crate) fn sent(&self, info: BlockInfo) {
+        self.0.send_if_modified(|p| {
+            if info <= p.position {
+                return false
+            }
+            p.position = info;
+            p.latest = p.latest.max(info.number());
+            true
+        });
+    }
+
+    pub(crate) fn latest(&self, n: BlockNum) {
+        self.0.send_if_modified(|p| {
+            let modified = n > p.latest;
+            p.latest = p.latest.max(n);
+            modified
+        });
+    }
+
+    pub(crate) fn acked(&self, info: BlockInfo) {
+        self.0.send_if_modified(|p| {
+            if info <= p.acked {
+                return false
+            }
+            p.acked = info;
+            p.acked_at = Some(Instant::now());
+            true
+        });
+    }
+
+    pub(crate) fn connected(&self) {
+        self.0.send_modify(|p| p.connections += 1)
+    }
+
+    pub(crate) fn disconnected(&self) {
+        self.0.send_modify(|p| p.connections = p.connections.saturating_sub(1))
+    }
+
+    pub(crate) fn reconnected(&self) {
+        self.0.send_modify(|p| p.reconnects += 1)
+    }
+
+    pub(crate) fn stalled(&self) {
+        self.0.send_modify(|p| {
+            p.stalled += 1;
+            p.stalls += 1
+        })
+    }
+
+    pub(crate) fn unstalled(&self) {
+        self.0.send_modify(|p| p.stalled = p.stalled.saturating_sub(1))
+    }
+
+    pub(crate) fn failed(&self, kind: ForwardErrorKind) {
+        self.0.send_modify(|p| p.last_error = Some(kind))
+    }
+
+    pub(crate) fn sent_bytes(&self, bytes_per_day: u64, capped: bool) {
+        self.0.send_if_modified(|p| {
+            let modified = p.bytes_per_day != bytes_per_day || p.capped != capped;
+            p.bytes_per_day = bytes_per_day;
+            p.capped = capped;
+            modified
+        });
+    }
+}
+
+/// How often gauges derived from progress and directory contents are updated.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Metrics of a forwarder, cf. `Forwarder::with_metrics`.
+///
+/// Counters are shared by all forwarders of a registry, gauges are labeled
+/// with the forwarder ID and stream, cf. `ForwardMetrics::report`.
+#[derive(Debug, Clone)]
+pub(crate) struct ForwardMetrics {
+    pub(crate) records_sent: Counter,
+    pub(crate) reconnects: Counter,
+    pub(crate) receiver_errors: Counter,
+    registry: Metrics
+}
+
+impl ForwardMetrics {
+    pub(crate) fn new(m: &Metrics) -> Self {
+        Self {
+            records_sent: m.counter("bogger_forward_records_sent_total", "Records sent to destinations."),
+            reconnects: m.counter("bogger_forward_reconnects_total", "Connections re-established to destinations."),
+            receiver_errors: m.counter("bogger_forward_receiver_errors_total", "Storage errors reported by destinations."),
+            registry: m.clone()
+        }
+    }
+
+    /// Periodically update the gauges of the forwarder with the given ID
+    /// and stream.
+    pub(crate) async fn report
+        ( self
+        , id: String
+        , stream: Option<String>
+        , dir: PathBuf
+        , names: BlockNames
+        , progress: watch::Receiver<ForwardProgress>
+        )
+    {
+        let mut labels = vec![("id", id.as_str())];
+        if let Some(s) = &stream {
+            labels.push(("stream", s.as_str()))
+        }
+        let m = &self.registry;
+        let lag = m.gauge_with_labels("bogger_forward_lag_blocks", "Local blocks not yet acknowledged by every destination.", &labels);
+        let disk_usage = m.gauge_with_labels("bogger_forward_disk_usage_bytes", "Size of the blocks in the forwarded directory.", &labels);
+        let bytes_per_day = m.gauge_with_labels("bogger_forward_daily_bytes", "Payload bytes sent within the last 24 hours, if capped.", &labels);
+        let capped = m.gauge_with_labels("bogger_forward_capped", "Whether forwarding waits for the daily transfer cap.", &labels);
+        loop {
+            let p = *progress.borrow();
+            lag.set(p.lag());
+            bytes_per_day.set(p.bytes_per_day());
+            capped.set(u64::from(p.is_capped()));
+            match list_blocks_with(&dir, &names).await {
+                Ok(blocks) => disk_usage.set(blocks.iter().map(BlockMeta::size).sum()),
+                Err(err)   => debug!(%err, path = ?dir, "failed to list blocks")
+            }
+            sleep(REPORT_INTERVAL).await
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::{io, path::{Path, PathBuf}, time::SystemTime};
+
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{BlockNames, BlockNum};
+use crate::fs::{block_path, portable};
+
+/// The subdirectory of a block directory corrupt blocks are moved to.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// The file in the quarantine directory which lists quarantined blocks.
+const EVENTS_FILENAME: &str = "events.log";
+
+/// Move a block into the quarantine directory and record why.
+///
+/// Every event is appended to `quarantine/events.log` as a line with the
+/// time in seconds since the Unix epoch, the block file name and the reason.
+pub(crate) async fn quarantine(dir: &Path, names: &BlockNames, n: BlockNum, reason: &str) -> io::Result<PathBuf> {
+    let qdir = dir.join(QUARANTINE_DIR);
+    fs::create_dir_all(&qdir).await?;
+    let name = names.file_name(n);
+    let target = qdir.join(&name);
+    portable::move_file(&block_path(dir, names, n).await, &target).await?;
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(qdir.join(EVENTS_FILENAME))
+        .await?;
+    log.write_all(format!("{secs} {name} {}\n", reason.replace('\n', " ")).as_bytes()).await?;
+    log.sync_data().await?;
+    Ok(target)
+}
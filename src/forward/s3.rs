@@ -0,0 +1,202 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use minicbor::{Encode, Decode};
+use object_store::{ObjectStore, PutPayload, aws::AmazonS3Builder, path::Path as ObjectPath};
+use tokio::{fs, sync::watch, time::sleep};
+use tracing::{debug, error, warn};
+
+use crate::{BlockInfo, BlockNames, BlockNum, BlockStatus, CRC32C, list_blocks_with, verify_block_with};
+use crate::retention::{Retention, ArchiveAction};
+
+use super::{ForwardConfig, ForwardError, ForwardProgress};
+use super::cursors::Cursors;
+use super::progress::Progress;
+
+/// The file name suffix of block manifests.
+pub const MANIFEST_SUFFIX: &str = ".manifest";
+
+/// Uploads completed blocks of a directory to an object store, e.g. an
+/// S3-compatible bucket.
+///
+/// Every block is stored as one object named like the block file, followed
+/// by a CBOR-encoded `Manifest` object with the suffix `.manifest`. Once
+/// both have been stored, the block is released according to the retention
+/// policy. The latest block is not uploaded as it may still be written to.
+#[derive(Debug)]
+pub struct S3Forwarder {
+    directory: PathBuf,
+    store: Arc<dyn ObjectStore>,
+    dest: String,
+    prefix: ObjectPath,
+    retention: Retention,
+    archive: ArchiveAction,
+    block_names: BlockNames,
+    progress: Arc<Progress>,
+    config: ForwardConfig
+}
+
+/// Describes an uploaded block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Manifest {
+    #[n(0)] number: BlockNum,
+    #[n(1)] size: u64,
+    #[n(2)] entries: u64,
+    #[n(3)] crc: u32,
+    #[n(4)] complete: bool
+}
+
+impl Manifest {
+    pub fn number(&self) -> BlockNum {
+        self.number
+    }
+
+    /// The size of the block object in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The number of intact entries.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// The CRC32C of the block object.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Does the block have a trailer matching its content?
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+impl S3Forwarder {
+    /// Upload blocks to the given object store.
+    pub async fn new<P: AsRef<Path>>(dir: P, store: Arc<dyn ObjectStore>) -> Result<Self, ForwardError> {
+        let path = dir.as_ref().to_path_buf();
+        if !path.is_dir() {
+            return Err(ForwardError::NoDir(path))
+        }
+        Ok(Self {
+            directory: path,
+            dest: store.to_string(),
+            store,
+            prefix: ObjectPath::default(),
+            retention: Retention::default(),
+            archive: ArchiveAction::default(),
+            block_names: BlockNames::default(),
+            progress: Arc::new(Progress::new()),
+            config: ForwardConfig::default()
+        })
+    }
+
+    /// Upload blocks to an S3 bucket.
+    ///
+    /// Region, endpoint and credentials are taken from the usual `AWS_*`
+    /// environment variables, cf. `AmazonS3Builder::from_env`.
+    pub async fn from_env<P: AsRef<Path>>(dir: P, bucket: &str) -> Result<Self, ForwardError> {
+        let store = AmazonS3Builder::from_env().with_bucket_name(bucket).build()?;
+        Self::new(dir, Arc::new(store)).await
+    }
+
+    /// Store objects below the given prefix.
+    pub fn with_prefix(mut self, p: &str) -> Self {
+        self.prefix = ObjectPath::from(p);
+        self
+    }
+
+    pub fn with_retention(mut self, r: Retention) -> Self {
+        self.retention = r;
+        self
+    }
+
+    pub fn with_archive_action(mut self, a: ArchiveAction) -> Self {
+        self.archive = a;
+        self
+    }
+
+    pub fn with_block_names(mut self, names: BlockNames) -> Self {
+        self.block_names = names;
+        self
+    }
+
+    pub fn with_config(mut self, c: ForwardConfig) -> Self {
+        self.config = c;
+        self
+    }
+
+    pub fn progress(&self) -> watch::Receiver<ForwardProgress> {
+        self.progress.subscribe()
+    }
+
+    pub async fn go(self) -> ! {
+        let dest = format!("{}/{}", self.dest, self.prefix);
+        let cursors = loop {
+            match Cursors::load(&self.directory, &self.block_names, [dest.as_str()]).await {
+                Ok(c) => break c,
+                Err(err) => {
+                    error!(path = ?self.directory, %err, "failed to load cursors");
+                    sleep(self.config.error_delay()).await
+                }
+            }
+        };
+        loop {
+            if let Err(err) = self.upload_all(&dest, &cursors).await {
+                error!(%dest, %err, "upload error");
+                sleep(self.config.error_delay()).await;
+                continue
+            }
+            sleep(self.config.poll_interval()).await
+        }
+    }
+
+    /// Upload all completed blocks which have not been uploaded before.
+    async fn upload_all(&self, dest: &str, cursors: &Cursors) -> Result<(), ForwardError> {
+        let acked = cursors.get(dest).await.unwrap_or_else(BlockInfo::zero);
+        let blocks = list_blocks_with(&self.directory, &self.block_names).await?;
+        let Some((latest, completed)) = blocks.split_last() else {
+            return Ok(())
+        };
+        self.progress.latest(latest.number());
+        for b in completed.iter().filter(|b| b.number() >= acked.number()) {
+            self.upload(b.number(), b.path()).await?;
+            // The block is acknowledged as a whole.
+            let next = BlockInfo::zero().with_number(b.number().value() + 1);
+            cursors.acknowledge(dest, next, &self.retention, &self.archive, false).await?;
+            self.progress.sent(next);
+            self.progress.acked(next)
+        }
+        Ok(())
+    }
+
+    async fn upload(&self, number: BlockNum, path: &Path) -> Result<(), ForwardError> {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return Err(ForwardError::NoDir(path.to_path_buf()))
+        };
+        let (entries, complete) = match verify_block_with(&self.directory, number, &self.block_names).await? {
+            BlockStatus::Complete { entries } => (entries, true),
+            BlockStatus::Valid { entries }    => (entries, false),
+            BlockStatus::Corrupt { entries }  => {
+                warn!(%number, %entries, "uploading corrupt block");
+                (entries, false)
+            }
+        };
+        let bytes = fs::read(path).await?;
+        let manifest = Manifest {
+            number,
+            size: bytes.len() as u64,
+            entries,
+            crc: CRC32C.checksum(&bytes),
+            complete
+        };
+        let block = self.prefix.child(name);
+        self.store.put(&block, PutPayload::from(bytes)).await?;
+        // The manifest is stored last and marks the block as uploaded.
+        let manifest = minicbor::to_vec(manifest).map_err(std::io::Error::other)?;
+        let object = self.prefix.child(format!("{name}{MANIFEST_SUFFIX}"));
+        self.store.put(&object, PutPayload::from(manifest)).await?;
+        debug!(%number, %block, "uploaded block");
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::{fmt, str::FromStr, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+const DAY_MILLIS: u64 = 86_400_000;
+
+/// A daily period of time in which records are forwarded, cf.
+/// `ForwardConfig::with_window`.
+///
+/// Times are offsets from midnight UTC. A window which ends before it
+/// starts spans midnight, e.g. `22:00-06:00`. Windows must not be empty,
+/// i.e. start and end differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub struct TimeWindow {
+    /// Minutes after midnight.
+    start: u16,
+    end: u16
+}
+
+impl TimeWindow {
+    /// Create a window between two times of day, given as hours and minutes.
+    ///
+    /// Returns `None` for invalid times and if `start` equals `end`.
+    pub fn new(start: (u8, u8), end: (u8, u8)) -> Option<Self> {
+        let minutes = |(h, m): (u8, u8)| (h < 24 && m < 60).then_some(u16::from(h) * 60 + u16::from(m));
+        let (start, end) = (minutes(start)?, minutes(end)?);
+        if start == end {
+            return None
+        }
+        Some(Self { start, end })
+    }
+
+    pub fn start(&self) -> Duration {
+        Duration::from_secs(u64::from(self.start) * 60)
+    }
+
+    pub fn end(&self) -> Duration {
+        Duration::from_secs(u64::from(self.end) * 60)
+    }
+
+    pub fn contains(&self, t: SystemTime) -> bool {
+        self.until_open(t).is_zero()
+    }
+
+    /// The time from `t` until the window opens next, zero if it is open.
+    pub fn until_open(&self, t: SystemTime) -> Duration {
+        let now = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64 % DAY_MILLIS;
+        let (start, end) = (u64::from(self.start) * 60_000, u64::from(self.end) * 60_000);
+        let open = if start <= end {
+            start <= now && now < end
+        } else {
+            start <= now || now < end
+        };
+        if open {
+            return Duration::ZERO
+        }
+        Duration::from_millis((start + DAY_MILLIS - now) % DAY_MILLIS)
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (s, e) = (self.start, self.end);
+        write!(f, "{:02}:{:02}-{:02}:{:02}", s / 60, s % 60, e / 60, e % 60)
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = InvalidTimeWindow;
+
+    /// Parse a window given as `HH:MM-HH:MM`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = |t: &str| {
+            let (h, m) = t.trim().split_once(':')?;
+            Some((h.parse().ok()?, m.parse().ok()?))
+        };
+        s.split_once('-')
+            .and_then(|(a, b)| TimeWindow::new(time(a)?, time(b)?))
+            .ok_or_else(|| InvalidTimeWindow(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = InvalidTimeWindow;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TimeWindow> for String {
+    fn from(w: TimeWindow) -> Self {
+        w.to_string()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid time window {0:?}, expected HH:MM-HH:MM with different times")]
+pub struct InvalidTimeWindow(String);
+
+/// The time from `t` until one of the windows opens, zero if one is open
+/// or if there are no windows at all.
+pub(crate) fn until_open(windows: &[TimeWindow], t: SystemTime) -> Duration {
+    windows.iter().map(|w| w.until_open(t)).min().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::TimeWindow;
+
+    #[test]
+    fn windows() {
+        let at = |h: u64, m: u64| UNIX_EPOCH + Duration::from_secs(3 * 86_400 + h * 3600 + m * 60);
+        let day: TimeWindow = "09:00-17:30".parse().unwrap();
+        assert!(day.contains(at(9, 0)));
+        assert!(!day.contains(at(17, 30)));
+        assert_eq!(Duration::from_secs(30 * 60), day.until_open(at(8, 30)));
+        assert_eq!(Duration::from_secs(15 * 3600 + 30 * 60), day.until_open(at(17, 30)));
+
+        let night: TimeWindow = "22:00-06:00".parse().unwrap();
+        assert!(night.contains(at(23, 0)));
+        assert!(night.contains(at(5, 59)));
+        assert_eq!(Duration::from_secs(16 * 3600), night.until_open(at(6, 0)));
+        assert_eq!("22:00-06:00", night.to_string());
+
+        assert!("24:00-01:00".parse::<TimeWindow>().is_err());
+        assert!("09:00-09:00".parse::<TimeWindow>().is_err());
+        assert!("09:00".parse::<TimeWindow>().is_err())
+    }
+}
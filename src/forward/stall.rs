@@ -0,0 +1,109 @@
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use super::{ForwardConfig, ForwardError, progress::Progress};
+
+/// Watches the acknowledgements of a connection for timeouts and stalls.
+///
+/// Records waiting for any ack longer than the ack timeout fail the
+/// connection. Acks which do not advance the acknowledged position for
+/// longer than the stall timeout while records are sent mark the
+/// connection as stalled, which is logged and reported via
+/// `ForwardProgress`, and optionally fails the connection as well.
+#[derive(Debug)]
+pub(crate) struct AckWatch {
+    /// The number of records sent over the connection.
+    sent: Arc<AtomicU64>,
+    progress: Arc<Progress>,
+    ack_timeout: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    stall_reconnect: bool,
+    /// Records sent when the last ack arrived.
+    sent_at_ack: u64,
+    /// Since when records have been waiting for an ack.
+    unacked_since: Option<Instant>,
+    /// Records sent when the acknowledged position last advanced.
+    sent_at_progress: u64,
+    /// Since when records have been waiting for the position to advance.
+    waiting_since: Option<Instant>,
+    stalled: bool
+}
+
+impl AckWatch {
+    pub(crate) fn new(sent: Arc<AtomicU64>, progress: Arc<Progress>, cfg: &ForwardConfig) -> Self {
+        Self {
+            sent,
+            progress,
+            ack_timeout: cfg.ack_timeout(),
+            stall_timeout: cfg.stall_timeout(),
+            stall_reconnect: cfg.stall_reconnect(),
+            sent_at_ack: 0,
+            unacked_since: None,
+            sent_at_progress: 0,
+            waiting_since: None,
+            stalled: false
+        }
+    }
+
+    /// How often `AckWatch::check` needs to be called while no acks arrive.
+    pub(crate) fn interval(&self) -> Option<Duration> {
+        match (self.ack_timeout, self.stall_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b)             => a.or(b)
+        }
+    }
+
+    /// Register an ack and whether it advanced the acknowledged position.
+    pub(crate) fn on_ack(&mut self, advanced: bool) {
+        let n = self.sent.load(Ordering::Relaxed);
+        self.sent_at_ack = n;
+        self.unacked_since = None;
+        if !advanced {
+            return
+        }
+        self.sent_at_progress = n;
+        self.waiting_since = None;
+        if self.stalled {
+            self.stalled = false;
+            self.progress.unstalled();
+            info!("acknowledgements resumed")
+        }
+    }
+
+    pub(crate) fn check(&mut self) -> Result<(), ForwardError> {
+        let now = Instant::now();
+        let n = self.sent.load(Ordering::Relaxed);
+        if let Some(t) = self.ack_timeout.filter(|_| n > self.sent_at_ack) {
+            if now - *self.unacked_since.get_or_insert(now) >= t {
+                return Err(ForwardError::AckTimeout)
+            }
+        }
+        if let Some(t) = self.stall_timeout.filter(|_| n > self.sent_at_progress && !self.stalled) {
+            let since = *self.waiting_since.get_or_insert(now);
+            if now - since >= t {
+                warn! {
+                    waiting = ?(now - since),
+                    unacked = n - self.sent_at_progress,
+                    "acknowledgements stalled"
+                }
+                self.stalled = true;
+                self.progress.stalled();
+                if self.stall_reconnect {
+                    return Err(ForwardError::Stalled)
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AckWatch {
+    fn drop(&mut self) {
+        if self.stalled {
+            self.progress.unstalled()
+        }
+    }
+}
@@ -1,23 +1,134 @@
 mod block;
+mod compact;
+mod counters;
+mod digest;
+mod log;
+mod metadata;
+mod migrate;
 mod reader;
+mod read_ahead;
+mod schema;
+mod store;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod names;
+pub(crate) mod portable;
+mod verify;
 mod writer;
 
-use std::{path::Path, io, ffi::OsStr};
-use tokio::fs;
+use std::{path::{Path, PathBuf}, io, ffi::OsStr, fmt, sync::Arc, time::SystemTime};
+use tokio::{fs, io::AsyncWriteExt};
 
-use crate::BLOCK_FILENAME_PREFIX;
+use crate::logger::{Transform, TransformHook};
 
-pub use block::{BlockInfo, BlockNum};
+
+pub use block::{BlockInfo, BlockNum, Trailer};
+pub use compact::{compact_blocks, compact_blocks_with, CompactError, CompactOptions, CompactSummary};
+#[cfg(feature = "bench")]
+pub use counters::{Counters, counters, reset_counters};
+pub use digest::{Digest, DigestKind};
+pub use names::BlockNames;
+pub use portable::available_space;
+pub use log::LogReader;
+pub use metadata::Metadata;
+pub use migrate::{migrate_blocks_with, MigrateError, MigrateOptions, MigrateSummary};
 pub use reader::{EntryReader, ReadError};
+pub use read_ahead::ReadAhead;
+pub use schema::{Schemas, SchemaError};
+pub use store::{BlockStore, BlockRead, BlockWrite, FileStore, MemoryStore};
+#[cfg(feature = "mmap")]
+pub use mmap::MmapEntryReader;
+pub use verify::{verify_block, verify_block_with, BlockStatus};
 pub use writer::{EntryWriter, WriteError};
 
 pub(crate) use writer::latest_block_number;
+pub(crate) use portable::sync_dir;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct Config {
     max_buffer_len: usize,
     max_block_len: u64,
-    max_entry_len: u16
+    max_entry_len: u32,
+    max_batch_len: usize,
+    max_batch_bytes: usize,
+    chunking: bool,
+    trailer: bool,
+    preallocate: bool,
+    durable_metadata: bool,
+    sharding: bool,
+    channel_capacity: usize,
+    encoding_stage: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    on_rotate: Option<RotateHook>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    transform: Option<TransformHook>,
+    block_names: BlockNames,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    quota: Option<u64>,
+    quota_policy: QuotaPolicy,
+    max_retry_bytes: usize,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    digest: Option<DigestKind>,
+    entry_metadata: bool,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    schema: Option<u32>,
+    #[cfg(feature = "testing")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    faults: Option<crate::testing::Faults>
+}
+
+/// The length of a block header in bytes.
+const BLOCK_HEADER_LEN: u64 = 8;
+
+/// An inconsistent `Config`, cf. `Config::validate`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    #[error("max. buffer length must not be 0")]
+    ZeroBuffer,
+
+    #[error("max. block length {0} leaves no room after the block header")]
+    BlockTooSmall(u64),
+
+    #[error("max. entry length {entry} exceeds max. block length {block}")]
+    EntryTooLarge { entry: u32, block: u64 },
+
+    #[error("max. batch length and bytes must not be 0")]
+    ZeroBatch,
+
+    #[error("channel capacity must not be 0")]
+    ZeroCapacity,
+
+    #[error("quota {quota} is smaller than max. block length {block}")]
+    QuotaTooSmall { quota: u64, block: u64 }
+}
+
+/// What to do when writing would exceed the disk quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum QuotaPolicy {
+    /// Reject new entries until blocks have been removed, e.g. by a forwarder.
+    #[default]
+    Refuse,
+    /// Delete the oldest blocks, even if they have not been forwarded yet.
+    DropOldest,
+    /// Discard new entries until blocks have been removed.
+    ///
+    /// The `EntryWriter` reports `WriteError::Quota` like with `Refuse`,
+    /// but a `Logger` accepts and discards entries instead of failing.
+    DropNew
+}
+
+/// A function called whenever a block is complete.
+#[derive(Clone)]
+struct RotateHook(Arc<dyn Fn(BlockInfo, u64) + Send + Sync>);
+
+impl fmt::Debug for RotateHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RotateHook")
+    }
 }
 
 impl Default for Config {
@@ -25,7 +136,27 @@ impl Default for Config {
         Self {
             max_buffer_len: 8192,
             max_block_len: 1024 * 1024,
-            max_entry_len: 1024
+            max_entry_len: 1024,
+            max_batch_len: 128,
+            max_batch_bytes: 32 * 1024,
+            chunking: false,
+            trailer: false,
+            preallocate: false,
+            durable_metadata: false,
+            sharding: false,
+            channel_capacity: 100,
+            encoding_stage: false,
+            on_rotate: None,
+            transform: None,
+            block_names: BlockNames::default(),
+            quota: None,
+            quota_policy: QuotaPolicy::default(),
+            max_retry_bytes: 1024 * 1024,
+            digest: None,
+            entry_metadata: false,
+            schema: None,
+            #[cfg(feature = "testing")]
+            faults: None
         }
     }
 }
@@ -35,6 +166,40 @@ impl Config {
         Self::default()
     }
 
+    /// Validate this config and return it.
+    pub fn build(self) -> Result<Self, ConfigError> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check that the settings of this config are consistent.
+    ///
+    /// `EntryWriter` and `Logger` refuse to use an invalid config.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_buffer_len == 0 {
+            return Err(ConfigError::ZeroBuffer)
+        }
+        if self.max_block_len <= BLOCK_HEADER_LEN {
+            return Err(ConfigError::BlockTooSmall(self.max_block_len))
+        }
+        if u64::from(self.max_entry_len) > self.max_block_len {
+            return Err(ConfigError::EntryTooLarge {
+                entry: self.max_entry_len,
+                block: self.max_block_len
+            })
+        }
+        if self.max_batch_len == 0 || self.max_batch_bytes == 0 {
+            return Err(ConfigError::ZeroBatch)
+        }
+        if self.channel_capacity == 0 {
+            return Err(ConfigError::ZeroCapacity)
+        }
+        if let Some(q) = self.quota.filter(|q| *q < self.max_block_len) {
+            return Err(ConfigError::QuotaTooSmall { quota: q, block: self.max_block_len })
+        }
+        Ok(())
+    }
+
     pub fn with_max_buffer_len(mut self, val: usize) -> Self {
         self.max_buffer_len = val;
         self
@@ -45,41 +210,531 @@ impl Config {
         self
     }
 
-    pub fn with_max_entry_len(mut self, val: u16) -> Self {
+    /// Entries larger than `u16::MAX` bytes require block format version 2,
+    /// which is used automatically if this value exceeds `u16::MAX`.
+    pub fn with_max_entry_len(mut self, val: u32) -> Self {
         self.max_entry_len = val;
         self
     }
+
+    /// The maximum number of entries the `Logger` writes at once.
+    pub fn with_max_batch_len(mut self, val: usize) -> Self {
+        self.max_batch_len = val;
+        self
+    }
+
+    /// The maximum number of encoded bytes the `Logger` writes at once.
+    pub fn with_max_batch_bytes(mut self, val: usize) -> Self {
+        self.max_batch_bytes = val;
+        self
+    }
+
+    /// Split entries larger than the maximum entry size into several frames
+    /// instead of rejecting them.
+    pub fn with_chunking(mut self, val: bool) -> Self {
+        self.chunking = val;
+        self
+    }
+
+    /// End every block with a `Trailer` when starting a new one.
+    pub fn with_trailer(mut self, val: bool) -> Self {
+        self.trailer = val;
+        self
+    }
+
+    /// Reserve `max_block_len` bytes of disk space for every new block file
+    /// to reduce fragmentation. Only supported on Linux.
+    pub fn with_preallocate(mut self, val: bool) -> Self {
+        self.preallocate = val;
+        self
+    }
+
+    /// Sync the directory after creating a block file, so that a new block
+    /// survives a crash.
+    pub fn with_durable_metadata(mut self, val: bool) -> Self {
+        self.durable_metadata = val;
+        self
+    }
+
+    /// Create new blocks in subdirectories of `SHARD_LEN` blocks each
+    /// (e.g. `00042/block.42123`) to keep directories small.
+    ///
+    /// Readers find blocks in either layout, so this setting may be changed
+    /// for existing directories.
+    pub fn with_sharding(mut self, val: bool) -> Self {
+        self.sharding = val;
+        self
+    }
+
+    /// The number of values the `Logger` queues before `Logger::add` waits.
+    pub fn with_channel_capacity(mut self, val: usize) -> Self {
+        self.channel_capacity = val;
+        self
+    }
+
+    /// Encode values in a separate task of the `Logger`, so that encoding
+    /// large values does not delay writing.
+    pub fn with_encoding_stage(mut self, val: bool) -> Self {
+        self.encoding_stage = val;
+        self
+    }
+
+    /// Call the given function whenever the `EntryWriter` starts a new block
+    /// or is closed (cf. `EntryWriter::close`).
+    ///
+    /// It receives the info of the finished block, positioned at its end,
+    /// and the block's size in bytes. The function is called from within
+    /// the writer and should return quickly, e.g. by sending the info to
+    /// another task which compresses or uploads the block.
+    pub fn with_on_rotate<F>(mut self, f: F) -> Self
+    where
+        F: Fn(BlockInfo, u64) + Send + Sync + 'static
+    {
+        self.on_rotate = Some(RotateHook(Arc::new(f)));
+        self
+    }
+
+    /// Let the `Logger` transform every entry before appending it.
+    ///
+    /// Entries are transformed after encoding and before they are checked
+    /// against `Config::max_entry_len`. Entries the transform drops are
+    /// counted by `Health::filtered`.
+    pub fn with_transform<T: Transform>(mut self, t: T) -> Self {
+        self.transform = Some(TransformHook(Arc::new(t)));
+        self
+    }
+
+    pub fn with_block_names(mut self, n: BlockNames) -> Self {
+        self.block_names = n;
+        self
+    }
+
+    /// Limit the total size of all blocks in the directory to the given
+    /// number of bytes.
+    ///
+    /// The `EntryWriter` re-examines the directory whenever its estimate
+    /// exceeds the quota, so blocks removed by others are taken into account.
+    /// What happens if the quota is exceeded depends on the `QuotaPolicy`.
+    pub fn with_quota(mut self, bytes: u64) -> Self {
+        self.quota = Some(bytes);
+        self
+    }
+
+    pub fn with_quota_policy(mut self, p: QuotaPolicy) -> Self {
+        self.quota_policy = p;
+        self
+    }
+
+    /// The maximum number of bytes a `Logger` keeps in memory while
+    /// appending fails. Further entries are discarded.
+    pub fn with_max_retry_bytes(mut self, val: usize) -> Self {
+        self.max_retry_bytes = val;
+        self
+    }
+
+    /// Store a digest of the given kind after every entry.
+    ///
+    /// Blocks with digests use version 3 of the block format, which older
+    /// readers do not support.
+    pub fn with_digest(mut self, d: Option<DigestKind>) -> Self {
+        self.digest = d;
+        self
+    }
+
+    /// Store `Metadata` in front of every entry.
+    ///
+    /// Like digests, this requires version 3 of the block format.
+    pub fn with_entry_metadata(mut self, val: bool) -> Self {
+        self.entry_metadata = val;
+        self
+    }
+
+    /// Store the given schema ID with every entry, cf. `Schemas`.
+    ///
+    /// Like digests, this requires version 3 of the block format.
+    pub fn with_schema(mut self, id: Option<u32>) -> Self {
+        self.schema = id;
+        self
+    }
+
+    /// Inject faults into writes and syncs of block files.
+    #[cfg(feature = "testing")]
+    pub fn with_faults(mut self, f: crate::testing::Faults) -> Self {
+        self.faults = Some(f);
+        self
+    }
+
+    pub fn max_buffer_len(&self) -> usize {
+        self.max_buffer_len
+    }
+
+    pub fn max_block_len(&self) -> u64 {
+        self.max_block_len
+    }
+
+    pub fn max_entry_len(&self) -> u32 {
+        self.max_entry_len
+    }
+
+    pub fn max_batch_len(&self) -> usize {
+        self.max_batch_len
+    }
+
+    pub fn max_batch_bytes(&self) -> usize {
+        self.max_batch_bytes
+    }
+
+    pub fn chunking(&self) -> bool {
+        self.chunking
+    }
+
+    pub fn trailer(&self) -> bool {
+        self.trailer
+    }
+
+    pub fn preallocate(&self) -> bool {
+        self.preallocate
+    }
+
+    pub fn durable_metadata(&self) -> bool {
+        self.durable_metadata
+    }
+
+    pub fn sharding(&self) -> bool {
+        self.sharding
+    }
+
+    pub fn channel_capacity(&self) -> usize {
+        self.channel_capacity
+    }
+
+    pub fn encoding_stage(&self) -> bool {
+        self.encoding_stage
+    }
+
+    pub fn block_names(&self) -> &BlockNames {
+        &self.block_names
+    }
+
+    pub fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+
+    pub fn quota_policy(&self) -> QuotaPolicy {
+        self.quota_policy
+    }
+
+    pub fn max_retry_bytes(&self) -> usize {
+        self.max_retry_bytes
+    }
+
+    pub(crate) fn transform(&self) -> Option<&TransformHook> {
+        self.transform.as_ref()
+    }
+
+    pub fn digest(&self) -> Option<DigestKind> {
+        self.digest
+    }
+
+    pub fn entry_metadata(&self) -> bool {
+        self.entry_metadata
+    }
+
+    pub fn schema(&self) -> Option<u32> {
+        self.schema
+    }
+
+    pub(crate) fn rotated(&self, info: BlockInfo) {
+        if let Some(RotateHook(f)) = &self.on_rotate {
+            f(info, info.offset())
+        }
+    }
 }
 
-pub async fn delete_blocks<P>(dir: P, to: BlockNum) -> io::Result<()>
+/// Options for `delete_blocks_with`.
+#[derive(Debug, Clone, Default)]
+pub struct DeleteOptions {
+    durable_metadata: bool,
+    dry_run: bool,
+    delete_latest: bool,
+    block_names: BlockNames
+}
+
+impl DeleteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sync the directory after deleting blocks.
+    pub fn with_durable_metadata(mut self, val: bool) -> Self {
+        self.durable_metadata = val;
+        self
+    }
+
+    /// Only report which blocks would be deleted.
+    pub fn with_dry_run(mut self, val: bool) -> Self {
+        self.dry_run = val;
+        self
+    }
+
+    /// Delete the latest block too, if its number is less than `to`.
+    ///
+    /// By default the latest block is kept, because a writer may still be
+    /// appending to it. Only enable this if no writer uses the directory.
+    pub fn with_delete_latest(mut self, val: bool) -> Self {
+        self.delete_latest = val;
+        self
+    }
+
+    pub fn with_block_names(mut self, n: BlockNames) -> Self {
+        self.block_names = n;
+        self
+    }
+}
+
+/// The outcome of deleting (or otherwise releasing) blocks.
+#[derive(Debug, Default)]
+pub struct DeleteSummary {
+    deleted: Vec<BlockNum>,
+    failed: Vec<(BlockNum, io::Error)>
+}
+
+impl DeleteSummary {
+    /// The blocks which have been deleted.
+    pub fn deleted(&self) -> &[BlockNum] {
+        &self.deleted
+    }
+
+    /// The blocks which could not be deleted.
+    pub fn failed(&self) -> &[(BlockNum, io::Error)] {
+        &self.failed
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    pub(crate) fn add(&mut self, n: BlockNum, r: io::Result<()>) {
+        match r {
+            Ok(())   => self.deleted.push(n),
+            Err(err) => self.failed.push((n, err))
+        }
+    }
+}
+
+pub async fn delete_blocks<P>(dir: P, to: BlockNum) -> io::Result<DeleteSummary>
 where
     P: AsRef<Path>
 {
-    let mut dir = fs::read_dir(dir.as_ref()).await?;
-    while let Some(e) = dir.next_entry().await? {
-        if !e.file_name().to_str().map(|n| n.starts_with(BLOCK_FILENAME_PREFIX)).unwrap_or(false) {
-            continue
+    delete_blocks_with(dir, to, &DeleteOptions::default()).await
+}
+
+/// Delete all blocks with a number less than `to`, except for the latest
+/// block, cf. `DeleteOptions::with_delete_latest`.
+///
+/// Deletion continues if a block can not be deleted. Only errors while
+/// reading the directory are returned directly.
+pub async fn delete_blocks_with<P>(dir: P, to: BlockNum, opts: &DeleteOptions) -> io::Result<DeleteSummary>
+where
+    P: AsRef<Path>
+{
+    let path = dir.as_ref();
+    let mut blocks = list_blocks_with(path, &opts.block_names).await?;
+    let to = if opts.delete_latest { to } else { to.min(writable_block(&blocks)) };
+    blocks.retain(|b| b.number < to);
+    let mut summary = DeleteSummary::default();
+    for b in &blocks {
+        if opts.dry_run {
+            summary.add(b.number, Ok(()))
+        } else {
+            summary.add(b.number, portable::remove_file(&b.path).await)
+        }
+    }
+    if opts.dry_run || summary.deleted.is_empty() {
+        return Ok(summary)
+    }
+    let shards = remove_empty_shards(path, &blocks).await;
+    if opts.durable_metadata {
+        for s in shards {
+            sync_dir(s).await?
         }
-        if !e.file_type().await?.is_file() {
+        sync_dir(path).await?
+    }
+    Ok(summary)
+}
+
+/// The number of the block a writer may be appending to, i.e. the latest
+/// one, given all blocks sorted by number.
+///
+/// Blocks from this number on must not be deleted (or otherwise released),
+/// even if acknowledgements or retention limits cover them.
+pub(crate) fn writable_block(blocks: &[BlockMeta]) -> BlockNum {
+    blocks.last().map(|b| b.number).unwrap_or_else(BlockNum::zero)
+}
+
+/// The number of blocks per subdirectory if sharding is enabled.
+pub const SHARD_LEN: u64 = 1000;
+
+/// A block file of a block directory, cf. `list_blocks`.
+#[derive(Debug, Clone)]
+pub struct BlockMeta {
+    number: BlockNum,
+    size: u64,
+    modified: Option<SystemTime>,
+    path: PathBuf
+}
+
+impl BlockMeta {
+    pub fn number(&self) -> BlockNum {
+        self.number
+    }
+
+    /// The file size in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The last modification time, if the platform supports it.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Find all blocks of a directory, including those in shard subdirectories,
+/// sorted by block number.
+pub async fn list_blocks<P: AsRef<Path>>(dir: P) -> io::Result<Vec<BlockMeta>> {
+    list_blocks_with(dir, &BlockNames::default()).await
+}
+
+/// Like `list_blocks` for block files named according to `names`.
+pub async fn list_blocks_with<P: AsRef<Path>>(dir: P, names: &BlockNames) -> io::Result<Vec<BlockMeta>> {
+    let mut blocks = Vec::new();
+    let mut shards = Vec::new();
+    scan_blocks(dir.as_ref(), names, &mut blocks, Some(&mut shards)).await?;
+    for s in shards {
+        scan_blocks(&s, names, &mut blocks, None).await?
+    }
+    blocks.sort_unstable_by_key(|b| b.number);
+    Ok(blocks)
+}
+
+async fn scan_blocks
+    ( dir: &Path
+    , names: &BlockNames
+    , blocks: &mut Vec<BlockMeta>
+    , mut shards: Option<&mut Vec<PathBuf>>
+    ) -> io::Result<()>
+{
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(e) = entries.next_entry().await? {
+        let name = e.file_name();
+        if let Some(number) = names.parse(&name) {
+            let meta = e.metadata().await?;
+            if meta.is_file() {
+                blocks.push(BlockMeta {
+                    number,
+                    size: meta.len(),
+                    modified: meta.modified().ok(),
+                    path: e.path()
+                })
+            }
             continue
         }
-        let p = e.path();
-        if read_block_num(&p) < to {
-            fs::remove_file(&p).await?
+        if let Some(s) = shards.as_mut() {
+            if is_shard_name(&name) && e.file_type().await?.is_dir() {
+                s.push(e.path())
+            }
         }
     }
     Ok(())
 }
 
-fn block_file_name(n: BlockNum) -> String {
-    format!("{BLOCK_FILENAME_PREFIX}{}", n.value())
+/// Remove shard subdirectories which became empty by deleting the given blocks.
+///
+/// Returns the shards which still contain blocks.
+async fn remove_empty_shards<'a>(dir: &Path, deleted: &'a [BlockMeta]) -> Vec<&'a Path> {
+    let mut shards: Vec<&Path> = deleted.iter()
+        .filter_map(|b| b.path.parent())
+        .filter(|p| *p != dir)
+        .collect();
+    shards.dedup();
+    let mut remaining = Vec::new();
+    for s in shards {
+        // Fails if the shard still contains blocks, which is fine.
+        if fs::remove_dir(s).await.is_err() {
+            remaining.push(s)
+        }
+    }
+    remaining
+}
+
+/// The path of a temporary file next to `path` which is renamed to it.
+fn temp_path(path: &Path, ext: &str) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".");
+    p.push(ext);
+    PathBuf::from(p)
+}
+
+/// Set the modification time of a rewritten block and sync it if `durable`.
+async fn seal_file(file: fs::File, modified: Option<SystemTime>, durable: bool) -> io::Result<()> {
+    let file = file.into_std().await;
+    tokio::task::spawn_blocking(move || {
+        if let Some(t) = modified {
+            file.set_modified(t)?
+        }
+        if durable {
+            file.sync_all()?
+        }
+        Ok(())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Durably replace the file `name` in `dir` with `bytes`.
+///
+/// The bytes are synced to a temporary file before it is renamed, so that
+/// after a crash the file contains either its old or its new bytes.
+pub(crate) async fn replace_file(dir: &Path, name: &str, bytes: &[u8]) -> io::Result<()> {
+    let path = dir.join(name);
+    let tmp = temp_path(&path, "tmp");
+    let mut file = fs::File::create(&tmp).await?;
+    file.write_all(bytes).await?;
+    file.sync_data().await?;
+    drop(file);
+    portable::rename(&tmp, &path).await?;
+    sync_dir(dir).await
+}
+
+/// The path of a block file.
+///
+/// A block is looked up at the top level of `dir` first and in its shard
+/// subdirectory otherwise.
+pub(crate) async fn block_path(dir: &Path, names: &BlockNames, n: BlockNum) -> PathBuf {
+    let flat = dir.join(names.file_name(n));
+    if fs::try_exists(&flat).await.unwrap_or(false) {
+        return flat
+    }
+    let sharded = shard_path(dir, n).join(names.file_name(n));
+    if fs::try_exists(&sharded).await.unwrap_or(false) {
+        sharded
+    } else {
+        flat
+    }
+}
+
+/// The shard subdirectory of a block.
+fn shard_path(dir: &Path, n: BlockNum) -> PathBuf {
+    dir.join(format!("{:05}", n.value() / SHARD_LEN))
 }
 
-pub(crate) fn read_block_num(p: impl AsRef<Path>) -> BlockNum {
-    let n = p.as_ref()
-        .extension()
-        .and_then(OsStr::to_str)
-        .and_then(|e| e.parse().ok())
-        .unwrap_or(0);
-    BlockNum::from(n)
+fn is_shard_name(name: &OsStr) -> bool {
+    name.to_str()
+        .map(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        .unwrap_or(false)
 }
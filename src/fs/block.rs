@@ -2,9 +2,33 @@ use std::fmt;
 
 use minicbor::{Encode, Decode};
 
+use super::DigestKind;
+
 const HEADER_V1: u64 =
     u64::from_be_bytes([b'b', b'l', b'o', b'c', b'k', 1, 0, 0]);
 
+const HEADER_MAGIC_MASK: u64 = 0xFF_FF_FF_FF_FF_00_00_00;
+
+/// Header flag: The most significant bit of an entry length indicates
+/// that the entry continues in the next frame.
+pub const FLAG_CHUNKED: u16 = 0x1;
+
+/// Header flag: Blocks end with a `Trailer` once they are complete.
+pub const FLAG_TRAILER: u16 = 0x2;
+
+/// Header flag (version 3): Every entry is followed by its xxHash64 digest.
+pub const FLAG_DIGEST_XXH64: u16 = 0x4;
+
+/// Header flag (version 3): Every entry is followed by its BLAKE3 digest.
+pub const FLAG_DIGEST_BLAKE3: u16 = 0x8;
+
+/// Header flag (version 3): Every entry starts with a frame of `Metadata`.
+pub const FLAG_METADATA: u16 = 0x10;
+
+/// Header flag (version 3): The first frame of every entry starts with the
+/// entry's schema ID as unsigned LEB128, covered by the frame's CRC.
+pub const FLAG_SCHEMA: u16 = 0x20;
+
 #[derive(Debug, Clone, Copy)]
 pub struct BlockHeader(u64);
 
@@ -20,9 +44,10 @@ impl BlockHeader {
     }
 
     pub fn from_u64(n: u64) -> Option<Self> {
-        match n {
-            HEADER_V1 => Some(Self(n)),
-            _         => None
+        if n & HEADER_MAGIC_MASK == HEADER_V1 & HEADER_MAGIC_MASK {
+            Some(Self(n))
+        } else {
+            None
         }
     }
 
@@ -34,10 +59,111 @@ impl BlockHeader {
         ((self.0 & 0xFF_00_00) >> 16) as u8
     }
 
-    #[allow(unused)]
     pub fn with_version(self, v: u8) -> Self {
         Self(self.0 & 0xFF_FF_FF_FF_FF_00_FF_FF | ((v as u64) << 16))
     }
+
+    pub fn flags(self) -> u16 {
+        self.0 as u16
+    }
+
+    pub fn with_flags(self, f: u16) -> Self {
+        Self(self.0 & !0xFF_FF | u64::from(f))
+    }
+
+    pub fn is_chunked(self) -> bool {
+        self.flags() & FLAG_CHUNKED != 0
+    }
+
+    pub fn has_trailer(self) -> bool {
+        self.flags() & FLAG_TRAILER != 0
+    }
+
+    /// The entry length value which marks the start of the trailer.
+    ///
+    /// Entries must be shorter than this value (without continuation bit).
+    pub fn trailer_marker(self) -> u32 {
+        if self.entry_len_size() == 2 { 0xFF_FF } else { 0xFF_FF_FF_FF }
+    }
+
+    /// The bit of an entry length marking a continued entry.
+    pub fn continuation_bit(self) -> u32 {
+        if self.entry_len_size() == 2 { 0x80_00 } else { 0x80_00_00_00 }
+    }
+
+    /// The digest following every entry (version 3 only).
+    pub fn digest(self) -> Option<DigestKind> {
+        if self.version() < 3 {
+            return None
+        }
+        if self.flags() & FLAG_DIGEST_XXH64 != 0 {
+            Some(DigestKind::Xxh64)
+        } else if self.flags() & FLAG_DIGEST_BLAKE3 != 0 {
+            Some(DigestKind::Blake3)
+        } else {
+            None
+        }
+    }
+
+    /// Check if every entry is preceded by its metadata (version 3 only).
+    pub fn has_metadata(self) -> bool {
+        self.version() >= 3 && self.flags() & FLAG_METADATA != 0
+    }
+
+    /// Check if every entry has a schema ID (version 3 only).
+    pub fn has_schema(self) -> bool {
+        self.version() >= 3 && self.flags() & FLAG_SCHEMA != 0
+    }
+
+    /// Check if all flags are known and valid for the header version.
+    ///
+    /// Readers parse the entries of every block according to its header,
+    /// so blocks of different versions can be mixed in a directory, but
+    /// unknown flags may change the framing in ways they do not understand.
+    pub fn is_supported(self) -> bool {
+        let known = match self.version() {
+            1 | 2 => FLAG_CHUNKED | FLAG_TRAILER,
+            3     => FLAG_CHUNKED | FLAG_TRAILER | FLAG_DIGEST_XXH64 | FLAG_DIGEST_BLAKE3 | FLAG_METADATA | FLAG_SCHEMA,
+            _     => return false
+        };
+        let digests = FLAG_DIGEST_XXH64 | FLAG_DIGEST_BLAKE3;
+        self.flags() & !known == 0 && self.flags() & digests != digests
+    }
+
+    /// Number of bytes used to encode the length of an entry.
+    ///
+    /// Version 1 uses a `u16`, versions 2 and 3 a `u32` entry length.
+    pub fn entry_len_size(self) -> u8 {
+        if self.version() == 1 { 2 } else { 4 }
+    }
+}
+
+/// Summary of a complete block.
+///
+/// The trailer follows the last entry. It consists of the trailer marker
+/// (cf. `BlockHeader::trailer_marker`), the number of entries as `u64` and
+/// the CRC32C of all bytes between block header and trailer as `u32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trailer {
+    entries: u64,
+    crc: u32
+}
+
+impl Trailer {
+    /// Length of the trailer without the marker.
+    pub(crate) const LEN: u64 = 12;
+
+    pub(crate) fn new(entries: u64, crc: u32) -> Self {
+        Self { entries, crc }
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
 }
 
 #[derive(Debug)]
@@ -70,6 +196,10 @@ impl<F> Block<F> {
     pub fn file_mut(&mut self) -> &mut F {
         &mut self.file
     }
+
+    pub fn into_file(self) -> F {
+        self.file
+    }
 }
 
 #[derive(Debug, Clone, Copy, Encode, Decode, PartialEq, Eq, PartialOrd, Ord)]
@@ -154,8 +284,23 @@ impl BlockNum {
         self.0
     }
 
+    /// Add to this block number, saturating at the maximum.
     pub fn add<N: Into<u64>>(&self, n: N) -> BlockNum {
-        Self(self.0 + n.into())
+        self.saturating_add(n)
+    }
+
+    pub fn saturating_add<N: Into<u64>>(&self, n: N) -> BlockNum {
+        Self(self.0.saturating_add(n.into()))
+    }
+
+    /// Add to this block number, returning `None` on overflow.
+    pub fn checked_add<N: Into<u64>>(&self, n: N) -> Option<BlockNum> {
+        self.0.checked_add(n.into()).map(Self)
+    }
+
+    /// The block number following this one, if any.
+    pub fn next(&self) -> Option<BlockNum> {
+        self.checked_add(1u8)
     }
 }
 
@@ -168,11 +313,24 @@ impl From<u64> for BlockNum {
 #[cfg(test)]
 mod tests {
     use quickcheck::quickcheck;
-    use super::BlockHeader;
+    use super::{BlockHeader, BlockNum};
 
     quickcheck! {
         fn header_version(v: u8) -> bool {
             v == BlockHeader::new().with_version(v).version()
         }
+
+        fn block_num_checked_add(a: u64, b: u64) -> bool {
+            let n = BlockNum::from(a);
+            match a.checked_add(b) {
+                Some(c) => n.checked_add(b) == Some(BlockNum::from(c)) && n.saturating_add(b).value() == c,
+                None    => n.checked_add(b).is_none() && n.saturating_add(b).value() == u64::MAX
+            }
+        }
+
+        fn header_flags(v: u8, f: u16) -> bool {
+            let h = BlockHeader::new().with_version(v).with_flags(f);
+            h.version() == v && h.flags() == f && BlockHeader::from_u64(h.to_u64()).is_some()
+        }
     }
 }
@@ -0,0 +1,209 @@
+use std::{io, path::{Path, PathBuf}};
+
+use tokio::fs;
+use tracing::debug;
+
+use super::{BlockMeta, BlockNames, EntryReader, EntryWriter, ReadError, WriteError};
+use super::{list_blocks_with, portable, remove_empty_shards, seal_file, temp_path, writable_block};
+use super::block::{BlockHeader, BlockInfo, BlockNum};
+use super::reader::read_header;
+
+/// Options for `compact_blocks_with`.
+#[derive(Debug, Clone)]
+pub struct CompactOptions {
+    max_block_len: u64,
+    durable_metadata: bool,
+    block_names: BlockNames
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        Self {
+            max_block_len: 16 * 1024 * 1024,
+            durable_metadata: false,
+            block_names: BlockNames::default()
+        }
+    }
+}
+
+impl CompactOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum length of a compacted block.
+    ///
+    /// Only blocks whose lengths add up to at most this value are merged.
+    pub fn with_max_block_len(mut self, val: u64) -> Self {
+        self.max_block_len = val;
+        self
+    }
+
+    /// Sync compacted blocks and the directory.
+    pub fn with_durable_metadata(mut self, val: bool) -> Self {
+        self.durable_metadata = val;
+        self
+    }
+
+    pub fn with_block_names(mut self, n: BlockNames) -> Self {
+        self.block_names = n;
+        self
+    }
+}
+
+/// The outcome of compacting blocks.
+#[derive(Debug, Default)]
+pub struct CompactSummary {
+    merged: Vec<BlockNum>,
+    removed: Vec<BlockNum>
+}
+
+impl CompactSummary {
+    /// The blocks which have been replaced by a merged block.
+    pub fn merged(&self) -> &[BlockNum] {
+        &self.merged
+    }
+
+    /// The blocks whose entries have been merged into a later block.
+    pub fn removed(&self) -> &[BlockNum] {
+        &self.removed
+    }
+}
+
+pub async fn compact_blocks<P>(dir: P, to: BlockNum) -> Result<CompactSummary, CompactError>
+where
+    P: AsRef<Path>
+{
+    compact_blocks_with(dir, to, &CompactOptions::default()).await
+}
+
+/// Merge adjacent blocks with a number less than `to` into fewer blocks.
+///
+/// Only blocks which no reader still positions into should be compacted,
+/// usually those before the `forwarded` mark. The latest block is never
+/// included. Adjacent blocks with the same header are written into a new
+/// block, which replaces the last of them, and the others are removed.
+///
+/// The merged block keeps the number and modification time of the block
+/// it replaces, so that marks like `forwarded` and retention by age remain
+/// valid, and a `LogReader` positioned in a removed block continues at the
+/// start of the merged block. Readers which have opened a block before it
+/// was replaced keep reading its previous contents. If compaction is
+/// interrupted, entries may exist twice but are never lost.
+pub async fn compact_blocks_with<P>(dir: P, to: BlockNum, opts: &CompactOptions) -> Result<CompactSummary, CompactError>
+where
+    P: AsRef<Path>
+{
+    let path = dir.as_ref();
+    let lock_path = path.join(opts.block_names.compact_lock_file_name());
+    let Some(_lock) = portable::try_lock(&lock_path).await? else {
+        return Err(CompactError::Locked(lock_path))
+    };
+    let mut blocks = list_blocks_with(path, &opts.block_names).await?;
+    let to = to.min(writable_block(&blocks));
+    blocks.retain(|b| b.number < to);
+
+    let mut runs = Vec::new();
+    let mut run: Vec<BlockMeta> = Vec::new();
+    let mut current = None;
+    let mut len = 0;
+    for b in blocks {
+        let header = if b.size < opts.max_block_len { block_header(&b.path).await } else { None };
+        let fits = header.is_some()
+            && current.map(BlockHeader::to_u64) == header.map(BlockHeader::to_u64)
+            && len + b.size <= opts.max_block_len;
+        if !fits {
+            runs.push((current, std::mem::take(&mut run)));
+            len = 0
+        }
+        current = header;
+        if header.is_some() {
+            len += b.size;
+            run.push(b)
+        }
+    }
+    runs.push((current, run));
+
+    let mut summary = CompactSummary::default();
+    let mut removed = Vec::new();
+    for (header, run) in runs {
+        let Some(header) = header.filter(|_| run.len() > 1) else {
+            continue
+        };
+        merge(&run, header, opts.durable_metadata).await?;
+        let n = run.len() - 1;
+        for b in &run[.. n] {
+            portable::remove_file(&b.path).await?;
+            summary.removed.push(b.number)
+        }
+        debug!(first = %run[0].number, last = %run[n].number, blocks = run.len(), "compacted blocks");
+        summary.merged.push(run[n].number);
+        removed.extend(run.into_iter().take(n))
+    }
+    if removed.is_empty() {
+        return Ok(summary)
+    }
+    let shards = remove_empty_shards(path, &removed).await;
+    if opts.durable_metadata {
+        for s in shards {
+            portable::sync_dir(s).await?
+        }
+        portable::sync_dir(path).await?
+    }
+    Ok(summary)
+}
+
+/// Read the header of a block, if it has a valid one.
+async fn block_header(path: &Path) -> Option<BlockHeader> {
+    let mut file = fs::File::open(path).await.ok()?;
+    match read_header(&mut file).await {
+        Ok(h) => Some(h),
+        Err(err) => {
+            debug!(?path, %err, "not compacting block");
+            None
+        }
+    }
+}
+
+/// Write the entries of all blocks into a temporary file which then
+/// replaces the last block.
+async fn merge(run: &[BlockMeta], header: BlockHeader, durable: bool) -> Result<(), CompactError> {
+    let last = run.last().expect("run is not empty");
+    let tmp = temp_path(&last.path, "compact");
+    if let Err(e) = write_merged(run, header, &tmp, durable).await {
+        let _ = portable::remove_file(&tmp).await;
+        return Err(e)
+    }
+    portable::rename(&tmp, &last.path).await?;
+    Ok(())
+}
+
+async fn write_merged(run: &[BlockMeta], header: BlockHeader, tmp: &Path, durable: bool) -> Result<(), CompactError> {
+    let file = fs::File::create(tmp).await?;
+    let mut w = EntryWriter::from_writer_with_header(file, header).await?;
+    for b in run {
+        let file = fs::File::open(&b.path).await?;
+        let mut r = EntryReader::from_reader(file, BlockInfo::zero().with_number(b.number)).await?;
+        while let Some((entry, _)) = r.next_entry().await? {
+            w.append_entries([(&entry[..], r.metadata(), r.schema())]).await?
+        }
+    }
+    let file = w.finish().await?;
+    seal_file(file, run.last().and_then(|b| b.modified), durable).await?;
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompactError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+
+    #[error("write error: {0}")]
+    Write(#[from] WriteError),
+
+    #[error("directory is being compacted: {0:?}")]
+    Locked(PathBuf)
+}
@@ -0,0 +1,64 @@
+//! Process-wide counters of storage operations.
+//!
+//! Counting only happens with feature `bench`, otherwise `count` is a no-op.
+
+#[cfg(feature = "bench")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Counter {
+    EntriesWritten,
+    BytesWritten,
+    WriteCalls,
+    Syncs,
+    Blocks,
+    EntriesRead,
+    BytesRead
+}
+
+#[cfg(feature = "bench")]
+static COUNTERS: [AtomicU64; 7] = [const { AtomicU64::new(0) }; 7];
+
+#[inline]
+pub(crate) fn count(_c: Counter, _n: u64) {
+    #[cfg(feature = "bench")]
+    COUNTERS[_c as usize].fetch_add(_n, Ordering::Relaxed);
+}
+
+/// A snapshot of the storage counters, cf. `counters`.
+#[cfg(feature = "bench")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub entries_written: u64,
+    pub bytes_written: u64,
+    /// Number of vectored writes to (buffered) block files.
+    pub write_calls: u64,
+    pub syncs: u64,
+    /// Number of blocks started after the first one.
+    pub blocks: u64,
+    pub entries_read: u64,
+    pub bytes_read: u64
+}
+
+/// Get the current values of all storage counters.
+#[cfg(feature = "bench")]
+pub fn counters() -> Counters {
+    let get = |c: Counter| COUNTERS[c as usize].load(Ordering::Relaxed);
+    Counters {
+        entries_written: get(Counter::EntriesWritten),
+        bytes_written: get(Counter::BytesWritten),
+        write_calls: get(Counter::WriteCalls),
+        syncs: get(Counter::Syncs),
+        blocks: get(Counter::Blocks),
+        entries_read: get(Counter::EntriesRead),
+        bytes_read: get(Counter::BytesRead)
+    }
+}
+
+/// Set all storage counters to zero.
+#[cfg(feature = "bench")]
+pub fn reset_counters() {
+    for c in &COUNTERS {
+        c.store(0, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,87 @@
+use minicbor::{Encode, Decode};
+
+/// A digest algorithm for block entries, stronger than their CRC32C.
+///
+/// Each algorithm requires the corresponding crate feature (`xxhash` or
+/// `blake3`) to compute digests. Blocks with digests can be read without
+/// it, but their digests are not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cbor(index_only)]
+pub enum DigestKind {
+    #[n(0)] Xxh64,
+    #[n(1)] Blake3
+}
+
+/// The digest of an entry, cf. `Config::with_digest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Digest {
+    #[n(0)] Xxh64(#[n(0)] u64),
+    #[n(1)] Blake3(#[cbor(n(0), with = "minicbor::bytes")] [u8; 32])
+}
+
+impl DigestKind {
+    /// The length of an encoded digest in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Xxh64  => 8,
+            Self::Blake3 => 32
+        }
+    }
+
+    /// Check if digests of this kind can be computed.
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Self::Xxh64  => cfg!(feature = "xxhash"),
+            Self::Blake3 => cfg!(feature = "blake3")
+        }
+    }
+
+    /// Compute the digest of the given data.
+    ///
+    /// Returns `None` if the algorithm is not enabled.
+    #[cfg_attr(not(all(feature = "xxhash", feature = "blake3")), allow(unreachable_code, unused_variables))]
+    pub fn compute(self, data: &[u8]) -> Option<Digest> {
+        match self {
+            #[cfg(feature = "xxhash")]
+            Self::Xxh64  => Some(Digest::Xxh64(xxhash_rust::xxh64::xxh64(data, 0))),
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => Some(Digest::Blake3(*blake3::hash(data).as_bytes())),
+            #[allow(unreachable_patterns)]
+            _ => None
+        }
+    }
+
+    /// Decode a digest of this kind, cf. `Digest::write_to`.
+    pub(crate) fn decode(self, bytes: &[u8]) -> Option<Digest> {
+        match self {
+            Self::Xxh64  => Some(Digest::Xxh64(u64::from_be_bytes(bytes.try_into().ok()?))),
+            Self::Blake3 => Some(Digest::Blake3(bytes.try_into().ok()?))
+        }
+    }
+}
+
+impl Digest {
+    pub fn kind(&self) -> DigestKind {
+        match self {
+            Self::Xxh64(_)  => DigestKind::Xxh64,
+            Self::Blake3(_) => DigestKind::Blake3
+        }
+    }
+
+    /// Check the digest against the given data.
+    ///
+    /// Returns `None` if the algorithm is not enabled.
+    pub fn verify(&self, data: &[u8]) -> Option<bool> {
+        self.kind().compute(data).map(|d| d == *self)
+    }
+
+    /// Append the encoded digest to the given buffer.
+    pub(crate) fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Xxh64(n)  => buf.extend_from_slice(&n.to_be_bytes()),
+            Self::Blake3(b) => buf.extend_from_slice(b)
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use futures_util::{Stream, stream};
+
+use crate::{BlockInfo, EntryReader, ReadError};
+use super::{BlockNames, Metadata};
+use super::store::{BlockStore, FileStore};
+
+/// Reads the entries of all blocks in a directory, in order.
+///
+/// In contrast to `EntryReader`, which reads a single block, a `LogReader`
+/// continues with the next block once a block has been read completely.
+#[derive(Debug)]
+pub struct LogReader {
+    store: Box<dyn BlockStore>,
+    position: BlockInfo,
+    reader: Option<EntryReader>
+}
+
+impl LogReader {
+    /// Read entries starting at the given position.
+    ///
+    /// If the block of `start` does not exist, reading starts with the
+    /// next block after it.
+    pub fn new<P: AsRef<Path>>(dir: P, start: BlockInfo) -> Self {
+        Self::new_with(dir, start, BlockNames::default())
+    }
+
+    /// Like `LogReader::new` for block files named according to `names`.
+    pub fn new_with<P: AsRef<Path>>(dir: P, start: BlockInfo, names: BlockNames) -> Self {
+        Self::new_in(FileStore::new(dir).with_block_names(names), start)
+    }
+
+    /// Like `LogReader::new` for blocks of the given store.
+    pub fn new_in<S: BlockStore>(store: S, start: BlockInfo) -> Self {
+        Self {
+            store: Box::new(store),
+            position: start,
+            reader: None
+        }
+    }
+
+    /// The position of the next entry.
+    pub fn position(&self) -> BlockInfo {
+        self.position
+    }
+
+    /// The metadata of the entry last returned, if the block has metadata.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.reader.as_ref().and_then(EntryReader::metadata)
+    }
+
+    /// The schema ID of the entry last returned, if the block has schema IDs.
+    pub fn schema(&self) -> Option<u32> {
+        self.reader.as_ref().and_then(EntryReader::schema)
+    }
+
+    /// Read the next entry together with its position.
+    ///
+    /// Returns `None` if all existing entries have been read. Entries which
+    /// are appended later are returned by subsequent calls.
+    pub async fn next_entry(&mut self) -> Result<Option<(BlockInfo, Bytes)>, ReadError> {
+        loop {
+            if let Some(r) = &mut self.reader {
+                let pos = r.block_info();
+                let entry = r.next_entry().await;
+                self.position = r.block_info();
+                if let Some((bytes, _)) = entry? {
+                    return Ok(Some((pos, bytes)))
+                }
+            }
+            let Some(next) = self.next_block().await? else {
+                return Ok(None)
+            };
+            self.reader = Some(EntryReader::open_in(&*self.store, next).await?);
+            self.position = next
+        }
+    }
+
+    /// Find the block to read next, if any.
+    async fn next_block(&self) -> Result<Option<BlockInfo>, ReadError> {
+        let current = self.position.number();
+        let blocks = self.store.list().await?;
+        if self.reader.is_none() && blocks.iter().any(|b| b.0 == current) {
+            return Ok(Some(self.position))
+        }
+        let next = blocks.iter().find(|b| b.0 > current);
+        Ok(next.map(|b| BlockInfo::zero().with_number(b.0)))
+    }
+
+    /// Turn this reader into a stream of entries and their positions.
+    ///
+    /// The stream ends once all existing entries have been read or after
+    /// the first error.
+    pub fn into_stream(self) -> impl Stream<Item = Result<(BlockInfo, Bytes), ReadError>> {
+        stream::unfold(Some(self), |this| async move {
+            let mut this = this?;
+            match this.next_entry().await {
+                Ok(Some(e)) => Some((Ok(e), Some(this))),
+                Ok(None)    => None,
+                Err(e)      => Some((Err(e), None))
+            }
+        })
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use minicbor::{Encode, Decode};
+
+/// Key-value metadata of an entry, e.g. tenant, severity or source host.
+///
+/// Metadata is stored in front of the entry data (cf. `Config::with_entry_metadata`)
+/// and forwarded with every `Record`, so it can be inspected without
+/// decoding the entry itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(transparent)]
+pub struct Metadata(#[n(0)] BTreeMap<String, String>);
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<K: Into<String>, V: Into<String>>(mut self, key: K, val: V) -> Self {
+        self.insert(key, val);
+        self
+    }
+
+    /// Set a key to the given value, returning the previous one.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, val: V) -> Option<String> {
+        self.0.insert(key.into(), val.into())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        minicbor::to_vec(self).expect("writing to a vec never fails")
+    }
+
+    pub(crate) fn from_bytes(b: &[u8]) -> Option<Self> {
+        minicbor::decode(b).ok()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Metadata {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
@@ -0,0 +1,243 @@
+use std::{cmp::max, io, path::{Path, PathBuf}};
+
+use tokio::fs;
+use tracing::debug;
+
+use super::{BlockMeta, Config, EntryReader, EntryWriter, Metadata, ReadError, WriteError};
+use super::{list_blocks_with, portable, seal_file, sync_dir, temp_path};
+use super::block::{BlockHeader, BlockInfo, BlockNum};
+use super::block::{FLAG_DIGEST_BLAKE3, FLAG_DIGEST_XXH64, FLAG_METADATA, FLAG_SCHEMA};
+use super::reader::read_header;
+use super::writer::block_header;
+use crate::retention::forwarded_with;
+
+/// Flags which require a version 3 header.
+const FLAGS_V3: u16 = FLAG_DIGEST_XXH64 | FLAG_DIGEST_BLAKE3 | FLAG_METADATA | FLAG_SCHEMA;
+
+/// Options for `migrate_blocks_with`.
+#[derive(Debug, Clone, Default)]
+pub struct MigrateOptions {
+    config: Config,
+    version: u8,
+    target: Option<PathBuf>,
+    force: bool
+}
+
+impl MigrateOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The block format to migrate to and the block names to use.
+    ///
+    /// Blocks are upgraded to the header `cfg` would write, without
+    /// dropping features (like metadata) they already have. With
+    /// `Config::with_durable_metadata` migrated blocks are synced.
+    pub fn with_config(mut self, cfg: Config) -> Self {
+        self.config = cfg;
+        self
+    }
+
+    /// The minimum header version of migrated blocks, e.g. 2 for `u32`
+    /// entry lengths.
+    pub fn with_min_version(mut self, v: u8) -> Self {
+        self.version = v;
+        self
+    }
+
+    /// Write migrated blocks into another directory instead of replacing
+    /// them in place.
+    pub fn with_target_dir<P: AsRef<Path>>(mut self, dir: Option<P>) -> Self {
+        self.target = dir.map(|d| d.as_ref().to_path_buf());
+        self
+    }
+
+    /// Also migrate blocks which have not been forwarded yet.
+    ///
+    /// Migration moves entries within blocks, so positions into these
+    /// blocks, as persisted by forwarders and receivers, become invalid.
+    /// Only use this if the directory is not forwarded.
+    pub fn with_force(mut self, val: bool) -> Self {
+        self.force = val;
+        self
+    }
+}
+
+/// The outcome of migrating blocks.
+#[derive(Debug, Default)]
+pub struct MigrateSummary {
+    migrated: Vec<BlockNum>,
+    skipped: Vec<BlockNum>,
+    pending: Vec<BlockNum>
+}
+
+impl MigrateSummary {
+    /// The blocks which have been rewritten in the new format.
+    pub fn migrated(&self) -> &[BlockNum] {
+        &self.migrated
+    }
+
+    /// The blocks which already had the new format.
+    ///
+    /// When migrating into a target directory, they are copied unchanged.
+    pub fn skipped(&self) -> &[BlockNum] {
+        &self.skipped
+    }
+
+    /// The blocks which have not been migrated because they have not been
+    /// forwarded yet, cf. `MigrateOptions::with_force`.
+    ///
+    /// When migrating into a target directory, they are copied unchanged.
+    pub fn pending(&self) -> &[BlockNum] {
+        &self.pending
+    }
+}
+
+/// Rewrite all blocks of a directory in a newer block format.
+///
+/// Every block is written to a temporary file, whose entries are then
+/// compared with the original ones before it atomically replaces the
+/// block (or is moved into the target directory). Block numbers and
+/// modification times are kept. No writer or forwarder may use the
+/// directory while it is migrated, otherwise `MigrateError::Locked` is
+/// returned.
+///
+/// Forwarders and receivers persist positions within blocks which have
+/// not been forwarded completely, so only blocks before the `forwarded`
+/// mark are migrated, unless forced.
+pub async fn migrate_blocks_with<P>(dir: P, opts: &MigrateOptions) -> Result<MigrateSummary, MigrateError>
+where
+    P: AsRef<Path>
+{
+    let path = dir.as_ref();
+    let names = &opts.config.block_names;
+    let mut locks = Vec::new();
+    for d in std::iter::once(path).chain(opts.target.as_deref()) {
+        fs::create_dir_all(d).await?;
+        for name in [names.lock_file_name(), names.forwarder_lock_file_name()] {
+            let lock_path = d.join(name);
+            let Some(lock) = portable::try_lock(&lock_path).await? else {
+                return Err(MigrateError::Locked(lock_path))
+            };
+            locks.push(lock)
+        }
+    }
+    let forwarded =
+        if opts.force {
+            None
+        } else {
+            Some(forwarded_with(path, names).await?.unwrap_or_else(BlockNum::zero))
+        };
+    let target = block_header(&opts.config);
+    let mut summary = MigrateSummary::default();
+    for b in list_blocks_with(path, names).await? {
+        let header = {
+            let mut file = fs::File::open(&b.path).await?;
+            read_header(&mut file).await?
+        };
+        let upgraded = upgrade(header, target, opts.version);
+        let dest = match &opts.target {
+            Some(t) => t.join(b.path.strip_prefix(path).expect("block is in directory")),
+            None    => b.path.clone()
+        };
+        let pending = forwarded.is_some_and(|n| b.number >= n);
+        if pending || upgraded.to_u64() == header.to_u64() {
+            if dest != b.path {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent).await?
+                }
+                fs::copy(&b.path, &dest).await?;
+            }
+            if pending {
+                summary.pending.push(b.number)
+            } else {
+                summary.skipped.push(b.number)
+            }
+            continue
+        }
+        migrate(&b, upgraded, &dest, opts.config.durable_metadata).await?;
+        debug!(number = %b.number, from = header.version(), to = upgraded.version(), "migrated block");
+        summary.migrated.push(b.number)
+    }
+    if opts.config.durable_metadata {
+        sync_dir(opts.target.as_deref().unwrap_or(path)).await?
+    }
+    Ok(summary)
+}
+
+/// The header to migrate a block with header `h` to, given the header of
+/// the target format and its minimum version.
+fn upgrade(h: BlockHeader, target: BlockHeader, version: u8) -> BlockHeader {
+    let mut flags = h.flags() | target.flags();
+    if target.digest().is_some() {
+        flags = flags & !(FLAG_DIGEST_XXH64 | FLAG_DIGEST_BLAKE3) | target.flags()
+    }
+    let v3 = if flags & FLAGS_V3 != 0 { 3 } else { 1 };
+    let v = max(max(h.version(), target.version()), max(version, v3));
+    BlockHeader::new().with_flags(flags).with_version(v)
+}
+
+async fn migrate(b: &BlockMeta, header: BlockHeader, dest: &Path, durable: bool) -> Result<(), MigrateError> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).await?
+    }
+    let tmp = temp_path(dest, "migrate");
+    let result = async {
+        let file = fs::File::create(&tmp).await?;
+        let mut w = EntryWriter::from_writer_with_header(file, header).await?;
+        let mut r = open(&b.path, b.number).await?;
+        while let Some((entry, _)) = r.next_entry().await? {
+            w.append_entries([(&entry[..], r.metadata(), r.schema())]).await?
+        }
+        seal_file(w.finish().await?, b.modified, durable).await?;
+        verify(&b.path, &tmp, b.number).await
+    };
+    if let Err(e) = result.await {
+        let _ = portable::remove_file(&tmp).await;
+        return Err(e)
+    }
+    portable::rename(&tmp, dest).await?;
+    Ok(())
+}
+
+/// Check that a migrated block contains the same entries as the original.
+async fn verify(original: &Path, migrated: &Path, n: BlockNum) -> Result<(), MigrateError> {
+    let mut a = open(original, n).await?;
+    let mut b = open(migrated, n).await?;
+    loop {
+        let pos = a.block_info();
+        match (a.next_entry().await?, b.next_entry().await?) {
+            (None, None) => return Ok(()),
+            (Some(x), Some(y)) if x.0 == y.0 && meta(&a) == meta(&b) && a.schema() == b.schema() => continue,
+            _ => return Err(MigrateError::Mismatch(pos))
+        }
+    }
+}
+
+/// The metadata of the last entry, which is empty if the block has none.
+fn meta(r: &EntryReader<fs::File>) -> Option<&Metadata> {
+    r.metadata().filter(|m| !m.is_empty())
+}
+
+async fn open(path: &Path, n: BlockNum) -> Result<EntryReader<fs::File>, ReadError> {
+    let file = fs::File::open(path).await?;
+    EntryReader::from_reader(file, BlockInfo::zero().with_number(n)).await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrateError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+
+    #[error("write error: {0}")]
+    Write(#[from] WriteError),
+
+    #[error("directory is locked: {0:?}")]
+    Locked(PathBuf),
+
+    #[error("migrated block differs from original at {0}")]
+    Mismatch(BlockInfo)
+}
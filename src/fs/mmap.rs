@@ -0,0 +1,205 @@
+use std::{fs::File, path::Path};
+
+use bytes::{Bytes, BytesMut};
+use memmap2::Mmap;
+
+use crate::{CRC32C, BlockInfo};
+use super::{block::{BlockHeader, Trailer}, block_path, reader::{ReadError, check_header}, schema, BlockNames, Digest, Metadata};
+
+/// An `EntryReader` alternative which reads blocks via memory maps.
+///
+/// Entries which consist of a single frame are returned without copying.
+/// Block files must not be truncated while they are mapped.
+#[derive(Debug)]
+pub struct MmapEntryReader {
+    file: File,
+    data: Bytes,
+    header: BlockHeader,
+    info: BlockInfo,
+    trailer: Option<Trailer>,
+    digest: Option<Digest>,
+    metadata: Option<Metadata>,
+    schema: Option<u32>
+}
+
+impl MmapEntryReader {
+    pub async fn open<P>(dir: P, info: BlockInfo) -> Result<Self, ReadError>
+    where
+        P: AsRef<Path>
+    {
+        Self::open_with(dir, info, &BlockNames::default()).await
+    }
+
+    /// Like `MmapEntryReader::open` for block files named according to `names`.
+    pub async fn open_with<P>(dir: P, info: BlockInfo, names: &BlockNames) -> Result<Self, ReadError>
+    where
+        P: AsRef<Path>
+    {
+        let path = block_path(dir.as_ref(), names, info.number()).await;
+        let file = tokio::fs::File::open(path).await?.into_std().await;
+        let data = map(&file)?;
+        let Some(h) = data.get(.. 8) else {
+            return Err(ReadError::Header(None))
+        };
+        let header = check_header(u64::from_be_bytes(h.try_into().expect("8 bytes")))?;
+        let info = if info.offset() == 0 { info.with_offset(8u8) } else { info };
+        Ok(Self { file, data, header, info, trailer: None, digest: None, metadata: None, schema: None })
+    }
+
+    pub fn block_info(&self) -> BlockInfo {
+        self.info
+    }
+
+    /// The block trailer, once it has been reached.
+    pub fn trailer(&self) -> Option<Trailer> {
+        self.trailer
+    }
+
+    /// The digest of the entry last returned, if the block has digests.
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
+    /// The metadata of the entry last returned, if the block has metadata.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The schema ID of the entry last returned, if the block has schema IDs.
+    pub fn schema(&self) -> Option<u32> {
+        self.schema
+    }
+
+    pub async fn next_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        if let Some(entry) = self.read_entry()? {
+            return Ok(Some(entry))
+        }
+        if self.trailer.is_some() {
+            return Ok(None)
+        }
+        // The file may have grown since it was mapped.
+        if self.file.metadata()?.len() > self.data.len() as u64 {
+            self.data = map(&self.file)?;
+            return self.read_entry()
+        }
+        Ok(None)
+    }
+
+    /// Read the entry at the current offset, if it is complete.
+    fn read_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        if self.trailer.is_some() {
+            return Ok(None)
+        }
+        let size = usize::from(self.header.entry_len_size());
+        let more = if self.header.is_chunked() { self.header.continuation_bit() } else { 0 };
+        let start = self.info.offset() as usize;
+        let mut pos = start;
+        let mut frames = Vec::new();
+        let mut metadata = None;
+        let mut schema = None;
+        self.digest = None;
+        self.metadata = None;
+        self.schema = None;
+        loop {
+            let Some(len) = self.data.get(pos .. pos + size) else {
+                return Ok(None)
+            };
+            let len =
+                if size == 2 {
+                    u32::from(u16::from_be_bytes([len[0], len[1]]))
+                } else {
+                    u32::from_be_bytes([len[0], len[1], len[2], len[3]])
+                };
+            if frames.is_empty() && self.header.has_trailer() && len == self.header.trailer_marker() {
+                let Some(t) = self.data.get(pos + size .. pos + size + Trailer::LEN as usize) else {
+                    return Ok(None)
+                };
+                let entries = u64::from_be_bytes(t[.. 8].try_into().expect("8 bytes"));
+                let crc = u32::from_be_bytes(t[8 ..].try_into().expect("4 bytes"));
+                self.info.add_offset(size as u64 + Trailer::LEN);
+                self.trailer = Some(Trailer::new(entries, crc));
+                return Ok(None)
+            }
+            let data_start = pos + size;
+            let data_end = data_start + if metadata.is_none() && self.header.has_metadata() {
+                len as usize
+            } else {
+                (len & !more) as usize
+            };
+            let Some(crc) = self.data.get(data_end .. data_end + 4) else {
+                return Ok(None)
+            };
+            let crc = u32::from_be_bytes(crc.try_into().expect("4 bytes"));
+            pos = data_end + 4;
+            if crc != CRC32C.checksum(&self.data[data_start .. data_end]) {
+                let at = self.info;
+                self.info.add_offset((pos - start) as u64);
+                return Err(ReadError::Crc(at))
+            }
+            if metadata.is_none() && self.header.has_metadata() {
+                let Some(m) = Metadata::from_bytes(&self.data[data_start .. data_end]) else {
+                    let at = self.info;
+                    self.info.add_offset((pos - start) as u64);
+                    return Err(ReadError::Metadata(at))
+                };
+                metadata = Some(m);
+                continue
+            }
+            if frames.is_empty() && self.header.has_schema() {
+                let Some((id, n)) = schema::decode_id(&self.data[data_start .. data_end]) else {
+                    let at = self.info;
+                    self.info.add_offset((pos - start) as u64);
+                    return Err(ReadError::Schema(at))
+                };
+                schema = Some(id);
+                frames.push((data_start + n, data_end, crc));
+                if len & more == 0 {
+                    break
+                }
+                continue
+            }
+            frames.push((data_start, data_end, crc));
+            if len & more == 0 {
+                break
+            }
+        }
+        let at = self.info;
+        let digest = match self.header.digest() {
+            Some(kind) => {
+                let Some(d) = self.data.get(pos .. pos + kind.digest_len()) else {
+                    return Ok(None)
+                };
+                pos += kind.digest_len();
+                kind.decode(d)
+            }
+            None => None
+        };
+        self.info.add_offset((pos - start) as u64);
+        let (entry, crc) = if let [(a, b, crc)] = frames[..] {
+            // The CRC of the frame includes the schema ID.
+            let crc = if schema.is_some() { CRC32C.checksum(&self.data[a .. b]) } else { crc };
+            (self.data.slice(a .. b), crc)
+        } else {
+            let mut entry = BytesMut::new();
+            for (a, b, _) in frames {
+                entry.extend_from_slice(&self.data[a .. b])
+            }
+            let crc = CRC32C.checksum(&entry);
+            (entry.freeze(), crc)
+        };
+        if digest.and_then(|d| d.verify(&entry)) == Some(false) {
+            return Err(ReadError::Digest(at))
+        }
+        self.digest = digest;
+        self.metadata = metadata;
+        self.schema = schema;
+        Ok(Some((entry, crc)))
+    }
+}
+
+fn map(file: &File) -> Result<Bytes, ReadError> {
+    // SAFETY: Block files are only ever appended to. Modifications of the
+    // mapped range are not expected while the map exists.
+    let m = unsafe { Mmap::map(file)? };
+    Ok(Bytes::from_owner(m))
+}
@@ -0,0 +1,109 @@
+use std::ffi::OsStr;
+
+use super::BlockNum;
+
+/// How block files are named: `<prefix><number><suffix>`.
+///
+/// The default is `block.<number>`. Different prefixes allow several
+/// independent logs to share a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct BlockNames {
+    prefix: String,
+    suffix: String
+}
+
+impl Default for BlockNames {
+    fn default() -> Self {
+        Self { prefix: "block.".into(), suffix: String::new() }
+    }
+}
+
+impl BlockNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_prefix<S: Into<String>>(mut self, p: S) -> Self {
+        self.prefix = p.into();
+        self
+    }
+
+    /// A suffix such as `.blk`, appended after the block number.
+    pub fn with_suffix<S: Into<String>>(mut self, s: S) -> Self {
+        self.suffix = s.into();
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    /// The file name of the given block.
+    pub fn file_name(&self, n: BlockNum) -> String {
+        format!("{}{}{}", self.prefix, n.value(), self.suffix)
+    }
+
+    /// The name of the file a writer locks, cf. `WriteError::Locked`.
+    pub(crate) fn lock_file_name(&self) -> String {
+        format!("{}lock{}", self.prefix, self.suffix)
+    }
+
+    /// The name of the file locked while blocks are compacted, cf. `compact_blocks_with`.
+    pub(crate) fn compact_lock_file_name(&self) -> String {
+        format!("{}compact{}", self.prefix, self.suffix)
+    }
+
+    /// The name of the file with the positions destinations have acknowledged.
+    pub(crate) fn cursors_file_name(&self) -> String {
+        format!("{}cursors{}", self.prefix, self.suffix)
+    }
+
+    /// The name of the file with the first block not yet forwarded, cf. `forwarded_with`.
+    pub(crate) fn forwarded_file_name(&self) -> String {
+        format!("{}forwarded{}", self.prefix, self.suffix)
+    }
+
+    /// The name of the file a forwarder locks, cf. `Forwarder::with_shared_directory`.
+    pub(crate) fn forwarder_lock_file_name(&self) -> String {
+        format!("{}forwarder{}", self.prefix, self.suffix)
+    }
+
+    /// Parse the block number of a file name.
+    ///
+    /// Returns `None` if the name does not match or the number is invalid.
+    pub fn parse(&self, name: &OsStr) -> Option<BlockNum> {
+        let n = name.to_str()?.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)?;
+        if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+            return None
+        }
+        n.parse::<u64>().ok().map(BlockNum::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::{BlockNames, BlockNum};
+
+    #[test]
+    fn parse_file_names() {
+        let d = BlockNames::default();
+        assert_eq!(Some(BlockNum::from(42)), d.parse(OsStr::new("block.42")));
+        assert_eq!(None, d.parse(OsStr::new("block.")));
+        assert_eq!(None, d.parse(OsStr::new("block.4x")));
+        assert_eq!(None, d.parse(OsStr::new("block.99999999999999999999")));
+
+        let n = BlockNames::new().with_prefix("app-").with_suffix(".blk");
+        assert_eq!("app-7.blk", n.file_name(BlockNum::from(7)));
+        assert_eq!(Some(BlockNum::from(7)), n.parse(OsStr::new("app-7.blk")));
+        assert_eq!(None, n.parse(OsStr::new("app-7")));
+        assert_eq!(None, n.parse(OsStr::new("block.7")))
+    }
+}
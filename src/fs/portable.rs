@@ -0,0 +1,127 @@
+//! File system operations which behave differently across platforms.
+//!
+//! On Windows a file which is open in another process (e.g. a block being
+//! read by a forwarder or a virus scanner) can not always be removed or
+//! renamed right away. Such operations fail with `PermissionDenied` and
+//! are retried a few times before giving up.
+
+use std::{io, path::Path};
+use tokio::fs;
+
+#[cfg(windows)]
+const RETRIES: u32 = 5;
+
+#[cfg(windows)]
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Remove a file.
+pub(crate) async fn remove_file(path: &Path) -> io::Result<()> {
+    retry(|| fs::remove_file(path)).await
+}
+
+/// Rename a file, replacing the target if it exists.
+pub(crate) async fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    retry(|| fs::rename(from, to)).await
+}
+
+/// Move a file, copying it if source and target are on different devices.
+pub(crate) async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match rename(from, to).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to).await?;
+            remove_file(from).await
+        }
+        other => other
+    }
+}
+
+/// Sync a directory, i.e. make changes to its entries durable.
+#[cfg(unix)]
+pub(crate) async fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir).await?.sync_all().await
+}
+
+/// Directories can not be opened as files on this platform.
+#[cfg(not(unix))]
+pub(crate) async fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// An exclusive advisory lock of a file, released when dropped.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    _file: std::fs::File
+}
+
+/// Create the file if needed and lock it exclusively.
+///
+/// Returns `None` if the lock is held by another process or another open
+/// handle of this one. The lock is advisory, i.e. it only keeps out those
+/// who lock the file as well.
+pub(crate) async fn try_lock(path: &Path) -> io::Result<Option<FileLock>> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .await?
+        .into_std()
+        .await;
+    match file.try_lock() {
+        Ok(())                                 => Ok(Some(FileLock { _file: file })),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+        Err(std::fs::TryLockError::Error(e))   => Err(e)
+    }
+}
+
+/// The space in bytes available to unprivileged users on the file system
+/// containing `path`.
+///
+/// Returns `None` on platforms where this is not supported.
+#[cfg(target_os = "linux")]
+pub fn available_space<P: AsRef<Path>>(path: P) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+    let p = CString::new(path.as_ref().as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut st = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(p.as_ptr(), st.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    let st = unsafe { st.assume_init() };
+    // The field types differ between architectures.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(st.f_bavail as u64 * st.f_frsize as u64))
+}
+
+/// The available space is not determined on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn available_space<P: AsRef<Path>>(_: P) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(windows)]
+async fn retry<F, R>(mut f: F) -> io::Result<()>
+where
+    F: FnMut() -> R,
+    R: std::future::Future<Output = io::Result<()>>
+{
+    let mut n = 0;
+    loop {
+        match f().await {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && n < RETRIES => {
+                n += 1;
+                tokio::time::sleep(RETRY_DELAY * n).await
+            }
+            other => return other
+        }
+    }
+}
+
+#[cfg(not(windows))]
+async fn retry<F, R>(mut f: F) -> io::Result<()>
+where
+    F: FnMut() -> R,
+    R: std::future::Future<Output = io::Result<()>>
+{
+    f().await
+}
@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use tokio::{io::{AsyncRead, AsyncSeek}, spawn, sync::{mpsc, Notify}, task::JoinHandle};
+use std::sync::Arc;
+
+use crate::{BlockInfo, EntryReader, ReadError};
+use super::{Digest, Metadata};
+
+/// Reads entries of an `EntryReader` ahead of the consumer.
+///
+/// A separate task decodes up to a given number of entries into a queue
+/// while the consumer processes the current one, cf. `EntryReader::read_ahead`.
+/// Once the end of the block data has been reached, or reading fails, the
+/// task waits until `ReadAhead::next_entry` is called again, so that entries
+/// appended later are read like with the `EntryReader` itself.
+#[derive(Debug)]
+pub struct ReadAhead {
+    queue: mpsc::Receiver<Ahead>,
+    resume: Arc<Notify>,
+    task: JoinHandle<()>,
+    info: BlockInfo,
+    digest: Option<Digest>,
+    metadata: Option<Metadata>,
+    schema: Option<u32>,
+    /// The last item has been the end of data or an error.
+    stopped: bool
+}
+
+/// An item read ahead, with the reader state after reading it.
+#[derive(Debug)]
+struct Ahead {
+    entry: Result<Option<(Bytes, u32)>, ReadError>,
+    info: BlockInfo,
+    digest: Option<Digest>,
+    metadata: Option<Metadata>,
+    schema: Option<u32>
+}
+
+impl<R> EntryReader<R>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send + 'static
+{
+    /// Read up to `n` entries ahead in a separate task.
+    pub fn read_ahead(mut self, n: usize) -> ReadAhead {
+        let (tx, rx) = mpsc::channel(n.max(1));
+        let resume = Arc::new(Notify::new());
+        let info = self.block_info();
+        let task = spawn({
+            let resume = resume.clone();
+            async move {
+                loop {
+                    let entry = self.next_entry().await;
+                    let stop = !matches!(entry, Ok(Some(_)));
+                    let item = Ahead {
+                        entry,
+                        info: self.block_info(),
+                        digest: self.digest(),
+                        metadata: self.take_metadata(),
+                        schema: self.schema()
+                    };
+                    if tx.send(item).await.is_err() {
+                        break
+                    }
+                    if stop {
+                        resume.notified().await
+                    }
+                }
+            }
+        });
+        ReadAhead { queue: rx, resume, task, info, digest: None, metadata: None, schema: None, stopped: false }
+    }
+}
+
+impl ReadAhead {
+    /// The position after the entry last returned.
+    pub fn block_info(&self) -> BlockInfo {
+        self.info
+    }
+
+    /// The digest of the entry last returned, if the block has digests.
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
+    /// The metadata of the entry last returned, if the block has metadata.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The schema ID of the entry last returned, if the block has schema IDs.
+    pub fn schema(&self) -> Option<u32> {
+        self.schema
+    }
+
+    /// Like `ReadAhead::metadata` but leaves `None` behind.
+    pub(crate) fn take_metadata(&mut self) -> Option<Metadata> {
+        self.metadata.take()
+    }
+
+    /// Like `EntryReader::next_entry`.
+    pub async fn next_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        if self.stopped {
+            self.resume.notify_one();
+            self.stopped = false
+        }
+        let Some(item) = self.queue.recv().await else {
+            return Ok(None)
+        };
+        self.stopped = !matches!(item.entry, Ok(Some(_)));
+        self.info = item.info;
+        self.digest = item.digest;
+        self.metadata = item.metadata;
+        self.schema = item.schema;
+        item.entry
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
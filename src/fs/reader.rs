@@ -1,16 +1,27 @@
 use std::{path::Path, io::SeekFrom};
 
 use bytes::{BytesMut, Bytes};
-use tokio::{io::{BufReader, self, AsyncReadExt, AsyncSeekExt}, fs::File};
+use futures_util::{Stream, stream};
+use tokio::io::{AsyncRead, AsyncSeek, BufReader, self, AsyncReadExt, AsyncSeekExt};
+
+use tracing::warn;
 
 use crate::{CRC32C, BlockInfo};
-use super::{block::BlockHeader, block_file_name};
+use super::{block::{BlockHeader, Trailer}, schema, BlockNames, Digest, Metadata};
+use super::store::{BlockRead, BlockStore, FileStore};
+use super::counters::{self, Counter};
 
 #[derive(Debug)]
-pub struct EntryReader {
-    inner: BufReader<File>,
+pub struct EntryReader<R = Box<dyn BlockRead>> {
+    inner: BufReader<R>,
+    header: BlockHeader,
     buffer: BytesMut,
-    info: BlockInfo
+    info: BlockInfo,
+    trailer: Option<Trailer>,
+    digest: Option<Digest>,
+    metadata: Option<Metadata>,
+    schema: Option<u32>,
+    skip_corrupt: bool
 }
 
 impl EntryReader {
@@ -18,11 +29,35 @@ impl EntryReader {
     where
         P: AsRef<Path>
     {
-        let mut file = {
-            let path = dir.as_ref().join(block_file_name(info.number()));
-            BufReader::with_capacity(32 * 1024, File::open(path).await?)
-        };
-        read_header(&mut file).await?;
+        Self::open_with(dir, info, &BlockNames::default()).await
+    }
+
+    /// Like `EntryReader::open` for block files named according to `names`.
+    pub async fn open_with<P>(dir: P, info: BlockInfo, names: &BlockNames) -> Result<Self, ReadError>
+    where
+        P: AsRef<Path>
+    {
+        let store = FileStore::new(dir).with_block_names(names.clone());
+        Self::open_in(&store, info).await
+    }
+
+    /// Read a block of the given store.
+    pub async fn open_in<S>(store: &S, info: BlockInfo) -> Result<Self, ReadError>
+    where
+        S: BlockStore + ?Sized
+    {
+        Self::from_reader(store.open(info.number()).await?, info).await
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> EntryReader<R> {
+    /// Read a block from any source of bytes, e.g. a decompressing wrapper.
+    ///
+    /// The source must be positioned at the start of the block header.
+    /// The number of `info` is only used to report positions.
+    pub async fn from_reader(r: R, info: BlockInfo) -> Result<Self, ReadError> {
+        let mut file = BufReader::with_capacity(32 * 1024, r);
+        let header = read_header(&mut file).await?;
         let info =
             if info.offset() == 0 {
                 info.with_offset(8u8) // header length
@@ -32,52 +67,315 @@ impl EntryReader {
             };
         Ok(Self {
             inner: file,
+            header,
             buffer: BytesMut::new(),
-            info
+            info,
+            trailer: None,
+            digest: None,
+            metadata: None,
+            schema: None,
+            skip_corrupt: false
         })
     }
 
+    /// Turn this reader into a stream of entries and their positions.
+    ///
+    /// The stream ends at the end of the block data written so far, at the
+    /// trailer, or after the first error.
+    pub fn into_stream(self) -> impl Stream<Item = Result<(BlockInfo, Bytes), ReadError>> {
+        stream::unfold(Some(self), |this| async move {
+            let mut this = this?;
+            let pos = this.block_info();
+            match this.next_entry().await {
+                Ok(Some((b, _))) => Some((Ok((pos, b)), Some(this))),
+                Ok(None)         => None,
+                Err(e)           => Some((Err(e), None))
+            }
+        })
+    }
+
+    /// Skip corrupt entries instead of returning an error.
+    ///
+    /// Cf. `EntryReader::recover_next_entry`.
+    pub fn with_skip_corrupt(mut self, val: bool) -> Self {
+        self.skip_corrupt = val;
+        self
+    }
+
     pub fn block_info(&self) -> BlockInfo {
         self.info
     }
 
+    /// The block trailer, once it has been reached.
+    pub fn trailer(&self) -> Option<Trailer> {
+        self.trailer
+    }
+
+    /// The digest of the entry last returned, if the block has digests.
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
+    /// The metadata of the entry last returned, if the block has metadata.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The schema ID of the entry last returned, if the block has schema IDs.
+    pub fn schema(&self) -> Option<u32> {
+        self.schema
+    }
+
+    /// Like `EntryReader::metadata` but leaves `None` behind.
+    pub(crate) fn take_metadata(&mut self) -> Option<Metadata> {
+        self.metadata.take()
+    }
+
     pub async fn reset(&mut self, info: BlockInfo) -> Result<(), ReadError> {
         assert_eq!(info.number(), self.info.number());
         self.inner.seek(SeekFrom::Start(info.offset())).await?;
         self.info = info;
+        self.trailer = None;
         Ok(())
     }
 
     pub async fn next_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
-        match self.inner.read_u16().await {
-            Ok(len) => {
-                self.buffer.clear();
-                self.buffer.resize(len as usize, 0);
-                self.inner.read_exact(&mut self.buffer).await?;
-                let crc = self.inner.read_u32().await?;
-                self.info.add_offset(2 + len + 4);
-                if crc != CRC32C.checksum(&self.buffer) {
-                    return Err(ReadError::Crc)
+        let entry =
+            if self.skip_corrupt {
+                self.recover_next_entry().await
+            } else {
+                self.read_next().await
+            };
+        if let Ok(Some((b, _))) = &entry {
+            counters::count(Counter::EntriesRead, 1);
+            counters::count(Counter::BytesRead, b.len() as u64)
+        }
+        entry
+    }
+
+    /// Read the next entry, skipping corrupt data.
+    ///
+    /// If an entry fails its integrity check, the block is scanned forward
+    /// for the next intact frame. If none is found, the reader is positioned
+    /// at the end of the block file, so that entries appended later are read.
+    pub async fn recover_next_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        let bad = match self.read_next().await {
+            Err(e) => match e.position() {
+                Some(at) => at,
+                None     => return Err(e)
+            },
+            ok => return ok
+        };
+        let size = u64::from(self.header.entry_len_size());
+        let end = self.inner.seek(SeekFrom::End(0)).await?;
+        let mut offset = bad.offset() + 1;
+        // The bytes from `start` on are scanned in memory and only frames
+        // that pass the checks of `is_frame_at` are read from the block.
+        let mut window = Vec::new();
+        let mut start = offset;
+        while offset + size <= end {
+            let i = (offset - start) as usize;
+            if i + size as usize > window.len() {
+                window = self.read_window(offset, end).await?;
+                start = offset;
+                continue
+            }
+            match self.is_frame_at(&window[i ..], end - offset) {
+                Some(false) => {
+                    offset += 1;
+                    continue
+                }
+                None if i > 0 => {
+                    // Move the window, unless the frame does not fit anyway.
+                    window = self.read_window(offset, end).await?;
+                    start = offset;
+                    continue
                 }
-                Ok(Some((self.buffer.split().freeze(), crc)))
+                None | Some(true) => {}
+            }
+            self.reset(bad.with_offset(offset)).await?;
+            match self.read_next().await {
+                Err(e) if e.position().is_some() => offset += 1,
+                Err(e) => return Err(e),
+                Ok(None) if self.trailer.is_none() => offset += 1,
+                ok => {
+                    warn!(from = %bad, to = %offset, "skipped corrupt data");
+                    return ok
+                }
+            }
+        }
+        warn!(from = %bad, to = %end, "skipped corrupt data");
+        self.reset(bad.with_offset(end)).await?;
+        Ok(None)
+    }
+
+    /// Read up to `RECOVERY_WINDOW` bytes of the block, starting at `offset`.
+    async fn read_window(&mut self, offset: u64, end: u64) -> Result<Vec<u8>, ReadError> {
+        let mut window = vec![0; (end - offset).min(RECOVERY_WINDOW as u64) as usize];
+        self.inner.seek(SeekFrom::Start(offset)).await?;
+        self.inner.read_exact(&mut window).await?;
+        Ok(window)
+    }
+
+    /// Check if a frame starts with the given bytes.
+    ///
+    /// The frame must end within the `left` bytes to the end of the block
+    /// and, unless it is a trailer, match its checksum. Returns `None` if
+    /// the frame may be valid but is not contained in `bytes`.
+    fn is_frame_at(&self, bytes: &[u8], left: u64) -> Option<bool> {
+        let size = usize::from(self.header.entry_len_size());
+        let len =
+            if size == 2 {
+                u32::from(u16::from_be_bytes([bytes[0], bytes[1]]))
+            } else {
+                u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            };
+        if self.header.has_trailer() && len == self.header.trailer_marker() {
+            return Some(size as u64 + Trailer::LEN <= left)
+        }
+        let more = if self.header.is_chunked() { self.header.continuation_bit() } else { 0 };
+        let len = (len & !more) as usize;
+        if (size + len + 4) as u64 > left {
+            return Some(false)
+        }
+        let frame = bytes.get(size .. size + len + 4)?;
+        let (data, crc) = frame.split_at(len);
+        Some(CRC32C.checksum(data).to_be_bytes() == crc)
+    }
+
+    async fn read_next(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        match self.read_entry().await {
+            Ok(entry) => Ok(entry),
+            Err(ReadError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                // The entry may not have been written completely yet. Rewind to
+                // its start so that a later call can read it in full.
+                self.inner.seek(SeekFrom::Start(self.info.offset())).await?;
+                Ok(None)
             }
-            Err(e) => {
-                if e.kind() == io::ErrorKind::UnexpectedEof {
-                    Ok(None)
+            Err(e) => Err(e)
+        }
+    }
+
+    async fn read_entry(&mut self) -> Result<Option<(Bytes, u32)>, ReadError> {
+        if self.trailer.is_some() {
+            return Ok(None)
+        }
+        let size = self.header.entry_len_size();
+        let more = if self.header.is_chunked() { self.header.continuation_bit() } else { 0 };
+        let mut offset = 0;
+        let mut frames = 0;
+        let mut crc;
+        let mut metadata = self.header.has_metadata();
+        let pos = self.info;
+        self.buffer.clear();
+        self.digest = None;
+        self.metadata = None;
+        self.schema = None;
+        loop {
+            let len =
+                if size == 2 {
+                    u32::from(self.inner.read_u16().await?)
                 } else {
-                    Err(e.into())
+                    self.inner.read_u32().await?
+                };
+            if frames == 0 && self.header.has_trailer() && len == self.header.trailer_marker() {
+                let entries = self.inner.read_u64().await?;
+                let crc = self.inner.read_u32().await?;
+                self.info.add_offset(u64::from(size) + Trailer::LEN);
+                self.trailer = Some(Trailer::new(entries, crc));
+                return Ok(None)
+            }
+            if metadata {
+                metadata = false;
+                self.read_frame(len as usize).await?;
+                let crc = self.inner.read_u32().await?;
+                offset += u64::from(size) + u64::from(len) + 4;
+                if crc != CRC32C.checksum(&self.buffer) {
+                    self.info.add_offset(offset);
+                    return Err(ReadError::Crc(pos))
                 }
+                let Some(m) = Metadata::from_bytes(&self.buffer) else {
+                    self.info.add_offset(offset);
+                    return Err(ReadError::Metadata(pos))
+                };
+                self.metadata = Some(m);
+                self.buffer.clear();
+                continue
+            }
+            let start = self.buffer.len();
+            self.read_frame((len & !more) as usize).await?;
+            crc = self.inner.read_u32().await?;
+            offset += u64::from(size) + u64::from(len & !more) + 4;
+            frames += 1;
+            if crc != CRC32C.checksum(&self.buffer[start ..]) {
+                self.info.add_offset(offset);
+                return Err(ReadError::Crc(pos))
+            }
+            if frames == 1 && self.header.has_schema() {
+                let Some((id, n)) = schema::decode_id(&self.buffer) else {
+                    self.info.add_offset(offset);
+                    return Err(ReadError::Schema(pos))
+                };
+                let _ = self.buffer.split_to(n);
+                self.schema = Some(id)
             }
+            if len & more == 0 {
+                break
+            }
+        }
+        if let Some(kind) = self.header.digest() {
+            let mut d = [0; 32];
+            let d = &mut d[.. kind.digest_len()];
+            self.inner.read_exact(d).await?;
+            offset += d.len() as u64;
+            let digest = kind.decode(d).expect("digest length");
+            if digest.verify(&self.buffer) == Some(false) {
+                self.info.add_offset(offset);
+                return Err(ReadError::Digest(pos))
+            }
+            self.digest = Some(digest)
         }
+        self.info.add_offset(offset);
+        if frames > 1 || self.schema.is_some() {
+            crc = CRC32C.checksum(&self.buffer)
+        }
+        Ok(Some((self.buffer.split().freeze(), crc)))
     }
+
+    /// Append the next `n` bytes to the buffer.
+    ///
+    /// The buffer grows as data is read, so that a corrupt length does not
+    /// cause a huge allocation.
+    async fn read_frame(&mut self, n: usize) -> Result<(), ReadError> {
+        let mut r = (&mut self.inner).take(n as u64);
+        self.buffer.reserve(n.min(MAX_RESERVE));
+        while r.limit() > 0 {
+            if r.read_buf(&mut self.buffer).await? == 0 {
+                return Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into()))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The maximum number of bytes reserved up-front for a frame.
+const MAX_RESERVE: usize = 64 * 1024;
+
+/// The number of bytes scanned in memory when skipping corrupt data.
+const RECOVERY_WINDOW: usize = 64 * 1024;
+
+pub(super) async fn read_header<R: AsyncRead + Unpin>(r: &mut R) -> Result<BlockHeader, ReadError> {
+    check_header(r.read_u64().await?)
 }
 
-async fn read_header(r: &mut BufReader<File>) -> Result<BlockHeader, ReadError> {
-    let number = r.read_u64().await?;
+pub(super) fn check_header(number: u64) -> Result<BlockHeader, ReadError> {
     if let Some(h) = BlockHeader::from_u64(number) {
-        if h.version() != 1 {
+        if !matches!(h.version(), 1 ..= 3) {
             return Err(ReadError::Header(Some(h.version())))
         }
+        if !h.is_supported() {
+            return Err(ReadError::Flags(h.version(), h.flags()))
+        }
         Ok(h)
     } else {
         Err(ReadError::Header(None))
@@ -89,9 +387,31 @@ pub enum ReadError {
     #[error("i/o error: {0}")]
     Io(#[from] io::Error),
 
-    #[error("crc check failed")]
-    Crc,
+    #[error("crc check failed at {0}")]
+    Crc(BlockInfo),
+
+    #[error("digest check failed at {0}")]
+    Digest(BlockInfo),
+
+    #[error("invalid entry metadata at {0}")]
+    Metadata(BlockInfo),
+
+    #[error("invalid schema ID at {0}")]
+    Schema(BlockInfo),
 
     #[error("header {0:?} not supported")]
     Header(Option<u8>),
+
+    #[error("header flags {1:#06x} not supported in version {0}")]
+    Flags(u8, u16),
+}
+
+impl ReadError {
+    /// The position of the corrupt entry, if this is an integrity error.
+    pub fn position(&self) -> Option<BlockInfo> {
+        match self {
+            Self::Crc(at) | Self::Digest(at) | Self::Metadata(at) | Self::Schema(at) => Some(*at),
+            Self::Io(_) | Self::Header(_) | Self::Flags(..) => None
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use minicbor::Decode;
+
+type Decoder<T> = Arc<dyn Fn(&[u8]) -> Result<T, minicbor::decode::Error> + Send + Sync>;
+
+/// Decoders of entries by their schema ID, cf. `Config::with_schema`.
+///
+/// When the type of logged values changes, writers switch to a new schema
+/// ID and readers of old blocks keep decoding older entries with the
+/// decoder of their schema.
+pub struct Schemas<T> {
+    decoders: HashMap<u32, Decoder<T>>,
+    default: Option<Decoder<T>>
+}
+
+impl<T> fmt::Debug for Schemas<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Schemas")
+            .field("decoders", &self.decoders.keys())
+            .field("default", &self.default.is_some())
+            .finish()
+    }
+}
+
+impl<T> Clone for Schemas<T> {
+    fn clone(&self) -> Self {
+        Self { decoders: self.decoders.clone(), default: self.default.clone() }
+    }
+}
+
+impl<T> Default for Schemas<T> {
+    fn default() -> Self {
+        Self { decoders: HashMap::new(), default: None }
+    }
+}
+
+impl<T: 'static> Schemas<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode entries of the given schema with `f`.
+    pub fn with_schema<F>(mut self, id: u32, f: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<T, minicbor::decode::Error> + Send + Sync + 'static
+    {
+        self.decoders.insert(id, Arc::new(f));
+        self
+    }
+
+    /// Decode entries of the given schema as CBOR values of type `U`.
+    pub fn with_cbor<U>(self, id: u32) -> Self
+    where
+        U: for<'b> Decode<'b, ()> + Into<T>
+    {
+        self.with_schema(id, |b| minicbor::decode::<U>(b).map(Into::into))
+    }
+
+    /// Decode entries without schema ID, e.g. from blocks written before
+    /// schema IDs were enabled, with `f`.
+    pub fn with_default<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<T, minicbor::decode::Error> + Send + Sync + 'static
+    {
+        self.default = Some(Arc::new(f));
+        self
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.decoders.contains_key(&id)
+    }
+
+    /// Decode an entry with the decoder of its schema.
+    pub fn decode(&self, schema: Option<u32>, entry: &[u8]) -> Result<T, SchemaError> {
+        let decoder = match schema {
+            Some(id) => self.decoders.get(&id),
+            None     => self.default.as_ref()
+        };
+        let Some(d) = decoder else {
+            return Err(SchemaError::Unknown(schema))
+        };
+        d(entry).map_err(|e| SchemaError::Decode(schema, e))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("no decoder for schema {0:?}")]
+    Unknown(Option<u32>),
+
+    #[error("failed to decode entry of schema {0:?}: {1}")]
+    Decode(Option<u32>, #[source] minicbor::decode::Error)
+}
+
+/// The maximum length of an encoded schema ID.
+pub(crate) const MAX_ID_LEN: usize = 5;
+
+/// Encode a schema ID as unsigned LEB128.
+pub(crate) fn encode_id(mut id: u32, buf: &mut [u8; MAX_ID_LEN]) -> &[u8] {
+    let mut i = 0;
+    loop {
+        let b = (id & 0x7F) as u8;
+        id >>= 7;
+        if id == 0 {
+            buf[i] = b;
+            return &buf[..= i]
+        }
+        buf[i] = b | 0x80;
+        i += 1
+    }
+}
+
+/// Decode a schema ID from the start of `b`, returning it and its length.
+pub(crate) fn decode_id(b: &[u8]) -> Option<(u32, usize)> {
+    let mut id = 0u32;
+    for (i, x) in b.iter().take(MAX_ID_LEN).enumerate() {
+        let bits = u32::from(x & 0x7F);
+        if i == MAX_ID_LEN - 1 && bits > 0xF {
+            return None
+        }
+        id |= bits << (7 * i);
+        if x & 0x80 == 0 {
+            return Some((id, i + 1))
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_id, encode_id, MAX_ID_LEN};
+
+    #[test]
+    fn id_roundtrip() {
+        for id in [0, 1, 127, 128, 300, 16_383, 16_384, u32::MAX] {
+            let mut buf = [0; MAX_ID_LEN];
+            let b = encode_id(id, &mut buf);
+            assert_eq!(Some((id, b.len())), decode_id(b))
+        }
+        assert_eq!(None, decode_id(&[0x80, 0x80]));
+        assert_eq!(None, decode_id(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]))
+    }
+}
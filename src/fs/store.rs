@@ -0,0 +1,50 @@
+//! Storage backends of block logs.
+//!
+//! An `EntryWriter`, `EntryReader` or `LogReader` reads and writes blocks
+//! through a `BlockStore`. By default blocks are files in a directory,
+//! cf. `FileStore`, but a `MemoryStore` keeps them in memory instead.
+
+use std::{fmt, io};
+use futures_util::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+use super::BlockNum;
+
+mod file;
+mod memory;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+
+/// A collection of numbered blocks.
+pub trait BlockStore: fmt::Debug + Send + Sync + 'static {
+    /// The numbers and sizes in bytes of all blocks, sorted by number.
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<(BlockNum, u64)>>>;
+
+    /// Create a new, empty block to append to.
+    ///
+    /// Fails with `io::ErrorKind::AlreadyExists` if the block exists.
+    fn create(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockWrite>>>;
+
+    /// Open an existing block for reading.
+    ///
+    /// Data appended to the block later is visible to the reader.
+    fn open(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockRead>>>;
+
+    /// Remove a block.
+    fn remove(&self, n: BlockNum) -> BoxFuture<'_, io::Result<()>>;
+}
+
+/// A block being appended to.
+pub trait BlockWrite: AsyncWrite + fmt::Debug + Send + Sync + Unpin {
+    /// Make the data written so far durable.
+    fn sync(&mut self) -> BoxFuture<'_, io::Result<()>>;
+}
+
+/// A block being read.
+pub trait BlockRead: AsyncRead + AsyncSeek + fmt::Debug + Send + Sync + Unpin {}
+
+impl<T> BlockRead for T
+where
+    T: AsyncRead + AsyncSeek + fmt::Debug + Send + Sync + Unpin
+{}
@@ -0,0 +1,160 @@
+use std::{io, path::{Path, PathBuf}};
+use futures_util::future::BoxFuture;
+use tokio::fs::{File, OpenOptions, self};
+use tracing::debug;
+
+use crate::fs::{BlockNames, BlockNum, Config, block_path, list_blocks_with, portable, shard_path, sync_dir};
+use super::{BlockRead, BlockStore, BlockWrite};
+
+/// Blocks stored as files in a directory.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    directory: PathBuf,
+    block_names: BlockNames,
+    sharding: bool,
+    durable_metadata: bool,
+    preallocate: Option<u64>
+}
+
+impl FileStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            directory: dir.as_ref().to_path_buf(),
+            block_names: BlockNames::default(),
+            sharding: false,
+            durable_metadata: false,
+            preallocate: None
+        }
+    }
+
+    /// A store with the file settings of the given config.
+    pub(crate) fn from_config(dir: &Path, cfg: &Config) -> Self {
+        Self::new(dir)
+            .with_block_names(cfg.block_names.clone())
+            .with_sharding(cfg.sharding)
+            .with_durable_metadata(cfg.durable_metadata)
+            .with_preallocate(cfg.preallocate.then_some(cfg.max_block_len))
+    }
+
+    pub fn with_block_names(mut self, n: BlockNames) -> Self {
+        self.block_names = n;
+        self
+    }
+
+    /// Create new blocks in shard subdirectories, cf. `Config::with_sharding`.
+    pub fn with_sharding(mut self, val: bool) -> Self {
+        self.sharding = val;
+        self
+    }
+
+    /// Sync the directory after creating blocks.
+    pub fn with_durable_metadata(mut self, val: bool) -> Self {
+        self.durable_metadata = val;
+        self
+    }
+
+    /// Reserve disk space of the given size for new blocks.
+    pub fn with_preallocate(mut self, len: Option<u64>) -> Self {
+        self.preallocate = len;
+        self
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    async fn create_file(&self, n: BlockNum) -> io::Result<File> {
+        let dir = &self.directory;
+        let parent =
+            if self.sharding {
+                let shard = shard_path(dir, n);
+                if !shard.is_dir() {
+                    fs::create_dir_all(&shard).await?;
+                    if self.durable_metadata {
+                        sync_dir(dir).await?
+                    }
+                }
+                shard
+            } else {
+                dir.to_path_buf()
+            };
+        let path = parent.join(self.block_names.file_name(n));
+        let f = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        if self.durable_metadata {
+            sync_dir(&parent).await?
+        }
+        if let Some(len) = self.preallocate {
+            if let Err(err) = preallocate(&f, len) {
+                debug!(?path, %err, "failed to preallocate block file")
+            }
+        }
+        Ok(f)
+    }
+}
+
+impl BlockStore for FileStore {
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<(BlockNum, u64)>>> {
+        Box::pin(async move {
+            let blocks = list_blocks_with(&self.directory, &self.block_names).await?;
+            Ok(blocks.iter().map(|b| (b.number(), b.size())).collect())
+        })
+    }
+
+    fn create(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockWrite>>> {
+        Box::pin(async move {
+            let f = self.create_file(n).await?;
+            Ok(Box::new(f) as Box<dyn BlockWrite>)
+        })
+    }
+
+    fn open(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockRead>>> {
+        Box::pin(async move {
+            let path = block_path(&self.directory, &self.block_names, n).await;
+            let f = File::open(path).await?;
+            Ok(Box::new(f) as Box<dyn BlockRead>)
+        })
+    }
+
+    fn remove(&self, n: BlockNum) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async move {
+            let path = block_path(&self.directory, &self.block_names, n).await;
+            portable::remove_file(&path).await?;
+            // Removing a shard fails as long as it contains blocks, which is fine.
+            if let Some(shard) = path.parent().filter(|p| *p != self.directory) {
+                let _ = fs::remove_dir(shard).await;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl BlockWrite for File {
+    fn sync(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.sync_data())
+    }
+}
+
+/// Reserve disk space for a block file.
+///
+/// The file size is not changed because readers rely on it to find the
+/// end of the data written so far.
+#[cfg(target_os = "linux")]
+fn preallocate(f: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let len = libc::off_t::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // SAFETY: The file descriptor is valid for the duration of the call.
+    if unsafe { libc::fallocate(f.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(_: &File, _: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,130 @@
+use std::{collections::{BTreeMap, btree_map::Entry}, io::{self, SeekFrom}, pin::Pin, sync::{Arc, Mutex, MutexGuard}};
+use std::task::{Context, Poll};
+use futures_util::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::fs::BlockNum;
+use super::{BlockRead, BlockStore, BlockWrite};
+
+/// Blocks stored in memory.
+///
+/// Clones share the same blocks, e.g. a writer and several readers.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    blocks: Arc<Mutex<BTreeMap<BlockNum, Data>>>
+}
+
+type Data = Arc<Mutex<Vec<u8>>>;
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The contents of a block, if it exists.
+    pub fn get(&self, n: BlockNum) -> Option<Vec<u8>> {
+        self.lock().get(&n).map(|d| lock(d).clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<BlockNum, Data>> {
+        self.blocks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn lock(d: &Data) -> MutexGuard<'_, Vec<u8>> {
+    d.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl BlockStore for MemoryStore {
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<(BlockNum, u64)>>> {
+        let blocks = self.lock().iter().map(|(n, d)| (*n, lock(d).len() as u64)).collect();
+        Box::pin(async move { Ok(blocks) })
+    }
+
+    fn create(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockWrite>>> {
+        let result = match self.lock().entry(n) {
+            Entry::Occupied(_) => Err(io::ErrorKind::AlreadyExists.into()),
+            Entry::Vacant(e)   => {
+                let data = e.insert(Data::default()).clone();
+                Ok(Box::new(MemoryBlock { data, pos: 0 }) as Box<dyn BlockWrite>)
+            }
+        };
+        Box::pin(async move { result })
+    }
+
+    fn open(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockRead>>> {
+        let result = match self.lock().get(&n) {
+            Some(d) => Ok(Box::new(MemoryBlock { data: d.clone(), pos: 0 }) as Box<dyn BlockRead>),
+            None    => Err(io::ErrorKind::NotFound.into())
+        };
+        Box::pin(async move { result })
+    }
+
+    fn remove(&self, n: BlockNum) -> BoxFuture<'_, io::Result<()>> {
+        let result = match self.lock().remove(&n) {
+            Some(_) => Ok(()),
+            None    => Err(io::ErrorKind::NotFound.into())
+        };
+        Box::pin(async move { result })
+    }
+}
+
+/// A handle to a block of a `MemoryStore`.
+///
+/// Writes always append, reads start at the current position.
+#[derive(Debug)]
+struct MemoryBlock {
+    data: Data,
+    pos: u64
+}
+
+impl AsyncWrite for MemoryBlock {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        lock(&self.data).extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for MemoryBlock {
+    fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let data = lock(&this.data);
+        let start = usize::try_from(this.pos).unwrap_or(usize::MAX).min(data.len());
+        let n = buf.remaining().min(data.len() - start);
+        buf.put_slice(&data[start .. start + n]);
+        this.pos += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for MemoryBlock {
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let len = lock(&this.data).len() as u64;
+        let new = match pos {
+            SeekFrom::Start(n)   => Some(n),
+            SeekFrom::End(n)     => len.checked_add_signed(n),
+            SeekFrom::Current(n) => this.pos.checked_add_signed(n)
+        };
+        this.pos = new.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+impl BlockWrite for MemoryBlock {
+    fn sync(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
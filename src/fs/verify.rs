@@ -0,0 +1,98 @@
+use std::{path::Path, io::SeekFrom};
+
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt, BufReader}};
+
+use crate::{CRC32C, BlockInfo, BlockNum, EntryReader, ReadError};
+use super::{block::{BlockHeader, Trailer}, block_path, reader::read_header, BlockNames};
+
+/// The outcome of `verify_block`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The block's trailer matches its content.
+    Complete { entries: u64 },
+
+    /// The block has no trailer and all entries are intact.
+    Valid { entries: u64 },
+
+    /// The block is damaged after the given number of intact entries.
+    Corrupt { entries: u64 }
+}
+
+/// Check the integrity of a block.
+///
+/// If the block has a trailer, only a single checksum over the whole block
+/// is computed. Otherwise every entry is read and checked individually.
+pub async fn verify_block<P>(dir: P, number: BlockNum) -> Result<BlockStatus, ReadError>
+where
+    P: AsRef<Path>
+{
+    verify_block_with(dir, number, &BlockNames::default()).await
+}
+
+/// Like `verify_block` for block files named according to `names`.
+pub async fn verify_block_with<P>(dir: P, number: BlockNum, names: &BlockNames) -> Result<BlockStatus, ReadError>
+where
+    P: AsRef<Path>
+{
+    let mut file = {
+        let path = block_path(dir.as_ref(), names, number).await;
+        BufReader::with_capacity(32 * 1024, File::open(path).await?)
+    };
+    let header = read_header(&mut file).await?;
+    if header.has_trailer() {
+        if let Some(t) = check_trailer(&mut file, header).await? {
+            return Ok(BlockStatus::Complete { entries: t.entries() })
+        }
+    }
+    let mut reader = EntryReader::open_with(dir, BlockInfo::zero().with_number(number), names).await?;
+    let mut entries = 0;
+    loop {
+        match reader.next_entry().await {
+            Ok(Some(_)) => entries += 1,
+            Ok(None)    => break,
+            Err(ReadError::Crc(_) | ReadError::Digest(_)) => return Ok(BlockStatus::Corrupt { entries }),
+            Err(e)      => return Err(e)
+        }
+    }
+    if reader.trailer().is_some() {
+        // The trailer does not match intact entries.
+        return Ok(BlockStatus::Corrupt { entries })
+    }
+    Ok(BlockStatus::Valid { entries })
+}
+
+/// Read the trailer at the end of the file and check the block payload.
+///
+/// Returns `None` if there is no trailer or if it does not match.
+async fn check_trailer(file: &mut BufReader<File>, header: BlockHeader) -> Result<Option<Trailer>, ReadError> {
+    const HEADER_LEN: u64 = 8;
+    let size = u64::from(header.entry_len_size());
+    let len = file.get_ref().metadata().await?.len();
+    if len < HEADER_LEN + size + Trailer::LEN {
+        return Ok(None)
+    }
+    let end = len - size - Trailer::LEN;
+    file.seek(SeekFrom::Start(end)).await?;
+    let marker =
+        if size == 2 {
+            u32::from(file.read_u16().await?)
+        } else {
+            file.read_u32().await?
+        };
+    if marker != header.trailer_marker() {
+        return Ok(None)
+    }
+    let trailer = Trailer::new(file.read_u64().await?, file.read_u32().await?);
+    file.seek(SeekFrom::Start(HEADER_LEN)).await?;
+    let mut digest = CRC32C.digest();
+    let mut payload = file.take(end - HEADER_LEN);
+    let mut buf = vec![0; 32 * 1024];
+    loop {
+        let n = payload.read(&mut buf).await?;
+        if n == 0 {
+            break
+        }
+        digest.update(&buf[.. n])
+    }
+    Ok((digest.finalize() == trailer.crc()).then_some(trailer))
+}
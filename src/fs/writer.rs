@@ -1,110 +1,721 @@
-use crate::{CRC32C, BLOCK_FILENAME_PREFIX};
-use std::{path::{Path, PathBuf}, io};
-use tokio::{io::{BufWriter, AsyncWriteExt}, fs::{File, OpenOptions, self}};
-use super::{Config, block_file_name, read_block_num};
-use super::block::{Block, BlockInfo, BlockNum, BlockHeader};
+use crate::CRC32C;
+use tracing::{debug, warn};
+use crc::Digest;
+use std::{path::{Path, PathBuf}, io::{self, IoSlice}, fmt, ops::Range, time::{Duration, Instant}};
+use futures_util::future::BoxFuture;
+use tokio::io::{BufWriter, AsyncWrite, AsyncWriteExt};
+use super::{BlockNames, Config, ConfigError, QuotaPolicy, list_blocks_with};
+use super::portable::{self, FileLock};
+use super::store::{BlockStore, BlockWrite, FileStore};
+use super::{DigestKind, Metadata};
+use super::counters::{self, Counter};
+use super::block::{Block, BlockInfo, BlockNum, BlockHeader, Trailer, FLAG_CHUNKED, FLAG_TRAILER};
+use super::block::{FLAG_DIGEST_BLAKE3, FLAG_DIGEST_XXH64, FLAG_METADATA, FLAG_SCHEMA};
+use super::schema::{self, MAX_ID_LEN};
+
+const HEADER_LEN: u64 = 8;
+
+/// How often the disk usage is recounted while the quota is exceeded.
+const QUOTA_RESCAN: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
-pub struct EntryWriter {
+pub struct EntryWriter<W = Box<dyn BlockWrite>> {
     header: BlockHeader,
     config: Config,
-    directory: PathBuf,
-    current: Block<BufWriter<File>>,
-    buffer: Vec<u8>
+    /// The blocks to continue in, unless only a single block is written.
+    blocks: Option<Box<dyn Blocks<W>>>,
+    current: Block<BufWriter<W>>,
+    /// Frame lengths and checksums of the entries being appended.
+    buffer: Vec<u8>,
+    summary: Summary,
+    /// Estimated size of all blocks in bytes.
+    usage: u64,
+    /// When the disk usage was last counted.
+    scanned: Option<Instant>,
+    /// A write failed and the current block may contain partial data.
+    poisoned: bool,
+    /// Bytes of a failed write which were accepted before it failed.
+    excess: u64,
+    /// Keeps other writers out of the block directory.
+    lock: Option<FileLock>
+}
+
+/// Part of a batch of frames to write.
+enum Part<'a> {
+    /// A range of `EntryWriter::buffer`.
+    Meta(Range<usize>),
+    /// Entry data.
+    Data(&'a [u8])
+}
+
+/// Entry count and checksum of the current block's payload.
+struct Summary {
+    entries: u64,
+    digest: Digest<'static, u32>
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self { entries: 0, digest: CRC32C.digest() }
+    }
+}
+
+impl fmt::Debug for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Summary").field("entries", &self.entries).finish_non_exhaustive()
+    }
 }
 
 impl EntryWriter {
+    /// Append to blocks of the given directory.
+    ///
+    /// Only one writer may use a directory (and block names) at a time,
+    /// otherwise `WriteError::Locked` is returned.
     pub async fn open<P>(dir: P, cfg: Config) -> Result<Self, WriteError>
     where
         P: AsRef<Path>
     {
-        let path = dir.as_ref().to_path_buf();
+        let path = dir.as_ref();
         if !path.is_dir() {
-            return Err(WriteError::NoDir(path))
+            return Err(WriteError::NoDir(path.to_path_buf()))
+        }
+        let lock_path = path.join(cfg.block_names.lock_file_name());
+        let Some(lock) = portable::try_lock(&lock_path).await? else {
+            return Err(WriteError::Locked(lock_path))
+        };
+        let mut this = Self::open_in(FileStore::from_config(path, &cfg), cfg).await?;
+        this.lock = Some(lock);
+        Ok(this)
+    }
+
+    /// Append to blocks of the given store.
+    ///
+    /// Settings of `cfg` which concern block files, like block names or
+    /// sharding, do not apply. A `FileStore` is configured separately.
+    pub async fn open_in<S: BlockStore>(store: S, cfg: Config) -> Result<Self, WriteError> {
+        cfg.validate()?;
+        if let Some(d) = cfg.digest.filter(|d| !d.is_enabled()) {
+            let e = io::Error::new(io::ErrorKind::Unsupported, format!("{d:?} digests are not enabled"));
+            return Err(e.into())
+        }
+        let (num, usage) = {
+            let blocks = BlockStore::list(&store).await?;
+            let latest = blocks.last().map(|b| b.0).unwrap_or_else(BlockNum::zero);
+            (latest.next().ok_or(WriteError::Exhausted)?, blocks.iter().map(|b| b.1).sum())
+        };
+        let blocks: Box<dyn Blocks<_>> = Box::new(store);
+        let f = append_to(&*blocks, &cfg, num).await?;
+        Self::start(Some(blocks), f, num, usage, block_header(&cfg), cfg).await
+    }
+}
+
+impl<W: AsyncWrite + Unpin> EntryWriter<W> {
+    /// Write a single block to any sink of bytes, e.g. a compressing wrapper.
+    ///
+    /// The block is numbered 1 and never rotated, i.e. the maximum block
+    /// length and disk quota do not apply. Use `EntryWriter::finish` to
+    /// write the trailer, if any, and get the sink back.
+    pub async fn from_writer(w: W, cfg: Config) -> Result<Self, WriteError> {
+        cfg.validate()?;
+        let f = BufWriter::with_capacity(cfg.max_buffer_len, w);
+        Self::start(None, f, BlockNum::from(1), 0, block_header(&cfg), cfg).await
+    }
+
+    /// Write a single block in the format of another block's header,
+    /// cf. `compact_blocks_with`.
+    pub(crate) async fn from_writer_with_header(w: W, header: BlockHeader) -> Result<Self, WriteError> {
+        let cfg = Config::new().with_max_entry_len(u32::MAX);
+        let f = BufWriter::with_capacity(cfg.max_buffer_len, w);
+        Self::start(None, f, BlockNum::from(1), 0, header, cfg).await
+    }
+
+    async fn start
+        ( blocks: Option<Box<dyn Blocks<W>>>
+        , f: BufWriter<W>
+        , num: BlockNum
+        , usage: u64
+        , header: BlockHeader
+        , cfg: Config
+        ) -> Result<Self, WriteError>
+    {
+        if let Some(d) = header.digest().filter(|d| !d.is_enabled()) {
+            let e = io::Error::new(io::ErrorKind::Unsupported, format!("{d:?} digests are not enabled"));
+            return Err(e.into())
         }
-        let num = latest_block_number(&path).await?.add(1u8);
-        let buf = cfg.max_buffer_len;
         let mut this = Self {
-            header: BlockHeader::default(),
+            header,
+            current: Block::new(f).with_info(BlockInfo::zero().with_number(num)),
             config: cfg,
-            current: {
-                let f = append_to(buf, path.join(block_file_name(num))).await?;
-                let i = BlockInfo::zero().with_number(num);
-                Block::new(f).with_info(i)
-            },
-            directory: path,
-            buffer: Vec::new()
+            blocks,
+            buffer: Vec::new(),
+            summary: Summary::new(),
+            usage,
+            scanned: None,
+            poisoned: false,
+            excess: 0,
+            lock: None
         };
         this.write_header().await?;
         Ok(this)
     }
 
-    pub async fn append(&mut self, entry: &[u8]) -> Result<(), WriteError> {
-        if entry.len() > self.config.max_entry_len.into() {
-            return Err(WriteError::EntrySize)
+    /// Write the trailer, if enabled, flush and return the sink.
+    pub async fn finish(mut self) -> Result<W, WriteError> {
+        self.finish_block().await?;
+        Ok(self.current.into_file().into_inner())
+    }
+
+    /// Write the trailer, if enabled, and sync the current block.
+    ///
+    /// The rotate hook is called for the block unless it is empty, cf.
+    /// `Config::with_on_rotate`. Writers which are only dropped leave their
+    /// last block without trailer.
+    pub async fn close(mut self) -> Result<(), WriteError> {
+        let empty = self.current.info().offset() <= HEADER_LEN;
+        self.finish_block().await?;
+        if !empty {
+            self.config.rotated(*self.current.info())
+        }
+        Ok(())
+    }
+
+    async fn finish_block(&mut self) -> Result<(), WriteError> {
+        if self.poisoned {
+            return Err(WriteError::Poisoned)
         }
-        let crc = CRC32C.checksum(entry);
+        if self.header.has_trailer() {
+            self.write_trailer().await?
+        }
+        self.sync().await
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Apply the block length, quota and quota policy of `cfg`.
+    ///
+    /// Other settings determine the block format and can not change while
+    /// writing. The updated config is validated as a whole.
+    pub fn update_config(&mut self, cfg: &Config) -> Result<(), ConfigError> {
+        let mut c = self.config.clone();
+        c.max_block_len = cfg.max_block_len;
+        c.quota = cfg.quota;
+        c.quota_policy = cfg.quota_policy;
+        c.validate()?;
+        debug!(max_block_len = %c.max_block_len, quota = ?c.quota, "updated writer config");
+        self.config = c;
+        Ok(())
+    }
+
+    /// The position at which the next entry is appended, unless a new
+    /// block is started for it.
+    pub fn position(&self) -> BlockInfo {
+        *self.current.info()
+    }
+
+    pub async fn append(&mut self, entry: &[u8]) -> Result<(), WriteError> {
+        self.append_batch([entry]).await
+    }
+
+    /// Append an entry together with its metadata.
+    ///
+    /// Fails with `WriteError::NoMetadata` unless entry metadata is enabled,
+    /// cf. `Config::with_entry_metadata`.
+    pub async fn append_with_metadata(&mut self, entry: &[u8], meta: &Metadata) -> Result<(), WriteError> {
+        self.append_entries([(entry, Some(meta), None)]).await
+    }
+
+    /// Append an entry of the given schema instead of the configured one.
+    ///
+    /// Fails with `WriteError::NoSchema` unless schema IDs are enabled,
+    /// cf. `Config::with_schema`.
+    pub async fn append_with_schema(&mut self, entry: &[u8], meta: Option<&Metadata>, schema: u32) -> Result<(), WriteError> {
+        self.append_entries([(entry, meta, Some(schema))]).await
+    }
+
+    /// Append several entries with a single write.
+    ///
+    /// Either all or none of the entries are appended if an entry exceeds
+    /// the maximum entry size. If a batch does not fit into the current
+    /// block, it is split between entries across as many new blocks as
+    /// necessary.
+    pub async fn append_batch<'a, I>(&mut self, entries: I) -> Result<(), WriteError>
+    where
+        I: IntoIterator<Item = &'a [u8]>
+    {
+        self.append_entries(entries.into_iter().map(|e| (e, None, None))).await
+    }
+
+    /// Like `EntryWriter::append_batch` for entries with optional metadata.
+    ///
+    /// If entry metadata is enabled, entries without metadata are stored
+    /// with empty metadata.
+    pub async fn append_batch_with_metadata<'a, I>(&mut self, entries: I) -> Result<(), WriteError>
+    where
+        I: IntoIterator<Item = (&'a [u8], Option<&'a Metadata>)>
+    {
+        self.append_entries(entries.into_iter().map(|(e, m)| (e, m, None))).await
+    }
+
+    /// Append entries with optional metadata and schema ID.
+    ///
+    /// Entries without schema ID get the configured one.
+    pub(super) async fn append_entries<'a, I>(&mut self, entries: I) -> Result<(), WriteError>
+    where
+        I: IntoIterator<Item = (&'a [u8], Option<&'a Metadata>, Option<u32>)>
+    {
         self.buffer.clear();
-        self.buffer.extend_from_slice(&(entry.len() as u16).to_be_bytes());
-        self.buffer.extend_from_slice(entry);
-        self.buffer.extend_from_slice(&crc.to_be_bytes());
-        if self.current.info().offset() + self.buffer.len() as u64 > self.config.max_block_len {
-            self.start_new_block().await?
-        }
-        self.current.file_mut().write_all(&self.buffer).await?;
-        self.current.info_mut().add_offset(self.buffer.len() as u64);
+        let max = self.config.max_entry_len.min(self.frame_limit()) as usize;
+        let max_meta = self.max_metadata_len();
+        // Entry data is not copied but written together with the frame
+        // lengths and checksums in a single vectored write.
+        let mut parts = Vec::new();
+        let mut total = 0;
+        // Where each entry ends, as number of parts and bytes.
+        let mut ends = Vec::new();
+        for (entry, meta, schema) in entries {
+            // An empty part keeps frames of different entries apart.
+            parts.push(Part::Meta(self.buffer.len() .. self.buffer.len()));
+            if self.header.has_metadata() {
+                let meta = meta.map(Metadata::to_bytes).unwrap_or_else(|| Metadata::new().to_bytes());
+                if meta.len() > max_meta {
+                    return Err(WriteError::EntrySize)
+                }
+                total += self.push_metadata(&mut parts, &meta)
+            } else if meta.map(|m| !m.is_empty()).unwrap_or(false) {
+                return Err(WriteError::NoMetadata)
+            }
+            let mut id = [0; MAX_ID_LEN];
+            let id = match schema.or(self.config.schema) {
+                Some(s) if self.header.has_schema() => schema::encode_id(s, &mut id),
+                Some(_) if schema.is_some()         => return Err(WriteError::NoSchema),
+                _                                   => &[]
+            };
+            if id.len() + entry.len() <= max {
+                total += self.push_frame(&mut parts, id, entry, false)
+            } else if self.header.is_chunked() && id.len() < max {
+                let (first, rest) = entry.split_at(max - id.len());
+                total += self.push_frame(&mut parts, id, first, true);
+                let mut chunks = rest.chunks(max).peekable();
+                while let Some(c) = chunks.next() {
+                    total += self.push_frame(&mut parts, &[], c, chunks.peek().is_some())
+                }
+            } else {
+                return Err(WriteError::EntrySize)
+            }
+            if let Some(kind) = self.header.digest() {
+                let start = self.buffer.len();
+                kind.compute(entry).expect("digest is enabled").write_to(&mut self.buffer);
+                push_meta(&mut parts, start .. self.buffer.len());
+                total += kind.digest_len()
+            }
+            ends.push((parts.len(), total))
+        }
+        if total == 0 {
+            return Ok(())
+        }
+        if self.poisoned {
+            self.recover().await?
+        }
+        if let Some(quota) = self.config.quota {
+            let len = total as u64 + self.framing(total as u64);
+            if self.usage + len > quota {
+                self.enforce_quota(quota, len, false).await?
+            }
+        }
+        // Entries are written in runs which fit into the current block.
+        // A new block is started between entries, unless the current one
+        // is empty, so a single entry may still exceed the block size.
+        let (mut i, mut done) = (0, (0, 0));
+        while i < ends.len() {
+            let offset = self.current.info().offset();
+            let room = self.config.max_block_len.saturating_sub(offset);
+            let fits = ends[i ..].iter().take_while(|e| (e.1 - done.1) as u64 <= room).count();
+            let rotate = self.blocks.is_some() && offset > HEADER_LEN;
+            if fits == 0 && rotate {
+                if let Err(e) = self.start_new_block().await {
+                    self.poisoned = true;
+                    return Err(e)
+                }
+                continue
+            }
+            let n = if self.blocks.is_some() { fits.max(1) } else { ends.len() - i };
+            let end = ends[i + n - 1];
+            self.write_parts(&parts[done.0 .. end.0], n as u64, (end.1 - done.1) as u64).await?;
+            (i, done) = (i + n, end)
+        }
+        Ok(())
+    }
+
+    /// Write the parts of `count` entries, `len` bytes in total, to the current block.
+    async fn write_parts(&mut self, parts: &[Part<'_>], count: u64, len: u64) -> Result<(), WriteError> {
+        let mut slices: Vec<IoSlice> = parts.iter()
+            .map(|p| match p {
+                Part::Meta(r) => IoSlice::new(&self.buffer[r.clone()]),
+                Part::Data(d) => IoSlice::new(d)
+            })
+            .collect();
+        for s in &slices {
+            self.summary.digest.update(s)
+        }
+        let mut written = 0;
+        if let Err(e) = write_entries(self.current.file_mut(), &self.config, &mut slices, &mut written).await {
+            self.poisoned = true;
+            self.excess = written;
+            return Err(e.into())
+        }
+        self.current.info_mut().add_offset(len);
+        self.summary.entries += count;
+        self.usage += len;
+        counters::count(Counter::EntriesWritten, count);
+        counters::count(Counter::BytesWritten, len);
+        Ok(())
+    }
+
+    /// Continue in a new block after a failed write.
+    ///
+    /// The current block may end with a partial frame, so it is abandoned
+    /// without a trailer. Entries appended before the failed write, which
+    /// are still buffered, are written to it first.
+    async fn recover(&mut self) -> Result<(), WriteError> {
+        let Some(blocks) = &self.blocks else {
+            return Err(WriteError::Poisoned)
+        };
+        let f = self.current.file_mut();
+        let keep = (f.buffer().len() as u64).saturating_sub(self.excess) as usize;
+        if keep > 0 {
+            let data = f.buffer()[.. keep].to_vec();
+            f.get_mut().write_all(&data).await?;
+            self.excess += keep as u64;
+            blocks.sync(f.get_mut()).await?
+        }
+        let n = self.current.info().number().next().ok_or(WriteError::Exhausted)?;
+        debug!(block = %n, "continuing in new block after write error");
+        let f = append_to(&**blocks, &self.config, n).await?;
+        self.current = Block::new(f).with_info(BlockInfo::zero().with_number(n));
+        self.summary = Summary::new();
+        self.write_header().await?;
+        self.poisoned = false;
+        self.excess = 0;
         Ok(())
     }
 
+    /// Check if the disk quota, if any, permits further writes.
+    pub(crate) async fn has_room(&mut self) -> Result<bool, WriteError> {
+        let Some(quota) = self.config.quota else {
+            return Ok(true)
+        };
+        match self.enforce_quota(quota, 0, true).await {
+            Ok(())                 => Ok(true),
+            Err(WriteError::Quota) => Ok(false),
+            Err(e)                 => Err(e)
+        }
+    }
+
+    /// The bytes of block headers and trailers needed to append `len`
+    /// bytes of frames, in addition to those already written.
+    fn framing(&self, len: u64) -> u64 {
+        let trailer = if self.header.has_trailer() { Trailer::LEN + u64::from(self.header.entry_len_size()) } else { 0 };
+        let offset = self.current.info().offset();
+        let room = self.config.max_block_len.saturating_sub(offset);
+        if self.blocks.is_none() || len <= room {
+            return trailer
+        }
+        let per_block = self.config.max_block_len.saturating_sub(HEADER_LEN + trailer).max(1);
+        trailer + (len - room).div_ceil(per_block) * (HEADER_LEN + trailer)
+    }
+
+    /// Update the disk usage estimate and make room for `len` more bytes.
+    ///
+    /// The usage is a running total. Blocks are counted again only if the
+    /// quota appears to be exceeded, since others may have removed blocks
+    /// meanwhile, and not more often than every `QUOTA_RESCAN` unless
+    /// `force` is set or blocks must be deleted.
+    async fn enforce_quota(&mut self, quota: u64, len: u64, force: bool) -> Result<(), WriteError> {
+        let current = self.current.info().number();
+        let Some(store) = self.blocks.as_ref().map(|b| b.store()) else {
+            return Ok(())
+        };
+        let drop_oldest = self.config.quota_policy == QuotaPolicy::DropOldest;
+        let recent = self.scanned.map(|t| t.elapsed() < QUOTA_RESCAN).unwrap_or(false);
+        if !force && !drop_oldest && recent {
+            return if self.usage + len > quota { Err(WriteError::Quota) } else { Ok(()) }
+        }
+        self.scanned = Some(Instant::now());
+        let mut blocks = store.list().await?;
+        blocks.retain(|b| b.0 < current);
+        self.usage = self.current.info().offset() + blocks.iter().map(|b| b.1).sum::<u64>();
+        if drop_oldest {
+            for (n, size) in &blocks {
+                if self.usage + len <= quota {
+                    break
+                }
+                match store.remove(*n).await {
+                    Ok(()) => warn!(block = %n, "quota exceeded, deleted block"),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into())
+                }
+                self.usage = self.usage.saturating_sub(*size)
+            }
+        }
+        if self.usage + len > quota {
+            return Err(WriteError::Quota)
+        }
+        Ok(())
+    }
+
+    /// Add a frame to the given parts and return its length.
+    ///
+    /// The frame data consists of `prefix`, i.e. an encoded schema ID, and `data`.
+    fn push_frame<'a>(&mut self, parts: &mut Vec<Part<'a>>, prefix: &[u8], data: &'a [u8], more: bool) -> usize {
+        let n = prefix.len() + data.len();
+        let len = n as u32 | if more { self.header.continuation_bit() } else { 0 };
+        let start = self.buffer.len();
+        if self.header.entry_len_size() == 2 {
+            self.buffer.extend_from_slice(&(len as u16).to_be_bytes())
+        } else {
+            self.buffer.extend_from_slice(&len.to_be_bytes())
+        }
+        self.buffer.extend_from_slice(prefix);
+        // The length is adjacent to the checksum of the previous frame.
+        push_meta(parts, start .. self.buffer.len());
+        if !data.is_empty() {
+            parts.push(Part::Data(data))
+        }
+        let mut crc = CRC32C.digest();
+        crc.update(prefix);
+        crc.update(data);
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(&crc.finalize().to_be_bytes());
+        parts.push(Part::Meta(start .. self.buffer.len()));
+        usize::from(self.header.entry_len_size()) + n + 4
+    }
+
+    /// The maximum length of a frame.
+    ///
+    /// Frame lengths must not be mistaken for a trailer marker.
+    fn frame_limit(&self) -> u32 {
+        match (self.header.is_chunked(), self.header.has_trailer()) {
+            (true, true)   => self.header.continuation_bit() - 2,
+            (true, false)  => self.header.continuation_bit() - 1,
+            (false, true)  => self.header.trailer_marker() - 1,
+            (false, false) => u32::MAX
+        }
+    }
+
+    /// The maximum length of encoded entry metadata.
+    ///
+    /// Metadata is never chunked. Unless entries are, it is limited to the
+    /// maximum entry length like the entry itself.
+    pub(crate) fn max_metadata_len(&self) -> usize {
+        if self.header.is_chunked() {
+            self.frame_limit() as usize
+        } else {
+            self.config.max_entry_len.min(self.frame_limit()) as usize
+        }
+    }
+
+    /// The maximum length of an entry with the given schema ID that is not
+    /// chunked.
+    ///
+    /// The encoded schema ID is part of the frame and counts towards the limit.
+    pub(crate) fn max_entry_len(&self, schema: Option<u32>) -> usize {
+        let max = self.config.max_entry_len.min(self.frame_limit()) as usize;
+        match schema.or(self.config.schema) {
+            Some(s) if self.header.has_schema() => max.saturating_sub(schema::encode_id(s, &mut [0; MAX_ID_LEN]).len()),
+            _                                   => max
+        }
+    }
+
+    /// Add a frame of encoded metadata and return its length.
+    fn push_metadata(&mut self, parts: &mut Vec<Part<'_>>, meta: &[u8]) -> usize {
+        let start = self.buffer.len();
+        if self.header.entry_len_size() == 2 {
+            self.buffer.extend_from_slice(&(meta.len() as u16).to_be_bytes())
+        } else {
+            self.buffer.extend_from_slice(&(meta.len() as u32).to_be_bytes())
+        }
+        self.buffer.extend_from_slice(meta);
+        self.buffer.extend_from_slice(&CRC32C.checksum(meta).to_be_bytes());
+        push_meta(parts, start .. self.buffer.len());
+        usize::from(self.header.entry_len_size()) + meta.len() + 4
+    }
+
     pub async fn sync(&mut self) -> Result<(), WriteError> {
+        #[cfg(feature = "testing")]
+        if let Some(f) = &self.config.faults {
+            f.wrap(self.current.file_mut()).flush().await?
+        }
         self.current.file_mut().flush().await?;
-        self.current.file_mut().get_mut().sync_data().await?;
+        if let Some(b) = &self.blocks {
+            b.sync(self.current.file_mut().get_mut()).await?
+        }
+        counters::count(Counter::Syncs, 1);
         Ok(())
     }
 
     async fn start_new_block(&mut self) -> Result<(), WriteError> {
+        if self.header.has_trailer() {
+            self.write_trailer().await?
+        }
         self.sync().await?;
-        let n = self.current.info().number().add(1u8);
-        let f = append_to(self.config.max_buffer_len, self.directory.join(block_file_name(n))).await?;
+        self.config.rotated(*self.current.info());
+        let n = self.current.info().number().next().ok_or(WriteError::Exhausted)?;
+        let blocks = self.blocks.as_deref().expect("only blocks of a store are rotated");
+        let f = append_to(blocks, &self.config, n).await?;
         let i = BlockInfo::zero().with_number(n);
         self.current = Block::new(f).with_info(i);
         self.write_header().await?;
+        counters::count(Counter::Blocks, 1);
+        Ok(())
+    }
+
+    async fn write_trailer(&mut self) -> Result<(), WriteError> {
+        let summary = std::mem::replace(&mut self.summary, Summary::new());
+        let trailer = Trailer::new(summary.entries, summary.digest.finalize());
+        let marker = self.header.trailer_marker();
+        let mut buf = Vec::with_capacity(16);
+        if self.header.entry_len_size() == 2 {
+            buf.extend_from_slice(&(marker as u16).to_be_bytes())
+        } else {
+            buf.extend_from_slice(&marker.to_be_bytes())
+        }
+        buf.extend_from_slice(&trailer.entries().to_be_bytes());
+        buf.extend_from_slice(&trailer.crc().to_be_bytes());
+        self.current.file_mut().write_all(&buf).await?;
+        self.current.info_mut().add_offset(buf.len() as u64);
+        self.usage += buf.len() as u64;
         Ok(())
     }
 
+    /// Write the block header.
+    ///
+    /// The header is flushed right away, so that readers never see a block
+    /// file without one, even if the block is abandoned after a failed write.
     async fn write_header(&mut self) -> Result<(), WriteError> {
         self.current.file_mut().write_u64(self.header.to_u64()).await?;
-        self.current.info_mut().add_offset(8u8);
+        self.current.file_mut().flush().await?;
+        self.current.info_mut().add_offset(HEADER_LEN);
+        self.usage += HEADER_LEN;
         Ok(())
     }
 }
 
-async fn append_to(buf: usize, path: impl AsRef<Path>) -> Result<BufWriter<File>, WriteError> {
-    OpenOptions::new()
-        .append(true)
-        .create_new(true)
-        .open(path)
-        .await
-        .map(|f| BufWriter::with_capacity(buf, f))
-        .map_err(WriteError::Io)
+/// Add a range of `EntryWriter::buffer`, merging it with an adjacent one.
+fn push_meta(parts: &mut Vec<Part<'_>>, range: Range<usize>) {
+    match parts.last_mut() {
+        Some(Part::Meta(r)) if r.end == range.start => r.end = range.end,
+        _ => parts.push(Part::Meta(range))
+    }
 }
 
-pub(crate) async fn latest_block_number(dir: &Path) -> io::Result<BlockNum> {
-    let mut latest = BlockNum::zero();
-    let mut dir = fs::read_dir(dir).await?;
-    while let Some(e) = dir.next_entry().await? {
-        if !e.file_name().to_str().map(|n| n.starts_with(BLOCK_FILENAME_PREFIX)).unwrap_or(false) {
-            continue
-        }
-        if !e.file_type().await?.is_file() {
-            continue
-        }
-        let n = read_block_num(e.path());
-        if latest < n {
-            latest = n
+/// Write entries to a block file, injecting faults if configured.
+///
+/// `written` counts the bytes accepted, also if the write fails.
+async fn write_entries<W>
+    ( f: &mut BufWriter<W>
+    , _cfg: &Config
+    , bufs: &mut [IoSlice<'_>]
+    , written: &mut u64
+    ) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin
+{
+    #[cfg(feature = "testing")]
+    if let Some(faults) = &_cfg.faults {
+        return write_all_vectored(&mut faults.wrap(f), bufs, written).await
+    }
+    write_all_vectored(f, bufs, written).await
+}
+
+async fn write_all_vectored<W>(w: &mut W, mut bufs: &mut [IoSlice<'_>], written: &mut u64) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin
+{
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        let n = match w.write_vectored(bufs).await {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+        counters::count(Counter::WriteCalls, 1);
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into())
         }
+        *written += n as u64;
+        IoSlice::advance_slices(&mut bufs, n)
+    }
+    Ok(())
+}
+
+/// Create block `n`.
+async fn append_to<W>(blocks: &dyn Blocks<W>, cfg: &Config, n: BlockNum) -> Result<BufWriter<W>, WriteError>
+where
+    W: AsyncWrite
+{
+    let f = blocks.create(n).await?;
+    Ok(BufWriter::with_capacity(cfg.max_buffer_len, f))
+}
+
+/// The blocks of a store, as written by an `EntryWriter<W>`.
+trait Blocks<W>: fmt::Debug + Send + Sync {
+    fn store(&self) -> &dyn BlockStore;
+
+    fn create(&self, n: BlockNum) -> BoxFuture<'_, io::Result<W>>;
+
+    /// Make the data written to a block durable.
+    fn sync<'a>(&self, w: &'a mut W) -> BoxFuture<'a, io::Result<()>>;
+}
+
+impl<S: BlockStore> Blocks<Box<dyn BlockWrite>> for S {
+    fn store(&self) -> &dyn BlockStore {
+        self
+    }
+
+    fn create(&self, n: BlockNum) -> BoxFuture<'_, io::Result<Box<dyn BlockWrite>>> {
+        BlockStore::create(self, n)
+    }
+
+    fn sync<'a>(&self, w: &'a mut Box<dyn BlockWrite>) -> BoxFuture<'a, io::Result<()>> {
+        w.sync()
+    }
+}
+
+/// The header of blocks written with the given config.
+pub(super) fn block_header(cfg: &Config) -> BlockHeader {
+    let mut flags = 0;
+    if cfg.chunking {
+        flags |= FLAG_CHUNKED
+    }
+    if cfg.trailer {
+        flags |= FLAG_TRAILER
+    }
+    match cfg.digest {
+        Some(DigestKind::Xxh64)  => flags |= FLAG_DIGEST_XXH64,
+        Some(DigestKind::Blake3) => flags |= FLAG_DIGEST_BLAKE3,
+        None                     => {}
+    }
+    if cfg.entry_metadata {
+        flags |= FLAG_METADATA
+    }
+    if cfg.schema.is_some() {
+        flags |= FLAG_SCHEMA
+    }
+    let h = BlockHeader::new().with_flags(flags);
+    if cfg.digest.is_some() || cfg.entry_metadata || cfg.schema.is_some() {
+        h.with_version(3)
+    } else if cfg.max_entry_len > h.continuation_bit() - 1 {
+        h.with_version(2)
+    } else {
+        h
     }
-    Ok(latest)
+}
+
+pub(crate) async fn latest_block_number(dir: &Path, names: &BlockNames) -> io::Result<BlockNum> {
+    let blocks = list_blocks_with(dir, names).await?;
+    Ok(blocks.last().map(|b| b.number()).unwrap_or_else(BlockNum::zero))
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -116,5 +727,26 @@ pub enum WriteError {
     Io(#[from] io::Error),
 
     #[error("entry too large")]
-    EntrySize
+    EntrySize,
+
+    #[error("block numbers exhausted")]
+    Exhausted,
+
+    #[error("disk quota exceeded")]
+    Quota,
+
+    #[error("entry metadata not enabled")]
+    NoMetadata,
+
+    #[error("schema IDs not enabled")]
+    NoSchema,
+
+    #[error("block damaged by an earlier write error")]
+    Poisoned,
+
+    #[error("directory locked by another writer: {0:?}")]
+    Locked(PathBuf),
+
+    #[error("invalid config: {0}")]
+    Config(#[from] ConfigError)
 }
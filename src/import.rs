@@ -0,0 +1,304 @@
+//! Import newline-delimited plain text or JSON into block storage.
+
+use std::{io, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use serde_json::{Map, Value as Json};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tracing::warn;
+
+use crate::{EntryWriter, Level, LogRecord, Value, WriteError};
+
+/// The format of imported lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineFormat {
+    /// Every line is the message of a log record.
+    #[default]
+    Text,
+    /// Every line is a JSON object.
+    ///
+    /// The keys `level`, `timestamp` (or `time`, `ts`), `target` and
+    /// `message` (or `msg`) are mapped to the respective parts of a log
+    /// record, all other keys become fields.
+    Json
+}
+
+/// Appends lines as CBOR-encoded `LogRecord`s to an `EntryWriter`.
+#[derive(Debug, Clone)]
+pub struct Importer {
+    format: LineFormat,
+    level: Level,
+    target: String,
+    skip_invalid: bool
+}
+
+/// The outcome of an import.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    lines: u64,
+    imported: u64,
+    skipped: u64
+}
+
+impl ImportSummary {
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn imported(&self) -> u64 {
+        self.imported
+    }
+
+    /// Number of empty or (if skipped) invalid lines.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl Importer {
+    pub fn new(format: LineFormat) -> Self {
+        Self {
+            format,
+            level: Level::Info,
+            target: "import".to_string(),
+            skip_invalid: false
+        }
+    }
+
+    /// The level of records without one.
+    pub fn with_level(mut self, l: Level) -> Self {
+        self.level = l;
+        self
+    }
+
+    /// The target of records without one.
+    pub fn with_target<T: Into<String>>(mut self, t: T) -> Self {
+        self.target = t.into();
+        self
+    }
+
+    /// Skip lines which can not be parsed instead of failing.
+    pub fn with_skip_invalid(mut self, val: bool) -> Self {
+        self.skip_invalid = val;
+        self
+    }
+
+    /// Parse a single line.
+    pub fn parse(&self, line: &str) -> Result<LogRecord, ImportError> {
+        match self.format {
+            LineFormat::Text => Ok(LogRecord::new(self.level, &*self.target, line)),
+            LineFormat::Json => {
+                let Json::Object(obj) = serde_json::from_str(line)? else {
+                    return Err(ImportError::NoObject)
+                };
+                Ok(self.record(obj))
+            }
+        }
+    }
+
+    /// Read all lines and append them to the writer.
+    ///
+    /// The writer is synced at the end.
+    pub async fn import<R>(&self, r: R, w: &mut EntryWriter) -> Result<ImportSummary, ImportError>
+    where
+        R: AsyncBufRead + Unpin
+    {
+        let mut summary = ImportSummary::default();
+        let mut lines = r.lines();
+        while let Some(line) = lines.next_line().await? {
+            summary.lines += 1;
+            if line.trim().is_empty() {
+                summary.skipped += 1;
+                continue
+            }
+            let record = match self.parse(&line) {
+                Ok(r) => r,
+                Err(err) if self.skip_invalid => {
+                    warn!(line = %summary.lines, %err, "skipping invalid line");
+                    summary.skipped += 1;
+                    continue
+                }
+                Err(err) => return Err(ImportError::Line(summary.lines, Box::new(err)))
+            };
+            let bytes = minicbor::to_vec(&record).map_err(io::Error::other)?;
+            w.append(&bytes).await?;
+            summary.imported += 1
+        }
+        w.sync().await?;
+        Ok(summary)
+    }
+
+    fn record(&self, obj: Map<String, Json>) -> LogRecord {
+        let mut level = self.level;
+        let mut target = self.target.clone();
+        let mut message = String::new();
+        let mut timestamp = None;
+        let mut fields = Vec::new();
+        for (k, v) in obj {
+            match (k.as_str(), v) {
+                (_, Json::Null) => {}
+                ("level" | "severity", Json::String(s)) => match parse_level(&s) {
+                    Some(l) => level = l,
+                    None    => fields.push((k, to_value(Json::String(s))))
+                },
+                ("timestamp" | "time" | "ts", v) => match parse_time(&v) {
+                    Some(t) => timestamp = Some(t),
+                    None    => fields.push((k, to_value(v)))
+                },
+                ("target" | "logger", Json::String(s)) => target = s,
+                ("message" | "msg", Json::String(s)) => message = s,
+                (_, v) => fields.push((k, to_value(v)))
+            }
+        }
+        let mut r = LogRecord::new(level, target, message);
+        if let Some(t) = timestamp {
+            r = r.with_timestamp(t)
+        }
+        for (k, v) in fields {
+            r.set_field(k, v)
+        }
+        r
+    }
+}
+
+fn parse_level(s: &str) -> Option<Level> {
+    match s.to_ascii_lowercase().as_str() {
+        "trace"                                     => Some(Level::Trace),
+        "debug"                                     => Some(Level::Debug),
+        "info" | "notice"                           => Some(Level::Info),
+        "warn" | "warning"                          => Some(Level::Warn),
+        "error" | "err" | "fatal" | "critical" | "crit" => Some(Level::Error),
+        _                                           => None
+    }
+}
+
+fn to_value(v: Json) -> Value {
+    match v {
+        Json::Bool(b) => Value::Bool(b),
+        Json::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                Value::U64(u)
+            } else if let Some(i) = n.as_i64() {
+                Value::I64(i)
+            } else {
+                Value::F64(n.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        Json::String(s) => Value::Str(s),
+        other => Value::Str(other.to_string())
+    }
+}
+
+/// Parse a UNIX timestamp or an RFC 3339 string.
+///
+/// The unit of UNIX timestamps is guessed from their magnitude.
+fn parse_time(v: &Json) -> Option<SystemTime> {
+    match v {
+        Json::Number(n) => {
+            let x = n.as_f64()?;
+            if x < 0.0 {
+                return None
+            }
+            let secs = if x < 1e11 { x } else if x < 1e14 { x / 1e3 } else if x < 1e17 { x / 1e6 } else { x / 1e9 };
+            Some(UNIX_EPOCH + Duration::from_secs_f64(secs))
+        }
+        Json::String(s) => parse_rfc3339(s),
+        _ => None
+    }
+}
+
+fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let num = |a: usize, b: usize| s.get(a .. b)?.parse::<u32>().ok();
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || !matches!(b[10], b'T' | b't' | b' ') {
+        return None
+    }
+    let (y, m, d) = (num(0, 4)?, num(5, 7)?, num(8, 10)?);
+    let (hh, mm, ss) = (num(11, 13)?, num(14, 16)?, num(17, 19)?);
+    if !(1 ..= 12).contains(&m) || !(1 ..= 31).contains(&d) || hh > 23 || mm > 59 || ss > 60 {
+        return None
+    }
+    let mut rest = &s[19 ..];
+    let mut nanos = 0;
+    if let Some(frac) = rest.strip_prefix('.') {
+        let n = frac.bytes().take_while(u8::is_ascii_digit).count();
+        let digits = &frac[.. n.min(9)];
+        nanos = digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32);
+        rest = &frac[n ..]
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? { b'+' => 1, b'-' => -1, _ => return None };
+            let h = rest.get(1 .. 3)?.parse::<i64>().ok()?;
+            let m = rest.get(4 .. 6)?.parse::<i64>().ok()?;
+            sign * (h * 3600 + m * 60)
+        }
+    };
+    let days = days_from_civil(i64::from(y), m, d);
+    let secs = days * 86400 + i64::from(hh * 3600 + mm * 60 + ss) - offset;
+    let secs = u64::try_from(secs).ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+// (year, month, day) to days since 1970-01-01, cf.
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = i64::from(if m > 2 { m - 3 } else { m + 9 });
+    let doy = (153 * mp + 2) / 5 + i64::from(d) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("write error: {0}")]
+    Write(#[from] WriteError),
+
+    #[error("invalid json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("json line is not an object")]
+    NoObject,
+
+    #[error("line {0}: {1}")]
+    Line(u64, Box<ImportError>)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::{Level, Value};
+    use super::{Importer, LineFormat, parse_rfc3339};
+
+    #[test]
+    fn rfc3339() {
+        let t = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(Some(t), parse_rfc3339("2023-11-14T22:13:20.123Z"));
+        assert_eq!(Some(t), parse_rfc3339("2023-11-15T00:13:20.123+02:00"));
+        assert_eq!(Some(UNIX_EPOCH), parse_rfc3339("1970-01-01T00:00:00Z"));
+        assert_eq!(None, parse_rfc3339("2023-13-14T22:13:20Z"))
+    }
+
+    #[test]
+    fn json_line() {
+        let i = Importer::new(LineFormat::Json);
+        let r = i.parse(r#"{"level":"warning","ts":1700000000,"msg":"hi","n":1,"x":null}"#).unwrap();
+        assert_eq!(Level::Warn, r.level());
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(1_700_000_000), r.timestamp());
+        assert_eq!("import", r.target());
+        assert_eq!("hi", r.message());
+        assert_eq!(Some(&Value::U64(1)), r.field("n"));
+        assert_eq!(None, r.field("x"));
+        let r = i.parse(r#"{"level":"loud","time":"later","msg":"hi"}"#).unwrap();
+        assert_eq!(Level::Info, r.level());
+        assert_eq!(Some(&Value::Str("loud".into())), r.field("level"));
+        assert_eq!(Some(&Value::Str("later".into())), r.field("time"));
+        assert!(i.parse("[1]").is_err())
+    }
+}
@@ -1,13 +1,65 @@
 mod fs;
 mod logger;
 mod forward;
+mod metrics;
+mod query;
+mod receive;
+mod retention;
+mod record;
+mod stream;
 
-pub use fs::{BlockInfo, BlockNum, EntryReader, EntryWriter, Config, ReadError, WriteError};
-pub use fs::delete_blocks;
-pub use logger::{Logger, LogError};
-pub use forward::{Forwarder, ForwardError, Record, Handshake, HandshakeResponse, Ack};
+#[cfg(feature = "tracing-layer")]
+pub mod tracing_layer;
 
-const CRC32C: crc::Crc<u32> =
-    crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+#[cfg(feature = "log")]
+pub mod log_backend;
+
+#[cfg(feature = "import")]
+pub mod import;
+
+#[cfg(feature = "parquet")]
+pub mod export;
+
+#[cfg(feature = "testing")]
+pub mod testing;
 
-const BLOCK_FILENAME_PREFIX: &str = "block.";
+#[cfg(feature = "serde")]
+mod settings;
+
+#[cfg(all(feature = "systemd", unix))]
+pub mod systemd;
+
+pub use fs::{BlockInfo, BlockNum, EntryReader, EntryWriter, LogReader, Config, ConfigError, ReadAhead, ReadError, WriteError};
+pub use fs::{Digest, DigestKind, Metadata, Schemas, SchemaError, Trailer, BlockStatus, BlockNames, verify_block, verify_block_with, SHARD_LEN};
+#[cfg(feature = "mmap")]
+pub use fs::MmapEntryReader;
+#[cfg(feature = "bench")]
+pub use fs::{Counters, counters, reset_counters};
+pub use fs::{delete_blocks, delete_blocks_with, DeleteOptions, DeleteSummary};
+pub use fs::{compact_blocks, compact_blocks_with, CompactError, CompactOptions, CompactSummary};
+pub use fs::{migrate_blocks_with, MigrateError, MigrateOptions, MigrateSummary};
+pub use fs::{list_blocks, list_blocks_with, BlockMeta, QuotaPolicy, available_space};
+pub use fs::{BlockStore, BlockRead, BlockWrite, FileStore, MemoryStore};
+pub use logger::{Logger, LoggerGuard, LogError, Health, Router, Topic, Transform};
+pub use forward::{Forwarder, ForwardError, ForwardErrorKind, ForwardProgress, Record, RecordRef, Handshake, HandshakeResponse, Ack, AckStatus, Reply, RateLimit};
+pub use forward::{Strategy, Lane, Backfill, Capabilities, Compression, Multiplexed, Replay, Resume, Window, PROTOCOL_VERSION};
+pub use forward::{ForwardConfig, ForwarderHandle, Identity, MultiForwarder, QUARANTINE_DIR, TimeWindow, InvalidTimeWindow};
+#[cfg(feature = "http")]
+pub use forward::{HttpForwarder, HttpFormat};
+#[cfg(feature = "s3")]
+pub use forward::{S3Forwarder, Manifest, MANIFEST_SUFFIX};
+#[cfg(feature = "kafka")]
+pub use forward::KafkaForwarder;
+pub use receive::{Receiver, ReceiveError, AckCadence, Received, Replayer, Sink, FileSink, NullSink, RelaySink, RelayAck, Source, Authenticator, OwnRecords, ReplayPolicy, Tokens, Middleware, Enrich, Verdict, ReceiveContext};
+pub use metrics::{Metrics, Counter, Gauge};
+pub use query::{Query, QueryError, query};
+pub use retention::{Retention, ArchiveAction, forwarded, forwarded_with};
+pub use record::{LogRecord, Level, Value};
+#[cfg(feature = "syslog")]
+pub use record::SyslogFormat;
+pub use stream::{LogSet, Stream};
+#[cfg(feature = "serde")]
+pub use settings::{Settings, SettingsError};
+
+static CRC32C: crc::Crc<u32> =
+    crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::kv::{self, VisitSource};
+
+use crate::{Logger, LogRecord, Level, Value};
+
+/// A `log::Log` implementation which appends records to a `Logger`.
+///
+/// Within a tokio runtime, records are added without waiting, i.e. they are
+/// dropped (and counted) if the logger can not keep up. Outside of a runtime
+/// the calling thread blocks until the record is accepted.
+#[derive(Debug)]
+pub struct LogBackend {
+    logger: Logger<LogRecord>,
+    level: log::LevelFilter,
+    dropped: AtomicU64
+}
+
+impl LogBackend {
+    pub fn new(logger: Logger<LogRecord>) -> Self {
+        Self {
+            logger,
+            level: log::LevelFilter::Trace,
+            dropped: AtomicU64::new(0)
+        }
+    }
+
+    pub fn with_max_level(mut self, level: log::LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Number of records dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Install this backend as the global logger.
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl log::Log for LogBackend {
+    fn enabled(&self, m: &log::Metadata<'_>) -> bool {
+        m.level() <= self.level
+    }
+
+    fn log(&self, r: &log::Record<'_>) {
+        if !self.enabled(r.metadata()) {
+            return
+        }
+        let level = match r.level() {
+            log::Level::Trace => Level::Trace,
+            log::Level::Debug => Level::Debug,
+            log::Level::Info  => Level::Info,
+            log::Level::Warn  => Level::Warn,
+            log::Level::Error => Level::Error
+        };
+        let mut v = Visitor(LogRecord::new(level, r.target(), r.args().to_string()));
+        let _ = r.key_values().visit(&mut v);
+        let result =
+            if tokio::runtime::Handle::try_current().is_ok() {
+                self.logger.try_add(v.0)
+            } else {
+                self.logger.blocking_add(v.0)
+            };
+        if result.is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.logger.try_sync();
+    }
+}
+
+struct Visitor(LogRecord);
+
+impl<'kvs> VisitSource<'kvs> for Visitor {
+    fn visit_pair(&mut self, k: kv::Key<'kvs>, v: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let v = if let Some(b) = v.to_bool() {
+            Value::Bool(b)
+        } else if let Some(u) = v.to_u64() {
+            Value::U64(u)
+        } else if let Some(i) = v.to_i64() {
+            Value::I64(i)
+        } else if let Some(x) = v.to_f64() {
+            Value::F64(x)
+        } else {
+            Value::Str(v.to_string())
+        };
+        self.0.set_field(k.as_str(), v);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{BlockInfo, Config, Level, LogReader, LogRecord, Logger, Value};
+    use super::LogBackend;
+
+    #[test]
+    fn log_inside_and_outside_runtime() {
+        let dir = Path::new("/tmp/logs-test-log-backend");
+        if dir.is_dir() {
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+        std::fs::create_dir(dir).unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let logger = rt.block_on(Logger::<LogRecord>::new(dir, Config::default())).unwrap();
+        LogBackend::new(logger.clone()).with_max_level(log::LevelFilter::Info).install().unwrap();
+
+        // Outside of a runtime the thread waits until the record is accepted.
+        log::debug!(target: "app", n = 1; "hidden");
+        log::info!(target: "app", n = 2; "outside");
+        rt.block_on(async {
+            log::warn!(target: "app", ok = true, name = "x"; "inside")
+        });
+        log::logger().flush();
+        rt.block_on(logger.close()).unwrap();
+
+        let records = rt.block_on(async {
+            let mut r = LogReader::new(dir, BlockInfo::zero());
+            let mut records = Vec::new();
+            while let Some((_, e)) = r.next_entry().await.unwrap() {
+                records.push(minicbor::decode::<LogRecord>(&e).unwrap())
+            }
+            records
+        });
+        // Other tests may log through the global logger as well.
+        let records: Vec<_> = records.into_iter().filter(|r| r.target() == "app").collect();
+        assert_eq!(2, records.len());
+        assert_eq!((Level::Info, "outside"), (records[0].level(), records[0].message()));
+        assert_eq!(Some(&Value::U64(2)), records[0].field("n"));
+        assert_eq!((Level::Warn, "inside"), (records[1].level(), records[1].message()));
+        assert_eq!(Some(&Value::Bool(true)), records[1].field("ok"));
+        assert_eq!(Some(&Value::Str("x".into())), records[1].field("name"))
+    }
+}
@@ -1,123 +1,697 @@
-use std::{path::Path, time::Duration};
+use std::{num::NonZeroU32, ops::Deref, path::Path, pin::Pin, sync::{Arc, Mutex, mpsc as std_mpsc}, time::Duration};
+use std::{fmt, task::{Context, Poll}};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
-use minicbor::Encode;
-use tokio::{sync::{mpsc::{self, error::TryRecvError}, oneshot}, select};
-use tokio::time::sleep;
+use bytes::Bytes;
+use futures_util::{Sink, ready};
+use minicbor::{Encode, Encoder, encode};
+use tokio::{io::AsyncWrite, sync::{mpsc::{self, error::TryRecvError}, oneshot, Notify}, select};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_util::sync::PollSender;
 
-use crate::{EntryWriter, Config, WriteError};
+use crate::{BlockStore, EntryWriter, Config, ConfigError, Metadata, QuotaPolicy, WriteError};
+
+mod router;
+mod transform;
+
+pub use router::{Router, Topic};
+pub use transform::Transform;
+pub(crate) use transform::TransformHook;
+
+use transform::Transformed;
 
-#[derive(Debug)]
 pub struct Logger<T> {
-    sender: mpsc::Sender<Command<T>>
+    sender: mpsc::Sender<Command<T>>,
+    /// Created on demand by the `Sink` implementation.
+    sink: Option<PollSender<Command<T>>>,
+    /// Decides which values are logged, cf. `Logger::with_filter`.
+    filter: Option<Filter<T>>,
+    state: Arc<State>
+}
+
+type Filter<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+
+impl<T> fmt::Debug for Logger<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger").field("state", &self.state).finish_non_exhaustive()
+    }
+}
+
+/// State shared between a `Logger` and its writer task.
+#[derive(Debug, Default)]
+struct State {
+    /// Set while the disk quota is exceeded and entries are refused.
+    refused: AtomicBool,
+    /// Set while appending fails and entries are kept for retrying.
+    failing: AtomicBool,
+    pending: AtomicUsize,
+    dropped: AtomicU64,
+    /// Values not logged because of filters or sampling.
+    filtered: AtomicU64,
+    /// Whether entries may have metadata, cf. `Config::with_entry_metadata`.
+    metadata: bool,
+    /// The maximum length of encoded metadata the writer accepts.
+    max_metadata_len: usize,
+    /// Whether values are encoded in a separate task, which then receives
+    /// close requests.
+    encoding_stage: bool,
+    /// Parties which asked to close the logger while its channel was full,
+    /// cf. `LoggerGuard`.
+    closers: Mutex<Vec<Closer>>,
+    close: Notify
+}
+
+impl State {
+    /// Ask the logger to close without going through its channel.
+    fn request_close(&self, c: Closer) {
+        self.closers.lock().unwrap_or_else(|e| e.into_inner()).push(c);
+        self.close.notify_one()
+    }
+
+    /// Take requests made with `State::request_close`.
+    ///
+    /// The channel is closed with the first request, so that the logger
+    /// stops once all values sent before have been written.
+    fn take_closers<T>(&self, rx: &mut mpsc::Receiver<Command<T>>, closers: &mut Vec<Closer>) {
+        let requested = std::mem::take(&mut *self.closers.lock().unwrap_or_else(|e| e.into_inner()));
+        if requested.is_empty() {
+            return
+        }
+        if closers.is_empty() {
+            rx.close()
+        }
+        closers.extend(requested)
+    }
+}
+
+/// The health of a `Logger`, cf. `Logger::health`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    refused: bool,
+    failing: bool,
+    pending: usize,
+    dropped: u64,
+    filtered: u64
+}
+
+impl Health {
+    /// Entries are written without problems.
+    pub fn is_healthy(&self) -> bool {
+        !self.refused && !self.failing
+    }
+
+    /// New entries are refused because the disk quota is exceeded.
+    pub fn is_refusing(&self) -> bool {
+        self.refused
+    }
+
+    /// Appending entries fails and is retried.
+    pub fn is_failing(&self) -> bool {
+        self.failing
+    }
+
+    /// The number of entries waiting to be retried.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// The total number of entries which have been discarded.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The total number of values which were filtered or sampled out, cf.
+    /// `Logger::with_filter`, or dropped by a `Transform`.
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
 }
 
 enum Command<T> {
-    Add(T),
+    Add(T, Option<Metadata>),
+    /// An entry which the caller has already encoded.
+    Raw(Bytes, Option<Metadata>),
     Sync,
-    Close(oneshot::Sender<()>)
+    Update(Box<Config>, oneshot::Sender<Result<(), ConfigError>>),
+    Close(Closer)
+}
+
+/// A party waiting for the logger to close.
+#[derive(Debug)]
+enum Closer {
+    Async(oneshot::Sender<()>),
+    Blocking(std_mpsc::Sender<()>)
+}
+
+impl Closer {
+    fn notify(self) {
+        match self {
+            Self::Async(tx)    => { let _ = tx.send(()); }
+            Self::Blocking(tx) => { let _ = tx.send(()); }
+        }
+    }
 }
 
 impl<T> Clone for Logger<T> {
     fn clone(&self) -> Self {
-        Self { sender: self.sender.clone() }
+        Self {
+            sender: self.sender.clone(),
+            sink: None,
+            filter: self.filter.clone(),
+            state: self.state.clone()
+        }
     }
 }
 
 impl<T: Encode<()> + Send + 'static> Logger<T> {
     pub async fn new<P: AsRef<Path>>(dir: P, cfg: Config) -> Result<Self, LogError> {
-        let mut writer = EntryWriter::open(dir, cfg).await?;
-        let (tx, mut rx) = mpsc::channel(100);
-        tokio::spawn(async move {
-            let mut buf = Vec::new();
-            let mut closers = Vec::new();
-
-            'main: loop {
-                // Try to process all immediately available items.
-                loop {
-                    match rx.try_recv() {
-                        Ok(it) => on_item(it, &mut writer, &mut buf, &mut closers, &mut rx).await,
-                        Err(TryRecvError::Empty) => break,
-                        Err(TryRecvError::Disconnected) => break 'main
-                    }
-                }
-                // Once the channel is empty, wait for the next item or sync the writer
-                // after a short amount of time if no command shows up.
-                select! {
-                    it = rx.recv() =>
-                        if let Some(it) = it {
-                            on_item(it, &mut writer, &mut buf, &mut closers, &mut rx).await
-                        } else {
-                            break
-                        },
-                    () = sleep(Duration::from_secs(3)) =>
-                        if let Err(err) = writer.sync().await {
-                            tracing::error!(%err, "failed to sync log writer")
-                        }
-                }
-                // To not repeat the syncing over and over again in case no item appears for
-                // some time we now wait indefinitely for the next one before starting over.
-                if let Some(it) = rx.recv().await {
-                    on_item(it, &mut writer, &mut buf, &mut closers, &mut rx).await
-                } else {
-                    break
-                }
-            }
+        Ok(Self::new_with_writer(EntryWriter::open(dir, cfg).await?))
+    }
 
-            // A final sync after the channel is closed.
-            if let Err(err) = writer.sync().await {
-                tracing::error!(%err, "failed to sync log writer")
-            }
+    /// Log to blocks of the given store, cf. `EntryWriter::open_in`.
+    pub async fn new_in<S: BlockStore>(store: S, cfg: Config) -> Result<Self, LogError> {
+        Ok(Self::new_with_writer(EntryWriter::open_in(store, cfg).await?))
+    }
 
-            // Unblock all parties that closed the logger and exit.
-            for tx in closers {
-                let _ = tx.send(());
-            }
+    /// Log with an entry writer the caller has set up.
+    ///
+    /// Batching, channel capacity and quota handling follow the writer's
+    /// config. Writers without a block store, cf. `EntryWriter::from_writer`,
+    /// can not recover from failed writes.
+    pub fn new_with_writer<W>(writer: EntryWriter<W>) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + Sync + 'static
+    {
+        Self::new_with_writer_and_context(writer, ())
+    }
+}
+
+impl<T: Send + 'static> Logger<T> {
+    /// Log values which are encoded with the given context.
+    pub async fn new_with_context<P, C>(dir: P, cfg: Config, ctx: C) -> Result<Self, LogError>
+    where
+        P: AsRef<Path>,
+        T: Encode<C>,
+        C: Send + 'static
+    {
+        Ok(Self::new_with_writer_and_context(EntryWriter::open(dir, cfg).await?, ctx))
+    }
+
+    /// Log with an entry writer the caller has set up and encode values with
+    /// the given context, cf. `Logger::new_with_writer`.
+    ///
+    /// The writer task, or the encoding stage if enabled, owns the context.
+    pub fn new_with_writer_and_context<W, C>(writer: EntryWriter<W>, ctx: C) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + Sync + 'static,
+        T: Encode<C>,
+        C: Send + 'static
+    {
+        let cfg = writer.config();
+        let encoding_stage = cfg.encoding_stage();
+        let state = Arc::new(State {
+            metadata: cfg.entry_metadata(),
+            max_metadata_len: writer.max_metadata_len(),
+            encoding_stage,
+            ..State::default()
         });
-        Ok(Self { sender: tx })
+        let batch = Batch::new(&writer, state.clone());
+        let capacity = cfg.channel_capacity();
+        let (tx, rx) = mpsc::channel(capacity);
+        if encoding_stage {
+            let (etx, erx) = mpsc::channel(capacity);
+            tokio::spawn(encode_values(rx, etx, state.clone(), ctx));
+            tokio::spawn(write_values(erx, writer, batch, ()));
+        } else {
+            tokio::spawn(write_values(rx, writer, batch, ctx));
+        }
+        Self { sender: tx, sink: None, filter: None, state }
+    }
+
+    /// Only log values for which `f` returns true.
+    ///
+    /// Filters are applied in the order they were added, before values are
+    /// encoded, and apply to this logger and its clones made afterwards.
+    /// Encoded entries, cf. `Logger::add_raw`, are not filtered.
+    pub fn with_filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        self.filter = Some(match self.filter.take() {
+            Some(g) => Arc::new(move |v| g(v) && f(v)),
+            None    => Arc::new(f)
+        });
+        self
+    }
+
+    /// Only log every `n`th value for which `f` returns true.
+    ///
+    /// Other values are not affected, e.g. sampling debug entries with
+    /// `n = 100` keeps the first of every 100 of them.
+    pub fn with_sampling<F>(self, n: NonZeroU32, f: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        let count = AtomicU64::new(0);
+        self.with_filter(move |v| !f(v) || count.fetch_add(1, Ordering::Relaxed).is_multiple_of(u64::from(n.get())))
+    }
+
+    /// Check if `val` passes all filters, counting it as filtered otherwise.
+    fn accepts(&self, val: &T) -> bool {
+        if self.filter.as_ref().is_none_or(|f| f(val)) {
+            return true
+        }
+        self.state.filtered.fetch_add(1, Ordering::Relaxed);
+        false
     }
 
     pub async fn add(&self, val: T) -> Result<(), LogError> {
-        self.sender.send(Command::Add(val)).await.map_err(|_| LogError::Closed)
+        self.check_quota()?;
+        if !self.accepts(&val) {
+            return Ok(())
+        }
+        self.sender.send(Command::Add(val, None)).await.map_err(|_| LogError::Closed)
+    }
+
+    /// Add a value together with its metadata, cf. `Config::with_entry_metadata`.
+    pub async fn add_with_metadata(&self, val: T, meta: Metadata) -> Result<(), LogError> {
+        self.check_quota()?;
+        self.check_metadata(&meta)?;
+        if !self.accepts(&val) {
+            return Ok(())
+        }
+        self.sender.send(Command::Add(val, Some(meta))).await.map_err(|_| LogError::Closed)
+    }
+
+    /// Add an entry which is already CBOR encoded.
+    ///
+    /// The bytes are appended as they are, without being encoded or copied
+    /// into an intermediate buffer. They must form exactly one CBOR data item.
+    pub async fn add_raw(&self, bytes: Bytes) -> Result<(), LogError> {
+        self.check_quota()?;
+        self.sender.send(Command::Raw(bytes, None)).await.map_err(|_| LogError::Closed)
+    }
+
+    /// Add an encoded entry together with its metadata, cf. `Logger::add_raw`.
+    pub async fn add_raw_with_metadata(&self, bytes: Bytes, meta: Metadata) -> Result<(), LogError> {
+        self.check_quota()?;
+        self.check_metadata(&meta)?;
+        self.sender.send(Command::Raw(bytes, Some(meta))).await.map_err(|_| LogError::Closed)
+    }
+
+    /// Add an encoded entry without waiting if the logger is busy.
+    pub fn try_add_raw(&self, bytes: Bytes) -> Result<(), LogError> {
+        self.check_quota()?;
+        self.sender.try_send(Command::Raw(bytes, None)).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_)   => LogError::Full,
+            mpsc::error::TrySendError::Closed(_) => LogError::Closed
+        })
+    }
+
+    /// Add a value without waiting if the logger is busy.
+    pub fn try_add(&self, val: T) -> Result<(), LogError> {
+        self.check_quota()?;
+        if !self.accepts(&val) {
+            return Ok(())
+        }
+        self.sender.try_send(Command::Add(val, None)).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_)   => LogError::Full,
+            mpsc::error::TrySendError::Closed(_) => LogError::Closed
+        })
+    }
+
+    /// Add a value, blocking the current thread if the logger is busy.
+    ///
+    /// This must not be called from within an asynchronous execution context.
+    pub fn blocking_add(&self, val: T) -> Result<(), LogError> {
+        self.check_quota()?;
+        if !self.accepts(&val) {
+            return Ok(())
+        }
+        self.sender.blocking_send(Command::Add(val, None)).map_err(|_| LogError::Closed)
+    }
+
+    fn check_quota(&self) -> Result<(), LogError> {
+        if self.state.refused.load(Ordering::Relaxed) {
+            return Err(LogError::Quota)
+        }
+        Ok(())
+    }
+
+    /// Refuse metadata the writer could not store, which would fail the
+    /// whole batch the entry is appended with.
+    fn check_metadata(&self, meta: &Metadata) -> Result<(), LogError> {
+        if meta.is_empty() {
+            return Ok(())
+        }
+        if !self.state.metadata {
+            return Err(LogError::NoMetadata)
+        }
+        if meta.to_bytes().len() > self.state.max_metadata_len {
+            return Err(LogError::MetadataSize)
+        }
+        Ok(())
+    }
+
+    /// Check whether entries are written without problems.
+    pub fn health(&self) -> Health {
+        Health {
+            refused: self.state.refused.load(Ordering::Relaxed),
+            failing: self.state.failing.load(Ordering::Relaxed),
+            pending: self.state.pending.load(Ordering::Relaxed),
+            dropped: self.state.dropped.load(Ordering::Relaxed),
+            filtered: self.state.filtered.load(Ordering::Relaxed)
+        }
     }
 
     pub async fn sync(&self) -> Result<(), LogError> {
         self.sender.send(Command::Sync).await.map_err(|_| LogError::Closed)
     }
 
+    /// Request a sync without waiting if the logger is busy.
+    pub fn try_sync(&self) -> Result<(), LogError> {
+        self.sender.try_send(Command::Sync).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_)   => LogError::Full,
+            mpsc::error::TrySendError::Closed(_) => LogError::Closed
+        })
+    }
+
+    /// Change the block length, disk quota and retry settings while running.
+    ///
+    /// Buffered entries are kept. Other settings of `cfg` are ignored, cf.
+    /// `EntryWriter::update_config`.
+    pub async fn update_config(&self, cfg: Config) -> Result<(), LogError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(Command::Update(Box::new(cfg), tx)).await.map_err(|_| LogError::Closed)?;
+        rx.await.map_err(|_| LogError::Closed)?.map_err(|e| LogError::Write(e.into()))
+    }
+
     pub async fn close(&self) -> Result<(), LogError> {
         let (tx, rx) = oneshot::channel();
-        self.sender.send(Command::Close(tx)).await.map_err(|_| LogError::Closed)?;
+        self.sender.send(Command::Close(Closer::Async(tx))).await.map_err(|_| LogError::Closed)?;
         rx.await.map_err(|_| LogError::Closed)?;
         Ok(())
     }
+
+    /// Like `Logger::close` but gives up after the given duration.
+    ///
+    /// The logger is still closed, but buffered entries may not have been
+    /// written yet when this returns with `LogError::Timeout`.
+    pub async fn close_timeout(&self, d: Duration) -> Result<(), LogError> {
+        timeout(d, self.close()).await.map_err(|_| LogError::Timeout)?
+    }
+
+    /// Create a guard which closes this logger when dropped.
+    pub fn guard(&self, timeout: Duration) -> LoggerGuard<T> {
+        LoggerGuard { logger: self.clone(), timeout }
+    }
+}
+
+/// Values are added like with `Logger::add`, waiting while the logger is busy.
+///
+/// Closing the sink only releases its channel capacity. Use `Logger::close`
+/// to close the logger itself.
+impl<T: Send + 'static> Sink<T> for Logger<T> {
+    type Error = LogError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.check_quota()?;
+        let this = &mut *self;
+        let sink = this.sink.get_or_insert_with(|| PollSender::new(this.sender.clone()));
+        ready!(sink.poll_reserve(cx)).map_err(|_| LogError::Closed)?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, val: T) -> Result<(), Self::Error> {
+        let accepted = self.accepts(&val);
+        let Some(sink) = &mut self.sink else {
+            return Err(LogError::Closed)
+        };
+        if !accepted {
+            sink.abort_send();
+            return Ok(())
+        }
+        sink.send_item(Command::Add(val, None)).map_err(|_| LogError::Closed)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Some(mut sink) = self.sink.take() {
+            sink.close()
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Closes a `Logger` when dropped, e.g. when unwinding from a panic.
+///
+/// Dropping the guard waits up to its timeout for buffered entries to be
+/// written and synced. Within a current-thread runtime the logger can not
+/// make progress while the guard waits, so the guard only requests the
+/// logger to close without waiting. The same applies to multi-threaded
+/// runtimes unless the `rt-multi-thread` feature is enabled. The request
+/// is made even if the logger's channel is full.
+#[derive(Debug)]
+pub struct LoggerGuard<T> {
+    logger: Logger<T>,
+    timeout: Duration
 }
 
-async fn on_item<T>
+impl<T> Deref for LoggerGuard<T> {
+    type Target = Logger<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.logger
+    }
+}
+
+impl<T> Drop for LoggerGuard<T> {
+    fn drop(&mut self) {
+        let (tx, rx) = std_mpsc::channel();
+        match self.logger.sender.try_send(Command::Close(Closer::Blocking(tx))) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(Command::Close(c))) => self.logger.state.request_close(c),
+            Err(_) => return
+        }
+        let wait = || {
+            if rx.recv_timeout(self.timeout).is_err() {
+                tracing::warn!("logger did not close in time")
+            }
+        };
+        match Handle::try_current().map(|h| h.runtime_flavor()) {
+            Ok(RuntimeFlavor::CurrentThread) => {}
+            // Other tasks are moved off this worker while it waits.
+            #[cfg(feature = "rt-multi-thread")]
+            Ok(_)  => tokio::task::block_in_place(wait),
+            #[cfg(not(feature = "rt-multi-thread"))]
+            Ok(_)  => {}
+            Err(_) => wait()
+        }
+    }
+}
+
+/// Encode values and pass them on to the writer task.
+async fn encode_values<T, C>
+    ( mut rx: mpsc::Receiver<Command<T>>
+    , tx: mpsc::Sender<Command<Encoded>>
+    , state: Arc<State>
+    , mut ctx: C
+    )
+where
+    T: Encode<C>
+{
+    let mut closers = Vec::new();
+    loop {
+        let cmd = select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None      => break
+            },
+            () = state.close.notified() => {
+                state.take_closers(&mut rx, &mut closers);
+                continue
+            }
+        };
+        let cmd = match cmd {
+            Command::Add(v, m) => match minicbor::to_vec_with(v, &mut ctx) {
+                Ok(bytes) => Command::Add(Encoded(bytes), m),
+                Err(err)  => {
+                    tracing::error!(%err, "failed to encode log entry");
+                    state.dropped.fetch_add(1, Ordering::Relaxed);
+                    continue
+                }
+            },
+            Command::Raw(b, m) => Command::Raw(b, m),
+            Command::Sync      => Command::Sync,
+            Command::Update(c, tx) => Command::Update(c, tx),
+            Command::Close(c) => {
+                rx.close();
+                Command::Close(c)
+            }
+        };
+        if tx.send(cmd).await.is_err() {
+            break
+        }
+    }
+    for c in closers {
+        let _ = tx.send(Command::Close(c)).await;
+    }
+}
+
+async fn write_values<T, C, W>
+    ( mut rx: mpsc::Receiver<Command<T>>
+    , mut writer: EntryWriter<W>
+    , mut batch: Batch
+    , mut ctx: C
+    )
+where
+    T: Encode<C>,
+    W: AsyncWrite + Unpin
+{
+    let mut closers = Vec::new();
+    // Close requests go to the encoding stage if there is one.
+    let requests = !batch.state.encoding_stage;
+
+    'main: loop {
+        // Try to process all immediately available items.
+        loop {
+            if requests {
+                batch.state.take_closers(&mut rx, &mut closers)
+            }
+            match rx.try_recv() {
+                Ok(it) => on_item(it, &mut writer, &mut batch, &mut closers, &mut rx, &mut ctx).await,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break 'main
+            }
+        }
+        batch.write(&mut writer).await;
+        // Once the channel is empty, wait for the next item or sync the writer
+        // after a short amount of time if no command shows up.
+        match next_item(&mut rx, &mut writer, &mut batch, Some(Duration::from_secs(3)), requests).await {
+            Next::Item(it) => {
+                // More items may have been queued in the meantime, which
+                // are added to the batch before it is written.
+                on_item(it, &mut writer, &mut batch, &mut closers, &mut rx, &mut ctx).await;
+                continue
+            }
+            Next::Idle     =>
+                if let Err(err) = writer.sync().await {
+                    tracing::error!(%err, "failed to sync log writer")
+                },
+            Next::Close    => batch.state.take_closers(&mut rx, &mut closers),
+            Next::Closed   => break
+        }
+        batch.write(&mut writer).await;
+        // To not repeat the syncing over and over again in case no item appears for
+        // some time we now wait indefinitely for the next one before starting over.
+        match next_item(&mut rx, &mut writer, &mut batch, None, requests).await {
+            Next::Item(it) => on_item(it, &mut writer, &mut batch, &mut closers, &mut rx, &mut ctx).await,
+            Next::Idle     => {}
+            Next::Close    => batch.state.take_closers(&mut rx, &mut closers),
+            Next::Closed   => break
+        }
+    }
+
+    // A final write attempt after the channel is closed.
+    batch.retry_now();
+    batch.write(&mut writer).await;
+    if let Err(err) = writer.close().await {
+        tracing::error!(%err, "failed to close log writer")
+    }
+
+    // Unblock all parties that closed the logger and exit.
+    if requests {
+        batch.state.take_closers(&mut rx, &mut closers)
+    }
+    for c in closers {
+        c.notify()
+    }
+}
+
+enum Next<T> {
+    Item(Command<T>),
+    Idle,
+    /// Closing has been requested, cf. `State::request_close`.
+    Close,
+    Closed
+}
+
+/// Wait for the next command, at most for the given idle duration.
+///
+/// While entries are refused because the disk quota is exceeded, check
+/// periodically whether blocks have been removed in the meantime. While
+/// appending fails, retry writing the pending entries.
+async fn next_item<T, W: AsyncWrite + Unpin>
+    ( rx: &mut mpsc::Receiver<Command<T>>
+    , writer: &mut EntryWriter<W>
+    , batch: &mut Batch
+    , idle: Option<Duration>
+    , requests: bool
+    ) -> Next<T>
+{
+    let deadline = idle.map(|d| Instant::now() + d);
+    loop {
+        let wakeup = batch.retry_at.or_else(|| {
+            batch.state.refused.load(Ordering::Relaxed).then(|| Instant::now() + Duration::from_secs(1))
+        });
+        select! {
+            it = rx.recv() => return it.map(Next::Item).unwrap_or(Next::Closed),
+            () = batch.state.close.notified(), if requests => return Next::Close,
+            () = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => return Next::Idle,
+            () = sleep_until(wakeup.unwrap_or_else(Instant::now)), if wakeup.is_some() => batch.recheck(writer).await
+        }
+    }
+}
+
+/// A value which has already been encoded by the encoding stage.
+struct Encoded(Vec<u8>);
+
+impl Encode<()> for Encoded {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>, _: &mut ()) -> Result<(), encode::Error<W::Error>> {
+        e.writer_mut().write_all(&self.0).map_err(encode::Error::write)
+    }
+}
+
+async fn on_item<T, C, W>
     ( item: Command<T>
-    , writer: &mut EntryWriter
-    , buf: &mut Vec<u8>
-    , closers: &mut Vec<oneshot::Sender<()>>
+    , writer: &mut EntryWriter<W>
+    , batch: &mut Batch
+    , closers: &mut Vec<Closer>
     , rx: &mut mpsc::Receiver<Command<T>>
+    , ctx: &mut C
     )
 where
-    T: Encode<()>
+    T: Encode<C>,
+    W: AsyncWrite + Unpin
 {
     match item {
-        Command::Add(v) => {
-            buf.clear();
-            if let Err(err) = minicbor::encode(v, &mut *buf) {
-                tracing::error!(%err, "failed to encode log entry");
-                return
+        Command::Add(v, m) => {
+            if batch.push(v, m, ctx) {
+                batch.write(writer).await
             }
-            if let Err(err) = writer.append(buf).await {
-                tracing::error!(%err, "failed to append log entry")
+        }
+        Command::Raw(b, m) => {
+            if batch.push_raw(b, m) {
+                batch.write(writer).await
             }
         }
         Command::Sync => {
+            batch.write(writer).await;
             if let Err(err) = writer.sync().await {
                 tracing::error!(%err, "failed to sync log writer")
             }
         }
+        Command::Update(cfg, tx) => {
+            let result = writer.update_config(&cfg);
+            if result.is_ok() {
+                batch.update(&cfg)
+            }
+            let _ = tx.send(result);
+        }
         Command::Close(tx) => {
             if closers.is_empty() {
                 rx.close();
@@ -127,11 +701,251 @@ where
     }
 }
 
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Encoded entries waiting to be appended.
+///
+/// If appending fails the entries are kept, up to `max_retry_bytes`, and
+/// appending is retried with exponential backoff.
+struct Batch {
+    buffer: Vec<u8>,
+    entries: Vec<Slot>,
+    /// The total length of all entries.
+    bytes: usize,
+    metadata: Vec<Option<Metadata>>,
+    max_len: usize,
+    max_bytes: usize,
+    max_entry_len: Option<usize>,
+    max_retry_bytes: usize,
+    quota_policy: QuotaPolicy,
+    transform: Option<TransformHook>,
+    retry_at: Option<Instant>,
+    backoff: Duration,
+    state: Arc<State>
+}
+
+/// Where the bytes of a batch entry are.
+enum Slot {
+    /// Encoded into the batch buffer, up to the given offset.
+    Buffer(usize),
+    /// Passed in by the caller, cf. `Logger::add_raw`.
+    Raw(Bytes)
+}
+
+impl Batch {
+    fn new<W: AsyncWrite + Unpin>(writer: &EntryWriter<W>, state: Arc<State>) -> Self {
+        let cfg = writer.config();
+        Self {
+            buffer: Vec::new(),
+            entries: Vec::new(),
+            bytes: 0,
+            metadata: Vec::new(),
+            max_len: cfg.max_batch_len(),
+            max_bytes: cfg.max_batch_bytes(),
+            max_entry_len: (!cfg.chunking()).then(|| writer.max_entry_len(None)),
+            max_retry_bytes: cfg.max_retry_bytes(),
+            quota_policy: cfg.quota_policy(),
+            transform: cfg.transform().cloned(),
+            retry_at: None,
+            backoff: MIN_BACKOFF,
+            state
+        }
+    }
+
+    fn update(&mut self, cfg: &Config) {
+        self.max_retry_bytes = cfg.max_retry_bytes();
+        self.quota_policy = cfg.quota_policy()
+    }
+
+    fn retry_now(&mut self) {
+        if self.retry_at.is_some() {
+            self.retry_at = Some(Instant::now())
+        }
+    }
+
+    /// Retry appending pending entries or check if the disk quota
+    /// permits writing again.
+    async fn recheck<W: AsyncWrite + Unpin>(&mut self, writer: &mut EntryWriter<W>) {
+        if self.retry_at.is_some() {
+            return self.write(writer).await
+        }
+        match writer.has_room().await {
+            Ok(true)  => self.state.refused.store(false, Ordering::Relaxed),
+            Ok(false) => {}
+            Err(err)  => tracing::error!(%err, "failed to check disk quota")
+        }
+    }
+
+    fn drop_entries(&self, n: usize) {
+        self.state.dropped.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.entries.clear();
+        self.bytes = 0;
+        self.metadata.clear();
+        self.state.pending.store(0, Ordering::Relaxed)
+    }
+
+    /// Encode and add a value to this batch.
+    ///
+    /// Returns `true` if the batch is full and should be written.
+    fn push<T: Encode<C>, C>(&mut self, val: T, meta: Option<Metadata>, ctx: &mut C) -> bool {
+        if self.is_retry_full() {
+            tracing::warn!("retry buffer full, dropping log entry");
+            self.drop_entries(1);
+            return false
+        }
+        let start = self.buffer.len();
+        if let Err(err) = minicbor::encode_with(val, &mut self.buffer, ctx) {
+            tracing::error!(%err, "failed to encode log entry");
+            self.buffer.truncate(start);
+            self.drop_entries(1);
+            return false
+        }
+        if let Some(t) = &self.transform {
+            match t.apply(&self.buffer[start ..]) {
+                Transformed::Unchanged   => {}
+                Transformed::Replaced(e) => {
+                    self.buffer.truncate(start);
+                    self.buffer.extend_from_slice(&e)
+                }
+                Transformed::Dropped     => {
+                    self.buffer.truncate(start);
+                    self.state.filtered.fetch_add(1, Ordering::Relaxed);
+                    return false
+                }
+            }
+        }
+        if self.max_entry_len.map(|n| self.buffer.len() - start > n).unwrap_or(false) {
+            tracing::error!(err = %WriteError::EntrySize, "failed to append log entry");
+            self.buffer.truncate(start);
+            self.drop_entries(1);
+            return false
+        }
+        self.bytes += self.buffer.len() - start;
+        self.entries.push(Slot::Buffer(self.buffer.len()));
+        self.added(meta)
+    }
+
+    /// Add an already encoded entry to this batch without copying it.
+    ///
+    /// Returns `true` if the batch is full and should be written.
+    fn push_raw(&mut self, mut bytes: Bytes, meta: Option<Metadata>) -> bool {
+        if self.is_retry_full() {
+            tracing::warn!("retry buffer full, dropping log entry");
+            self.drop_entries(1);
+            return false
+        }
+        if let Some(t) = &self.transform {
+            match t.apply(&bytes) {
+                Transformed::Unchanged   => {}
+                Transformed::Replaced(e) => bytes = Bytes::from(e),
+                Transformed::Dropped     => {
+                    self.state.filtered.fetch_add(1, Ordering::Relaxed);
+                    return false
+                }
+            }
+        }
+        if self.max_entry_len.map(|n| bytes.len() > n).unwrap_or(false) {
+            tracing::error!(err = %WriteError::EntrySize, "failed to append log entry");
+            self.drop_entries(1);
+            return false
+        }
+        self.bytes += bytes.len();
+        self.entries.push(Slot::Raw(bytes));
+        self.added(meta)
+    }
+
+    fn is_retry_full(&self) -> bool {
+        self.retry_at.is_some() && self.bytes >= self.max_retry_bytes
+    }
+
+    fn added(&mut self, meta: Option<Metadata>) -> bool {
+        self.metadata.push(meta);
+        self.state.pending.store(self.entries.len(), Ordering::Relaxed);
+        self.entries.len() >= self.max_len || self.bytes >= self.max_bytes
+    }
+
+    async fn write<W: AsyncWrite + Unpin>(&mut self, writer: &mut EntryWriter<W>) {
+        if self.entries.is_empty() {
+            return
+        }
+        if self.retry_at.map(|t| t > Instant::now()).unwrap_or(false) {
+            return
+        }
+        let mut start = 0;
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for (slot, meta) in self.entries.iter().zip(&self.metadata) {
+            match slot {
+                Slot::Buffer(end) => {
+                    entries.push((&self.buffer[start .. *end], meta.as_ref()));
+                    start = *end
+                }
+                Slot::Raw(bytes) => entries.push((&bytes[..], meta.as_ref()))
+            }
+        }
+        let n = self.entries.len();
+        match writer.append_batch_with_metadata(entries).await {
+            Ok(()) => {
+                if self.retry_at.is_some() {
+                    tracing::info!(n, "appended pending log entries")
+                }
+            }
+            Err(WriteError::Io(err)) => {
+                tracing::error!(%err, n, backoff = ?self.backoff, "failed to append log entries, will retry");
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                self.state.failing.store(true, Ordering::Relaxed);
+                return
+            }
+            Err(WriteError::Quota) if self.quota_policy == QuotaPolicy::DropNew => {
+                tracing::warn!(n, "disk quota exceeded, dropping log entries");
+                self.drop_entries(n)
+            }
+            Err(WriteError::Quota) => {
+                tracing::error!(n, "disk quota exceeded, refusing log entries");
+                self.state.refused.store(true, Ordering::Relaxed);
+                self.drop_entries(n)
+            }
+            Err(err) => {
+                tracing::error!(%err, n, "failed to append log entries");
+                self.drop_entries(n)
+            }
+        }
+        if self.retry_at.take().is_some() {
+            self.backoff = MIN_BACKOFF;
+            self.state.failing.store(false, Ordering::Relaxed)
+        }
+        self.clear()
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum LogError {
     #[error("storage error: {0}")]
     Write(#[from] WriteError),
 
     #[error("logger closed")]
-    Closed
+    Closed,
+
+    #[error("logger busy")]
+    Full,
+
+    #[error("timeout")]
+    Timeout,
+
+    #[error("disk quota exceeded")]
+    Quota,
+
+    #[error("entry metadata not enabled")]
+    NoMetadata,
+
+    #[error("entry metadata too large")]
+    MetadataSize,
+
+    #[error("no logger for topic {0:?}")]
+    NoRoute(String)
 }
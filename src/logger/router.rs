@@ -0,0 +1,125 @@
+use std::{collections::HashMap, fmt};
+
+use minicbor::Encode;
+
+use crate::{Config, Logger, LogError, Stream};
+
+/// The category of a value, which `Router` uses to choose a logger.
+pub trait Topic {
+    fn topic(&self) -> &str;
+}
+
+/// Logs values to different loggers depending on their topic.
+///
+/// This allows e.g. audit, metrics and debug logs to be kept in different
+/// directories or streams of a `LogSet`, each with its own config, retention
+/// and forwarding. Values of topics without a logger go to the default
+/// logger, if there is one, or are refused with `LogError::NoRoute`.
+pub struct Router<T> {
+    topic: fn(&T) -> &str,
+    routes: HashMap<String, Logger<T>>,
+    default: Option<Logger<T>>
+}
+
+impl<T> fmt::Debug for Router<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("routes", &self.routes.keys())
+            .field("default", &self.default.is_some())
+            .finish()
+    }
+}
+
+impl<T> Clone for Router<T> {
+    fn clone(&self) -> Self {
+        Self {
+            topic: self.topic,
+            routes: self.routes.clone(),
+            default: self.default.clone()
+        }
+    }
+}
+
+impl<T: Topic> Router<T> {
+    pub fn new() -> Self {
+        Self::with_topic_fn(T::topic)
+    }
+}
+
+impl<T: Topic> Default for Router<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Router<T> {
+    /// Route values by the topic `f` extracts from them.
+    pub fn with_topic_fn(f: fn(&T) -> &str) -> Self {
+        Self { topic: f, routes: HashMap::new(), default: None }
+    }
+
+    /// Log values of the given topic with `logger`.
+    pub fn with_route<S: Into<String>>(mut self, topic: S, logger: Logger<T>) -> Self {
+        self.routes.insert(topic.into(), logger);
+        self
+    }
+
+    /// Log values of topics without a route with `logger`.
+    pub fn with_default(mut self, logger: Logger<T>) -> Self {
+        self.default = Some(logger);
+        self
+    }
+
+    /// Get the logger of values with the given topic.
+    pub fn logger(&self, topic: &str) -> Option<&Logger<T>> {
+        self.routes.get(topic).or(self.default.as_ref())
+    }
+
+    fn route(&self, val: &T) -> Result<&Logger<T>, LogError> {
+        let topic = (self.topic)(val);
+        self.logger(topic).ok_or_else(|| LogError::NoRoute(topic.to_string()))
+    }
+
+    fn loggers(&self) -> impl Iterator<Item = &Logger<T>> {
+        self.routes.values().chain(&self.default)
+    }
+}
+
+impl<T: Encode<()> + Send + 'static> Router<T> {
+    /// Log values of the stream's name as topic to the stream.
+    pub async fn with_stream(self, stream: &Stream, cfg: Config) -> Result<Self, LogError> {
+        let logger = stream.logger(cfg).await?;
+        Ok(self.with_route(stream.name(), logger))
+    }
+}
+
+impl<T: Send + 'static> Router<T> {
+    pub async fn add(&self, val: T) -> Result<(), LogError> {
+        self.route(&val)?.add(val).await
+    }
+
+    /// Add a value without waiting if its logger is busy.
+    pub fn try_add(&self, val: T) -> Result<(), LogError> {
+        self.route(&val)?.try_add(val)
+    }
+
+    /// Sync all loggers.
+    pub async fn sync(&self) -> Result<(), LogError> {
+        for l in self.loggers() {
+            l.sync().await?
+        }
+        Ok(())
+    }
+
+    /// Close all loggers.
+    pub async fn close(&self) -> Result<(), LogError> {
+        for l in self.loggers() {
+            // A logger may be used for several topics.
+            match l.close().await {
+                Ok(()) | Err(LogError::Closed) => {}
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+}
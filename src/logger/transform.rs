@@ -0,0 +1,38 @@
+use std::{borrow::Cow, fmt, sync::Arc};
+
+/// Rewrites encoded entries before the `Logger` appends them, e.g. to
+/// redact personal data or to shorten large payloads.
+///
+/// Cf. `Config::with_transform`.
+pub trait Transform: Send + Sync + 'static {
+    /// Return the entry to append in place of `entry`, or `None` to drop it.
+    ///
+    /// The result must be a single CBOR data item, like `entry`.
+    fn transform<'a>(&self, entry: &'a [u8]) -> Option<Cow<'a, [u8]>>;
+}
+
+#[derive(Clone)]
+pub(crate) struct TransformHook(pub(crate) Arc<dyn Transform>);
+
+impl fmt::Debug for TransformHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TransformHook")
+    }
+}
+
+/// The result of transforming an entry.
+pub(crate) enum Transformed {
+    Unchanged,
+    Replaced(Vec<u8>),
+    Dropped
+}
+
+impl TransformHook {
+    pub(crate) fn apply(&self, entry: &[u8]) -> Transformed {
+        match self.0.transform(entry) {
+            None                                              => Transformed::Dropped,
+            Some(Cow::Borrowed(e)) if std::ptr::eq(e, entry) => Transformed::Unchanged,
+            Some(e)                                           => Transformed::Replaced(e.into_owned())
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Counters and gauges rendered in the Prometheus text format, and
+//! health checks for liveness and readiness probes.
+
+use std::{fmt::{self, Write}, io, net::SocketAddr, sync::{Arc, Mutex}};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, spawn};
+use tracing::{debug, error};
+
+/// Maximum size of an HTTP request head.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// A registry of metrics and health checks.
+///
+/// Metrics are registered by name and labels. Registering a name again
+/// returns the metric already registered, so forwarders and receivers
+/// sharing a registry add to the same counters.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    metrics: Arc<Mutex<Vec<Metric>>>,
+    checks: Arc<Mutex<Vec<(String, Check)>>>
+}
+
+/// A health check returns a description of the problem if unhealthy.
+type Check = Arc<dyn Fn() -> Result<(), String> + Send + Sync>;
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let checks = self.checks.lock().unwrap();
+        f.debug_struct("Metrics")
+            .field("metrics", &self.metrics)
+            .field("checks", &checks.iter().map(|c| &c.0).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Metric {
+    name: String,
+    /// Rendered labels, e.g. `{id="a"}`, or empty.
+    labels: String,
+    help: String,
+    kind: Kind,
+    value: Arc<AtomicU64>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge
+}
+
+/// A monotonically increasing metric.
+///
+/// A default counter is not registered anywhere.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+/// A metric which can go up and down.
+///
+/// A default gauge is not registered anywhere.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Gauge {
+    pub fn set(&self, n: u64) {
+        self.0.store(n, Ordering::Relaxed)
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get or register the counter of the given name.
+    pub fn counter<N, H>(&self, name: N, help: H) -> Counter
+    where
+        N: Into<String>,
+        H: Into<String>
+    {
+        Counter(self.register(name.into(), String::new(), help.into(), Kind::Counter))
+    }
+
+    /// Get or register the gauge of the given name.
+    pub fn gauge<N, H>(&self, name: N, help: H) -> Gauge
+    where
+        N: Into<String>,
+        H: Into<String>
+    {
+        Gauge(self.register(name.into(), String::new(), help.into(), Kind::Gauge))
+    }
+
+    /// Get or register the gauge of the given name and labels.
+    ///
+    /// Gauges of the same name but different labels are separate series,
+    /// e.g. one per forwarder.
+    pub fn gauge_with_labels<N, H>(&self, name: N, help: H, labels: &[(&str, &str)]) -> Gauge
+    where
+        N: Into<String>,
+        H: Into<String>
+    {
+        Gauge(self.register(name.into(), render_labels(labels), help.into(), Kind::Gauge))
+    }
+
+    fn register(&self, name: String, labels: String, help: String, kind: Kind) -> Arc<AtomicU64> {
+        let mut metrics = self.metrics.lock().unwrap();
+        if let Some(m) = metrics.iter().find(|m| m.name == name) {
+            assert_eq!(m.kind, kind, "metric {name} registered with different kind")
+        }
+        if let Some(m) = metrics.iter().find(|m| m.name == name && m.labels == labels) {
+            return m.value.clone()
+        }
+        let value = Arc::new(AtomicU64::new(0));
+        metrics.push(Metric { name, labels, help, kind, value: value.clone() });
+        value
+    }
+
+    /// Add a health check.
+    ///
+    /// Checks are run whenever the health endpoint is requested, so they
+    /// should be cheap.
+    pub fn add_check<N, F>(&self, name: N, check: F)
+    where
+        N: Into<String>,
+        F: Fn() -> Result<(), String> + Send + Sync + 'static
+    {
+        self.checks.lock().unwrap().push((name.into(), Arc::new(check)))
+    }
+
+    /// Run all health checks and return the failed ones.
+    pub fn failed_checks(&self) -> Vec<(String, String)> {
+        let checks = self.checks.lock().unwrap().clone();
+        checks.into_iter()
+            .filter_map(|(name, check)| check().err().map(|e| (name, e)))
+            .collect()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let metrics = self.metrics.lock().unwrap();
+        for (i, m) in metrics.iter().enumerate() {
+            // All series of a metric are rendered after its first one.
+            if metrics[.. i].iter().any(|n| n.name == m.name) {
+                continue
+            }
+            let kind = match m.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge   => "gauge"
+            };
+            let _ = writeln!(out, "# HELP {} {}", m.name, m.help);
+            let _ = writeln!(out, "# TYPE {} {kind}", m.name);
+            for s in metrics[i ..].iter().filter(|n| n.name == m.name) {
+                let _ = writeln!(out, "{}{} {}", s.name, s.labels, s.value.load(Ordering::Relaxed));
+            }
+        }
+        out
+    }
+
+    /// Serve the metrics over HTTP at `/metrics` and the health checks at
+    /// `/health`.
+    ///
+    /// The health endpoint responds with status 200 if all checks pass and
+    /// with status 503 and the problems found otherwise.
+    ///
+    /// Returns the address of the listening socket. Connections are served
+    /// in the background for as long as the runtime exists.
+    pub async fn serve(&self, address: &str) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address).await?;
+        let local = listener.local_addr()?;
+        let this = self.clone();
+        spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((sock, addr)) => {
+                        let this = this.clone();
+                        spawn(async move {
+                            if let Err(err) = this.respond(sock).await {
+                                debug!(%err, remote = %addr, "failed to serve metrics")
+                            }
+                        });
+                    }
+                    Err(err) => error!(%err, "failed to accept metrics connection")
+                }
+            }
+        });
+        Ok(local)
+    }
+
+    fn render_health(&self) -> (&'static str, String) {
+        let failed = self.failed_checks();
+        if failed.is_empty() {
+            return ("200 OK", String::from("ok\n"))
+        }
+        let mut out = String::new();
+        for (name, problem) in failed {
+            let _ = writeln!(out, "{name}: {problem}");
+        }
+        ("503 Service Unavailable", out)
+    }
+
+    async fn respond(&self, mut sock: TcpStream) -> io::Result<()> {
+        let mut buf = Vec::new();
+        while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            if buf.len() >= MAX_REQUEST_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "request too large"))
+            }
+            let mut chunk = [0; 1024];
+            let n = sock.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(())
+            }
+            buf.extend_from_slice(&chunk[.. n])
+        }
+        let line = buf.split(|b| *b == b'\r').next().unwrap_or_default();
+        let mut parts = line.split(|b| *b == b' ');
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some(b"GET"), Some(b"/metrics")) => ("200 OK", self.render()),
+            (Some(b"GET"), Some(b"/health"))  => self.render_health(),
+            (Some(b"GET"), _)                 => ("404 Not Found", String::new()),
+            _                                 => ("405 Method Not Allowed", String::new())
+        };
+        let head = format! {
+            "HTTP/1.1 {status}\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            body.len()
+        };
+        sock.write_all(head.as_bytes()).await?;
+        sock.write_all(body.as_bytes()).await?;
+        sock.shutdown().await
+    }
+}
+
+/// Render labels as `{name="value",...}`, escaping label values.
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new()
+    }
+    let mut out = String::from("{");
+    for (i, (name, value)) in labels.iter().enumerate() {
+        if i > 0 {
+            out.push(',')
+        }
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let _ = write!(out, "{name}=\"{value}\"");
+    }
+    out.push('}');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn render_text_format() {
+        let m = Metrics::new();
+        let c = m.counter("records_total", "Records seen.");
+        let g = m.gauge("lag_blocks", "Blocks behind.");
+        c.add(3);
+        m.counter("records_total", "Records seen.").inc();
+        g.set(7);
+        g.dec();
+        assert_eq! {
+            m.render(),
+            "# HELP records_total Records seen.\n\
+             # TYPE records_total counter\n\
+             records_total 4\n\
+             # HELP lag_blocks Blocks behind.\n\
+             # TYPE lag_blocks gauge\n\
+             lag_blocks 6\n"
+        }
+    }
+
+    #[test]
+    fn render_labeled_gauges() {
+        let m = Metrics::new();
+        m.gauge_with_labels("lag_blocks", "Blocks behind.", &[("id", "a")]).set(1);
+        m.counter("records_total", "Records seen.").inc();
+        m.gauge_with_labels("lag_blocks", "Blocks behind.", &[("id", "b\"c")]).set(2);
+        m.gauge_with_labels("lag_blocks", "Blocks behind.", &[("id", "a")]).inc();
+        assert_eq! {
+            m.render(),
+            "# HELP lag_blocks Blocks behind.\n\
+             # TYPE lag_blocks gauge\n\
+             lag_blocks{id=\"a\"} 2\n\
+             lag_blocks{id=\"b\\\"c\"} 2\n\
+             # HELP records_total Records seen.\n\
+             # TYPE records_total counter\n\
+             records_total 1\n"
+        }
+    }
+
+    #[test]
+    fn report_failed_checks() {
+        let m = Metrics::new();
+        let g = m.gauge("connections", "Open connections.");
+        let c = g.clone();
+        m.add_check("connection", move || if c.get() == 0 { Err("not connected".into()) } else { Ok(()) });
+        m.add_check("disk", || Ok(()));
+        assert_eq!(m.failed_checks(), vec![("connection".to_string(), "not connected".to_string())]);
+        g.inc();
+        assert!(m.failed_checks().is_empty())
+    }
+}
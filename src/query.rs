@@ -0,0 +1,141 @@
+use std::{collections::VecDeque, io, ops::Range, path::{Path, PathBuf}, time::SystemTime};
+
+use futures_util::{Stream, stream};
+use minicbor::Decode;
+
+use crate::{BlockInfo, BlockNames, BlockNum, EntryReader, FileStore, LogRecord, ReadError, list_blocks_with};
+
+/// Reads the entries of a directory which have been logged within a time range.
+///
+/// Every entry has been written before the last modification of its block,
+/// so blocks last modified before the start of the range are skipped. The
+/// entries of all other blocks are decoded and filtered by their timestamp.
+#[derive(Debug, Clone)]
+pub struct Query<T = LogRecord> {
+    dir: PathBuf,
+    names: BlockNames,
+    range: Range<SystemTime>,
+    sorted: bool,
+    timestamp: fn(&T) -> SystemTime
+}
+
+impl Query {
+    /// Query the `LogRecord`s of a directory.
+    pub fn new<P: AsRef<Path>>(dir: P, range: Range<SystemTime>) -> Self {
+        Self::with_timestamp_fn(dir, range, LogRecord::timestamp)
+    }
+}
+
+impl<T> Query<T> {
+    /// Query entries of another type, whose timestamp `f` extracts.
+    pub fn with_timestamp_fn<P: AsRef<Path>>(dir: P, range: Range<SystemTime>, f: fn(&T) -> SystemTime) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            names: BlockNames::default(),
+            range,
+            sorted: false,
+            timestamp: f
+        }
+    }
+
+    pub fn with_block_names(mut self, n: BlockNames) -> Self {
+        self.names = n;
+        self
+    }
+
+    /// Entries have been written in timestamp order, e.g. by a single
+    /// `Logger`, so reading stops at the first entry after the range.
+    pub fn with_sorted(mut self, val: bool) -> Self {
+        self.sorted = val;
+        self
+    }
+
+    /// The blocks which may contain entries of the range.
+    pub async fn blocks(&self) -> io::Result<Vec<BlockNum>> {
+        let blocks = list_blocks_with(&self.dir, &self.names).await?;
+        Ok(blocks.into_iter()
+            .filter(|b| b.modified().is_none_or(|t| t >= self.range.start))
+            .map(|b| b.number())
+            .collect())
+    }
+}
+
+impl<T: for<'b> Decode<'b, ()>> Query<T> {
+    /// Get the entries of the range together with their positions.
+    ///
+    /// The stream ends once all existing entries have been read or after
+    /// the first error.
+    pub fn into_stream(self) -> impl Stream<Item = Result<(BlockInfo, T), QueryError>> {
+        let store = FileStore::new(&self.dir).with_block_names(self.names.clone());
+        let cursor = Cursor { query: self, store, blocks: None, reader: None };
+        stream::unfold(Some(cursor), |this| async move {
+            let mut this = this?;
+            match this.next().await {
+                Ok(Some(e)) => Some((Ok(e), Some(this))),
+                Ok(None)    => None,
+                Err(e)      => Some((Err(e), None))
+            }
+        })
+    }
+}
+
+/// Read the `LogRecord`s of a directory logged at or after `from` and before `to`.
+pub fn query<P: AsRef<Path>>(dir: P, from: SystemTime, to: SystemTime) -> impl Stream<Item = Result<(BlockInfo, LogRecord), QueryError>> {
+    Query::new(dir, from .. to).into_stream()
+}
+
+struct Cursor<T> {
+    query: Query<T>,
+    store: FileStore,
+    /// The blocks still to read, once listed.
+    blocks: Option<VecDeque<BlockNum>>,
+    reader: Option<EntryReader>
+}
+
+impl<T: for<'b> Decode<'b, ()>> Cursor<T> {
+    async fn next(&mut self) -> Result<Option<(BlockInfo, T)>, QueryError> {
+        if self.blocks.is_none() {
+            self.blocks = Some(self.query.blocks().await?.into())
+        }
+        loop {
+            if let Some(r) = &mut self.reader {
+                let pos = r.block_info();
+                match r.next_entry().await? {
+                    Some((bytes, _)) => {
+                        let val = minicbor::decode::<T>(&bytes).map_err(|e| QueryError::Decode(pos, e))?;
+                        let t = (self.query.timestamp)(&val);
+                        if self.query.range.contains(&t) {
+                            return Ok(Some((pos, val)))
+                        }
+                        if self.query.sorted && t >= self.query.range.end {
+                            return Ok(None)
+                        }
+                        continue
+                    }
+                    None => self.reader = None
+                }
+            }
+            let Some(n) = self.blocks.as_mut().and_then(VecDeque::pop_front) else {
+                return Ok(None)
+            };
+            match EntryReader::open_in(&self.store, BlockInfo::zero().with_number(n)).await {
+                Ok(r) => self.reader = Some(r),
+                // Blocks may be deleted while they are queried.
+                Err(ReadError::Io(e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into())
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+
+    #[error("failed to decode entry at {0}: {1}")]
+    Decode(BlockInfo, #[source] minicbor::decode::Error)
+}
@@ -0,0 +1,703 @@
+use std::{fmt, future::Future, hash::{DefaultHasher, Hash, Hasher}, io, net::SocketAddr, pin::pin, sync::Arc, time::Duration};
+use std::time::Instant;
+
+use bytes::{Bytes, BytesMut};
+use minicbor_io::{AsyncReader, AsyncWriter};
+use futures_util::future;
+use tokio::{io::AsyncReadExt, net::{TcpListener, TcpStream, tcp::{OwnedReadHalf, OwnedWriteHalf}}, select, sync::{broadcast, mpsc, Mutex}, task::JoinSet, time::timeout};
+use tokio_util::{compat::{Compat, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt}, sync::CancellationToken};
+use tracing::{debug, error, info, warn};
+
+use crate::{BlockInfo, LogReader, ReadError};
+use crate::forward::{Ack, AckStatus, Capabilities, Handshake, HandshakeResponse, Lane, Record, RecordRef, Reply, Resume, Window, PROTOCOL_VERSION};
+use crate::metrics::{Counter, Gauge, Metrics};
+use middleware::Chain;
+
+mod auth;
+mod middleware;
+mod relay;
+mod replay;
+mod sink;
+
+pub use auth::{Authenticator, OwnRecords, ReplayPolicy, Tokens};
+pub use middleware::{ReceiveContext, Enrich, Middleware, Verdict};
+pub use relay::{RelayAck, RelaySink};
+pub use replay::Replayer;
+pub use sink::{Sink, FileSink, NullSink, Source};
+
+type Reader = AsyncReader<Compat<OwnedReadHalf>>;
+type Writer = AsyncWriter<Compat<OwnedWriteHalf>>;
+
+/// Accepts connections from forwarders and passes their records to a `Sink`.
+pub struct Receiver<S> {
+    listener: TcpListener,
+    sinks: Arc<Shards<S>>,
+    max_record_len: u32,
+    auth: Option<Arc<dyn Authenticator>>,
+    replay: Option<Arc<dyn ReplayPolicy>>,
+    middleware: Chain,
+    subscribers: broadcast::Sender<Received>,
+    ack_cadence: AckCadence,
+    window: Option<Window>,
+    metrics: ReceiveMetrics
+}
+
+/// When the `Receiver` flushes a stream and acknowledges its position.
+///
+/// Regardless of these settings, a stream is acknowledged whenever its
+/// sink reports progress to a new block and when the forwarder has been
+/// idle for a second. Acknowledging more often lets forwarders release
+/// blocks sooner at the cost of more flushes and acks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AckCadence {
+    records: Option<u64>,
+    bytes: Option<u64>,
+    interval: Option<Duration>
+}
+
+impl AckCadence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Acknowledge after the given number of records.
+    pub fn with_records(mut self, n: u64) -> Self {
+        self.records = Some(n);
+        self
+    }
+
+    /// Acknowledge after records with the given number of item bytes.
+    pub fn with_bytes(mut self, n: u64) -> Self {
+        self.bytes = Some(n);
+        self
+    }
+
+    /// Acknowledge at least once per interval while records are received.
+    pub fn with_interval(mut self, d: Duration) -> Self {
+        self.interval = Some(d);
+        self
+    }
+
+    pub fn records(&self) -> Option<u64> {
+        self.records
+    }
+
+    pub fn bytes(&self) -> Option<u64> {
+        self.bytes
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+}
+
+/// Records of a stream received since its last acknowledgement.
+#[derive(Debug, Clone, Copy)]
+struct Unacked {
+    acked: BlockInfo,
+    /// The latest position received.
+    received: BlockInfo,
+    records: u64,
+    bytes: u64,
+    since: Option<Instant>
+}
+
+impl Unacked {
+    fn new() -> Self {
+        Self { acked: BlockInfo::zero(), received: BlockInfo::zero(), records: 0, bytes: 0, since: None }
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.since.is_some()
+    }
+
+    fn add(&mut self, info: BlockInfo, bytes: usize) {
+        self.received = self.received.max(info);
+        self.records += 1;
+        self.bytes += bytes as u64;
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    fn is_due(&self, c: &AckCadence) -> bool {
+        c.records.map(|n| self.records >= n).unwrap_or(false)
+            || c.bytes.map(|n| self.bytes >= n).unwrap_or(false)
+            || self.deadline(c).map(|t| t <= Instant::now()).unwrap_or(false)
+    }
+
+    fn deadline(&self, c: &AckCadence) -> Option<Instant> {
+        Some(self.since? + c.interval?)
+    }
+
+    fn reset(&mut self) {
+        self.records = 0;
+        self.bytes = 0;
+        self.since = None
+    }
+}
+
+/// A validated record, as passed to subscribers.
+#[derive(Debug, Clone)]
+pub struct Received {
+    source: Arc<Source>,
+    record: Record
+}
+
+impl Received {
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn record(&self) -> &Record {
+        &self.record
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Receiver<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("listener", &self.listener)
+            .field("sinks", &self.sinks)
+            .field("max_record_len", &self.max_record_len)
+            .field("auth", &self.auth.is_some())
+            .field("replay", &self.replay.is_some())
+            .field("middleware", &self.middleware)
+            .field("subscribers", &self.subscribers.receiver_count())
+            .field("ack_cadence", &self.ack_cadence)
+            .field("window", &self.window)
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+impl<S: Sink> Receiver<S> {
+    pub async fn new(address: &str, sink: S) -> Result<Self, ReceiveError> {
+        Self::from_sinks(address, vec![Mutex::new(sink)]).await
+    }
+
+    /// Store records with `n` sinks created by `make`.
+    ///
+    /// Every source is assigned to one of the sinks, so that records of
+    /// sources assigned to different sinks are stored concurrently and one
+    /// slow sink does not hold up all connections. The sinks must not share
+    /// per-source state, e.g. a `FileSink` per worker on the same directory.
+    pub async fn new_with_workers<F>(address: &str, n: usize, mut make: F) -> Result<Self, ReceiveError>
+    where
+        F: FnMut() -> S
+    {
+        let sinks = (0 .. n.max(1)).map(|_| Mutex::new(make())).collect();
+        Self::from_sinks(address, sinks).await
+    }
+
+    async fn from_sinks(address: &str, sinks: Vec<Mutex<S>>) -> Result<Self, ReceiveError> {
+        Ok(Self {
+            listener: TcpListener::bind(address).await?,
+            sinks: Arc::new(Shards(sinks)),
+            max_record_len: 512 * 1024,
+            auth: None,
+            replay: None,
+            middleware: Chain::default(),
+            subscribers: broadcast::channel(1024).0,
+            ack_cadence: AckCadence::default(),
+            window: None,
+            metrics: ReceiveMetrics::default()
+        })
+    }
+
+    /// Limit the records and bytes a forwarder may send ahead of the
+    /// receiver, if the forwarder supports flow control.
+    ///
+    /// Credit is granted whenever half of the window has been processed.
+    pub fn with_window(mut self, w: Window) -> Self {
+        self.window = Some(w);
+        self
+    }
+
+    /// Set how often streams are flushed and acknowledged.
+    pub fn with_ack_cadence(mut self, c: AckCadence) -> Self {
+        self.ack_cadence = c;
+        self
+    }
+
+    /// The maximum size of an encoded record and of a decompressed item.
+    pub fn with_max_record_len(mut self, n: u32) -> Self {
+        self.max_record_len = n;
+        self
+    }
+
+    /// Only accept forwarders the given authenticator approves of.
+    pub fn with_authenticator<A: Authenticator>(mut self, a: A) -> Self {
+        self.auth = Some(Arc::new(a));
+        self
+    }
+
+    /// Let clients replay stored records if `p` allows it, cf. `Replayer`.
+    ///
+    /// Replay is disabled by default. `OwnRecords` only lets clients replay
+    /// the records they have forwarded. Authentication applies to replaying
+    /// clients as well.
+    pub fn with_replay<P: ReplayPolicy>(mut self, p: P) -> Self {
+        self.replay = Some(Arc::new(p));
+        self
+    }
+
+    /// Pass every valid record through the given middleware before it is
+    /// stored or passed to subscribers.
+    ///
+    /// Middleware is applied in the order it has been added. Rejected
+    /// records are dropped like invalid ones.
+    pub fn with_middleware<M: Middleware>(mut self, m: M) -> Self {
+        self.middleware.push(Arc::new(m));
+        self
+    }
+
+    /// Record metrics of this receiver in the given registry.
+    pub fn with_metrics(mut self, m: &Metrics) -> Self {
+        self.metrics = ReceiveMetrics::new(m);
+        self
+    }
+
+    /// Get every validated record in real time, in addition to the sink.
+    ///
+    /// Subscribers which fall behind miss records, cf. `broadcast::Receiver`.
+    /// Use `NullSink` to only pass records to subscribers.
+    pub fn subscribe(&self) -> broadcast::Receiver<Received> {
+        self.subscribers.subscribe()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ReceiveError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn go(self) -> ! {
+        self.go_until(future::pending()).await;
+        unreachable!("receiver only stops when signalled")
+    }
+
+    /// Receive until `signal` completes, then shut down gracefully.
+    ///
+    /// On shutdown no more connections are accepted and every connection
+    /// flushes and acknowledges the records received before it is closed.
+    /// Connections which do not finish in time are aborted.
+    pub async fn go_until<F: Future<Output = ()>>(self, signal: F) {
+        let stop = CancellationToken::new();
+        let mut tasks = JoinSet::new();
+        let mut signal = pin!(signal);
+        loop {
+            let (sock, addr) = select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok(a) => a,
+                    Err(err) => {
+                        error!(%err, "failed to accept connection");
+                        continue
+                    }
+                },
+                Some(_) = tasks.join_next() => continue,
+                _ = &mut signal => break
+            };
+            debug!(remote = %addr, "accepted connection");
+            let connections = self.metrics.connections.clone();
+            let incoming = Incoming {
+                sock,
+                sinks: self.sinks.clone(),
+                auth: self.auth.clone(),
+                replay: self.replay.clone(),
+                middleware: self.middleware.clone(),
+                subscribers: self.subscribers.clone(),
+                cadence: self.ack_cadence,
+                window: self.window,
+                max: self.max_record_len,
+                metrics: self.metrics.clone(),
+                stop: stop.clone()
+            };
+            tasks.spawn(async move {
+                connections.inc();
+                match receive(incoming).await {
+                    Ok(()) => debug!(remote = %addr, "connection closed"),
+                    Err(err) => error!(%err, remote = %addr, "receiver error")
+                }
+                connections.dec()
+            });
+        }
+        info!(connections = %tasks.len(), "shutting down");
+        stop.cancel();
+        let closed = timeout(CLOSE_TIMEOUT, async {
+            while tasks.join_next().await.is_some() {}
+        });
+        if closed.await.is_err() {
+            warn!(connections = %tasks.len(), "timeout closing connections, aborting");
+            tasks.shutdown().await
+        }
+    }
+}
+
+/// Sinks to which sources are assigned by their hash.
+#[derive(Debug)]
+struct Shards<S>(Vec<Mutex<S>>);
+
+impl<S> Shards<S> {
+    fn get(&self, source: &Source) -> &Mutex<S> {
+        let mut h = DefaultHasher::new();
+        source.hash(&mut h);
+        &self.0[h.finish() as usize % self.0.len()]
+    }
+}
+
+/// A connection accepted by a `Receiver`.
+struct Incoming<S> {
+    sock: TcpStream,
+    sinks: Arc<Shards<S>>,
+    auth: Option<Arc<dyn Authenticator>>,
+    replay: Option<Arc<dyn ReplayPolicy>>,
+    middleware: Chain,
+    subscribers: broadcast::Sender<Received>,
+    cadence: AckCadence,
+    window: Option<Window>,
+    max: u32,
+    metrics: ReceiveMetrics,
+    stop: CancellationToken
+}
+
+async fn receive<S: Sink>(incoming: Incoming<S>) -> Result<(), ReceiveError> {
+    let Incoming { sock, sinks, auth, replay, middleware, subscribers, cadence, window, max, metrics, stop } = incoming;
+    let peer = sock.peer_addr()?;
+    let (r, w) = sock.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    r.set_max_len(max);
+
+    let (sources, window, version, rewind) = {
+        let Some(hs) = r.read::<Handshake>().await? else {
+            return Ok(())
+        };
+        if let Some(a) = &auth {
+            if !a.authenticate(&hs) {
+                warn!(client = %hs.id(), "unauthorized forwarder");
+                w.write(HandshakeResponse::abort("unauthorized")).await?;
+                return Ok(())
+            }
+        }
+        if let Some(rp) = hs.replay() {
+            let source = Source::new(rp.client(), rp.stream());
+            if !replay.as_ref().is_some_and(|p| p.may_replay(&hs, &source)) {
+                warn!(client = %hs.id(), %source, "replay not allowed");
+                w.write(HandshakeResponse::abort("replay not allowed")).await?;
+                return Ok(())
+            }
+            let Some((dir, names)) = sinks.get(&source).lock().await.replay_dir(&source) else {
+                warn!(client = %hs.id(), %source, "replay not available");
+                w.write(HandshakeResponse::abort("replay not available")).await?;
+                return Ok(())
+            };
+            debug!(client = %hs.id(), %source, from = %rp.from(), "replaying records");
+            w.write(HandshakeResponse::go(rp.from()).negotiate(&hs)).await?;
+            let (from, until) = (rp.from(), rp.until());
+            return replay::serve(r, w, LogReader::new_with(dir, from, names), until).await
+        }
+        // Every stream is started individually, the first one being the
+        // handshake's own stream.
+        let multiplexed = hs.capabilities().contains(Capabilities::MULTIPLEX);
+        let count = if multiplexed { hs.multiplexed().len() + 1 } else { 1 };
+        let mut sources = Vec::with_capacity(count);
+        let mut response = None;
+        let mut resumes = Vec::new();
+        for i in 0 .. count as u32 {
+            let hs = hs.for_stream(i).expect("stream in handshake");
+            let source = Source::from_handshake(&hs);
+            let rsp = sinks.get(&source).lock().await.start(&hs).await.map_err(sink_error)?;
+            if let HandshakeResponse::Abort { message } = rsp {
+                warn!(client = %hs.id(), stream = ?hs.stream(), %message, "aborted handshake");
+                w.write(HandshakeResponse::Abort { message }).await?;
+                return Ok(())
+            }
+            if let HandshakeResponse::Go { start, backfill, .. } = rsp {
+                if i > 0 {
+                    resumes.push(Resume::new(start, backfill))
+                }
+            }
+            response.get_or_insert(rsp);
+            sources.push(Arc::new(source))
+        }
+        let mut response = response.expect("at least one stream");
+        if multiplexed {
+            response = response.with_multiplexed(resumes)
+        }
+        if replay.is_none() {
+            let caps = response.capabilities().without(Capabilities::REPLAY);
+            response = response.with_capabilities(caps)
+        }
+        let window = window.filter(|_| hs.capabilities().contains(Capabilities::FLOW_CONTROL));
+        if let Some(win) = window {
+            response = response.with_window(win)
+        }
+        w.write(&response).await?;
+        let rewind = hs.capabilities().contains(Capabilities::REWIND);
+        (sources, window, hs.version().min(PROTOCOL_VERSION), rewind)
+    };
+
+    // Records are read from the socket while earlier ones are stored.
+    let (tx, rx) = mpsc::channel(PIPELINE_LEN);
+    let connection = Connection { sources, peer, sinks, middleware, subscribers, cadence, window, max, version, rewind, metrics, stop };
+    tokio::try_join!(read_records(r, tx, max, &connection.stop), connection.store_records(rx, w))?;
+    Ok(())
+}
+
+/// Read encoded records until the forwarder or the receiver stops.
+///
+/// Records are read into a shared buffer, from which they are decoded
+/// without copying their items, cf. `RecordRef::into_record`.
+async fn read_records
+    ( r: Reader
+    , tx: mpsc::Sender<Bytes>
+    , max: u32
+    , stop: &CancellationToken
+    ) -> Result<(), ReceiveError>
+{
+    let mut r = r.into_parts().0.into_inner();
+    let mut buffer = BytesMut::new();
+    loop {
+        let read = select! {
+            read = read_frame(&mut r, &mut buffer, max as usize) => read?,
+            _ = stop.cancelled() => return Ok(())
+        };
+        let Some(frame) = read else {
+            return Ok(())
+        };
+        if tx.send(frame).await.is_err() {
+            return Ok(())
+        }
+    }
+}
+
+/// Read a CBOR item preceded by its length, like `AsyncReader::read`.
+async fn read_frame(r: &mut OwnedReadHalf, buffer: &mut BytesMut, max: usize) -> Result<Option<Bytes>, ReceiveError> {
+    let mut len = [0; 4];
+    if r.read(&mut len[.. 1]).await? == 0 {
+        return Ok(None)
+    }
+    r.read_exact(&mut len[1 ..]).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+        return Err(minicbor_io::Error::InvalidLen.into())
+    }
+    if buffer.capacity() < len {
+        buffer.reserve(len.max(FRAME_BUFFER_LEN))
+    }
+    buffer.resize(len, 0);
+    r.read_exact(&mut buffer[..]).await?;
+    Ok(Some(buffer.split().freeze()))
+}
+
+/// An established connection from a forwarder.
+struct Connection<S> {
+    sources: Vec<Arc<Source>>,
+    peer: SocketAddr,
+    sinks: Arc<Shards<S>>,
+    middleware: Chain,
+    subscribers: broadcast::Sender<Received>,
+    cadence: AckCadence,
+    window: Option<Window>,
+    max: u32,
+    /// The negotiated protocol version.
+    version: u8,
+    /// The forwarder may be asked to rewind, cf. `Sink::rewind`.
+    rewind: bool,
+    metrics: ReceiveMetrics,
+    stop: CancellationToken
+}
+
+impl<S: Sink> Connection<S> {
+    /// Validate and store records, acknowledging them to the forwarder.
+    ///
+    /// If the sink fails, forwarders which understand it are told why
+    /// before the connection is closed.
+    async fn store_records(&self, rx: mpsc::Receiver<Bytes>, mut w: Writer) -> Result<(), ReceiveError> {
+        let result = self.store(rx, &mut w).await;
+        if let Err(ReceiveError::Sink(e)) = &result {
+            if self.version >= 2 {
+                let _ = w.write(Reply::explained(Ack::zero().with_status(AckStatus::StorageError), e.to_string())).await;
+            }
+        }
+        result
+    }
+
+    async fn store(&self, mut rx: mpsc::Receiver<Bytes>, w: &mut Writer) -> Result<(), ReceiveError> {
+        let Self { sources, peer, sinks, middleware, subscribers, cadence, window, max, version, rewind, metrics, stop } = self;
+        let (cadence, window, max) = (*cadence, *window, *max);
+        let mut unacked = vec![Unacked::new(); sources.len()];
+        let mut consumed = Window::new(0, 0);
+        let mut throttled: Option<Instant> = None;
+        loop {
+            // A full queue means records arrive faster than they can be stored.
+            if *version >= 2 && rx.capacity() == 0 && throttled.is_none_or(|t| t.elapsed() >= IDLE) {
+                debug!(client = %sources[0].client(), "receive queue full, throttling forwarder");
+                w.write(Reply::explained(Ack::zero().with_status(AckStatus::Throttle), "receive queue full")).await?;
+                throttled = Some(Instant::now())
+            }
+            // Processed records are paid back to the forwarder in chunks.
+            if let Some(win) = window {
+                if !consumed.is_empty() && (consumed.records() >= win.records() / 2 || consumed.bytes() >= win.bytes() / 2) {
+                    w.write(Ack::grant(consumed)).await?;
+                    consumed = Window::new(0, 0)
+                }
+            }
+            // Records are flushed once the forwarder has been idle for a while
+            // or the ack interval of a stream has passed.
+            let wait = unacked.iter()
+                .filter_map(|u| u.deadline(&cadence))
+                .min()
+                .map(|t| t.saturating_duration_since(Instant::now()))
+                .unwrap_or(IDLE)
+                .min(IDLE);
+            let read = select! {
+                read = timeout(wait, rx.recv()) => read,
+                _ = stop.cancelled() => break
+            };
+            match read {
+                Ok(read) => match read {
+                    Some(frame) => {
+                        let record = minicbor::decode::<RecordRef>(&frame)
+                            .map_err(minicbor_io::Error::Decode)?
+                            .into_record(&frame);
+                        if let Some(win) = window {
+                            consumed.add(record.item_bytes().len(), win.bytes())
+                        }
+                        let i = record.stream() as usize;
+                        let Some(source) = sources.get(i) else {
+                            error!(client = %sources[0].client(), stream = %i, "unknown stream, dropping record");
+                            metrics.records_dropped.inc();
+                            continue
+                        };
+                        let record = record.decompress(max as usize)?;
+                        if !record.is_valid() {
+                            error!(%source, info = %record.info(), "crc mismatch, dropping record");
+                            metrics.records_dropped.inc();
+                            continue
+                        }
+                        // Backfill completion markers are no records to middleware
+                        // and subscribers.
+                        let marker = record.lane() == Lane::Backfill && record.item_bytes().is_empty();
+                        let record = if middleware.is_empty() || marker {
+                            record
+                        } else {
+                            match middleware.apply(&ReceiveContext::new(source, *peer), record) {
+                                Verdict::Accept(r) => r,
+                                Verdict::Reject(reason) => {
+                                    warn!(%source, %reason, "record rejected, dropping it");
+                                    metrics.records_rejected.inc();
+                                    continue
+                                }
+                            }
+                        };
+                        metrics.records_received.inc();
+                        metrics.bytes_received.add(record.item_bytes().len() as u64);
+                        if subscribers.receiver_count() > 0 && !marker {
+                            let _ = subscribers.send(Received { source: source.clone(), record: record.clone() });
+                        }
+                        unacked[i].add(record.info(), record.item_bytes().len());
+                        let (pos, to) = {
+                            let mut sink = sinks.get(source).lock().await;
+                            let pos = sink.store(source, record).await.map_err(sink_error)?;
+                            (pos, sink.rewind(source).filter(|_| *rewind))
+                        };
+                        if let Some(to) = to {
+                            warn!(%source, %to, "asking forwarder to rewind");
+                            w.write(Ack::rewind(to).with_stream(i as u32)).await?;
+                        }
+                        if unacked[i].is_due(&cadence) {
+                            unacked[i].reset();
+                            let pos = sinks.get(source).lock().await.flush(source).await.map_err(sink_error)?;
+                            ack(w, i, &mut unacked[i].acked, pos).await?
+                        } else if pos.number() > unacked[i].acked.number() {
+                            ack(w, i, &mut unacked[i].acked, pos).await?
+                        }
+                    }
+                    None => break
+                },
+                Err(_) => {
+                    let idle = wait >= IDLE;
+                    for (i, source) in sources.iter().enumerate() {
+                        // Sinks may acknowledge records only after they have been
+                        // flushed, cf. `RelaySink`, so those are asked again.
+                        let behind = idle && unacked[i].received > unacked[i].acked;
+                        if behind || unacked[i].is_dirty() && (idle || unacked[i].is_due(&cadence)) {
+                            unacked[i].reset();
+                            let pos = sinks.get(source).lock().await.flush(source).await.map_err(sink_error)?;
+                            ack(w, i, &mut unacked[i].acked, pos).await?
+                        }
+                    }
+                }
+            }
+        }
+        // The forwarder may still read the final acknowledgements.
+        for (i, source) in sources.iter().enumerate() {
+            let pos = sinks.get(source).lock().await.flush(source).await.map_err(sink_error)?;
+            let _ = ack(w, i, &mut unacked[i].acked, pos).await;
+        }
+        Ok(())
+    }
+}
+
+/// Metrics of a receiver, cf. `Receiver::with_metrics`.
+#[derive(Debug, Clone, Default)]
+struct ReceiveMetrics {
+    records_received: Counter,
+    bytes_received: Counter,
+    records_dropped: Counter,
+    records_rejected: Counter,
+    connections: Gauge
+}
+
+impl ReceiveMetrics {
+    fn new(m: &Metrics) -> Self {
+        Self {
+            records_received: m.counter("bogger_receive_records_total", "Valid records received from forwarders."),
+            bytes_received: m.counter("bogger_receive_bytes_total", "Bytes of valid records received from forwarders."),
+            records_dropped: m.counter("bogger_receive_records_dropped_total", "Records dropped as invalid."),
+            records_rejected: m.counter("bogger_receive_records_rejected_total", "Records rejected by middleware."),
+            connections: m.gauge("bogger_receive_connections", "Open connections from forwarders.")
+        }
+    }
+}
+
+/// The number of records read ahead of the sink per connection.
+const PIPELINE_LEN: usize = 256;
+
+/// The minimum capacity to allocate for reading records.
+const FRAME_BUFFER_LEN: usize = 64 * 1024;
+
+/// How long connections may take to close when shutting down.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a forwarder may be idle before its streams are flushed.
+const IDLE: Duration = Duration::from_secs(1);
+
+/// Acknowledge a stream position if it is after the last one acknowledged.
+async fn ack(w: &mut Writer, stream: usize, acked: &mut BlockInfo, pos: BlockInfo) -> Result<(), ReceiveError> {
+    if pos > *acked {
+        *acked = pos;
+        w.write(Ack::new(pos).with_stream(stream as u32)).await?;
+    }
+    Ok(())
+}
+
+fn sink_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> ReceiveError {
+    ReceiveError::Sink(Box::new(e))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReceiveError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("receive error: {0}")]
+    Recv(#[from] minicbor_io::Error),
+
+    #[error("sink error: {0}")]
+    Sink(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+
+    #[error("handshake aborted: {0}")]
+    Aborted(String)
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use crate::forward::Handshake;
+use super::Source;
+
+/// Decides whether a forwarder may connect.
+pub trait Authenticator: Send + Sync + 'static {
+    fn authenticate(&self, hs: &Handshake<'_>) -> bool;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&Handshake<'_>) -> bool + Send + Sync + 'static
+{
+    fn authenticate(&self, hs: &Handshake<'_>) -> bool {
+        self(hs)
+    }
+}
+
+/// Decides whether a client may replay the stored records of a source,
+/// cf. `Receiver::with_replay`.
+pub trait ReplayPolicy: Send + Sync + 'static {
+    fn may_replay(&self, hs: &Handshake<'_>, source: &Source) -> bool;
+}
+
+impl<F> ReplayPolicy for F
+where
+    F: Fn(&Handshake<'_>, &Source) -> bool + Send + Sync + 'static
+{
+    fn may_replay(&self, hs: &Handshake<'_>, source: &Source) -> bool {
+        self(hs, source)
+    }
+}
+
+/// Lets clients only replay records they have forwarded themselves.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OwnRecords;
+
+impl ReplayPolicy for OwnRecords {
+    fn may_replay(&self, hs: &Handshake<'_>, source: &Source) -> bool {
+        hs.id() == source.client()
+    }
+}
+
+/// Accepts forwarders whose token matches the one registered for their ID.
+#[derive(Default)]
+pub struct Tokens {
+    tokens: HashMap<String, String>
+}
+
+impl Tokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_token<I: Into<String>, T: Into<String>>(mut self, id: I, token: T) -> Self {
+        self.tokens.insert(id.into(), token.into());
+        self
+    }
+}
+
+impl Authenticator for Tokens {
+    fn authenticate(&self, hs: &Handshake<'_>) -> bool {
+        match (self.tokens.get(hs.id()), hs.token()) {
+            (Some(expected), Some(actual)) => constant_time_eq(expected.as_bytes(), actual.as_bytes()),
+            _ => false
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BlockNum, Handshake};
+    use super::{Authenticator, Tokens};
+
+    #[test]
+    fn tokens() {
+        let t = Tokens::new().with_token("a", "secret");
+        let hs = Handshake::new("a", BlockNum::zero());
+        assert!(!t.authenticate(&hs));
+        assert!(!t.authenticate(&hs.with_token(Some("secreT"))));
+        let hs = Handshake::new("a", BlockNum::zero());
+        assert!(t.authenticate(&hs.with_token(Some("secret"))));
+        let hs = Handshake::new("b", BlockNum::zero());
+        assert!(!t.authenticate(&hs.with_token(Some("secret"))))
+    }
+}
@@ -0,0 +1,132 @@
+use std::{fmt, net::SocketAddr, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::forward::Record;
+use super::Source;
+
+/// What a middleware decided about a record.
+#[derive(Debug)]
+pub enum Verdict {
+    /// Pass the (possibly modified) record on.
+    Accept(Record),
+    /// Drop the record for the given reason.
+    Reject(String)
+}
+
+/// Where and when a record has been received.
+#[derive(Debug, Clone, Copy)]
+pub struct ReceiveContext<'a> {
+    source: &'a Source,
+    peer: SocketAddr,
+    received: SystemTime
+}
+
+impl<'a> ReceiveContext<'a> {
+    pub(crate) fn new(source: &'a Source, peer: SocketAddr) -> Self {
+        Self { source, peer, received: SystemTime::now() }
+    }
+
+    pub fn source(&self) -> &'a Source {
+        self.source
+    }
+
+    /// The address of the forwarder's connection.
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn received(&self) -> SystemTime {
+        self.received
+    }
+}
+
+/// Validates, enriches or transforms records before they are stored,
+/// cf. `Receiver::with_middleware`.
+pub trait Middleware: Send + Sync + 'static {
+    fn process(&self, cx: &ReceiveContext<'_>, record: Record) -> Verdict;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&ReceiveContext<'_>, Record) -> Verdict + Send + Sync + 'static
+{
+    fn process(&self, cx: &ReceiveContext<'_>, record: Record) -> Verdict {
+        self(cx, record)
+    }
+}
+
+/// Adds the receive time (in milliseconds since the Unix epoch) and the
+/// forwarder's address to the metadata of every record.
+///
+/// Sinks only keep metadata if configured to, cf. `Config::with_entry_metadata`.
+#[derive(Debug, Clone)]
+pub struct Enrich {
+    received: Option<String>,
+    peer: Option<String>
+}
+
+impl Default for Enrich {
+    fn default() -> Self {
+        Self { received: Some("received_at".into()), peer: Some("peer".into()) }
+    }
+}
+
+impl Enrich {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The metadata key of the receive time, if it should be added.
+    pub fn with_received_key<K: Into<String>>(mut self, key: Option<K>) -> Self {
+        self.received = key.map(Into::into);
+        self
+    }
+
+    /// The metadata key of the forwarder's address, if it should be added.
+    pub fn with_peer_key<K: Into<String>>(mut self, key: Option<K>) -> Self {
+        self.peer = key.map(Into::into);
+        self
+    }
+}
+
+impl Middleware for Enrich {
+    fn process(&self, cx: &ReceiveContext<'_>, mut record: Record) -> Verdict {
+        if let Some(k) = &self.received {
+            let millis = cx.received.duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+            record.metadata_mut().insert(k.as_str(), millis.to_string());
+        }
+        if let Some(k) = &self.peer {
+            record.metadata_mut().insert(k.as_str(), cx.peer.to_string());
+        }
+        Verdict::Accept(record)
+    }
+}
+
+/// The middleware of a receiver, applied in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Chain(Vec<Arc<dyn Middleware>>);
+
+impl Chain {
+    pub(crate) fn push(&mut self, m: Arc<dyn Middleware>) {
+        self.0.push(m)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn apply(&self, cx: &ReceiveContext<'_>, mut record: Record) -> Verdict {
+        for m in &self.0 {
+            match m.process(cx, record) {
+                Verdict::Accept(r) => record = r,
+                reject             => return reject
+            }
+        }
+        Verdict::Accept(record)
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Chain").field(&self.0.len()).finish()
+    }
+}
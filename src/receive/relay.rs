@@ -0,0 +1,171 @@
+use std::{collections::{HashMap, VecDeque}, io, path::{Path, PathBuf}};
+
+use tokio::{spawn, sync::watch, task::AbortHandle};
+use tracing::debug;
+
+use crate::{BlockInfo, BlockNames, Config, WriteError};
+use crate::forward::{ForwardConfig, ForwardProgress, Forwarder, Handshake, HandshakeResponse, Lane, Record};
+use crate::retention::Retention;
+
+use super::{FileSink, Sink, Source};
+
+/// The file name of the receiving side's cursors in a relayed directory.
+///
+/// The forwarder of a directory keeps its own cursors next to it.
+const CURSORS_FILENAME: &str = "relay-cursors";
+
+/// When a `RelaySink` acknowledges records to their origin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelayAck {
+    /// Once records are stored durably on this node.
+    #[default]
+    Durable,
+    /// Once records have been acknowledged by the upstream receiver.
+    Upstream
+}
+
+/// A sink which stores records like `FileSink` and forwards every source
+/// to an upstream receiver, e.g. to relay records out of a network.
+///
+/// A `Forwarder` is started for every source when it first connects. Its
+/// client id and stream are those of the source.
+#[derive(Debug)]
+pub struct RelaySink {
+    inner: FileSink,
+    upstream: String,
+    policy: RelayAck,
+    config: ForwardConfig,
+    retention: Retention,
+    relays: HashMap<Source, Relay>
+}
+
+#[derive(Debug)]
+struct Relay {
+    task: AbortHandle,
+    progress: watch::Receiver<ForwardProgress>,
+    /// Local positions of live records with their origin positions.
+    pending: VecDeque<(BlockInfo, BlockInfo)>,
+    /// The latest origin position acknowledged upstream.
+    acked: BlockInfo
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl Relay {
+    /// Map the upstream acknowledgement back to origin positions.
+    fn update(&mut self) -> BlockInfo {
+        let upstream = self.progress.borrow().acked();
+        while let Some((local, origin)) = self.pending.front().copied() {
+            if local > upstream {
+                break
+            }
+            self.acked = self.acked.max(origin);
+            self.pending.pop_front();
+        }
+        self.acked
+    }
+}
+
+impl RelaySink {
+    /// Create a sink storing records below the given directory and
+    /// forwarding them to the receiver at `upstream`.
+    pub fn new<P: AsRef<Path>>(dir: P, upstream: &str) -> Self {
+        Self {
+            inner: FileSink::new(dir).with_cursors_file(CURSORS_FILENAME),
+            upstream: upstream.to_string(),
+            policy: RelayAck::default(),
+            config: ForwardConfig::default(),
+            retention: Retention::default(),
+            relays: HashMap::new()
+        }
+    }
+
+    pub fn with_config(mut self, c: Config) -> Self {
+        self.inner = self.inner.with_config(c);
+        self
+    }
+
+    pub fn with_policy(mut self, p: RelayAck) -> Self {
+        self.policy = p;
+        self
+    }
+
+    /// Configure the forwarders of this sink.
+    pub fn with_forward_config(mut self, c: ForwardConfig) -> Self {
+        self.config = c;
+        self
+    }
+
+    /// What happens to blocks once they have been forwarded upstream.
+    pub fn with_retention(mut self, r: Retention) -> Self {
+        self.retention = r;
+        self
+    }
+
+    async fn relay(&mut self, source: &Source, start: BlockInfo) -> Result<(), WriteError> {
+        if self.relays.contains_key(source) {
+            return Ok(())
+        }
+        let dir = self.inner.client_dir(source);
+        let forwarder = Forwarder::new(source.client(), &dir, &self.upstream).await
+            .map_err(|e| WriteError::Io(io::Error::other(e)))?
+            .with_block_names(self.inner.config().block_names().clone())
+            .with_retention(self.retention.clone())
+            .with_config(self.config.clone());
+        let forwarder = match source.stream() {
+            Some(s) => forwarder.with_stream(s),
+            None    => forwarder
+        };
+        let progress = forwarder.progress();
+        let task = spawn(async move { forwarder.go().await; }).abort_handle();
+        debug!(%source, upstream = %self.upstream, "relaying records");
+        // Without a record of their upstream positions, records stored
+        // before are considered acknowledged.
+        let relay = Relay { task, progress, pending: VecDeque::new(), acked: start };
+        self.relays.insert(source.clone(), relay);
+        Ok(())
+    }
+
+    fn ackable(&mut self, source: &Source, durable: BlockInfo) -> BlockInfo {
+        match (self.policy, self.relays.get_mut(source)) {
+            (RelayAck::Upstream, Some(r)) => durable.min(r.update()),
+            _                             => durable
+        }
+    }
+}
+
+impl Sink for RelaySink {
+    type Error = WriteError;
+
+    async fn start(&mut self, hs: &Handshake<'_>) -> Result<HandshakeResponse<'static>, Self::Error> {
+        let rsp = self.inner.start(hs).await?;
+        if let HandshakeResponse::Go { start, .. } = rsp {
+            self.relay(&Source::from_handshake(hs), start).await?
+        }
+        Ok(rsp)
+    }
+
+    async fn store(&mut self, source: &Source, record: Record) -> Result<BlockInfo, Self::Error> {
+        let (origin, lane) = (record.info(), record.lane());
+        let (durable, local) = self.inner.append(source, record).await?;
+        if let (Some(local), Lane::Live, RelayAck::Upstream) = (local, lane, self.policy) {
+            if let Some(r) = self.relays.get_mut(source) {
+                r.pending.push_back((local, origin))
+            }
+        }
+        Ok(self.ackable(source, durable))
+    }
+
+    async fn flush(&mut self, source: &Source) -> Result<BlockInfo, Self::Error> {
+        let durable = self.inner.flush(source).await?;
+        Ok(self.ackable(source, durable))
+    }
+
+    fn replay_dir(&self, source: &Source) -> Option<(PathBuf, BlockNames)> {
+        self.inner.replay_dir(source)
+    }
+}
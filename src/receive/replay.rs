@@ -0,0 +1,75 @@
+use tokio::{net::TcpStream, time::timeout};
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+use minicbor_io::{AsyncReader, AsyncWriter};
+use tracing::debug;
+
+use crate::{BlockInfo, CRC32C, LogReader};
+use crate::forward::{Ack, Capabilities, Handshake, HandshakeResponse, Lane, Record};
+
+use super::{IDLE, Reader, ReceiveError, Writer};
+
+/// Send stored records to a subscriber until `until` or until it disconnects.
+pub(super) async fn serve
+    ( mut r: Reader
+    , mut w: Writer
+    , mut log: LogReader
+    , until: Option<BlockInfo>
+    ) -> Result<(), ReceiveError>
+{
+    loop {
+        if until.map(|u| log.position() >= u).unwrap_or(false) {
+            break
+        }
+        match log.next_entry().await? {
+            Some((info, _)) if until.map(|u| info >= u).unwrap_or(false) => break,
+            Some((info, bytes)) => {
+                let crc = CRC32C.checksum(&bytes);
+                let record = Record::new(info, bytes, crc, Lane::Live)
+                    .with_metadata(log.metadata().cloned())
+                    .with_schema(log.schema());
+                w.write(&record).await?;
+            }
+            None => {
+                // Subscribers do not send anything, so reading only tells
+                // if the connection has been closed.
+                if let Ok(msg) = timeout(IDLE, r.read::<Ack>()).await {
+                    if msg?.is_none() {
+                        return Ok(())
+                    }
+                }
+            }
+        }
+    }
+    debug!(position = %log.position(), "replay complete");
+    Ok(())
+}
+
+/// Receives records a `Receiver` has stored, cf. `Handshake::with_replay`.
+#[derive(Debug)]
+pub struct Replayer {
+    reader: Reader,
+    /// Kept to keep the connection open.
+    _writer: Writer
+}
+
+impl Replayer {
+    /// Connect to a receiver and request the replay of `hs`.
+    pub async fn connect(address: &str, hs: &Handshake<'_>) -> Result<Self, ReceiveError> {
+        let (r, w) = TcpStream::connect(address).await?.into_split();
+        let mut reader = AsyncReader::new(r.compat());
+        let mut writer = AsyncWriter::new(w.compat_write());
+        writer.write(hs).await?;
+        match reader.read::<HandshakeResponse>().await? {
+            Some(rsp @ HandshakeResponse::Go { .. }) if rsp.capabilities().contains(Capabilities::REPLAY) => {}
+            Some(HandshakeResponse::Go { .. }) => return Err(ReceiveError::Aborted("replay not supported".into())),
+            Some(HandshakeResponse::Abort { message }) => return Err(ReceiveError::Aborted(message.to_string())),
+            None => return Err(ReceiveError::Aborted("connection closed".into()))
+        }
+        Ok(Self { reader, _writer: writer })
+    }
+
+    /// The next record, or `None` once the replay is complete.
+    pub async fn next(&mut self) -> Result<Option<Record>, ReceiveError> {
+        Ok(self.reader.read::<Record>().await?)
+    }
+}
@@ -0,0 +1,349 @@
+use std::{collections::HashMap, convert::Infallible, fmt, future::Future, io, path::{Path, PathBuf}};
+
+use minicbor::{Encode, Decode};
+use tokio::fs;
+use tracing::debug;
+
+use crate::fs::portable;
+use crate::{BlockInfo, BlockNames, Config, EntryWriter, WriteError};
+use crate::forward::{Backfill, Handshake, HandshakeResponse, Lane, Record};
+use crate::stream::is_valid_name;
+
+const CURSORS_FILENAME: &str = "cursors";
+
+/// Destination of records received from forwarders.
+pub trait Sink: Send + 'static {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Decide where a forwarder should resume.
+    fn start(&mut self, hs: &Handshake<'_>)
+        -> impl Future<Output = Result<HandshakeResponse<'static>, Self::Error>> + Send;
+
+    /// Store a record received from the given source.
+    ///
+    /// Returns the position up to which records of this source have been
+    /// stored durably. Blocks before this position may be released by the
+    /// forwarder.
+    fn store(&mut self, source: &Source, record: Record)
+        -> impl Future<Output = Result<BlockInfo, Self::Error>> + Send;
+
+    /// Make all records stored for the given source durable.
+    ///
+    /// Returns the same position as `Sink::store`.
+    fn flush(&mut self, source: &Source)
+        -> impl Future<Output = Result<BlockInfo, Self::Error>> + Send;
+
+    /// A position from which the forwarder of the given source should
+    /// resend records, e.g. because records stored after it turned out to
+    /// be corrupt.
+    ///
+    /// Asked after every stored record. Only forwarders which support
+    /// `Capabilities::REWIND` are asked to rewind, and only to positions
+    /// they have not released yet.
+    fn rewind(&mut self, _source: &Source) -> Option<BlockInfo> {
+        None
+    }
+
+    /// The block directory and file names of the records stored for the
+    /// given source, if they can be replayed (cf. `Replay`).
+    fn replay_dir(&self, _source: &Source) -> Option<(PathBuf, BlockNames)> {
+        None
+    }
+}
+
+/// The client and (optional) stream records are received from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Source {
+    client: String,
+    stream: Option<String>
+}
+
+impl Source {
+    pub fn new<C: Into<String>>(client: C, stream: Option<&str>) -> Self {
+        Self { client: client.into(), stream: stream.map(String::from) }
+    }
+
+    pub fn from_handshake(hs: &Handshake<'_>) -> Self {
+        Self::new(hs.id(), hs.stream())
+    }
+
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+
+    pub fn stream(&self) -> Option<&str> {
+        self.stream.as_deref()
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(s) = &self.stream {
+            write!(f, "{}/{s}", self.client)
+        } else {
+            f.write_str(&self.client)
+        }
+    }
+}
+
+/// A sink that appends records to block files, one directory per client
+/// and stream (e.g. `<client>/<stream>`).
+#[derive(Debug)]
+pub struct FileSink {
+    directory: PathBuf,
+    config: Config,
+    clients: HashMap<Source, Client>,
+    cursors_file: &'static str
+}
+
+#[derive(Debug)]
+struct Client {
+    directory: PathBuf,
+    cursors_file: &'static str,
+    writer: Option<EntryWriter>,
+    /// The positions of the last records received.
+    current: Cursors,
+    /// The positions of the last records stored durably.
+    durable: Cursors
+}
+
+#[derive(Debug, Clone, Copy, Encode, Decode)]
+struct Cursors {
+    #[n(0)] live: BlockInfo,
+    #[n(1)] backfill: Option<Backfill>
+}
+
+impl Cursors {
+    fn zero() -> Self {
+        Self { live: BlockInfo::zero(), backfill: None }
+    }
+
+    fn ackable(&self) -> BlockInfo {
+        match self.backfill {
+            Some(b) if b.is_pending() => b.cursor(),
+            _                         => self.live
+        }
+    }
+}
+
+impl FileSink {
+    /// Create a sink storing records below the given directory.
+    ///
+    /// By default oversized records are chunked, cf. `Config::with_chunking`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            directory: dir.as_ref().to_path_buf(),
+            config: Config::default().with_chunking(true),
+            clients: HashMap::new(),
+            cursors_file: CURSORS_FILENAME
+        }
+    }
+
+    /// Persist cursors under a different file name, e.g. if the directory
+    /// is forwarded as well, cf. `RelaySink`.
+    pub(crate) fn with_cursors_file(mut self, name: &'static str) -> Self {
+        self.cursors_file = name;
+        self
+    }
+
+    pub fn with_config(mut self, c: Config) -> Self {
+        self.config = c;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub(crate) fn client_dir(&self, source: &Source) -> PathBuf {
+        let mut dir = self.directory.join(source.client());
+        if let Some(s) = source.stream() {
+            dir.push(s)
+        }
+        dir
+    }
+
+    async fn client(&mut self, source: &Source) -> io::Result<&mut Client> {
+        if !self.clients.contains_key(source) {
+            let dir = self.client_dir(source);
+            fs::create_dir_all(&dir).await?;
+            let cursors = read_cursors(&dir, self.cursors_file).await?;
+            let client = Client {
+                directory: dir,
+                cursors_file: self.cursors_file,
+                writer: None,
+                current: cursors,
+                durable: cursors
+            };
+            self.clients.insert(source.clone(), client);
+        }
+        Ok(self.clients.get_mut(source).expect("client exists"))
+    }
+}
+
+impl Sink for FileSink {
+    type Error = WriteError;
+
+    async fn start(&mut self, hs: &Handshake<'_>) -> Result<HandshakeResponse<'static>, Self::Error> {
+        if !is_valid_name(hs.id()) {
+            return Ok(HandshakeResponse::abort("invalid client id"))
+        }
+        if !hs.stream().map(is_valid_name).unwrap_or(true) {
+            return Ok(HandshakeResponse::abort("invalid stream name"))
+        }
+        let source = Source::from_handshake(hs);
+        let client = self.client(&source).await?;
+        client.sync().await?;
+        let (start, backfill) = hs.resume_point(client.current.live, client.current.backfill);
+        client.current = Cursors { live: start, backfill: Some(backfill) };
+        client.persist().await?;
+        debug!(%source, %start, ?backfill, identity = ?hs.identity(), "resuming client");
+        Ok(HandshakeResponse::go(start).with_backfill(backfill).negotiate(hs))
+    }
+
+    async fn store(&mut self, source: &Source, record: Record) -> Result<BlockInfo, Self::Error> {
+        Ok(self.append(source, record).await?.0)
+    }
+
+    async fn flush(&mut self, source: &Source) -> Result<BlockInfo, Self::Error> {
+        let client = self.client(source).await?;
+        client.sync().await?;
+        client.persist().await?;
+        Ok(client.durable.ackable())
+    }
+
+    fn replay_dir(&self, source: &Source) -> Option<(PathBuf, BlockNames)> {
+        if !is_valid_name(source.client()) || !source.stream().map(is_valid_name).unwrap_or(true) {
+            return None
+        }
+        let dir = self.client_dir(source);
+        dir.is_dir().then(|| (dir, self.config.block_names().clone()))
+    }
+}
+
+impl FileSink {
+    /// Like `Sink::store` but also returns the local position the record
+    /// has been appended at, if it has been appended.
+    pub(crate) async fn append(&mut self, source: &Source, record: Record) -> Result<(BlockInfo, Option<BlockInfo>), WriteError> {
+        let config = self.config.clone();
+        let keep_metadata = config.entry_metadata();
+        let keep_schema = config.schema().is_some();
+        let client = self.client(source).await?;
+        let info = record.info();
+        let prev = match record.lane() {
+            Lane::Live => client.current.live,
+            Lane::Backfill => {
+                let Some(b) = client.current.backfill.filter(Backfill::is_pending) else {
+                    return Ok((client.durable.ackable(), None))
+                };
+                if info >= b.until() {
+                    // The end of the backfill has been reached.
+                    client.current.backfill = Some(b.with_cursor(b.until()));
+                    client.sync().await?;
+                    client.persist().await?;
+                    return Ok((client.durable.ackable(), None))
+                }
+                b.cursor()
+            }
+        };
+        if info <= prev {
+            return Ok((client.durable.ackable(), None))
+        }
+        // Whenever a lane enters a new block, everything received so far
+        // is made durable, so that previous blocks can be acknowledged.
+        if info.number() != prev.number() {
+            client.sync().await?;
+            client.persist().await?
+        }
+        if client.writer.is_none() {
+            client.writer = Some(EntryWriter::open(&client.directory, config).await?)
+        }
+        let Some(w) = &mut client.writer else {
+            unreachable!("writer has been opened")
+        };
+        let before = w.position();
+        // Metadata and schema IDs are kept if the sink's configuration allows it.
+        // Records without schema ID are stored with the configured one.
+        let meta = record.metadata().filter(|_| keep_metadata);
+        match (record.schema().filter(|_| keep_schema), meta) {
+            (Some(s), m)    => w.append_with_schema(record.item_bytes(), m, s).await?,
+            (None, Some(m)) => w.append_with_metadata(record.item_bytes(), m).await?,
+            (None, None)    => w.append(record.item_bytes()).await?
+        }
+        let after = w.position();
+        let local = if after.number() == before.number() {
+            before
+        } else {
+            BlockInfo::zero().with_number(after.number()).with_offset(8u8) // header length
+        };
+        match record.lane() {
+            Lane::Live => client.current.live = info,
+            Lane::Backfill => {
+                client.current.backfill = client.current.backfill.map(|b| b.with_cursor(info))
+            }
+        }
+        Ok((client.durable.ackable(), Some(local)))
+    }
+}
+
+/// A sink that discards all records, e.g. if records are only passed to
+/// subscribers (cf. `Receiver::subscribe`).
+///
+/// Forwarders are told to start with their latest block and every record
+/// is acknowledged right away.
+#[derive(Debug, Default)]
+pub struct NullSink {
+    positions: HashMap<Source, BlockInfo>
+}
+
+impl NullSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Sink for NullSink {
+    type Error = Infallible;
+
+    async fn start(&mut self, hs: &Handshake<'_>) -> Result<HandshakeResponse<'static>, Self::Error> {
+        let start = BlockInfo::zero().with_number(hs.latest());
+        self.positions.insert(Source::from_handshake(hs), start);
+        Ok(HandshakeResponse::go(start).negotiate(hs))
+    }
+
+    async fn store(&mut self, source: &Source, record: Record) -> Result<BlockInfo, Self::Error> {
+        let pos = self.positions.entry(source.clone()).or_insert_with(BlockInfo::zero);
+        *pos = (*pos).max(record.info());
+        Ok(*pos)
+    }
+
+    async fn flush(&mut self, source: &Source) -> Result<BlockInfo, Self::Error> {
+        Ok(self.positions.get(source).copied().unwrap_or_else(BlockInfo::zero))
+    }
+}
+
+impl Client {
+    async fn sync(&mut self) -> Result<(), WriteError> {
+        if let Some(w) = &mut self.writer {
+            w.sync().await?
+        }
+        Ok(())
+    }
+
+    async fn persist(&mut self) -> io::Result<()> {
+        let bytes = minicbor::to_vec(self.current).map_err(io::Error::other)?;
+        let tmp = self.directory.join(format!("{}.tmp", self.cursors_file));
+        fs::write(&tmp, bytes).await?;
+        portable::rename(&tmp, &self.directory.join(self.cursors_file)).await?;
+        self.durable = self.current;
+        Ok(())
+    }
+}
+
+async fn read_cursors(dir: &Path, name: &str) -> io::Result<Cursors> {
+    match fs::read(dir.join(name)).await {
+        Ok(bytes) => minicbor::decode(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Cursors::zero()),
+        Err(e) => Err(e)
+    }
+}
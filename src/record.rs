@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, fmt, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use minicbor::{Encode, Decode, Encoder, encode::{self, Write}, Decoder, decode, data::Type};
+
+#[cfg(feature = "syslog")]
+mod syslog;
+
+#[cfg(feature = "syslog")]
+pub use syslog::SyslogFormat;
+
+/// A structured log entry.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct LogRecord {
+    #[n(0)] level: Level,
+    #[n(1)] timestamp: u64,
+    #[n(2)] target: String,
+    #[n(3)] message: String,
+    #[n(4)] fields: BTreeMap<String, Value>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
+#[cbor(index_only)]
+pub enum Level {
+    #[n(0)] Trace,
+    #[n(1)] Debug,
+    #[n(2)] Info,
+    #[n(3)] Warn,
+    #[n(4)] Error
+}
+
+/// The value of a log record field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Str(String)
+}
+
+impl LogRecord {
+    /// Create a new record with the current time as timestamp.
+    pub fn new<T, M>(level: Level, target: T, message: M) -> Self
+    where
+        T: Into<String>,
+        M: Into<String>
+    {
+        Self {
+            level,
+            timestamp: 0,
+            target: target.into(),
+            message: message.into(),
+            fields: BTreeMap::new()
+        }
+        .with_timestamp(SystemTime::now())
+    }
+
+    pub fn trace<T: Into<String>, M: Into<String>>(target: T, message: M) -> Self {
+        Self::new(Level::Trace, target, message)
+    }
+
+    pub fn debug<T: Into<String>, M: Into<String>>(target: T, message: M) -> Self {
+        Self::new(Level::Debug, target, message)
+    }
+
+    pub fn info<T: Into<String>, M: Into<String>>(target: T, message: M) -> Self {
+        Self::new(Level::Info, target, message)
+    }
+
+    pub fn warn<T: Into<String>, M: Into<String>>(target: T, message: M) -> Self {
+        Self::new(Level::Warn, target, message)
+    }
+
+    pub fn error<T: Into<String>, M: Into<String>>(target: T, message: M) -> Self {
+        Self::new(Level::Error, target, message)
+    }
+
+    pub fn with_timestamp(mut self, t: SystemTime) -> Self {
+        self.timestamp = t.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos().try_into().unwrap_or(u64::MAX))
+            .unwrap_or(0);
+        self
+    }
+
+    pub fn with_field<K, V>(mut self, k: K, v: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<Value>
+    {
+        self.set_field(k, v);
+        self
+    }
+
+    pub fn set_message<M: Into<String>>(&mut self, m: M) {
+        self.message = m.into()
+    }
+
+    pub fn set_field<K, V>(&mut self, k: K, v: V)
+    where
+        K: Into<String>,
+        V: Into<Value>
+    {
+        self.fields.insert(k.into(), v.into());
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.timestamp)
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn field(&self, k: &str) -> Option<&Value> {
+        self.fields.get(k)
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:5} {}: {}", Rfc3339(self.timestamp()), self.level, self.target, self.message)?;
+        for (k, v) in &self.fields {
+            write!(f, " {k}={v}")?
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info  => "INFO",
+            Level::Warn  => "WARN",
+            Level::Error => "ERROR"
+        })
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => b.fmt(f),
+            Value::I64(i)  => i.fmt(f),
+            Value::U64(u)  => u.fmt(f),
+            Value::F64(x)  => x.fmt(f),
+            Value::Str(s)  => write!(f, "{s:?}")
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::I64(i)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(u: u64) -> Self {
+        Value::U64(u)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::F64(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl<C> Encode<C> for Value {
+    fn encode<W>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), encode::Error<W::Error>>
+    where
+        W: Write
+    {
+        match self {
+            Value::Bool(b) => e.bool(*b)?.ok(),
+            Value::I64(i)  => e.i64(*i)?.ok(),
+            Value::U64(u)  => e.u64(*u)?.ok(),
+            Value::F64(x)  => e.f64(*x)?.ok(),
+            Value::Str(s)  => e.str(s)?.ok()
+        }
+    }
+}
+
+impl<'b, C> Decode<'b, C> for Value {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, decode::Error> {
+        match d.datatype()? {
+            Type::Bool => d.bool().map(Value::Bool),
+            Type::U8 | Type::U16 | Type::U32 | Type::U64 => d.u64().map(Value::U64),
+            Type::I8 | Type::I16 | Type::I32 | Type::I64 => d.i64().map(Value::I64),
+            Type::F16 | Type::F32 | Type::F64 => d.f64().map(Value::F64),
+            Type::String => d.str().map(|s| Value::Str(s.to_string())),
+            t => Err(decode::Error::type_mismatch(t).with_message("unsupported field value"))
+        }
+    }
+}
+
+/// Formats a `SystemTime` as an RFC 3339 UTC timestamp.
+pub(crate) struct Rfc3339(pub(crate) SystemTime);
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = d.as_secs();
+        let (y, m, day) = civil_from_days((secs / 86400) as i64);
+        let s = secs % 86400;
+        write! { f, "{y:04}-{m:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+            s / 3600,
+            s % 3600 / 60,
+            s % 60,
+            d.subsec_micros()
+        }
+    }
+}
+
+// Days since 1970-01-01 to (year, month, day), cf.
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{LogRecord, Rfc3339};
+
+    #[test]
+    fn rfc3339() {
+        let t = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!("2023-11-14T22:13:20.123000Z", Rfc3339(t).to_string());
+        assert_eq!("1970-01-01T00:00:00.000000Z", Rfc3339(UNIX_EPOCH).to_string())
+    }
+
+    #[test]
+    fn roundtrip() {
+        let r = LogRecord::warn("bogger::test", "disk almost full")
+            .with_field("free", 1024u64)
+            .with_field("delta", -3i64)
+            .with_field("ratio", 0.5)
+            .with_field("path", "/var/log")
+            .with_field("fatal", false);
+        let b = minicbor::to_vec(&r).unwrap();
+        assert_eq!(r, minicbor::decode::<LogRecord>(&b).unwrap())
+    }
+}
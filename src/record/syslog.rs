@@ -0,0 +1,169 @@
+use std::fmt;
+
+use super::{Level, LogRecord, Rfc3339, Value};
+
+/// The enterprise number of structured data element IDs, as reserved for
+/// documentation by RFC 5612.
+const ENTERPRISE: u32 = 32473;
+
+/// Formats log records as RFC 5424 syslog messages.
+///
+/// The record's target and fields become parameters of a structured data
+/// element `fields@32473`.
+#[derive(Debug, Clone)]
+pub struct SyslogFormat {
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    procid: Option<u32>
+}
+
+impl Default for SyslogFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyslogFormat {
+    /// Use facility `user` (1) and the local host name.
+    pub fn new() -> Self {
+        Self {
+            facility: 1,
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            app_name: "-".to_string(),
+            procid: None
+        }
+    }
+
+    /// Set the facility code (0 – 23).
+    pub fn with_facility(mut self, f: u8) -> Self {
+        self.facility = f.min(23);
+        self
+    }
+
+    pub fn with_hostname<S: Into<String>>(mut self, h: S) -> Self {
+        self.hostname = h.into();
+        self
+    }
+
+    pub fn with_app_name<S: Into<String>>(mut self, a: S) -> Self {
+        self.app_name = a.into();
+        self
+    }
+
+    pub fn with_procid(mut self, p: u32) -> Self {
+        self.procid = Some(p);
+        self
+    }
+
+    /// Format a record as a single syslog message (without line ending).
+    pub fn format(&self, r: &LogRecord) -> String {
+        Message(self, r).to_string()
+    }
+}
+
+struct Message<'a>(&'a SyslogFormat, &'a LogRecord);
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Message(s, r) = self;
+        let pri = u32::from(s.facility) * 8 + severity(r.level());
+        write!(f, "<{pri}>1 {} ", Rfc3339(r.timestamp()))?;
+        write!(f, "{} ", Header(&s.hostname, 255))?;
+        write!(f, "{} ", Header(&s.app_name, 48))?;
+        match s.procid {
+            Some(p) => write!(f, "{p} - ")?,
+            None    => f.write_str("- - ")?
+        }
+        write!(f, "[fields@{ENTERPRISE} target=\"{}\"", Param(r.target()))?;
+        for (k, v) in r.fields() {
+            write!(f, " {}=\"", Name(k))?;
+            match v {
+                Value::Str(s) => write!(f, "{}\"", Param(s))?,
+                other         => write!(f, "{other}\"")?
+            }
+        }
+        write!(f, "] {}", r.message())
+    }
+}
+
+fn severity(l: Level) -> u32 {
+    match l {
+        Level::Error => 3,
+        Level::Warn  => 4,
+        Level::Info  => 6,
+        Level::Debug => 7,
+        Level::Trace => 7
+    }
+}
+
+/// A header field of printable ASCII, or `-` if empty.
+struct Header<'a>(&'a str, usize);
+
+impl fmt::Display for Header<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut n = 0;
+        for c in self.0.chars().filter(|c| c.is_ascii_graphic()).take(self.1) {
+            fmt::Write::write_char(f, c)?;
+            n += 1
+        }
+        if n == 0 {
+            f.write_str("-")?
+        }
+        Ok(())
+    }
+}
+
+/// A parameter name, without the characters RFC 5424 disallows.
+struct Name<'a>(&'a str);
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let valid = |c: &char| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"');
+        let mut n = 0;
+        for c in self.0.chars().filter(valid).take(32) {
+            fmt::Write::write_char(f, c)?;
+            n += 1
+        }
+        if n == 0 {
+            f.write_str("_")?
+        }
+        Ok(())
+    }
+}
+
+/// A parameter value with `"`, `\` and `]` escaped.
+struct Param<'a>(&'a str);
+
+impl fmt::Display for Param<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                f.write_str("\\")?
+            }
+            fmt::Write::write_char(f, c)?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::LogRecord;
+    use super::SyslogFormat;
+
+    #[test]
+    fn rfc5424() {
+        let r = LogRecord::warn("bogger::test", "disk almost full")
+            .with_timestamp(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123))
+            .with_field("free", 1024u64)
+            .with_field("path", "C:\\[\"logs\"]");
+        let s = SyslogFormat::new().with_hostname("host").with_app_name("app").with_procid(42);
+        assert_eq! {
+            "<12>1 2023-11-14T22:13:20.123000Z host app 42 - \
+             [fields@32473 target=\"bogger::test\" free=\"1024\" path=\"C:\\\\[\\\"logs\\\"\\]\"] disk almost full",
+            s.format(&r)
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{path::{Path, PathBuf}, time::{Duration, SystemTime}, io};
+
+use tokio::fs;
+use tracing::debug;
+
+use crate::fs::{portable, replace_file, writable_block};
+use crate::{BlockMeta, BlockNames, BlockNum, DeleteSummary, list_blocks_with};
+
+/// The name of the forwarded mark before it was derived from `BlockNames`.
+const LEGACY_FORWARDED_FILENAME: &str = "forwarded";
+
+/// What happens to blocks once the remote has acknowledged them.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Retention {
+    /// Keep blocks only until they have been acknowledged.
+    #[default]
+    UntilAck,
+
+    /// Keep acknowledged blocks (marked as forwarded) until they are older
+    /// than `max_age` or until their total size exceeds `max_bytes`.
+    ///
+    /// If both limits are `None`, forwarded blocks are kept indefinitely.
+    AfterAck {
+        #[cfg_attr(feature = "serde", serde(default, with = "crate::settings::duration::option"))]
+        #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+        max_age: Option<Duration>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        max_bytes: Option<u64>
+    }
+}
+
+/// How blocks are disposed of when they are no longer retained.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ArchiveAction {
+    /// Remove the block file.
+    #[default]
+    Delete,
+
+    /// Move the block file into the given directory.
+    MoveTo(PathBuf),
+
+    /// Rename `block.N` to `block.N.<suffix>`.
+    RenameSuffix(String)
+}
+
+impl ArchiveAction {
+    async fn apply_unless(&self, dry_run: bool, path: &Path) -> io::Result<()> {
+        if dry_run {
+            return Ok(())
+        }
+        self.apply(path).await
+    }
+
+    pub(crate) async fn apply(&self, path: &Path) -> io::Result<()> {
+        match self {
+            Self::Delete => portable::remove_file(path).await,
+            Self::MoveTo(dir) => {
+                let Some(name) = path.file_name() else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "missing file name"))
+                };
+                fs::create_dir_all(dir).await?;
+                portable::move_file(path, &dir.join(name)).await
+            }
+            Self::RenameSuffix(suffix) => {
+                let mut target = path.as_os_str().to_owned();
+                target.push(".");
+                target.push(suffix);
+                portable::rename(path, Path::new(&target)).await
+            }
+        }
+    }
+}
+
+/// Read the number of the first block not yet marked as forwarded.
+///
+/// All blocks with a smaller number have been acknowledged by the remote.
+pub async fn forwarded<P>(dir: P) -> io::Result<Option<BlockNum>>
+where
+    P: AsRef<Path>
+{
+    forwarded_with(dir, &BlockNames::default()).await
+}
+
+/// Like `forwarded` for the log whose blocks are named according to `names`.
+pub async fn forwarded_with<P>(dir: P, names: &BlockNames) -> io::Result<Option<BlockNum>>
+where
+    P: AsRef<Path>
+{
+    for name in [names.forwarded_file_name().as_str(), LEGACY_FORWARDED_FILENAME] {
+        match fs::read(dir.as_ref().join(name)).await {
+            Ok(bytes) => {
+                let n = minicbor::decode(&bytes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                return Ok(Some(n))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(None)
+}
+
+/// Release acknowledged blocks according to retention and archive action.
+///
+/// Blocks which can not be released are reported in the returned summary.
+/// On a dry run, the summary contains the blocks which would be released
+/// but nothing is changed.
+pub(crate) async fn release_blocks
+    ( dir: &Path
+    , names: &BlockNames
+    , to: BlockNum
+    , r: &Retention
+    , a: &ArchiveAction
+    , dry_run: bool
+    ) -> io::Result<DeleteSummary>
+{
+    match r {
+        Retention::UntilAck => {
+            let mut summary = DeleteSummary::default();
+            for b in blocks_before(dir, names, to).await? {
+                debug!(number = %b.number(), action = ?a, dry_run, "releasing acknowledged block");
+                summary.add(b.number(), a.apply_unless(dry_run, b.path()).await)
+            }
+            Ok(summary)
+        }
+        Retention::AfterAck { max_age, max_bytes } => {
+            if !dry_run {
+                mark_forwarded(dir, names, to).await?
+            }
+            prune_forwarded(dir, names, to, *max_age, *max_bytes, a, dry_run).await
+        }
+    }
+}
+
+async fn mark_forwarded(dir: &Path, names: &BlockNames, to: BlockNum) -> io::Result<()> {
+    let bytes = minicbor::to_vec(to).map_err(io::Error::other)?;
+    replace_file(dir, &names.forwarded_file_name(), &bytes).await
+}
+
+async fn prune_forwarded
+    ( dir: &Path
+    , names: &BlockNames
+    , to: BlockNum
+    , max_age: Option<Duration>
+    , max_bytes: Option<u64>
+    , action: &ArchiveAction
+    , dry_run: bool
+    ) -> io::Result<DeleteSummary>
+{
+    let mut summary = DeleteSummary::default();
+    if max_age.is_none() && max_bytes.is_none() {
+        return Ok(summary)
+    }
+
+    // Walk from newest to oldest so that the size budget is spent on the
+    // most recent forwarded blocks.
+    let now = SystemTime::now();
+    let mut total = 0;
+    for b in blocks_before(dir, names, to).await?.into_iter().rev() {
+        total += b.size();
+        let too_old = max_age
+            .and_then(|a| now.duration_since(b.modified()?).ok().map(|d| d > a))
+            .unwrap_or(false);
+        let too_big = max_bytes.map(|m| total > m).unwrap_or(false);
+        if too_old || too_big {
+            debug!(number = %b.number(), too_old, too_big, ?action, dry_run, "releasing forwarded block");
+            summary.add(b.number(), action.apply_unless(dry_run, b.path()).await)
+        }
+    }
+    Ok(summary)
+}
+
+/// Block files with a number less than `to`, sorted by block number.
+///
+/// The latest block is never included, as a writer may still append to it.
+async fn blocks_before(dir: &Path, names: &BlockNames, to: BlockNum) -> io::Result<Vec<BlockMeta>> {
+    let mut blocks = list_blocks_with(dir, names).await?;
+    let to = to.min(writable_block(&blocks));
+    blocks.retain(|b| b.number() < to);
+    Ok(blocks)
+}
@@ -0,0 +1,141 @@
+use std::{io, path::Path};
+use serde::{Deserialize, Serialize};
+
+use crate::{ArchiveAction, Config, ConfigError, ForwardConfig, RateLimit, Retention};
+
+pub(crate) mod duration;
+
+/// Settings loaded from TOML files.
+///
+/// A settings file has a `[storage]` table with `Config` fields, a
+/// `[forward]` table with `ForwardConfig` fields, a `[rate_limit]` table
+/// with `RateLimit` fields as well as `id`, `log`, `retention` and
+/// `archive` entries. All of them are optional:
+///
+/// ```toml
+/// id = "web-1"
+/// log = "bogger=info"
+/// retention = { after_ack = { max_age = "7d" } }
+/// archive = { move_to = "/var/log/archive" }
+///
+/// [storage]
+/// max_block_len = 4194304
+/// trailer = true
+/// block_names = { prefix = "app.", suffix = ".log" }
+///
+/// [forward]
+/// poll_interval = "500ms"
+/// ack_timeout = "30s"
+///
+/// [rate_limit]
+/// bytes_per_second = 1048576
+/// ```
+///
+/// Durations are given as an integer followed by one of the units `ms`,
+/// `s`, `m`, `h` or `d`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Forwarder id, cf. `Forwarder::new`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Log filter of the binaries, in `tracing_subscriber::EnvFilter` syntax.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+    pub storage: Config,
+    pub forward: ForwardConfig,
+    pub retention: Retention,
+    pub archive: ArchiveAction,
+    pub rate_limit: RateLimit
+}
+
+impl Settings {
+    pub async fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, SettingsError> {
+        let s = tokio::fs::read_to_string(path).await?;
+        Self::from_toml(&s)
+    }
+
+    /// Parse settings and validate the storage config.
+    pub fn from_toml(s: &str) -> Result<Self, SettingsError> {
+        let this: Self = toml::from_str(s)?;
+        this.storage.validate()?;
+        Ok(this)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("settings can be represented as TOML")
+    }
+}
+
+impl Config {
+    /// Read the `[storage]` table of a settings file, cf. `Settings`.
+    pub async fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, SettingsError> {
+        Ok(Settings::from_path(path).await?.storage)
+    }
+
+    /// Parse the `[storage]` table of settings, cf. `Settings`.
+    pub fn from_toml(s: &str) -> Result<Self, SettingsError> {
+        Ok(Settings::from_toml(s)?.storage)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid settings: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("invalid config: {0}")]
+    Config(#[from] ConfigError)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::{ArchiveAction, QuotaPolicy, Retention};
+    use super::Settings;
+
+    #[test]
+    fn parse_settings() {
+        let s = Settings::from_toml(r#"
+            retention = { after_ack = { max_age = "7d", max_bytes = 1000 } }
+            archive = { rename_suffix = "done" }
+
+            [storage]
+            max_block_len = 4096
+            quota = 8192
+            quota_policy = "drop_oldest"
+            block_names = { prefix = "app." }
+
+            [forward]
+            poll_interval = "500ms"
+            ack_timeout = "30s"
+            windows = ["22:00-06:00"]
+
+            [rate_limit]
+            records_per_second = 100
+            bytes_per_day = 1000000
+        "#).unwrap();
+        assert_eq!(4096, s.storage.max_block_len());
+        assert_eq!(QuotaPolicy::DropOldest, s.storage.quota_policy());
+        assert_eq!("app.7", s.storage.block_names().file_name(7.into()));
+        assert_eq!(Duration::from_millis(500), s.forward.poll_interval());
+        assert_eq!(Some(Duration::from_secs(30)), s.forward.ack_timeout());
+        assert_eq!("22:00-06:00", s.forward.windows()[0].to_string());
+        assert!(matches! {
+            &s.retention,
+            Retention::AfterAck { max_age: Some(d), max_bytes: Some(1000) } if *d == Duration::from_secs(7 * 86400)
+        });
+        assert!(matches!(&s.archive, ArchiveAction::RenameSuffix(x) if x == "done"));
+        assert_eq!(Some(100), s.rate_limit.records_per_second().map(|n| n.get()));
+        assert_eq!(None, s.rate_limit.bytes_per_second());
+
+        let t = Settings::from_toml(&s.to_toml()).unwrap();
+        assert_eq!(s.to_toml(), t.to_toml());
+
+        assert!(Settings::from_toml("[storage]\nmax_buffer_len = 0").is_err());
+        assert!(Settings::from_toml("[storage]\nunknown = 1").is_err())
+    }
+}
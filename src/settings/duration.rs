@@ -0,0 +1,45 @@
+//! (De-)serialisation of durations as strings like `"500ms"` or `"7d"`.
+
+use std::time::Duration;
+use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+const UNITS: [(&str, u64); 5] = [("d", 86_400_000), ("h", 3_600_000), ("m", 60_000), ("s", 1000), ("ms", 1)];
+
+pub(crate) fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    let millis = d.as_millis() as u64;
+    let (unit, n) = UNITS.iter()
+        .find(|(_, n)| millis > 0 && millis.is_multiple_of(*n))
+        .unwrap_or(&("ms", 1));
+    s.serialize_str(&format!("{}{unit}", millis / n))
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    let s = String::deserialize(d)?;
+    parse(&s).ok_or_else(|| D::Error::custom(format!("invalid duration {s:?}")))
+}
+
+fn parse(s: &str) -> Option<Duration> {
+    let i = s.find(|c: char| !c.is_ascii_digit())?;
+    let (n, unit) = s.split_at(i);
+    let n: u64 = n.parse().ok()?;
+    let (_, m) = UNITS.iter().find(|(u, _)| *u == unit)?;
+    Some(Duration::from_millis(n.checked_mul(*m)?))
+}
+
+pub(crate) mod option {
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match d {
+            Some(d) => super::serialize(d, s),
+            None    => s.serialize_none()
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super")] Duration);
+        Ok(Option::<Wrapper>::deserialize(d)?.map(|w| w.0))
+    }
+}
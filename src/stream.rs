@@ -0,0 +1,92 @@
+use std::{io, path::{Path, PathBuf}};
+
+use minicbor::Encode;
+use tokio::fs;
+
+use crate::{Config, Forwarder, ForwardError, Logger, LogError};
+
+/// A directory of named log streams.
+///
+/// Every stream is a subdirectory with its own blocks, `Config` and
+/// forwarding cursors. Forwarders of a stream send its name to the
+/// receiver, which keeps the streams of a client apart.
+#[derive(Debug, Clone)]
+pub struct LogSet {
+    directory: PathBuf
+}
+
+/// A named log stream of a `LogSet`.
+#[derive(Debug, Clone)]
+pub struct Stream {
+    name: String,
+    directory: PathBuf
+}
+
+impl LogSet {
+    /// Open a log set, creating its directory if necessary.
+    pub async fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let directory = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).await?;
+        Ok(Self { directory })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Get the stream with the given name, creating it if necessary.
+    pub async fn stream(&self, name: &str) -> io::Result<Stream> {
+        if !is_valid_name(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid stream name: {name:?}")))
+        }
+        let directory = self.directory.join(name);
+        fs::create_dir_all(&directory).await?;
+        Ok(Stream { name: name.to_string(), directory })
+    }
+
+    /// All existing streams, sorted by name.
+    pub async fn streams(&self) -> io::Result<Vec<Stream>> {
+        let mut streams = Vec::new();
+        let mut entries = fs::read_dir(&self.directory).await?;
+        while let Some(e) = entries.next_entry().await? {
+            let Some(name) = e.file_name().to_str().map(String::from) else {
+                continue
+            };
+            if is_valid_name(&name) && e.file_type().await?.is_dir() {
+                streams.push(Stream { name, directory: e.path() })
+            }
+        }
+        streams.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Ok(streams)
+    }
+}
+
+impl Stream {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Create a logger writing to this stream.
+    pub async fn logger<T>(&self, cfg: Config) -> Result<Logger<T>, LogError>
+    where
+        T: Encode<()> + Send + 'static
+    {
+        Logger::new(&self.directory, cfg).await
+    }
+
+    /// Create a forwarder of this stream.
+    pub async fn forwarder<S: ToString>(&self, id: S, address: &str) -> Result<Forwarder, ForwardError> {
+        Ok(Forwarder::new(id, &self.directory, address).await?.with_stream(&self.name))
+    }
+}
+
+/// Client IDs and stream names are used as directory names.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
@@ -0,0 +1,72 @@
+//! Notifications to the systemd service manager, cf. `sd_notify(3)`.
+//!
+//! All functions do nothing if the process has not been started by
+//! systemd, i.e. if `NOTIFY_SOCKET` is not set.
+
+use std::{env, io, os::unix::net::UnixDatagram, time::Duration};
+
+use tokio::{spawn, task::JoinHandle, time::sleep};
+use tracing::warn;
+
+/// Send a state string like `"READY=1"` to the service manager.
+///
+/// Returns `false` if there is no service manager to notify.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false)
+    };
+    let sock = UnixDatagram::unbound()?;
+    match path.to_str().and_then(|p| p.strip_prefix('@')) {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let addr = SocketAddr::from_abstract_name(name)?;
+            sock.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            sock.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Tell the service manager that start-up is complete.
+pub fn notify_ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Tell the service manager that the service is shutting down.
+pub fn notify_stopping() -> io::Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Tell the service manager that the service is alive.
+pub fn notify_watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+/// The watchdog interval configured for this process, if any.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid.to_str()?.parse::<u32>().ok()? != std::process::id() {
+            return None
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Send watchdog notifications at half the configured interval.
+///
+/// Returns `None` if no watchdog is configured.
+pub fn spawn_watchdog() -> Option<JoinHandle<()>> {
+    let interval = watchdog_interval()? / 2;
+    Some(spawn(async move {
+        loop {
+            if let Err(err) = notify_watchdog() {
+                warn!(%err, "failed to notify watchdog")
+            }
+            sleep(interval).await
+        }
+    }))
+}
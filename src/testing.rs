@@ -0,0 +1,16 @@
+//! Deterministic fault injection for tests.
+//!
+//! A `Faults` plan decides, based on a seeded pseudo-random sequence,
+//! which I/O operations fail. It can be applied to block files via
+//! `Config::with_faults`, to any I/O object via `Faults::wrap` and to
+//! TCP connections via a `FaultProxy`. The `protocol` module contains
+//! test vectors of protocol messages.
+
+mod faults;
+mod proxy;
+mod schema;
+
+pub mod protocol;
+
+pub use faults::{Faults, FaultyIo};
+pub use proxy::FaultProxy;
@@ -0,0 +1,217 @@
+use std::{fmt, io, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll}};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// A plan of faults to inject into I/O operations.
+///
+/// Clones share their state, i.e. they continue the same sequence of
+/// decisions and count injected faults together.
+#[derive(Clone)]
+pub struct Faults {
+    state: Arc<Mutex<State>>
+}
+
+#[derive(Debug)]
+struct State {
+    rng: u64,
+    short_writes: f64,
+    interrupts: f64,
+    no_space: f64,
+    drops: f64,
+    /// Maximum number of faults to inject.
+    limit: u64,
+    injected: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Fault {
+    /// Write only part of the given data.
+    ShortWrite,
+    /// Fail with `io::ErrorKind::Interrupted` (EINTR).
+    Interrupted,
+    /// Fail with `io::ErrorKind::StorageFull` (ENOSPC).
+    NoSpace,
+    /// Lose data in transit and close the connection.
+    Drop
+}
+
+impl Faults {
+    /// A plan which does not inject any faults until configured otherwise.
+    pub fn new(seed: u64) -> Self {
+        let state = State {
+            // The generator state must not be zero.
+            rng: seed ^ 0x9e37_79b9_7f4a_7c15,
+            short_writes: 0.0,
+            interrupts: 0.0,
+            no_space: 0.0,
+            drops: 0.0,
+            limit: u64::MAX,
+            injected: 0
+        };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Probability of a write accepting only part of its data.
+    pub fn with_short_writes(self, p: f64) -> Self {
+        self.lock().short_writes = p;
+        self
+    }
+
+    /// Probability of an operation failing with EINTR.
+    pub fn with_interrupts(self, p: f64) -> Self {
+        self.lock().interrupts = p;
+        self
+    }
+
+    /// Probability of a write or flush failing with ENOSPC.
+    pub fn with_no_space(self, p: f64) -> Self {
+        self.lock().no_space = p;
+        self
+    }
+
+    /// Probability of a `FaultProxy` losing a segment.
+    pub fn with_drops(self, p: f64) -> Self {
+        self.lock().drops = p;
+        self
+    }
+
+    /// Stop injecting faults after `n` of them.
+    pub fn with_limit(self, n: u64) -> Self {
+        self.lock().limit = n;
+        self
+    }
+
+    /// The number of faults injected so far.
+    pub fn injected(&self) -> u64 {
+        self.lock().injected
+    }
+
+    /// Inject faults into reads and writes of the given I/O object.
+    pub fn wrap<T>(&self, io: T) -> FaultyIo<T> {
+        FaultyIo { inner: io, faults: self.clone() }
+    }
+
+    /// Decide if one of the given faults happens.
+    ///
+    /// Every candidate consumes a random number, whether or not an
+    /// earlier one already applies, so that the sequence of decisions
+    /// only depends on the seed and the sequence of operations.
+    pub(super) fn next(&self, candidates: &[Fault]) -> Option<Fault> {
+        let mut s = self.lock();
+        let mut fault = None;
+        for c in candidates {
+            let p = match c {
+                Fault::ShortWrite  => s.short_writes,
+                Fault::Interrupted => s.interrupts,
+                Fault::NoSpace     => s.no_space,
+                Fault::Drop        => s.drops
+            };
+            if s.uniform() < p && fault.is_none() {
+                fault = Some(*c)
+            }
+        }
+        if fault.is_some() {
+            if s.injected >= s.limit {
+                return None
+            }
+            s.injected += 1
+        }
+        fault
+    }
+
+    /// A pseudo-random number in `1 ..= max`.
+    pub(super) fn len(&self, max: usize) -> usize {
+        1 + (self.lock().random() % max.max(1) as u64) as usize
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.lock().fmt(f)
+    }
+}
+
+impl State {
+    /// xorshift64*
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn uniform(&mut self) -> f64 {
+        (self.random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Fault {
+    fn error(self) -> io::Error {
+        match self {
+            Fault::Interrupted => io::ErrorKind::Interrupted.into(),
+            Fault::NoSpace     => io::ErrorKind::StorageFull.into(),
+            Fault::Drop        => io::ErrorKind::ConnectionReset.into(),
+            Fault::ShortWrite  => unreachable!("short writes are not errors")
+        }
+    }
+}
+
+/// An I/O object with faults injected, cf. `Faults::wrap`.
+#[derive(Debug)]
+pub struct FaultyIo<T> {
+    inner: T,
+    faults: Faults
+}
+
+impl<T> FaultyIo<T> {
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FaultyIo<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(f) = this.faults.next(&[Fault::Interrupted]) {
+            return Poll::Ready(Err(f.error()))
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FaultyIo<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.faults.next(&[Fault::Interrupted, Fault::NoSpace, Fault::ShortWrite]) {
+            Some(Fault::ShortWrite) if buf.len() > 1 => {
+                let n = this.faults.len(buf.len() - 1);
+                Pin::new(&mut this.inner).poll_write(cx, &buf[.. n])
+            }
+            Some(Fault::ShortWrite) | None => Pin::new(&mut this.inner).poll_write(cx, buf),
+            Some(f) => Poll::Ready(Err(f.error()))
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(f) = this.faults.next(&[Fault::Interrupted, Fault::NoSpace]) {
+            return Poll::Ready(Err(f.error()))
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,316 @@
+//! Canonical CBOR encodings of protocol messages.
+//!
+//! Forwarders and receivers implemented in other languages can check their
+//! encoders and decoders against these byte sequences. Messages are sent as
+//! CBOR items, each preceded by its length as a 4 byte big-endian integer,
+//! which is not part of the vectors.
+//!
+//! The messages are described in CDDL (RFC 8610) by `cddl`, which is kept
+//! as `protocol.cddl` in the repository, and `validate` checks encoded
+//! messages against that description.
+
+use super::schema;
+
+/// The type of message a `TestVector` encodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Handshake,
+    HandshakeResponse,
+    Record,
+    Ack
+}
+
+impl MessageKind {
+    /// The name of the CDDL rule describing messages of this kind.
+    pub fn rule(self) -> &'static str {
+        match self {
+            Self::Handshake         => "handshake",
+            Self::HandshakeResponse => "handshake-response",
+            Self::Record            => "record",
+            Self::Ack               => "ack"
+        }
+    }
+}
+
+/// A message which does not match the CDDL description.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid {kind:?} message: {message}")]
+pub struct SchemaError {
+    kind: MessageKind,
+    message: String
+}
+
+/// The CDDL description of all protocol messages.
+pub fn cddl() -> String {
+    schema::render()
+}
+
+/// Check that `bytes` are a message of the given kind.
+pub fn validate(kind: MessageKind, bytes: &[u8]) -> Result<(), SchemaError> {
+    schema::validate(kind.rule(), bytes).map_err(|message| SchemaError { kind, message })
+}
+
+/// A protocol message and its canonical encoding.
+#[derive(Debug, Clone, Copy)]
+pub struct TestVector {
+    name: &'static str,
+    kind: MessageKind,
+    description: &'static str,
+    hex: &'static str
+}
+
+impl TestVector {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        self.kind
+    }
+
+    /// The message content in prose.
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// The encoding as lower-case hexadecimal string.
+    pub fn hex(&self) -> &'static str {
+        self.hex
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        (0 .. self.hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&self.hex[i .. i + 2], 16).expect("valid hex"))
+            .collect()
+    }
+}
+
+/// All test vectors.
+pub fn test_vectors() -> &'static [TestVector] {
+    VECTORS
+}
+
+const VECTORS: &[TestVector] = &[
+    TestVector {
+        name: "handshake",
+        kind: MessageKind::Handshake,
+        description: "client \"host-1\", latest block 7, version 2, capabilities 0x39",
+        hex: "8666686f73742d3107f6f6021839"
+    },
+    TestVector {
+        name: "handshake-stream",
+        kind: MessageKind::Handshake,
+        description: "client \"host-1\", latest block 7, version 2, capabilities 0x1, newest first with backfill, \
+                      token \"secret\", stream \"app\"",
+        hex: "8866686f73742d310701f602016673656372657463617070"
+    },
+    TestVector {
+        name: "handshake-multiplexed",
+        kind: MessageKind::Handshake,
+        description: "client \"host-1\", latest block 7, version 2, capabilities 0x8, stream \"app\", \
+                      multiplexed stream \"audit\" with latest block 3",
+        hex: "8966686f73742d3107f6f60208f663617070818265617564697403"
+    },
+    TestVector {
+        name: "handshake-response-go",
+        kind: MessageKind::HandshakeResponse,
+        description: "go, start at block 3 offset 128, version 2, no capabilities",
+        hex: "82008682031880f60200f6f6"
+    },
+    TestVector {
+        name: "handshake-response-go-backfill",
+        kind: MessageKind::HandshakeResponse,
+        description: "go, start at block 7 offset 0, backfill from block 2 offset 64 until block 7 offset 0, \
+                      version 2, capabilities 0x11, window of 100 records and 65536 bytes",
+        hex: "82008682070082820218408207000211f68218641a00010000"
+    },
+    TestVector {
+        name: "handshake-response-abort",
+        kind: MessageKind::HandshakeResponse,
+        description: "abort with message \"unauthorized\"",
+        hex: "8201816c756e617574686f72697a6564"
+    },
+    TestVector {
+        name: "record",
+        kind: MessageKind::Record,
+        description: "live record at block 1 offset 8 with item \"hello\" and its CRC-32C",
+        hex: "838201084568656c6c6f1a9a71bb4c"
+    },
+    TestVector {
+        name: "record-backfill",
+        kind: MessageKind::Record,
+        description: "backfill record of stream 2 at block 5 offset 24 with item \"world\" and its CRC-32C",
+        hex: "868205181845776f726c641a31aa814e01f602"
+    },
+    TestVector {
+        name: "record-schema",
+        kind: MessageKind::Record,
+        description: "live record at block 1 offset 8 with item \"hello\", its CRC-32C and schema ID 42",
+        hex: "898201084568656c6c6f1a9a71bb4cf6f6f6f6f6182a"
+    },
+    TestVector {
+        name: "ack",
+        kind: MessageKind::Ack,
+        description: "ack of block 2 offset 64",
+        hex: "8182021840"
+    },
+    TestVector {
+        name: "ack-grant",
+        kind: MessageKind::Ack,
+        description: "ack of block 0 offset 0 for stream 1, granting 100 records and 65536 bytes",
+        hex: "83820000018218641a00010000"
+    },
+    TestVector {
+        name: "ack-throttle",
+        kind: MessageKind::Ack,
+        description: "ack of block 0 offset 0, asking to slow down with message \"slow down\"",
+        hex: "85820000f6f60169736c6f7720646f776e"
+    },
+    TestVector {
+        name: "ack-rewind",
+        kind: MessageKind::Ack,
+        description: "ack of block 0 offset 0 for stream 1, asking to rewind to block 3 offset 16",
+        hex: "8682000001f6f6f6820310"
+    }
+];
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use crate::{Ack, AckStatus, Backfill, BlockInfo, BlockNum, Capabilities, Handshake, HandshakeResponse, Reply};
+    use crate::{Compression, Digest, Identity, Lane, Metadata, Multiplexed, Record, Replay, Resume, Strategy, Window, CRC32C};
+    use super::{cddl, schema, test_vectors, validate, MessageKind};
+
+    fn encode(name: &str) -> Vec<u8> {
+        let latest = BlockNum::from(7);
+        let at = |n: u64, o: u64| BlockInfo::zero().with_number(n).with_offset(o);
+        let record = |info, item: &'static [u8], lane| {
+            Record::new(info, Bytes::from_static(item), CRC32C.checksum(item), lane)
+        };
+        let caps = Capabilities::BACKFILL | Capabilities::MULTIPLEX | Capabilities::FLOW_CONTROL | Capabilities::REPLAY;
+        let result = match name {
+            "handshake" => minicbor::to_vec(Handshake::new("host-1", latest).with_capabilities(caps)),
+            "handshake-stream" => minicbor::to_vec(Handshake::new("host-1", latest)
+                .with_capabilities(Capabilities::BACKFILL)
+                .with_strategy(Strategy::NewestFirstWithBackfill)
+                .with_token(Some("secret"))
+                .with_stream(Some("app"))),
+            "handshake-multiplexed" => minicbor::to_vec(Handshake::new("host-1", latest)
+                .with_capabilities(Capabilities::MULTIPLEX)
+                .with_stream(Some("app"))
+                .with_multiplexed(vec![Multiplexed::new("audit", BlockNum::from(3))])),
+            "handshake-response-go" => minicbor::to_vec(HandshakeResponse::go(at(3, 128))),
+            "handshake-response-go-backfill" => minicbor::to_vec(HandshakeResponse::go(at(7, 0))
+                .with_backfill(Backfill::new(at(2, 64), at(7, 0)))
+                .with_capabilities(Capabilities::BACKFILL | Capabilities::FLOW_CONTROL)
+                .with_window(Window::new(100, 65536))),
+            "handshake-response-abort" => minicbor::to_vec(HandshakeResponse::abort("unauthorized")),
+            "record" => minicbor::to_vec(record(at(1, 8), b"hello", Lane::Live)),
+            "record-backfill" => minicbor::to_vec(record(at(5, 24), b"world", Lane::Backfill).with_stream(2)),
+            "record-schema" => minicbor::to_vec(record(at(1, 8), b"hello", Lane::Live).with_schema(Some(42))),
+            "ack" => minicbor::to_vec(Ack::new(at(2, 64))),
+            "ack-grant" => minicbor::to_vec(Ack::grant(Window::new(100, 65536)).with_stream(1)),
+            "ack-throttle" => minicbor::to_vec(Reply::explained(Ack::zero().with_status(AckStatus::Throttle), "slow down")),
+            "ack-rewind" => minicbor::to_vec(Ack::rewind(at(3, 16)).with_stream(1)),
+            other => panic!("unknown test vector {other}")
+        };
+        result.unwrap()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn encode_test_vectors() {
+        for v in test_vectors() {
+            assert_eq!(v.hex(), hex(&encode(v.name())), "{}", v.name())
+        }
+    }
+
+    #[test]
+    fn decode_test_vectors() {
+        for v in test_vectors() {
+            let bytes = v.bytes();
+            let again = match v.kind() {
+                MessageKind::Handshake         => minicbor::to_vec(minicbor::decode::<Handshake>(&bytes).unwrap()),
+                MessageKind::HandshakeResponse => minicbor::to_vec(minicbor::decode::<HandshakeResponse>(&bytes).unwrap()),
+                MessageKind::Record            => minicbor::to_vec(minicbor::decode::<Record>(&bytes).unwrap()),
+                MessageKind::Ack               => minicbor::to_vec(minicbor::decode::<Reply>(&bytes).unwrap())
+            };
+            assert_eq!(bytes, again.unwrap(), "{}", v.name())
+        }
+    }
+    #[test]
+    fn validate_against_cddl() {
+        for v in test_vectors() {
+            validate(v.kind(), &v.bytes()).unwrap()
+        }
+        let at = BlockInfo::zero().with_number(4);
+        let hs = Handshake::new("host-1", BlockNum::from(4))
+            .with_identity(Some(Identity::new("host-1").with_label("zone", "a")))
+            .with_replay(Some(Replay::new("other", at).with_until(Some(at.with_offset(64u8)))));
+        validate(MessageKind::Handshake, &minicbor::to_vec(hs).unwrap()).unwrap();
+        let record = Record::new(at, Bytes::from_static(b"x"), CRC32C.checksum(b"x"), Lane::Live)
+            .with_metadata(Some(Metadata::new().with("k", "v")))
+            .with_digest(Some(Digest::Blake3([0; 32])));
+        validate(MessageKind::Record, &minicbor::to_vec(record).unwrap()).unwrap();
+        let ack = minicbor::to_vec(Ack::new(at)).unwrap();
+        assert!(validate(MessageKind::Record, &ack).is_err());
+        assert!(validate(MessageKind::Ack, &ack[.. ack.len() - 1]).is_err())
+    }
+
+    #[test]
+    fn validate_all_fields() {
+        let at = |n: u64, o: u64| BlockInfo::zero().with_number(n).with_offset(o);
+        let backfill = Backfill::new(at(2, 64), at(7, 0));
+        let caps = Capabilities::BACKFILL | Capabilities::MULTIPLEX;
+        let hs = Handshake::new("host-1", BlockNum::from(7))
+            .with_strategy(Strategy::NewestFirstWithBackfill)
+            .with_client_cursor(true)
+            .with_capabilities(caps)
+            .with_token(Some("secret"))
+            .with_stream(Some("app"))
+            .with_multiplexed(vec![Multiplexed::new("audit", BlockNum::from(3))])
+            .with_identity(Some(Identity::new("host-1").with_label("zone", "a")))
+            .with_replay(Some(Replay::new("other", at(1, 8)).with_stream(Some("app")).with_until(Some(at(4, 0)))));
+        let go = HandshakeResponse::go(at(7, 0))
+            .with_backfill(backfill)
+            .with_capabilities(caps)
+            .with_multiplexed(vec![Resume::new(at(3, 0), Some(backfill))])
+            .with_window(Window::new(100, 65536));
+        let item = Bytes::from(vec![b'a'; 256]);
+        let record = |d| {
+            Record::new(at(1, 8), item.clone(), CRC32C.checksum(&item), Lane::Backfill)
+                .with_stream(2)
+                .with_digest(Some(d))
+                .with_metadata(Some(Metadata::new().with("k", "v")))
+                .with_schema(Some(42))
+                .compress(Compression::Lz4)
+                .unwrap()
+        };
+        let ack = Ack::rewind(at(3, 16))
+            .with_stream(1)
+            .with_credit(Window::new(100, 65536))
+            .with_status(AckStatus::Throttle);
+        let messages = [
+            (MessageKind::Handshake, minicbor::to_vec(hs).unwrap()),
+            (MessageKind::HandshakeResponse, minicbor::to_vec(go).unwrap()),
+            (MessageKind::HandshakeResponse, minicbor::to_vec(HandshakeResponse::abort("no")).unwrap()),
+            (MessageKind::Record, minicbor::to_vec(record(Digest::Xxh64(1))).unwrap()),
+            (MessageKind::Record, minicbor::to_vec(record(Digest::Blake3([0; 32]))).unwrap()),
+            (MessageKind::Ack, minicbor::to_vec(Reply::explained(ack, "slow down")).unwrap())
+        ];
+        for (kind, bytes) in messages {
+            if let Err(e) = schema::validate_complete(kind.rule(), &bytes) {
+                panic!("{kind:?}: {e}")
+            }
+        }
+    }
+
+    #[test]
+    fn cddl_is_up_to_date() {
+        assert_eq!(include_str!("../../protocol.cddl"), cddl())
+    }
+}
@@ -0,0 +1,89 @@
+use std::{io, net::SocketAddr};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream, tcp::{OwnedReadHalf, OwnedWriteHalf}}};
+use tokio::task::{AbortHandle, JoinSet};
+use tracing::debug;
+
+use super::faults::{Fault, Faults};
+
+/// A TCP proxy which injects transport faults.
+///
+/// Every connection accepted at `FaultProxy::local_addr` is forwarded to
+/// the upstream address. Segments in either direction may be split into
+/// several writes (short writes) or lost, in which case the connection
+/// is closed, as a lost segment which is never retransmitted would
+/// eventually do. The proxy stops when dropped.
+#[derive(Debug)]
+pub struct FaultProxy {
+    addr: SocketAddr,
+    task: AbortHandle
+}
+
+impl FaultProxy {
+    pub async fn start(upstream: &str, faults: Faults) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let upstream = upstream.to_string();
+        let task = tokio::spawn(async move {
+            let mut conns = JoinSet::new();
+            while let Ok((client, _)) = listener.accept().await {
+                let server = match TcpStream::connect(&upstream).await {
+                    Ok(s)    => s,
+                    Err(err) => {
+                        debug!(%err, %upstream, "proxy failed to connect upstream");
+                        continue
+                    }
+                };
+                let (cr, cw) = client.into_split();
+                let (sr, sw) = server.into_split();
+                let faults = faults.clone();
+                conns.spawn(async move {
+                    // Whichever direction ends first closes the connection.
+                    tokio::select! {
+                        _ = pump(cr, sw, faults.clone()) => {}
+                        _ = pump(sr, cw, faults) => {}
+                    }
+                });
+            }
+        });
+        Ok(Self { addr, task: task.abort_handle() })
+    }
+
+    /// The address to connect to instead of the upstream address.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for FaultProxy {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+/// Copy data from `r` to `w`, injecting faults.
+async fn pump(mut r: OwnedReadHalf, mut w: OwnedWriteHalf, faults: Faults) -> io::Result<()> {
+    let mut buf = vec![0; 4096];
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            return w.shutdown().await
+        }
+        match faults.next(&[Fault::Drop, Fault::ShortWrite]) {
+            Some(Fault::Drop) => {
+                debug!(len = %n, "proxy dropped segment");
+                return Ok(())
+            }
+            Some(Fault::ShortWrite) => {
+                let mut data = &buf[.. n];
+                while !data.is_empty() {
+                    let k = faults.len(data.len());
+                    w.write_all(&data[.. k]).await?;
+                    w.flush().await?;
+                    tokio::task::yield_now().await;
+                    data = &data[k ..]
+                }
+            }
+            _ => w.write_all(&buf[.. n]).await?
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! A description of the wire messages, rendered as CDDL (RFC 8610) and
+//! checked against encoded messages.
+//!
+//! Only the subset of CDDL the messages need is supported. Optional array
+//! elements may be `null`, or left out if all following elements are left
+//! out as well, which is how optional fields are encoded.
+
+use std::fmt::Write;
+
+use minicbor::{Decoder, data::Type as DataType};
+
+/// A data type of the schema.
+#[derive(Debug)]
+pub(super) enum Type {
+    Uint,
+    Bool,
+    Text,
+    Bytes,
+    /// An unsigned integer literal, e.g. an enum variant index.
+    Literal(u64),
+    /// A reference to a rule by name.
+    Ref(&'static str),
+    Choice(&'static [Type]),
+    /// An array of the given elements.
+    Array(&'static [Field]),
+    /// An array of any number of elements of a type.
+    ArrayOf(&'static Type),
+    /// A map with any number of entries.
+    MapOf(&'static Type, &'static Type)
+}
+
+/// An array element.
+#[derive(Debug)]
+pub(super) struct Field {
+    name: &'static str,
+    ty: Type,
+    optional: bool
+}
+
+/// A named type.
+#[derive(Debug)]
+pub(super) struct Rule {
+    name: &'static str,
+    ty: Type,
+    comment: &'static str
+}
+
+const fn field(name: &'static str, ty: Type) -> Field {
+    Field { name, ty, optional: false }
+}
+
+const fn optional(name: &'static str, ty: Type) -> Field {
+    Field { name, ty, optional: true }
+}
+
+const fn rule(name: &'static str, ty: Type, comment: &'static str) -> Rule {
+    Rule { name, ty, comment }
+}
+
+use Type::*;
+
+/// The rules of all wire messages, the top-level messages first.
+pub(super) const RULES: &[Rule] = &[
+    rule("handshake", Array(&[
+        field("id", Text),
+        field("latest", Ref("block-num")),
+        optional("strategy", Ref("strategy")),
+        optional("client-cursor", Bool),
+        optional("version", Uint),
+        optional("capabilities", Ref("capabilities")),
+        optional("token", Text),
+        optional("stream", Text),
+        optional("multiplexed", ArrayOf(&Ref("multiplexed"))),
+        optional("identity", Ref("identity")),
+        optional("replay", Ref("replay"))
+    ]), "Sent by a forwarder after connecting."),
+    rule("handshake-response", Choice(&[
+        Array(&[field("", Literal(0)), field("", Ref("go"))]),
+        Array(&[field("", Literal(1)), field("", Ref("abort"))])
+    ]), "Sent by a receiver in reply to a handshake."),
+    rule("record", Array(&[
+        field("info", Ref("block-info")),
+        field("item", Bytes),
+        field("crc", Uint),
+        optional("lane", Ref("lane")),
+        optional("compression", Ref("compression")),
+        optional("stream", Uint),
+        optional("digest", Ref("digest")),
+        optional("metadata", Ref("metadata")),
+        optional("schema", Uint)
+    ]), "Sent by a forwarder for every entry, the CRC-32C is of the uncompressed item."),
+    rule("ack", Array(&[
+        field("info", Ref("block-info")),
+        optional("stream", Uint),
+        optional("credit", Ref("window")),
+        optional("status", Ref("ack-status")),
+        optional("message", Text),
+        optional("rewind", Ref("block-info"))
+    ]), "Sent by a receiver once records are stored."),
+    rule("go", Array(&[
+        field("start", Ref("block-info")),
+        optional("backfill", Ref("backfill")),
+        optional("version", Uint),
+        optional("capabilities", Ref("capabilities")),
+        optional("multiplexed", ArrayOf(&Ref("resume"))),
+        optional("window", Ref("window"))
+    ]), ""),
+    rule("abort", Array(&[field("message", Text)]), ""),
+    rule("block-num", Uint, ""),
+    rule("block-info", Array(&[field("number", Ref("block-num")), field("offset", Uint)]), ""),
+    rule("strategy", Uint, "0: oldest first, 1: newest first with backfill"),
+    rule("capabilities", Uint, "bit set, cf. `Capabilities`"),
+    rule("lane", Uint, "0: live, 1: backfill"),
+    rule("compression", Uint, "0: none, 1: lz4, 2: zstd"),
+    rule("ack-status", Uint, "0: accepted, 1: throttle, 2: storage error"),
+    rule("window", Array(&[field("records", Uint), field("bytes", Uint)]), ""),
+    rule("backfill", Array(&[field("cursor", Ref("block-info")), field("until", Ref("block-info"))]), ""),
+    rule("resume", Array(&[field("start", Ref("block-info")), optional("backfill", Ref("backfill"))]), ""),
+    rule("multiplexed", Array(&[field("name", Text), field("latest", Ref("block-num"))]), ""),
+    rule("identity", Array(&[field("hostname", Text), field("pid", Uint), field("labels", Ref("labels"))]), ""),
+    rule("replay", Array(&[
+        field("client", Text),
+        optional("stream", Text),
+        field("from", Ref("block-info")),
+        optional("until", Ref("block-info"))
+    ]), ""),
+    rule("digest", Choice(&[
+        Array(&[field("", Literal(0)), field("", Array(&[field("xxh64", Uint)]))]),
+        Array(&[field("", Literal(1)), field("", Array(&[field("blake3", Bytes)]))])
+    ]), ""),
+    rule("metadata", MapOf(&Text, &Text), ""),
+    rule("labels", MapOf(&Text, &Text), "")
+];
+
+/// Render all rules as CDDL.
+pub(super) fn render() -> String {
+    let mut out = String::from("; The wire messages of bogger, cf. `bogger::testing::protocol`.\n");
+    for r in RULES {
+        out.push('\n');
+        if !r.comment.is_empty() {
+            let _ = writeln!(out, "; {}", r.comment);
+        }
+        match &r.ty {
+            Array(fields) if fields.len() > 2 => {
+                let _ = writeln!(out, "{} = [", r.name);
+                for i in 0 .. fields.len() {
+                    let sep = if i + 1 < fields.len() { "," } else { "" };
+                    let _ = writeln!(out, "    {}{sep}", render_field(fields, i));
+                }
+                out.push_str("]\n")
+            }
+            ty => {
+                let _ = writeln!(out, "{} = {}", r.name, render_type(ty));
+            }
+        }
+    }
+    out
+}
+
+/// Render the `i`th array element.
+///
+/// Optional elements are `null` if a later element is present.
+fn render_field(fields: &[Field], i: usize) -> String {
+    let f = &fields[i];
+    let ty = render_type(&f.ty);
+    let ty = if f.optional { format!("{ty} / null") } else { ty };
+    let entry = if f.name.is_empty() { ty } else { format!("{}: {ty}", f.name) };
+    if fields[i ..].iter().all(|f| f.optional) {
+        format!("? {entry}")
+    } else {
+        entry
+    }
+}
+
+fn render_type(ty: &Type) -> String {
+    match ty {
+        Uint         => "uint".into(),
+        Bool         => "bool".into(),
+        Text         => "tstr".into(),
+        Bytes        => "bstr".into(),
+        Literal(n)   => n.to_string(),
+        Ref(r)       => (*r).into(),
+        Choice(ts)   => ts.iter().map(render_type).collect::<Vec<_>>().join(" / "),
+        Array(fs)    => format!("[{}]", (0 .. fs.len()).map(|i| render_field(fs, i)).collect::<Vec<_>>().join(", ")),
+        ArrayOf(t)   => format!("[* {}]", render_type(t)),
+        MapOf(k, v)  => format!("{{ * {} => {} }}", render_type(k), render_type(v))
+    }
+}
+
+/// Check that `bytes` are a single data item matching the named rule.
+pub(super) fn validate(name: &str, bytes: &[u8]) -> Result<(), String> {
+    validate_with(name, bytes, false)
+}
+
+/// Like `validate` but every optional array element must be present.
+///
+/// A message with all optional fields set passes only if the rules describe
+/// every field, which keeps the hand-written rules in line with the types.
+#[cfg(test)]
+pub(super) fn validate_complete(name: &str, bytes: &[u8]) -> Result<(), String> {
+    validate_with(name, bytes, true)
+}
+
+fn validate_with(name: &str, bytes: &[u8], complete: bool) -> Result<(), String> {
+    let mut d = Decoder::new(bytes);
+    check(&Ref(lookup(name)?.name), &mut d, complete)?;
+    if d.position() != bytes.len() {
+        return Err(format!("{} trailing bytes", bytes.len() - d.position()))
+    }
+    Ok(())
+}
+
+fn lookup(name: &str) -> Result<&'static Rule, String> {
+    RULES.iter().find(|r| r.name == name).ok_or_else(|| format!("unknown rule {name}"))
+}
+
+fn check(ty: &Type, d: &mut Decoder, complete: bool) -> Result<(), String> {
+    let at = d.position();
+    let err = |e: minicbor::decode::Error| format!("{e} at {at}");
+    match ty {
+        Uint  => { d.u64().map_err(err)?; }
+        Bool  => { d.bool().map_err(err)?; }
+        Text  => { d.str().map_err(err)?; }
+        Bytes => { d.bytes().map_err(err)?; }
+        Literal(n) => {
+            let m = d.u64().map_err(err)?;
+            if m != *n {
+                return Err(format!("expected {n}, found {m} at {at}"))
+            }
+        }
+        Ref(r) => return check(&lookup(r)?.ty, d, complete).map_err(|e| format!("{r}: {e}")),
+        Choice(ts) => {
+            let mut errors = Vec::new();
+            for t in *ts {
+                match check(t, d, complete) {
+                    Ok(())  => return Ok(()),
+                    Err(e)  => errors.push(e)
+                }
+                d.set_position(at)
+            }
+            return Err(format!("no choice matches: {}", errors.join("; ")))
+        }
+        Array(fields) => {
+            let n = d.array().map_err(err)?.ok_or_else(|| format!("indefinite array at {at}"))? as usize;
+            if n > fields.len() {
+                return Err(format!("array of {n} elements, expected at most {} at {at}", fields.len()))
+            }
+            if let Some(f) = fields[n ..].iter().find(|f| complete || !f.optional) {
+                return Err(format!("missing element {} at {at}", f.name))
+            }
+            for f in &fields[.. n] {
+                if f.optional && d.datatype().map_err(err)? == DataType::Null {
+                    if complete {
+                        return Err(format!("null element {} at {at}", f.name))
+                    }
+                    d.skip().map_err(err)?;
+                    continue
+                }
+                check(&f.ty, d, complete).map_err(|e| if f.name.is_empty() { e } else { format!("{}: {e}", f.name) })?
+            }
+        }
+        ArrayOf(t) => {
+            let n = d.array().map_err(err)?.ok_or_else(|| format!("indefinite array at {at}"))?;
+            for _ in 0 .. n {
+                check(t, d, complete)?
+            }
+        }
+        MapOf(k, v) => {
+            let n = d.map().map_err(err)?.ok_or_else(|| format!("indefinite map at {at}"))?;
+            for _ in 0 .. n {
+                check(k, d, complete)?;
+                check(v, d, complete)?
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::{fmt, sync::{Arc, atomic::{AtomicU64, Ordering}}};
+
+use tracing::{Event, Subscriber, field::{Field, Visit}, level_filters::LevelFilter};
+use tracing_subscriber::{Layer, layer::Context};
+
+use crate::{Logger, LogRecord, Level};
+
+/// A `tracing_subscriber::Layer` which appends events as `LogRecord`s to a `Logger`.
+///
+/// Events are added without waiting. If the logger can not keep up, events
+/// are dropped and counted (cf. `BoggerLayer::dropped`). Events emitted by
+/// this crate itself are ignored to avoid feedback loops.
+#[derive(Debug)]
+pub struct BoggerLayer {
+    logger: Logger<LogRecord>,
+    level: LevelFilter,
+    dropped: Arc<AtomicU64>
+}
+
+impl BoggerLayer {
+    pub fn new(logger: Logger<LogRecord>) -> Self {
+        Self {
+            logger,
+            level: LevelFilter::TRACE,
+            dropped: Arc::new(AtomicU64::new(0))
+        }
+    }
+
+    /// Ignore events above the given level.
+    pub fn with_max_level<L: Into<LevelFilter>>(mut self, level: L) -> Self {
+        self.level = level.into();
+        self
+    }
+
+    /// Number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: Subscriber> Layer<S> for BoggerLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let meta = event.metadata();
+        if *meta.level() > self.level || meta.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            return
+        }
+        let level = match *meta.level() {
+            tracing::Level::TRACE => Level::Trace,
+            tracing::Level::DEBUG => Level::Debug,
+            tracing::Level::INFO  => Level::Info,
+            tracing::Level::WARN  => Level::Warn,
+            tracing::Level::ERROR => Level::Error
+        };
+        let mut v = Visitor(LogRecord::new(level, meta.target(), String::new()));
+        event.record(&mut v);
+        if self.logger.try_add(v.0).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Visitor(LogRecord);
+
+impl Visit for Visitor {
+    fn record_debug(&mut self, f: &Field, v: &dyn fmt::Debug) {
+        if f.name() == "message" {
+            self.0.set_message(format!("{v:?}"))
+        } else {
+            self.0.set_field(f.name(), format!("{v:?}"))
+        }
+    }
+
+    fn record_str(&mut self, f: &Field, v: &str) {
+        if f.name() == "message" {
+            self.0.set_message(v)
+        } else {
+            self.0.set_field(f.name(), v)
+        }
+    }
+
+    fn record_i64(&mut self, f: &Field, v: i64) {
+        self.0.set_field(f.name(), v)
+    }
+
+    fn record_u64(&mut self, f: &Field, v: u64) {
+        self.0.set_field(f.name(), v)
+    }
+
+    fn record_f64(&mut self, f: &Field, v: f64) {
+        self.0.set_field(f.name(), v)
+    }
+
+    fn record_bool(&mut self, f: &Field, v: bool) {
+        self.0.set_field(f.name(), v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::fs;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::{BlockInfo, Config, Level, LogReader, LogRecord, Logger, Value};
+    use super::BoggerLayer;
+
+    #[tokio::test]
+    async fn write_enabled_events() {
+        let dir = Path::new("/tmp/logs-test-tracing-layer");
+        if dir.is_dir() {
+            fs::remove_dir_all(dir).await.unwrap();
+        }
+        fs::create_dir(dir).await.unwrap();
+
+        let logger = Logger::<LogRecord>::new(dir, Config::default()).await.unwrap();
+        let layer = BoggerLayer::new(logger.clone()).with_max_level(tracing::Level::INFO);
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::trace!(target: "app", n = 1, "trace");
+            tracing::debug!(target: "app", n = 2, "debug");
+            tracing::info!(target: "app", n = 3u64, name = "x", "info");
+            tracing::warn!(target: "app", n = -4, ok = false, "warn");
+            tracing::error!(target: "app", ratio = 0.5, err = ?"oops", "error")
+        });
+        logger.close().await.unwrap();
+
+        let mut r = LogReader::new(dir, BlockInfo::zero());
+        let mut records = Vec::new();
+        while let Some((_, e)) = r.next_entry().await.unwrap() {
+            records.push(minicbor::decode::<LogRecord>(&e).unwrap())
+        }
+        assert_eq!(3, records.len());
+        assert!(records.iter().all(|r| r.target() == "app"));
+        assert_eq!((Level::Info, "info"), (records[0].level(), records[0].message()));
+        assert_eq!(Some(&Value::U64(3)), records[0].field("n"));
+        assert_eq!(Some(&Value::Str("x".into())), records[0].field("name"));
+        assert_eq!((Level::Warn, "warn"), (records[1].level(), records[1].message()));
+        assert_eq!(Some(&Value::I64(-4)), records[1].field("n"));
+        assert_eq!(Some(&Value::Bool(false)), records[1].field("ok"));
+        assert_eq!((Level::Error, "error"), (records[2].level(), records[2].message()));
+        assert_eq!(Some(&Value::F64(0.5)), records[2].field("ratio"));
+        assert_eq!(Some(&Value::Str("\"oops\"".into())), records[2].field("err"))
+    }
+}
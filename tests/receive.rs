@@ -0,0 +1,1595 @@
+use std::{io, num::NonZeroU64, path::Path, sync::{Arc, Mutex}, time::{Duration, Instant, SystemTime}};
+
+use bogger::{Ack, AckCadence, AckStatus, BlockInfo, BlockNum, Config, EntryReader, EntryWriter, FileSink, ForwardConfig, ForwardErrorKind, Forwarder, Handshake, Enrich, ReceiveContext, Verdict};
+use bogger::{HandshakeResponse, Identity, LogSet, Metadata, MultiForwarder, RateLimit, Receiver, OwnRecords, Record, RecordRef, RelayAck, RelaySink, Replay, Replayer, Reply, Sink, Source, Tokens, Window};
+use bogger::QUARANTINE_DIR;
+use bytes::Bytes;
+use minicbor_io::{AsyncReader, AsyncWriter};
+use tokio::{fs, net::{TcpListener, TcpStream}, sync::oneshot, time::{sleep, timeout}};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+const ENTRIES: &[&[u8]] = &[b"first", b"second", b"third"];
+
+#[tokio::test]
+async fn forward_to_file_sink() {
+    let src = Path::new("/tmp/logs-test-forward-to-file-sink-src");
+    let dst = Path::new("/tmp/logs-test-forward-to-file-sink-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 3).await, ENTRIES)
+}
+
+#[tokio::test]
+async fn forward_progress() {
+    let src = Path::new("/tmp/logs-test-forward-progress-src");
+    let dst = Path::new("/tmp/logs-test-forward-progress-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let p = timeout(Duration::from_secs(10), progress.wait_for(|p| {
+        p.position().number() == p.latest() && p.acked().number().value() > 0
+    }));
+    let p = *p.await.unwrap().unwrap();
+    assert!(p.latest().value() > 1);
+    assert_eq!(0, p.reconnects())
+}
+
+#[tokio::test]
+async fn shut_down_gracefully() {
+    let src = Path::new("/tmp/logs-test-shut-down-src");
+    let dst = Path::new("/tmp/logs-test-shut-down-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst)).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    let (stop_receiver, stopped) = oneshot::channel::<()>();
+    let receiver = tokio::spawn(receiver.go_until(async { let _ = stopped.await; }));
+
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    let mut progress = forwarder.progress();
+    let (stop_forwarder, stopped) = oneshot::channel::<()>();
+    let forwarder = tokio::spawn(forwarder.go_until(async { let _ = stopped.await; }));
+
+    assert_eq!(read_entries(&dst.join("test"), 3).await, ENTRIES);
+    progress.wait_for(|p| p.connections() == 1).await.unwrap();
+
+    stop_forwarder.send(()).unwrap();
+    timeout(Duration::from_secs(10), forwarder).await.unwrap().unwrap();
+    assert_eq!(0, progress.borrow().connections());
+
+    stop_receiver.send(()).unwrap();
+    timeout(Duration::from_secs(10), receiver).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn shut_down_while_backing_off() {
+    let src = Path::new("/tmp/logs-test-shut-down-back-off-src");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default().with_error_delay(Duration::from_secs(60));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_config(cfg);
+    let mut progress = forwarder.progress();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let forwarder = tokio::spawn(forwarder.go_until(async { let _ = stopped.await; }));
+
+    let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    r.read::<Handshake>().await.unwrap().unwrap();
+    w.write(HandshakeResponse::abort("unauthorized")).await.unwrap();
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.last_error().is_some())).await.unwrap().unwrap();
+
+    // The forwarder now waits for a minute before it retries.
+    stop.send(()).unwrap();
+    timeout(Duration::from_secs(5), forwarder).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn one_forwarder_per_directory() {
+    let src = Path::new("/tmp/logs-test-one-forwarder-src");
+    let dst1 = Path::new("/tmp/logs-test-one-forwarder-dst1");
+    let dst2 = Path::new("/tmp/logs-test-one-forwarder-dst2");
+    for d in [src, dst1, dst2] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let cfg = ForwardConfig::default().with_error_delay(Duration::from_millis(100));
+    let first = Forwarder::new("test", src, &spawn_receiver(dst1).await).await.unwrap().with_config(cfg.clone());
+    let (stop, stopped) = oneshot::channel::<()>();
+    let first = tokio::spawn(first.go_until(async { let _ = stopped.await; }));
+    assert_eq!(read_entries(&dst1.join("test"), 3).await, ENTRIES);
+
+    // The second forwarder waits until the first one stops.
+    let second = Forwarder::new("test", src, &spawn_receiver(dst2).await).await.unwrap().with_config(cfg);
+    let mut progress = second.progress();
+    tokio::spawn(second.go());
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(0, progress.borrow().connections());
+    stop.send(()).unwrap();
+    timeout(Duration::from_secs(10), first).await.unwrap().unwrap();
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.connections() == 1)).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn forward_to_multiple_destinations() {
+    let src = Path::new("/tmp/logs-test-fan-out-src");
+    let dst1 = Path::new("/tmp/logs-test-fan-out-dst1");
+    let dst2 = Path::new("/tmp/logs-test-fan-out-dst2");
+    for d in [src, dst1, dst2] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let address1 = spawn_receiver(dst1).await;
+    let address2 = spawn_receiver(dst2).await;
+    let forwarder = Forwarder::new("test", src, &address1).await.unwrap().with_destination(&address2);
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst1.join("test"), 3).await, ENTRIES);
+    assert_eq!(read_entries(&dst2.join("test"), 3).await, ENTRIES)
+}
+
+#[tokio::test]
+async fn forward_streams() {
+    let src = Path::new("/tmp/logs-test-forward-streams-src");
+    let dst = Path::new("/tmp/logs-test-forward-streams-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let set = LogSet::open(src).await.unwrap();
+    let address = spawn_receiver(dst).await;
+    for (name, entries) in [("a", &ENTRIES[.. 1]), ("b", &ENTRIES[1 ..])] {
+        let stream = set.stream(name).await.unwrap();
+        write_entries(stream.directory(), entries).await;
+        tokio::spawn(stream.forwarder("test", &address).await.unwrap().go());
+    }
+    assert!(set.stream("../x").await.is_err());
+
+    assert_eq!(read_entries(&dst.join("test/a"), 1).await, &ENTRIES[.. 1]);
+    assert_eq!(read_entries(&dst.join("test/b"), 2).await, &ENTRIES[1 ..])
+}
+
+#[tokio::test]
+async fn forward_multiplexed() {
+    let src = Path::new("/tmp/logs-test-forward-multiplexed-src");
+    let dst = Path::new("/tmp/logs-test-forward-multiplexed-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let set = LogSet::open(src).await.unwrap();
+    let a = set.stream("a").await.unwrap();
+    let b = set.stream("b").await.unwrap();
+    write_entries(a.directory(), &ENTRIES[.. 1]).await;
+    write_entries(b.directory(), &ENTRIES[1 ..]).await;
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = a.forwarder("test", &address)
+        .await
+        .unwrap()
+        .with_multiplexed(b.name(), b.directory());
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test/a"), 1).await, &ENTRIES[.. 1]);
+    assert_eq!(read_entries(&dst.join("test/b"), 2).await, &ENTRIES[1 ..])
+}
+
+#[tokio::test]
+async fn forward_multiple_directories() {
+    let src = Path::new("/tmp/logs-test-multi-forwarder-src");
+    let dst = Path::new("/tmp/logs-test-multi-forwarder-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let address = spawn_receiver(dst).await;
+    let mut forwarders = MultiForwarder::new();
+    for (name, entries) in [("a", &ENTRIES[.. 1]), ("b", &ENTRIES[1 ..])] {
+        let dir = src.join(name);
+        fs::create_dir(&dir).await.unwrap();
+        write_entries(&dir, entries).await;
+        let f = Forwarder::new("test", &dir, &address).await.unwrap().with_stream(name);
+        forwarders = forwarders.with_forwarder(f)
+    }
+    assert_eq!(2, forwarders.progress().len());
+    tokio::spawn(forwarders.go());
+
+    assert_eq!(read_entries(&dst.join("test/a"), 1).await, &ENTRIES[.. 1]);
+    assert_eq!(read_entries(&dst.join("test/b"), 2).await, &ENTRIES[1 ..])
+}
+
+#[tokio::test]
+async fn forward_blocks_in_parallel() {
+    let src = Path::new("/tmp/logs-test-parallel-blocks-src");
+    let dst = Path::new("/tmp/logs-test-parallel-blocks-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let entries: Vec<Vec<u8>> = (0 .. 20).map(|i| format!("entry {i}").into_bytes()).collect();
+    let entries: Vec<&[u8]> = entries.iter().map(Vec::as_slice).collect();
+    write_entries(src, &entries).await;
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address)
+        .await
+        .unwrap()
+        .with_config(ForwardConfig::default().with_parallel_blocks(4));
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 20).await, entries)
+}
+
+#[tokio::test]
+async fn receive_with_workers() {
+    let src = Path::new("/tmp/logs-test-receive-workers-src");
+    let dst = Path::new("/tmp/logs-test-receive-workers-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let receiver = Receiver::new_with_workers("127.0.0.1:0", 4, || FileSink::new(dst)).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    for id in ["a", "b", "c"] {
+        let dir = src.join(id);
+        fs::create_dir(&dir).await.unwrap();
+        write_entries(&dir, ENTRIES).await;
+        tokio::spawn(Forwarder::new(id, &dir, &address).await.unwrap().go());
+    }
+    for id in ["a", "b", "c"] {
+        assert_eq!(read_entries(&dst.join(id), 3).await, ENTRIES)
+    }
+}
+
+#[tokio::test]
+async fn pause_and_resume() {
+    let src = Path::new("/tmp/logs-test-pause-and-resume-src");
+    let dst = Path::new("/tmp/logs-test-pause-and-resume-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, &ENTRIES[.. 1]).await;
+
+    let address = spawn_receiver(dst).await;
+    let cfg = ForwardConfig::default().with_poll_interval(Duration::from_secs(60));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_config(cfg);
+    let handle = forwarder.handle();
+    handle.pause();
+    tokio::spawn(forwarder.go());
+
+    sleep(Duration::from_millis(300)).await;
+    assert!(EntryReader::open(&dst.join("test"), BlockInfo::zero().with_number(1)).await.is_err());
+    handle.resume();
+    assert_eq!(read_entries(&dst.join("test"), 1).await, &ENTRIES[.. 1]);
+
+    // New entries are only looked for every minute, unless flushed.
+    write_entries(src, &ENTRIES[1 ..]).await;
+    handle.flush_now();
+    assert_eq!(read_entries(&dst.join("test"), 3).await, ENTRIES)
+}
+
+#[tokio::test]
+async fn stop_at_daily_cap() {
+    let src = Path::new("/tmp/logs-test-daily-cap-src");
+    let dst = Path::new("/tmp/logs-test-daily-cap-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address)
+        .await
+        .unwrap()
+        .with_rate_limit(RateLimit::new().with_bytes_per_day(NonZeroU64::new(12).unwrap()));
+    let mut progress = forwarder.progress();
+    let handle = forwarder.handle();
+    tokio::spawn(forwarder.go());
+
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.is_capped())).await.unwrap().unwrap();
+    assert_eq!(11, progress.borrow().bytes_per_day());
+    assert_eq!(read_entries(&dst.join("test"), 2).await, &ENTRIES[.. 2]);
+
+    // Lifting the cap at runtime resumes forwarding.
+    handle.update_rate_limit(RateLimit::new());
+    assert_eq!(read_entries(&dst.join("test"), ENTRIES.len()).await, ENTRIES)
+}
+
+#[tokio::test]
+async fn limit_records_per_second() {
+    let src = Path::new("/tmp/logs-test-limit-records-per-second");
+    recreate(src).await;
+    let entries: &[&[u8]] = &[b"r1", b"r2", b"r3", b"r4", b"r5", b"r6"];
+    write_entries(src, entries).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let forwarder = Forwarder::new("test", src, &address)
+        .await
+        .unwrap()
+        .with_rate_limit(RateLimit::new().with_records_per_second(4.try_into().unwrap()));
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let (mut r, _w) = accept_forwarder(&listener).await;
+    let start = Instant::now();
+    let mut elapsed = Vec::new();
+    for e in entries {
+        let record = timeout(Duration::from_secs(5), r.read::<Record>()).await.unwrap().unwrap().unwrap();
+        assert_eq!(*e, record.item_bytes());
+        elapsed.push(start.elapsed())
+    }
+    // A burst of at most one second worth of records, then one record
+    // every 250 ms.
+    assert!(elapsed[4] >= Duration::from_millis(200), "{elapsed:?}");
+    assert!(elapsed[5] >= Duration::from_millis(450), "{elapsed:?}");
+
+    // The bytes sent are reported, even without a daily cap.
+    let n = entries.iter().map(|e| e.len() as u64).sum::<u64>();
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.bytes_per_day() == n)).await.unwrap().unwrap();
+    assert!(!progress.borrow().is_capped())
+}
+
+#[tokio::test]
+async fn resume_from_client_cursor() {
+    let src = Path::new("/tmp/logs-test-resume-from-client-cursor");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default().with_reconnect_delay(Duration::from_millis(10), Duration::from_millis(50));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap()
+        .with_config(cfg)
+        .with_client_cursor(true);
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let (mut r, mut w) = accept_forwarder(&listener).await;
+    let infos = read_infos(&mut r, ENTRIES.len()).await;
+    w.write(Ack::new(infos[1])).await.unwrap();
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.acked() == infos[1])).await.unwrap().unwrap();
+    drop((r, w));
+
+    // A start beyond the latest block is replaced by the local cursor.
+    let (mut r, w) = accept_forwarder_at(&listener, BlockInfo::zero().with_number(99)).await;
+    assert_eq!(infos[1 ..], read_infos(&mut r, 2).await);
+    drop((r, w));
+
+    // So is a zero start, if the forwarder asked to use its cursor.
+    let (mut r, _w) = accept_forwarder(&listener).await;
+    assert_eq!(infos[1 ..], read_infos(&mut r, 2).await)
+}
+
+#[tokio::test]
+async fn report_aborted_handshake() {
+    let src = Path::new("/tmp/logs-test-aborted-handshake-src");
+    let dst = Path::new("/tmp/logs-test-aborted-handshake-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst))
+        .await
+        .unwrap()
+        .with_authenticator(Tokens::new().with_token("test", "secret"));
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_token("wrong");
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let p = timeout(Duration::from_secs(5), progress.wait_for(|p| p.last_error().is_some())).await.unwrap().unwrap();
+    assert_eq!(Some(ForwardErrorKind::Aborted), p.last_error());
+    assert!(!ForwardErrorKind::Aborted.is_retryable())
+}
+
+#[tokio::test]
+async fn subscribe_to_records() {
+    let src = Path::new("/tmp/logs-test-subscribe-src");
+    let dst = Path::new("/tmp/logs-test-subscribe-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst)).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    let mut subscriber = receiver.subscribe();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+
+    for e in ENTRIES {
+        let r = timeout(Duration::from_secs(10), subscriber.recv()).await.unwrap().unwrap();
+        assert_eq!(r.source().client(), "test");
+        assert_eq!(r.record().item().as_ref(), *e)
+    }
+}
+
+#[tokio::test]
+async fn receive_middleware() {
+    let src = Path::new("/tmp/logs-test-middleware-src");
+    let dst = Path::new("/tmp/logs-test-middleware-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst))
+        .await
+        .unwrap()
+        .with_middleware(|_: &ReceiveContext<'_>, r: Record| {
+            if r.item_bytes() == b"second" {
+                return Verdict::Reject("no seconds".into())
+            }
+            let item = Bytes::from(r.item_bytes().to_ascii_uppercase());
+            Verdict::Accept(r.with_item(item))
+        })
+        .with_middleware(Enrich::new().with_received_key(None::<String>));
+    let address = receiver.local_addr().unwrap().to_string();
+    let mut subscriber = receiver.subscribe();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+
+    for e in [b"FIRST".as_slice(), b"THIRD"] {
+        let r = timeout(Duration::from_secs(10), subscriber.recv()).await.unwrap().unwrap();
+        assert!(r.record().is_valid());
+        assert_eq!(r.record().item().as_ref(), e);
+        let m = r.record().metadata().unwrap();
+        assert!(m.get("peer").unwrap().starts_with("127.0.0.1:"));
+        assert!(m.get("received_at").is_none())
+    }
+}
+
+#[tokio::test]
+async fn no_middleware_for_backfill_markers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use bogger::{Lane, Strategy};
+
+    let src = Path::new("/tmp/logs-test-middleware-markers-src");
+    let dst = Path::new("/tmp/logs-test-middleware-markers-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let markers = Arc::new(AtomicUsize::new(0));
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst))
+        .await
+        .unwrap()
+        .with_middleware({
+            let markers = markers.clone();
+            move |_: &ReceiveContext<'_>, r: Record| {
+                if r.lane() == Lane::Backfill && r.item_bytes().is_empty() {
+                    markers.fetch_add(1, Ordering::SeqCst);
+                    return Verdict::Reject("empty".into())
+                }
+                Verdict::Accept(r)
+            }
+        });
+    let address = receiver.local_addr().unwrap().to_string();
+    let mut subscriber = receiver.subscribe();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap()
+        .with_strategy(Strategy::NewestFirstWithBackfill);
+    tokio::spawn(forwarder.go());
+
+    for _ in ENTRIES {
+        timeout(Duration::from_secs(10), subscriber.recv()).await.unwrap().unwrap();
+    }
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(0, markers.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn forward_schemas() {
+    let src = Path::new("/tmp/logs-test-forward-schemas-src");
+    let dst = Path::new("/tmp/logs-test-forward-schemas-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let mut w = EntryWriter::open(src, Config::default().with_schema(Some(1))).await.unwrap();
+    w.append(b"first").await.unwrap();
+    w.append_with_schema(b"second", None, 2).await.unwrap();
+    w.sync().await.unwrap();
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst).with_config(Config::default().with_schema(Some(0))))
+        .await
+        .unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 2).await, [b"first".as_slice(), b"second"]);
+    let mut r = EntryReader::open(dst.join("test"), BlockInfo::zero().with_number(1)).await.unwrap();
+    r.next_entry().await.unwrap().unwrap();
+    assert_eq!(Some(1), r.schema());
+    r.next_entry().await.unwrap().unwrap();
+    assert_eq!(Some(2), r.schema())
+}
+
+#[cfg(feature = "lz4")]
+#[tokio::test]
+async fn forward_compressed() {
+    let src = Path::new("/tmp/logs-test-forward-compressed-src");
+    let dst = Path::new("/tmp/logs-test-forward-compressed-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let entry = "all work and no play makes jack a dull boy ".repeat(20);
+    let mut w = EntryWriter::open(src, Config::default()).await.unwrap();
+    w.append_batch([entry.as_bytes(), b"x"]).await.unwrap();
+    w.sync().await.unwrap();
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address)
+        .await
+        .unwrap()
+        .with_compression(bogger::Compression::Lz4);
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 2).await, [entry.as_bytes(), b"x"])
+}
+
+async fn recreate(dir: &Path) {
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap()
+}
+
+#[tokio::test]
+async fn forward_with_flow_control() {
+    let src = Path::new("/tmp/logs-test-forward-with-flow-control-src");
+    let dst = Path::new("/tmp/logs-test-forward-with-flow-control-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let entries = (0 .. 20u8).map(|i| vec![i; 10]).collect::<Vec<_>>();
+    write_entries(src, &entries.iter().map(Vec::as_slice).collect::<Vec<_>>()).await;
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst)).await.unwrap().with_window(Window::new(2, 16));
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 20).await, entries)
+}
+
+#[tokio::test]
+async fn quarantine_corrupt_block() {
+    let src = Path::new("/tmp/logs-test-quarantine-corrupt-block-src");
+    let dst = Path::new("/tmp/logs-test-quarantine-corrupt-block-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    // Damage the first entry of block 1.
+    let mut bytes = fs::read(src.join("block.1")).await.unwrap();
+    bytes[10] ^= 0xFF;
+    fs::write(src.join("block.1"), bytes).await.unwrap();
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_quarantine(true);
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 1).await, &ENTRIES[2 ..]);
+    assert!(src.join(QUARANTINE_DIR).join("block.1").is_file());
+    assert!(!src.join("block.1").exists())
+}
+
+#[tokio::test]
+async fn forward_entry_metadata() {
+    let src = Path::new("/tmp/logs-test-forward-entry-metadata-src");
+    let dst = Path::new("/tmp/logs-test-forward-entry-metadata-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let meta = Metadata::new().with("host", "a");
+    let mut w = EntryWriter::open(src, Config::default().with_entry_metadata(true)).await.unwrap();
+    w.append_with_metadata(b"first", &meta).await.unwrap();
+    w.append(b"second").await.unwrap();
+    w.sync().await.unwrap();
+
+    let cfg = Config::default().with_chunking(true).with_entry_metadata(true);
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst).with_config(cfg)).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 2).await, &ENTRIES[.. 2]);
+    let mut r = EntryReader::open(dst.join("test"), BlockInfo::zero().with_number(1)).await.unwrap();
+    r.next_entry().await.unwrap().unwrap();
+    assert_eq!(Some(&meta), r.metadata());
+    r.next_entry().await.unwrap().unwrap();
+    assert_eq!(Some(&Metadata::new()), r.metadata())
+}
+
+#[test]
+fn handshake_identity() {
+    let identity = Identity::new("host-1").with_label("zone", "eu-1");
+    let hs = Handshake::new("test", BlockNum::from(1)).with_identity(Some(identity.clone()));
+    let bytes = minicbor::to_vec(&hs).unwrap();
+    let hs: Handshake = minicbor::decode(&bytes).unwrap();
+    assert_eq!(Some(&identity), hs.identity());
+    assert_eq!(Some(std::process::id()), hs.identity().map(Identity::pid));
+    assert_eq!(Some("eu-1"), hs.identity().and_then(|i| i.label("zone")))
+}
+
+#[test]
+fn reconnect_delay_bounds() {
+    let cfg = ForwardConfig::default().with_reconnect_delay(Duration::ZERO, Duration::ZERO);
+    assert_eq!(Duration::from_millis(1), cfg.min_reconnect_delay());
+    assert_eq!(Duration::from_millis(1), cfg.max_reconnect_delay())
+}
+
+#[tokio::test]
+async fn back_off_when_connections_drop() {
+    let src = Path::new("/tmp/logs-test-back-off-when-connections-drop");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    // The remote accepts connections but closes them before the handshake.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default().with_reconnect_delay(Duration::from_millis(50), Duration::from_millis(200));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_config(cfg);
+    tokio::spawn(forwarder.go());
+
+    let mut n = 0;
+    let _ = timeout(Duration::from_millis(500), async {
+        loop {
+            drop(listener.accept().await.unwrap());
+            n += 1
+        }
+    })
+    .await;
+    // Attempts after 0, 50, 150 and 350 ms.
+    assert!((2 ..= 5).contains(&n), "{n} connection attempts")
+}
+
+#[test]
+fn decode_borrowed_record() {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(b"hello");
+    let mut e = minicbor::Encoder::new(Vec::new());
+    e.array(3).unwrap()
+        .encode(BlockInfo::zero().with_number(1).with_offset(8u8)).unwrap()
+        .bytes(b"hello").unwrap()
+        .u32(crc).unwrap();
+    let frame = Bytes::from(e.into_writer());
+    let r: RecordRef = minicbor::decode(&frame).unwrap();
+    assert!(r.is_valid());
+    let owned = r.into_record(&frame);
+    assert_eq!(b"hello", owned.item_bytes());
+    assert!(frame.as_ptr_range().contains(&owned.into_item().as_ptr()))
+}
+
+/// Decoding arbitrary bytes as protocol messages must never panic.
+#[test]
+fn decode_arbitrary_messages() {
+    fn prop(bytes: Vec<u8>) -> bool {
+        let _ = minicbor::decode::<Handshake>(&bytes);
+        let _ = minicbor::decode::<HandshakeResponse>(&bytes);
+        let _ = minicbor::decode::<Record>(&bytes);
+        let _ = minicbor::decode::<Ack>(&bytes);
+        true
+    }
+    quickcheck::quickcheck(prop as fn(Vec<u8>) -> bool)
+}
+
+#[tokio::test]
+async fn reconnect_after_ack_timeout() {
+    let src = Path::new("/tmp/logs-test-reconnect-after-ack-timeout");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default()
+        .with_poll_interval(Duration::from_millis(50))
+        .with_ack_timeout(Some(Duration::from_millis(200)));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_config(cfg);
+    tokio::spawn(forwarder.go());
+
+    // Receive records without ever acknowledging them.
+    let mut connections = Vec::new();
+    for _ in 0 .. 2 {
+        let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+        let (r, w) = s.into_split();
+        let mut r = AsyncReader::new(r.compat());
+        let mut w = AsyncWriter::new(w.compat_write());
+        r.read::<Handshake>().await.unwrap().unwrap();
+        w.write(HandshakeResponse::go(BlockInfo::zero())).await.unwrap();
+        let record = r.read::<Record>().await.unwrap().unwrap();
+        assert_eq!(ENTRIES[0], record.item().as_ref());
+        connections.push((r, w))
+    }
+}
+
+/// Forwarding recovers from lost and fragmented TCP segments.
+#[cfg(feature = "testing")]
+#[tokio::test]
+async fn forward_over_faulty_network() {
+    use bogger::testing::{FaultProxy, Faults};
+
+    let src = Path::new("/tmp/logs-test-forward-over-faulty-network-src");
+    let dst = Path::new("/tmp/logs-test-forward-over-faulty-network-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let entries = (0 .. 20u8).map(|i| vec![i; 10]).collect::<Vec<_>>();
+    write_entries(src, &entries.iter().map(Vec::as_slice).collect::<Vec<_>>()).await;
+
+    let address = spawn_receiver(dst).await;
+    let faults = Faults::new(7)
+        .with_drops(0.1)
+        .with_short_writes(0.3)
+        .with_limit(10);
+    let proxy = FaultProxy::start(&address, faults.clone()).await.unwrap();
+    let cfg = ForwardConfig::default().with_poll_interval(Duration::from_millis(50));
+    let forwarder = Forwarder::new("test", src, &proxy.local_addr().to_string())
+        .await
+        .unwrap()
+        .with_config(cfg);
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&dst.join("test"), 20).await, entries);
+    assert!(faults.injected() > 0)
+}
+
+#[tokio::test]
+async fn detect_ack_stall() {
+    let src = Path::new("/tmp/logs-test-detect-ack-stall");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default()
+        .with_poll_interval(Duration::from_millis(50))
+        .with_stall_timeout(Some(Duration::from_millis(200)));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_config(cfg);
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    r.read::<Handshake>().await.unwrap().unwrap();
+    w.write(HandshakeResponse::go(BlockInfo::zero())).await.unwrap();
+    r.read::<Record>().await.unwrap().unwrap();
+
+    // Acknowledge, but never beyond the start.
+    let acks = tokio::spawn(async move {
+        loop {
+            w.write(Ack::zero()).await.unwrap();
+            sleep(Duration::from_millis(50)).await
+        }
+    });
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.is_stalled())).await.unwrap().unwrap();
+    assert_eq!(1, progress.borrow().stalls());
+    acks.abort()
+}
+
+#[tokio::test]
+async fn pause_when_throttled() {
+    let src = Path::new("/tmp/logs-test-pause-when-throttled");
+    recreate(src).await;
+    write_entries(src, &ENTRIES[.. 1]).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default()
+        .with_poll_interval(Duration::from_millis(50))
+        .with_throttle_delay(Duration::from_millis(500));
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_config(cfg);
+    tokio::spawn(forwarder.go());
+
+    let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    r.read::<Handshake>().await.unwrap().unwrap();
+    w.write(HandshakeResponse::go(BlockInfo::zero())).await.unwrap();
+    r.read::<Record>().await.unwrap().unwrap();
+    w.write(Reply::explained(Ack::zero().with_status(AckStatus::Throttle), "busy")).await.unwrap();
+
+    sleep(Duration::from_millis(100)).await;
+    let start = Instant::now();
+    write_entries(src, &ENTRIES[1 .. 2]).await;
+    let record = timeout(Duration::from_secs(5), r.read::<Record>()).await.unwrap().unwrap().unwrap();
+    assert_eq!(ENTRIES[1], record.item_bytes());
+    assert!(start.elapsed() >= Duration::from_millis(300))
+}
+
+/// A sink which fails to store records.
+struct BrokenSink;
+
+impl Sink for BrokenSink {
+    type Error = io::Error;
+
+    async fn start(&mut self, hs: &Handshake<'_>) -> Result<HandshakeResponse<'static>, Self::Error> {
+        Ok(HandshakeResponse::go(BlockInfo::zero()).negotiate(hs))
+    }
+
+    async fn store(&mut self, _: &Source, _: Record) -> Result<BlockInfo, Self::Error> {
+        Err(io::Error::other("disk full"))
+    }
+
+    async fn flush(&mut self, _: &Source) -> Result<BlockInfo, Self::Error> {
+        Ok(BlockInfo::zero())
+    }
+}
+
+#[tokio::test]
+async fn report_storage_errors() {
+    let receiver = Receiver::new("127.0.0.1:0", BrokenSink).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+
+    let s = TcpStream::connect(&address).await.unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    w.write(Handshake::new("test", BlockNum::from(1))).await.unwrap();
+    r.read::<HandshakeResponse>().await.unwrap().unwrap();
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(ENTRIES[0]);
+    let mut e = minicbor::Encoder::new(Vec::new());
+    e.array(3).unwrap()
+        .encode(BlockInfo::zero().with_number(1).with_offset(8u8)).unwrap()
+        .bytes(ENTRIES[0]).unwrap()
+        .u32(crc).unwrap();
+    w.write(minicbor::decode::<Record>(e.writer()).unwrap()).await.unwrap();
+
+    let reply = timeout(Duration::from_secs(5), r.read::<Reply>()).await.unwrap().unwrap().unwrap();
+    assert_eq!(AckStatus::StorageError, reply.ack().status());
+    assert!(reply.message().unwrap().contains("disk full"))
+}
+
+/// A sink which asks to rewind to the first record once it has seen three.
+#[derive(Default)]
+struct RewindSink {
+    items: Arc<Mutex<Vec<Bytes>>>,
+    first: Option<BlockInfo>,
+    rewound: bool
+}
+
+impl Sink for RewindSink {
+    type Error = io::Error;
+
+    async fn start(&mut self, hs: &Handshake<'_>) -> Result<HandshakeResponse<'static>, Self::Error> {
+        Ok(HandshakeResponse::go(BlockInfo::zero()).negotiate(hs))
+    }
+
+    async fn store(&mut self, _: &Source, record: Record) -> Result<BlockInfo, Self::Error> {
+        self.first.get_or_insert(record.info());
+        self.items.lock().unwrap().push(record.into_item());
+        Ok(BlockInfo::zero())
+    }
+
+    async fn flush(&mut self, _: &Source) -> Result<BlockInfo, Self::Error> {
+        Ok(BlockInfo::zero())
+    }
+
+    fn rewind(&mut self, _: &Source) -> Option<BlockInfo> {
+        if self.rewound || self.items.lock().unwrap().len() < 3 {
+            return None
+        }
+        self.rewound = true;
+        self.first
+    }
+}
+
+#[tokio::test]
+async fn rewind_on_request() {
+    let src = Path::new("/tmp/logs-test-rewind-on-request");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let sink = RewindSink::default();
+    let items = sink.items.clone();
+    let receiver = Receiver::new("127.0.0.1:0", sink).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let cfg = ForwardConfig::default().with_poll_interval(Duration::from_millis(50));
+    tokio::spawn(Forwarder::new("test", src, &address).await.unwrap().with_config(cfg).go());
+
+    timeout(Duration::from_secs(5), async {
+        while items.lock().unwrap().len() < 6 {
+            sleep(Duration::from_millis(50)).await
+        }
+    })
+    .await
+    .unwrap();
+    let expected = [ENTRIES, ENTRIES].concat();
+    assert_eq!(*items.lock().unwrap(), expected);
+}
+
+#[tokio::test]
+async fn rewind_no_further_than_acknowledged() {
+    let src = Path::new("/tmp/logs-test-rewind-no-further-than-acknowledged");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let cfg = ForwardConfig::default().with_poll_interval(Duration::from_millis(50));
+    tokio::spawn(Forwarder::new("test", src, &address).await.unwrap().with_config(cfg).go());
+
+    let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    let hs = r.read::<Handshake>().await.unwrap().unwrap();
+    w.write(HandshakeResponse::go(BlockInfo::zero()).negotiate(&hs)).await.unwrap();
+    let mut infos = Vec::new();
+    for _ in ENTRIES {
+        infos.push(r.read::<Record>().await.unwrap().unwrap().info())
+    }
+
+    // Records before the acknowledged one are not sent again.
+    w.write(Ack::new(infos[1])).await.unwrap();
+    w.write(Ack::rewind(infos[0])).await.unwrap();
+    let record = timeout(Duration::from_secs(5), r.read::<Record>()).await.unwrap().unwrap().unwrap();
+    assert_eq!(infos[1], record.info());
+    assert_eq!(ENTRIES[1], record.item_bytes())
+}
+
+#[tokio::test]
+async fn keep_blocks_until_backfilled() {
+    use bogger::{Lane, Retention, Strategy};
+
+    let src = Path::new("/tmp/logs-test-keep-blocks-until-backfilled");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap()
+        .with_strategy(Strategy::NewestFirstWithBackfill)
+        .with_retention(Retention::UntilAck)
+        .with_rate_limit(RateLimit::new().with_records_per_second(1.try_into().unwrap()));
+    tokio::spawn(forwarder.go());
+
+    let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    let hs = r.read::<Handshake>().await.unwrap().unwrap();
+    w.write(HandshakeResponse::resume(&hs, BlockInfo::zero(), None)).await.unwrap();
+    let live = r.read::<Record>().await.unwrap().unwrap();
+    assert_eq!(Lane::Live, live.lane());
+    assert!(live.info().number() > BlockNum::from(1));
+
+    // The destination acknowledges the latest block before any older
+    // record has been sent in the backfill lane.
+    w.write(Ack::new(live.info())).await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    assert!(src.join("block.1").is_file());
+
+    let record = timeout(Duration::from_secs(5), r.read::<Record>()).await.unwrap().unwrap().unwrap();
+    assert_eq!(Lane::Backfill, record.lane());
+    assert_eq!(ENTRIES[0], record.item_bytes())
+}
+
+#[tokio::test]
+async fn replay_stored_records() {
+    let src = Path::new("/tmp/logs-test-replay-stored-records-src");
+    let dst = Path::new("/tmp/logs-test-replay-stored-records-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dst)).await.unwrap().with_replay(OwnRecords);
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    tokio::spawn(forwarder.go());
+    assert_eq!(read_entries(&dst.join("test"), 3).await, ENTRIES);
+
+    // Follow the stored records.
+    let start = BlockInfo::zero().with_number(1);
+    let hs = Handshake::new("test", BlockNum::zero()).with_replay(Some(Replay::new("test", start)));
+    let mut replayer = Replayer::connect(&address, &hs).await.unwrap();
+    let mut positions = Vec::new();
+    for e in ENTRIES {
+        let r = replayer.next().await.unwrap().unwrap();
+        assert!(r.is_valid());
+        assert_eq!(*e, r.item_bytes());
+        positions.push(r.info())
+    }
+    assert!(timeout(Duration::from_millis(200), replayer.next()).await.is_err());
+
+    // Replay a range.
+    let replay = Replay::new("test", positions[1]).with_until(Some(positions[2]));
+    let hs = Handshake::new("test", BlockNum::zero()).with_replay(Some(replay));
+    let mut replayer = Replayer::connect(&address, &hs).await.unwrap();
+    assert_eq!(ENTRIES[1], replayer.next().await.unwrap().unwrap().into_item());
+    assert!(replayer.next().await.unwrap().is_none());
+
+    // Clients may not replay records of other clients.
+    let hs = Handshake::new("other", BlockNum::zero()).with_replay(Some(Replay::new("test", start)));
+    assert!(Replayer::connect(&address, &hs).await.is_err());
+
+    // Replay is only available if enabled.
+    let address = spawn_receiver(dst).await;
+    let hs = Handshake::new("test", BlockNum::zero()).with_replay(Some(Replay::new("test", start)));
+    assert!(Replayer::connect(&address, &hs).await.is_err())
+}
+
+#[tokio::test]
+async fn relay_records_upstream() {
+    let src = Path::new("/tmp/logs-test-relay-records-src");
+    let mid = Path::new("/tmp/logs-test-relay-records-mid");
+    let dst = Path::new("/tmp/logs-test-relay-records-dst");
+    for d in [src, mid, dst] {
+        recreate(d).await
+    }
+    write_entries(src, ENTRIES).await;
+
+    let upstream = spawn_receiver(dst).await;
+    let config = ForwardConfig::default().with_poll_interval(Duration::from_millis(50));
+    let sink = RelaySink::new(mid, &upstream).with_policy(RelayAck::Upstream).with_forward_config(config);
+    let relay = Receiver::new("127.0.0.1:0", sink).await.unwrap();
+    let address = relay.local_addr().unwrap().to_string();
+    tokio::spawn(relay.go());
+
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap();
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    assert_eq!(read_entries(&mid.join("test"), 3).await, ENTRIES);
+    assert_eq!(read_entries(&dst.join("test"), 3).await, ENTRIES);
+
+    // The origin is acknowledged once the upstream receiver has the records.
+    let p = timeout(Duration::from_secs(10), progress.wait_for(|p| {
+        p.position().number() == p.latest() && p.acked().number() == p.latest()
+    }));
+    p.await.unwrap().unwrap();
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn forward_over_http() {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let src = Path::new("/tmp/logs-test-forward-over-http-src");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/records", listener.local_addr().unwrap());
+    let forwarder = bogger::HttpForwarder::new("test", src, &url).await.unwrap().with_batch_len(2);
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let (sock, _) = listener.accept().await.unwrap();
+    let mut sock = BufReader::new(sock);
+    let mut records = Vec::new();
+    while records.len() < ENTRIES.len() {
+        let mut len = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            sock.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break
+            }
+            if let Some(n) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                len = n.trim().parse().unwrap()
+            }
+        }
+        let mut body = vec![0; len];
+        sock.read_exact(&mut body).await.unwrap();
+        records.extend(minicbor::decode::<Vec<Record>>(&body).unwrap());
+        sock.get_mut().write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await.unwrap()
+    }
+    assert_eq!(ENTRIES, records.iter().map(|r| r.item().as_ref().to_vec()).collect::<Vec<_>>());
+    assert!(records.iter().all(Record::is_valid));
+
+    let last = records.last().unwrap().info();
+    timeout(Duration::from_secs(5), progress.wait_for(|p| p.acked() == last)).await.unwrap().unwrap();
+}
+
+#[cfg(feature = "s3")]
+#[tokio::test]
+async fn upload_blocks_to_object_store() {
+    use std::sync::Arc;
+    use bogger::{Manifest, S3Forwarder};
+    use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+
+    let src = Path::new("/tmp/logs-test-upload-blocks-src");
+    recreate(src).await;
+    write_entries(src, ENTRIES).await;
+
+    let store = Arc::new(InMemory::new());
+    let config = ForwardConfig::default().with_poll_interval(Duration::from_millis(50));
+    let forwarder = S3Forwarder::new(src, store.clone()).await.unwrap().with_prefix("logs").with_config(config);
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    timeout(Duration::from_secs(5), progress.wait_for(|p| {
+        p.latest().value() > 1 && p.acked().number() == p.latest()
+    }))
+    .await
+    .unwrap()
+    .unwrap();
+
+    // All but the latest block have been uploaded.
+    let latest = progress.borrow().latest().value();
+    for n in 1 .. latest {
+        let name = format!("logs/block.{n}.manifest");
+        let bytes = store.get(&ObjectPath::from(name)).await.unwrap().bytes().await.unwrap();
+        let manifest: Manifest = minicbor::decode(&bytes).unwrap();
+        assert_eq!(n, manifest.number().value());
+        assert!(manifest.entries() > 0)
+    }
+    let manifest = format!("logs/block.{latest}.manifest");
+    assert!(store.head(&ObjectPath::from(manifest)).await.is_err())
+}
+
+async fn write_entries(dir: &Path, entries: &[&[u8]]) {
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(32).with_max_entry_len(32)).await.unwrap();
+    for e in entries {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap()
+}
+
+async fn spawn_receiver(dir: &Path) -> String {
+    let receiver = Receiver::new("127.0.0.1:0", FileSink::new(dir)).await.unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    address
+}
+
+/// Wait until `n` entries have been received.
+async fn read_entries(dir: &Path, n: usize) -> Vec<Bytes> {
+    timeout(Duration::from_secs(10), async {
+        loop {
+            sleep(Duration::from_millis(100)).await;
+            let Ok(mut r) = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await else {
+                continue
+            };
+            let mut entries = Vec::new();
+            while let Some((e, _)) = r.next_entry().await.unwrap() {
+                entries.push(e)
+            }
+            if entries.len() == n {
+                return entries
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn forward_across_migration() {
+    use bogger::{MigrateError, MigrateOptions, Retention, forwarded, migrate_blocks_with};
+
+    let src = Path::new("/tmp/logs-test-forward-across-migration-src");
+    let dst = Path::new("/tmp/logs-test-forward-across-migration-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    let entries: Vec<Bytes> = (1 ..= 7).map(|i| Bytes::from(format!("e{i}"))).collect();
+
+    // Three entries per block, with the latest block being continued later.
+    let mut w = EntryWriter::open(src, Config::default().with_max_block_len(32).with_max_entry_len(8)).await.unwrap();
+    for e in &entries[.. 5] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let address = spawn_receiver(dst).await;
+    let retention = Retention::AfterAck { max_age: None, max_bytes: None };
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_retention(retention.clone());
+    let task = tokio::spawn(forwarder.go());
+    assert_eq!(read_entries(&dst.join("test"), 5).await, entries[.. 5]);
+    let mark = timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(n) = forwarded(src).await.unwrap() {
+                return n
+            }
+            sleep(Duration::from_millis(50)).await
+        }
+    })
+    .await
+    .unwrap();
+
+    // Blocks can not be migrated while they are written or forwarded.
+    let opts = MigrateOptions::new().with_min_version(2).with_config(Config::default().with_trailer(true));
+    assert!(matches!(migrate_blocks_with(src, &opts).await, Err(MigrateError::Locked(_))));
+    task.abort();
+    let _ = task.await;
+
+    for e in &entries[5 ..] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+    drop(w);
+
+    // Blocks with positions of the forwarder and receiver are left alone.
+    let s = migrate_blocks_with(src, &opts).await.unwrap();
+    assert_eq!([BlockNum::from(1)], s.migrated());
+    assert_eq!(BlockNum::from(2), mark);
+    assert_eq!([BlockNum::from(2), BlockNum::from(3)], s.pending());
+
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_retention(retention);
+    tokio::spawn(forwarder.go());
+    assert_eq!(read_entries(&dst.join("test"), 7).await, entries)
+}
+
+#[tokio::test]
+async fn retain_forwarded_blocks_by_age() {
+    use bogger::{Retention, forwarded, list_blocks};
+
+    let src = Path::new("/tmp/logs-test-retain-forwarded-blocks-by-age");
+    recreate(src).await;
+    write_blocks(src, 6).await;
+
+    // Every block but the third is older than the maximum age.
+    let old = SystemTime::now() - Duration::from_secs(7200);
+    for b in list_blocks(src).await.unwrap() {
+        if b.number() != BlockNum::from(3) {
+            std::fs::File::options().write(true).open(b.path()).unwrap().set_modified(old).unwrap()
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let retention = Retention::AfterAck { max_age: Some(Duration::from_secs(3600)), max_bytes: None };
+    tokio::spawn(Forwarder::new("test", src, &address).await.unwrap().with_retention(retention).go());
+    let (mut r, mut w) = accept_forwarder(&listener).await;
+    let infos = read_infos(&mut r, 6).await;
+
+    // Unacknowledged blocks are kept, no matter how old.
+    w.write(Ack::new(infos[3])).await.unwrap();
+    wait_for_blocks(src, &[3, 4, 5, 6]).await;
+    assert_eq!(Some(BlockNum::from(4)), forwarded(src).await.unwrap());
+
+    // The writable block is kept as well.
+    w.write(Ack::new(infos[5])).await.unwrap();
+    wait_for_blocks(src, &[3, 6]).await;
+    assert_eq!(Some(BlockNum::from(6)), forwarded(src).await.unwrap())
+}
+
+#[tokio::test]
+async fn retain_forwarded_blocks_by_size() {
+    use bogger::{Retention, forwarded, list_blocks};
+
+    let src = Path::new("/tmp/logs-test-retain-forwarded-blocks-by-size");
+    recreate(src).await;
+    write_blocks(src, 6).await;
+    let size = list_blocks(src).await.unwrap()[0].size();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let retention = Retention::AfterAck { max_age: None, max_bytes: Some(2 * size) };
+    tokio::spawn(Forwarder::new("test", src, &address).await.unwrap().with_retention(retention).go());
+    let (mut r, mut w) = accept_forwarder(&listener).await;
+    let infos = read_infos(&mut r, 6).await;
+
+    // The two most recent forwarded blocks fit into the budget.
+    w.write(Ack::new(infos[4])).await.unwrap();
+    wait_for_blocks(src, &[3, 4, 5, 6]).await;
+    assert_eq!(Some(BlockNum::from(5)), forwarded(src).await.unwrap());
+
+    // The writable block does not count against the budget.
+    w.write(Ack::new(infos[5])).await.unwrap();
+    wait_for_blocks(src, &[4, 5, 6]).await;
+    assert_eq!(Some(BlockNum::from(6)), forwarded(src).await.unwrap())
+}
+
+#[tokio::test]
+async fn move_forwarded_blocks() {
+    use bogger::{ArchiveAction, list_blocks};
+
+    let src = Path::new("/tmp/logs-test-move-forwarded-blocks-src");
+    let archive = Path::new("/tmp/logs-test-move-forwarded-blocks-archive");
+    for d in [src, archive] {
+        recreate(d).await
+    }
+    write_blocks(src, 4).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap()
+        .with_archive_action(ArchiveAction::MoveTo(archive.to_path_buf()));
+    tokio::spawn(forwarder.go());
+    let (mut r, mut w) = accept_forwarder(&listener).await;
+    let infos = read_infos(&mut r, 4).await;
+
+    w.write(Ack::new(infos[2])).await.unwrap();
+    wait_for_blocks(src, &[3, 4]).await;
+    let moved: Vec<_> = list_blocks(archive).await.unwrap().iter().map(|b| b.number().value()).collect();
+    assert_eq!([1, 2], &moved[..])
+}
+
+/// Blocks are copied if the archive is on another file system, like
+/// `/dev/shm` which usually is a tmpfs.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn move_forwarded_blocks_across_file_systems() {
+    use bogger::{ArchiveAction, LogReader};
+
+    let src = Path::new("/tmp/logs-test-move-forwarded-blocks-across-file-systems");
+    let archive = Path::new("/dev/shm/logs-test-move-forwarded-blocks-across-file-systems");
+    for d in [src, archive] {
+        recreate(d).await
+    }
+    write_blocks(src, 3).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap()
+        .with_archive_action(ArchiveAction::MoveTo(archive.to_path_buf()));
+    tokio::spawn(forwarder.go());
+    let (mut r, mut w) = accept_forwarder(&listener).await;
+    let infos = read_infos(&mut r, 3).await;
+
+    w.write(Ack::new(infos[2])).await.unwrap();
+    wait_for_blocks(src, &[3]).await;
+    let mut reader = LogReader::new(archive, BlockInfo::zero());
+    let mut entries = Vec::new();
+    while let Some((_, e)) = reader.next_entry().await.unwrap() {
+        entries.push(e)
+    }
+    assert_eq!([&b"e1"[..], b"e2"], &entries[..])
+}
+
+#[tokio::test]
+async fn rename_forwarded_blocks() {
+    use bogger::{ArchiveAction, BlockNames};
+
+    let src = Path::new("/tmp/logs-test-rename-forwarded-blocks");
+    recreate(src).await;
+    write_blocks(src, 4).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap()
+        .with_archive_action(ArchiveAction::RenameSuffix("done".to_string()));
+    let task = tokio::spawn(forwarder.go());
+    let (mut r, mut w) = accept_forwarder(&listener).await;
+    let infos = read_infos(&mut r, 4).await;
+
+    w.write(Ack::new(infos[2])).await.unwrap();
+    // Renamed blocks are no longer listed.
+    wait_for_blocks(src, &[3, 4]).await;
+    for n in [1, 2] {
+        assert!(src.join(format!("block.{n}.done")).is_file());
+        assert_eq!(None, BlockNames::new().parse(format!("block.{n}.done").as_ref()))
+    }
+    task.abort();
+    let _ = task.await;
+
+    // A new destination starts with the first block not renamed.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(Forwarder::new("test", src, &address).await.unwrap().go());
+    let (mut r, _w) = accept_forwarder(&listener).await;
+    assert_eq!(BlockNum::from(3), read_infos(&mut r, 1).await[0].number())
+}
+
+#[tokio::test]
+async fn keep_blocks_on_dry_run() {
+    use bogger::list_blocks;
+
+    let src = Path::new("/tmp/logs-test-keep-blocks-on-dry-run-src");
+    let dst = Path::new("/tmp/logs-test-keep-blocks-on-dry-run-dst");
+    for d in [src, dst] {
+        recreate(d).await
+    }
+    write_blocks(src, 4).await;
+
+    let address = spawn_receiver(dst).await;
+    let forwarder = Forwarder::new("test", src, &address).await.unwrap().with_dry_run(true);
+    let mut progress = forwarder.progress();
+    tokio::spawn(forwarder.go());
+
+    let acked = progress.wait_for(|p| p.acked().number() == BlockNum::from(4));
+    timeout(Duration::from_secs(10), acked).await.unwrap().unwrap();
+    let blocks: Vec<_> = list_blocks(src).await.unwrap().iter().map(|b| b.number().value()).collect();
+    assert_eq!([1, 2, 3, 4], &blocks[..])
+}
+
+/// A sink which only reports the position of stored records when flushed.
+#[derive(Default)]
+struct FlushSink {
+    stored: Option<BlockInfo>
+}
+
+impl Sink for FlushSink {
+    type Error = io::Error;
+
+    async fn start(&mut self, hs: &Handshake<'_>) -> Result<HandshakeResponse<'static>, Self::Error> {
+        Ok(HandshakeResponse::go(BlockInfo::zero()).negotiate(hs))
+    }
+
+    async fn store(&mut self, _: &Source, record: Record) -> Result<BlockInfo, Self::Error> {
+        self.stored = Some(record.info());
+        Ok(BlockInfo::zero())
+    }
+
+    async fn flush(&mut self, _: &Source) -> Result<BlockInfo, Self::Error> {
+        Ok(self.stored.unwrap_or_else(BlockInfo::zero))
+    }
+}
+
+#[tokio::test]
+async fn ack_after_records() {
+    let cadence = AckCadence::new().with_records(3);
+    let (mut r, mut w) = connect_receiver(cadence).await;
+    let infos: Vec<_> = (1 ..= 7u64).map(|i| BlockInfo::zero().with_number(1).with_offset(8 * i)).collect();
+    for i in &infos {
+        w.write(record(*i, b"abcd")).await.unwrap();
+    }
+
+    // The remaining record is acknowledged once the forwarder is idle.
+    let start = Instant::now();
+    assert_eq!([infos[2], infos[5], infos[6]], read_acks(&mut r, 3).await[..]);
+    assert!(start.elapsed() >= Duration::from_millis(900))
+}
+
+#[tokio::test]
+async fn ack_after_bytes() {
+    let cadence = AckCadence::new().with_bytes(10);
+    let (mut r, mut w) = connect_receiver(cadence).await;
+    let infos: Vec<_> = (1 ..= 7u64).map(|i| BlockInfo::zero().with_number(1).with_offset(8 * i)).collect();
+    for i in &infos {
+        w.write(record(*i, b"abcd")).await.unwrap();
+    }
+
+    let start = Instant::now();
+    assert_eq!([infos[2], infos[5], infos[6]], read_acks(&mut r, 3).await[..]);
+    assert!(start.elapsed() >= Duration::from_millis(900))
+}
+
+#[tokio::test]
+async fn ack_at_interval() {
+    let cadence = AckCadence::new().with_interval(Duration::from_millis(200));
+    let (mut r, mut w) = connect_receiver(cadence).await;
+
+    // An idle stream is acknowledged when its interval expires, before
+    // the forwarder counts as idle.
+    let info = BlockInfo::zero().with_number(1).with_offset(8u8);
+    let start = Instant::now();
+    w.write(record(info, b"abcd")).await.unwrap();
+    assert_eq!([info], read_acks(&mut r, 1).await[..]);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_millis(900));
+
+    // While records arrive, they are acknowledged about once per interval.
+    let sender = tokio::spawn(async move {
+        for i in 2 ..= 21u64 {
+            w.write(record(BlockInfo::zero().with_number(1).with_offset(8 * i), b"abcd")).await.unwrap();
+            sleep(Duration::from_millis(50)).await
+        }
+        w
+    });
+    let mut acks = 0;
+    while !sender.is_finished() {
+        if let Ok(ack) = timeout(Duration::from_millis(100), r.read::<Ack>()).await {
+            assert!(ack.unwrap().is_some());
+            acks += 1
+        }
+    }
+    assert!((3 .. 10).contains(&acks), "{acks} acks")
+}
+
+/// Connect to a receiver with the given ack cadence as a forwarder.
+async fn connect_receiver(cadence: AckCadence) -> (AsyncReader<Compat<OwnedReadHalf>>, AsyncWriter<Compat<OwnedWriteHalf>>) {
+    let receiver = Receiver::new("127.0.0.1:0", FlushSink::default()).await.unwrap().with_ack_cadence(cadence);
+    let address = receiver.local_addr().unwrap().to_string();
+    tokio::spawn(receiver.go());
+    let (r, w) = TcpStream::connect(&address).await.unwrap().into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    w.write(Handshake::new("test", BlockNum::from(1))).await.unwrap();
+    r.read::<HandshakeResponse>().await.unwrap().unwrap();
+    (r, w)
+}
+
+/// A record with the given position and item.
+fn record(info: BlockInfo, item: &[u8]) -> Record {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(item);
+    let mut e = minicbor::Encoder::new(Vec::new());
+    e.array(3).unwrap()
+        .encode(info).unwrap()
+        .bytes(item).unwrap()
+        .u32(crc).unwrap();
+    minicbor::decode(e.writer()).unwrap()
+}
+
+/// Read the positions of the next `n` acknowledgements.
+async fn read_acks(r: &mut AsyncReader<Compat<OwnedReadHalf>>, n: usize) -> Vec<BlockInfo> {
+    let mut infos = Vec::new();
+    for _ in 0 .. n {
+        let ack = timeout(Duration::from_secs(5), r.read::<Ack>()).await.unwrap().unwrap().unwrap();
+        infos.push(ack.info())
+    }
+    infos
+}
+
+/// Write an entry `e<i>` to each of `n` blocks.
+async fn write_blocks(dir: &Path, n: usize) {
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(16).with_max_entry_len(16)).await.unwrap();
+    for i in 1 ..= n {
+        w.append(format!("e{i}").as_bytes()).await.unwrap()
+    }
+    w.sync().await.unwrap()
+}
+
+/// Accept a forwarder's connection as a destination without any records.
+async fn accept_forwarder(listener: &TcpListener) -> (AsyncReader<Compat<OwnedReadHalf>>, AsyncWriter<Compat<OwnedWriteHalf>>) {
+    accept_forwarder_at(listener, BlockInfo::zero()).await
+}
+
+/// Like `accept_forwarder` but ask the forwarder to start at `start`.
+async fn accept_forwarder_at(listener: &TcpListener, start: BlockInfo) -> (AsyncReader<Compat<OwnedReadHalf>>, AsyncWriter<Compat<OwnedWriteHalf>>) {
+    let (s, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+    let (r, w) = s.into_split();
+    let mut r = AsyncReader::new(r.compat());
+    let mut w = AsyncWriter::new(w.compat_write());
+    let hs = r.read::<Handshake>().await.unwrap().unwrap();
+    w.write(HandshakeResponse::go(start).negotiate(&hs)).await.unwrap();
+    (r, w)
+}
+
+/// Read the positions of the next `n` records.
+async fn read_infos(r: &mut AsyncReader<Compat<OwnedReadHalf>>, n: usize) -> Vec<BlockInfo> {
+    let mut infos = Vec::new();
+    for _ in 0 .. n {
+        let record = timeout(Duration::from_secs(5), r.read::<Record>()).await.unwrap().unwrap().unwrap();
+        infos.push(record.info())
+    }
+    infos
+}
+
+/// Wait until exactly the given blocks are in `dir`.
+async fn wait_for_blocks(dir: &Path, expected: &[u64]) {
+    use bogger::list_blocks;
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let blocks: Vec<_> = list_blocks(dir).await.unwrap().iter().map(|b| b.number().value()).collect();
+            if blocks == expected {
+                return
+            }
+            sleep(Duration::from_millis(50)).await
+        }
+    })
+    .await
+    .unwrap()
+}
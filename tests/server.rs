@@ -29,7 +29,7 @@ async fn test_server() {
             }
             if gen.gen_range(0 .. 100) % 10 == 0 {
                 println!("sending ack: {:?}", state.2);
-                let _ = writer.write(state.2).await;
+                let _ = writer.write(&state.2).await;
             }
         }
     }
@@ -1,9 +1,17 @@
-use std::path::Path;
+use std::{borrow::Cow, io, num::NonZeroU32, path::Path, pin::Pin};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::task::{Context, Poll, Waker};
 
-use bogger::{Logger, Config};
+use bogger::{Logger, LogError, Config, EntryWriter, EntryReader, BlockInfo, BlockNames, BlockNum, BlockStatus, verify_block};
+use bogger::{LogReader, delete_blocks, delete_blocks_with, list_blocks, list_blocks_with, DeleteOptions, QuotaPolicy, WriteError};
+use bogger::{BlockStore, ConfigError, LogSet, MemoryStore, Router, Transform};
+use minicbor::bytes::ByteVec;
+use quickcheck::{QuickCheck, TestResult};
+use futures_util::{stream, StreamExt, TryStreamExt};
 use rand::distributions::{Alphanumeric, DistString};
 use rand::Rng;
-use tokio::fs;
+use std::time::Duration;
+use tokio::{fs::{self, OpenOptions}, io::{AsyncReadExt, AsyncWriteExt}, time::{sleep, timeout}};
 
 #[tokio::test]
 async fn log_some_records() {
@@ -24,3 +32,1774 @@ async fn log_some_records() {
 
     log.close().await.unwrap()
 }
+
+#[tokio::test]
+async fn read_partially_written_entry() {
+    let dir = Path::new("/tmp/logs-test-read-partially-written-entry");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    w.append(b"a").await.unwrap();
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert_eq!(b"a", &r.next_entry().await.unwrap().unwrap().0[..]);
+
+    // Append the first half of an entry by hand.
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(b"bcd");
+    let mut f = OpenOptions::new().append(true).open(dir.join("block.1")).await.unwrap();
+    f.write_all(&[0, 3, b'b']).await.unwrap();
+    f.flush().await.unwrap();
+    assert!(r.next_entry().await.unwrap().is_none());
+
+    // Complete the entry.
+    f.write_all(b"cd").await.unwrap();
+    f.write_all(&crc.to_be_bytes()).await.unwrap();
+    f.flush().await.unwrap();
+    assert_eq!(b"bcd", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn append_batches_across_blocks() {
+    let dir = Path::new("/tmp/logs-test-append-batches-across-blocks");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_block_len(256).with_max_entry_len(32);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    let entries: Vec<Vec<u8>> = (0 .. 100u8).map(|i| vec![i; usize::from(i % 20) + 1]).collect();
+    for batch in entries.chunks(30) {
+        w.append_batch(batch.iter().map(Vec::as_slice)).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let mut actual = Vec::new();
+    let mut block = 1;
+    while dir.join(format!("block.{block}")).is_file() {
+        assert!(fs::metadata(dir.join(format!("block.{block}"))).await.unwrap().len() <= 256);
+        let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(block)).await.unwrap();
+        while let Some((e, _)) = r.next_entry().await.unwrap() {
+            actual.push(e.to_vec())
+        }
+        block += 1
+    }
+    assert!(block > 2);
+    assert_eq!(entries, actual);
+
+    let start = BlockInfo::zero().with_number(1);
+    let actual: Vec<Vec<u8>> = LogReader::new(dir, start)
+        .into_stream()
+        .map_ok(|(_, e)| e.to_vec())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(entries, actual);
+
+    let first = EntryReader::open(dir, start).await.unwrap()
+        .into_stream()
+        .collect::<Vec<_>>()
+        .await;
+    assert!(first.iter().all(Result::is_ok));
+    assert!(first.len() < entries.len())
+}
+
+#[tokio::test]
+async fn large_entries() {
+    let dir = Path::new("/tmp/logs-test-large-entries");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_entry_len(1024 * 1024).with_max_block_len(4 * 1024 * 1024);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    let big = vec![42; 200_000];
+    w.append(b"small").await.unwrap();
+    w.append(&big).await.unwrap();
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert_eq!(b"small", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(big, r.next_entry().await.unwrap().unwrap().0);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn chunked_entries() {
+    let dir = Path::new("/tmp/logs-test-chunked-entries");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_entry_len(100).with_chunking(true);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    let big: Vec<u8> = (0 .. 1000).map(|i| i as u8).collect();
+    w.append(&big).await.unwrap();
+    w.append(b"small").await.unwrap();
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    let (e, crc) = r.next_entry().await.unwrap().unwrap();
+    assert_eq!(big, e);
+    assert_eq!(crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(&big), crc);
+    assert_eq!(b"small", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn block_trailer() {
+    let dir = Path::new("/tmp/logs-test-block-trailer");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_block_len(64).with_max_entry_len(16).with_trailer(true);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    for e in [&b"first entry"[..], b"second entry", b"third entry", b"fourth entry"] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    let mut n = 0;
+    while r.next_entry().await.unwrap().is_some() {
+        n += 1
+    }
+    assert_eq!(Some(n), r.trailer().map(|t| t.entries()));
+    assert_eq!(BlockStatus::Complete { entries: n }, verify_block(dir, 1.into()).await.unwrap());
+    assert!(matches!(verify_block(dir, 2.into()).await.unwrap(), BlockStatus::Valid { .. }));
+
+    // Closing the writer completes the last block.
+    w.close().await.unwrap();
+    assert!(matches!(verify_block(dir, 2.into()).await.unwrap(), BlockStatus::Complete { .. }));
+
+    // Flip a bit of the first entry.
+    let path = dir.join("block.1");
+    let mut bytes = fs::read(&path).await.unwrap();
+    bytes[12] ^= 1;
+    fs::write(&path, bytes).await.unwrap();
+    assert_eq!(BlockStatus::Corrupt { entries: 0 }, verify_block(dir, 1.into()).await.unwrap())
+}
+
+#[cfg(feature = "mmap")]
+#[tokio::test]
+async fn mmap_reader() {
+    let dir = Path::new("/tmp/logs-test-mmap-reader");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_entry_len(100).with_chunking(true).with_trailer(true);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    let big: Vec<u8> = (0 .. 1000).map(|i| i as u8).collect();
+    w.append(b"small").await.unwrap();
+    w.append(&big).await.unwrap();
+    w.sync().await.unwrap();
+
+    let mut r = bogger::MmapEntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert_eq!(b"small", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(big, r.next_entry().await.unwrap().unwrap().0);
+    assert!(r.next_entry().await.unwrap().is_none());
+
+    // Entries appended after mapping are picked up.
+    w.append(b"late").await.unwrap();
+    w.sync().await.unwrap();
+    assert_eq!(b"late", &r.next_entry().await.unwrap().unwrap().0[..])
+}
+
+#[tokio::test]
+async fn preallocated_blocks() {
+    let dir = Path::new("/tmp/logs-test-preallocated-blocks");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default().with_preallocate(true)).await.unwrap();
+    w.append(b"a").await.unwrap();
+    w.sync().await.unwrap();
+
+    // Preallocation must not change the file size readers see.
+    assert_eq!(8 + 2 + 1 + 4, fs::metadata(dir.join("block.1")).await.unwrap().len());
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert_eq!(b"a", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn delete_blocks_summary() {
+    let dir = Path::new("/tmp/logs-test-delete-blocks-summary");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(16).with_max_entry_len(8)).await.unwrap();
+    for e in [b"first", b"secnd", b"third"] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let s = delete_blocks_with(dir, BlockNum::from(3), &DeleteOptions::new().with_dry_run(true)).await.unwrap();
+    assert_eq!([BlockNum::from(1), BlockNum::from(2)], s.deleted());
+    assert!(dir.join("block.1").is_file());
+
+    let s = delete_blocks(dir, BlockNum::from(3)).await.unwrap();
+    assert!(s.is_complete());
+    assert_eq!(2, s.deleted().len());
+    assert!(!dir.join("block.1").exists());
+    assert!(dir.join("block.3").is_file());
+
+    // The latest block may still be written to.
+    let s = delete_blocks(dir, BlockNum::from(10)).await.unwrap();
+    assert!(s.deleted().is_empty());
+    assert!(dir.join("block.3").is_file())
+}
+
+#[tokio::test]
+async fn sharded_blocks() {
+    let dir = Path::new("/tmp/logs-test-sharded-blocks");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_block_len(16).with_max_entry_len(8).with_sharding(true);
+    let mut w = EntryWriter::open(dir, cfg.clone()).await.unwrap();
+    w.append(b"first").await.unwrap();
+    w.sync().await.unwrap();
+    drop(w);
+    assert!(dir.join("00000/block.1").is_file());
+
+    // Reopening continues after the latest sharded block.
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    w.append(b"second").await.unwrap();
+    w.sync().await.unwrap();
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(2)).await.unwrap();
+    assert_eq!(b"second", &r.next_entry().await.unwrap().unwrap().0[..]);
+
+    drop(w);
+    let s = delete_blocks_with(dir, BlockNum::from(3), &DeleteOptions::new().with_delete_latest(true)).await.unwrap();
+    assert_eq!([BlockNum::from(1), BlockNum::from(2)], s.deleted());
+    // The shard is removed once it is empty.
+    assert!(!dir.join("00000").exists())
+}
+
+#[tokio::test]
+async fn durable_metadata() {
+    let dir = Path::new("/tmp/logs-test-durable-metadata");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    // Creating the shard and every block syncs its parent directory, deleting
+    // blocks syncs the shard and the block directory.
+    let cfg = Config::default()
+        .with_max_block_len(16)
+        .with_max_entry_len(8)
+        .with_sharding(true)
+        .with_durable_metadata(true);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    for e in [b"first", b"secnd", b"third"] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+    drop(w);
+    assert_eq!(3, list_blocks(dir).await.unwrap().len());
+
+    let opts = DeleteOptions::new().with_durable_metadata(true);
+    let s = delete_blocks_with(dir, BlockNum::from(3), &opts).await.unwrap();
+    assert!(s.is_complete());
+    assert_eq!([BlockNum::from(1), BlockNum::from(2)], s.deleted());
+    assert!(!dir.join("00000/block.1").exists());
+
+    let mut r = LogReader::new(dir, BlockInfo::zero());
+    assert_eq!(b"third", &r.next_entry().await.unwrap().unwrap().1[..]);
+    assert!(r.next_entry().await.unwrap().is_none());
+
+    // Deleting the last block removes the shard.
+    let s = delete_blocks_with(dir, BlockNum::from(4), &opts.with_delete_latest(true)).await.unwrap();
+    assert_eq!([BlockNum::from(3)], s.deleted());
+    assert!(!dir.join("00000").exists())
+}
+
+#[tokio::test]
+async fn list_block_files() {
+    let dir = Path::new("/tmp/logs-test-list-block-files");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    for d in ["00000", "00001", "other", "block.5"] {
+        fs::create_dir_all(dir.join(d)).await.unwrap()
+    }
+    let files = [
+        ("block.3", 3),
+        ("00000/block.1", 1),
+        ("00000/block.20", 20),
+        ("00001/block.1002", 1002),
+        ("a.block.4.blk", 4),
+        // Not blocks of the default names.
+        ("block.7.tmp", 7),
+        ("00000/block.2.tmp", 2),
+        ("block.x", 0),
+        ("block.", 0),
+        ("block.lock", 0),
+        ("notes.txt", 0),
+        // Only shard subdirectories are searched.
+        ("other/block.6", 6)
+    ];
+    for (name, len) in files {
+        fs::write(dir.join(name), vec![0; len]).await.unwrap()
+    }
+
+    let blocks = list_blocks(dir).await.unwrap();
+    let numbers = blocks.iter().map(|b| b.number().value()).collect::<Vec<_>>();
+    assert_eq!([1, 3, 20, 1002][..], numbers);
+    for b in &blocks {
+        assert_eq!(b.number().value(), b.size());
+        assert_eq!(Some(b.number()), BlockNames::new().parse(b.path().file_name().unwrap()));
+        assert!(b.modified().is_some())
+    }
+    assert_eq!(dir.join("00001/block.1002"), blocks[3].path());
+
+    let names = BlockNames::new().with_prefix("a.block.").with_suffix(".blk");
+    let blocks = list_blocks_with(dir, &names).await.unwrap();
+    assert_eq!(1, blocks.len());
+    assert_eq!((BlockNum::from(4), 4), (blocks[0].number(), blocks[0].size()))
+}
+
+#[tokio::test]
+async fn logger_encoding_stage() {
+    let dir = Path::new("/tmp/logs-test-logger-encoding-stage");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_channel_capacity(4).with_encoding_stage(true);
+    let log = Logger::new(dir, cfg).await.unwrap();
+    for i in 0 .. 100u32 {
+        log.add(i).await.unwrap()
+    }
+    log.close().await.unwrap();
+    assert!(log.add(0).await.is_err());
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    for i in 0 .. 100u32 {
+        let (e, _) = r.next_entry().await.unwrap().unwrap();
+        assert_eq!(i, minicbor::decode::<u32>(&e).unwrap())
+    }
+}
+
+#[tokio::test]
+async fn logger_sink() {
+    let dir = Path::new("/tmp/logs-test-logger-sink");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_channel_capacity(4);
+    let log = Logger::new(dir, cfg).await.unwrap();
+    stream::iter(0 .. 100u32).map(Ok).forward(log.clone()).await.unwrap();
+    log.close().await.unwrap();
+
+    let actual: Vec<u32> = LogReader::new(dir, BlockInfo::zero().with_number(1))
+        .into_stream()
+        .map_ok(|(_, e)| minicbor::decode::<u32>(&e).unwrap())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!((0 .. 100).collect::<Vec<_>>(), actual)
+}
+
+#[tokio::test]
+async fn rotation_hook() {
+    let dir = Path::new("/tmp/logs-test-rotation-hook");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let cfg = Config::default()
+        .with_max_block_len(16)
+        .with_max_entry_len(8)
+        .with_on_rotate(move |info, size| tx.send((info.number(), size)).unwrap());
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    w.append(b"first").await.unwrap();
+    w.append(b"second").await.unwrap();
+
+    assert_eq!((BlockNum::from(1), 8 + 2 + 5 + 4), rx.try_recv().unwrap());
+    assert!(rx.try_recv().is_err());
+
+    // The last block is finished when the writer is closed.
+    w.close().await.unwrap();
+    assert_eq!((BlockNum::from(2), 8 + 2 + 6 + 4), rx.try_recv().unwrap())
+}
+
+#[tokio::test]
+async fn custom_block_names() {
+    let dir = Path::new("/tmp/logs-test-custom-block-names");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let a = BlockNames::new().with_prefix("a-").with_suffix(".blk");
+    let b = BlockNames::new().with_prefix("b-");
+    for (names, entry) in [(&a, b"from a"), (&b, b"from b")] {
+        let mut w = EntryWriter::open(dir, Config::default().with_block_names(names.clone())).await.unwrap();
+        w.append(entry).await.unwrap();
+        w.sync().await.unwrap()
+    }
+    assert!(dir.join("a-1.blk").is_file());
+    assert!(dir.join("b-1").is_file());
+    assert!(list_blocks(dir).await.unwrap().is_empty());
+
+    let mut r = EntryReader::open_with(dir, BlockInfo::zero().with_number(1), &b).await.unwrap();
+    assert_eq!(b"from b", &r.next_entry().await.unwrap().unwrap().0[..]);
+
+    let opts = DeleteOptions::new().with_block_names(a).with_delete_latest(true);
+    let s = delete_blocks_with(dir, BlockNum::from(2), &opts).await.unwrap();
+    assert_eq!([BlockNum::from(1)], s.deleted());
+    assert!(dir.join("b-1").is_file())
+}
+
+#[tokio::test]
+async fn disk_quota() {
+    let dir = Path::new("/tmp/logs-test-disk-quota");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let entry = [0; 1000];
+    let cfg = Config::default().with_max_block_len(2048).with_quota(8192);
+
+    let mut w = EntryWriter::open(dir, cfg.clone()).await.unwrap();
+    let mut n = 0;
+    loop {
+        match w.append(&entry).await {
+            Ok(()) => n += 1,
+            Err(WriteError::Quota) => break,
+            Err(e) => panic!("{e}")
+        }
+    }
+    drop(w);
+    assert!(n > 0);
+    let blocks = list_blocks(dir).await.unwrap();
+    assert!(blocks.iter().map(|b| b.size()).sum::<u64>() <= 8192);
+    let first = blocks[0].number();
+
+    let mut w = EntryWriter::open(dir, cfg.with_quota_policy(QuotaPolicy::DropOldest)).await.unwrap();
+    for _ in 0 .. 20 {
+        w.append(&entry).await.unwrap()
+    }
+    drop(w);
+    let blocks = list_blocks(dir).await.unwrap();
+    assert!(blocks.iter().map(|b| b.size()).sum::<u64>() <= 8192);
+    assert!(blocks[0].number() > first)
+}
+
+#[tokio::test]
+async fn disk_quota_with_trailers_and_shards() {
+    let dir = Path::new("/tmp/logs-test-disk-quota-with-trailers-and-shards");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    // One entry per block: 8 bytes header, 26 bytes frame and 14 bytes trailer.
+    let cfg = Config::default()
+        .with_max_block_len(48)
+        .with_max_entry_len(32)
+        .with_trailer(true)
+        .with_sharding(true)
+        .with_quota(1024)
+        .with_quota_policy(QuotaPolicy::DropOldest);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    for _ in 0 .. 1100 {
+        w.append(&[0; 20]).await.unwrap()
+    }
+    w.close().await.unwrap();
+
+    let blocks = list_blocks(dir).await.unwrap();
+    assert!(blocks.iter().all(|b| b.size() == 48));
+    assert!(blocks.iter().map(|b| b.size()).sum::<u64>() <= 1024);
+    // Shards are removed once all their blocks have been dropped.
+    assert!(!dir.join("00000").exists());
+    assert!(dir.join("00001").is_dir())
+}
+
+#[tokio::test]
+async fn logger_health() {
+    let dir = Path::new("/tmp/logs-test-logger-health");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default()
+        .with_max_entry_len(2000)
+        .with_max_block_len(2048)
+        .with_quota(8192);
+    let log = Logger::new(dir, cfg).await.unwrap();
+    assert!(log.health().is_healthy());
+
+    let mut refused = false;
+    for _ in 0 .. 100 {
+        if let Err(e) = log.add(ByteVec::from(vec![0; 1000])).await {
+            assert!(matches!(e, LogError::Quota));
+            refused = true;
+            break
+        }
+        log.sync().await.unwrap();
+        sleep(Duration::from_millis(10)).await
+    }
+    assert!(refused);
+    assert!(log.health().is_refusing());
+    assert!(log.health().dropped() > 0);
+
+    let last = list_blocks(dir).await.unwrap().last().unwrap().number();
+    delete_blocks(dir, last).await.unwrap();
+    sleep(Duration::from_millis(1500)).await;
+    assert!(log.health().is_healthy());
+    log.add(ByteVec::from(vec![0; 1000])).await.unwrap();
+    log.close().await.unwrap()
+}
+
+#[tokio::test]
+async fn close_guard_with_full_channel() {
+    let dir = Path::new("/tmp/logs-test-close-guard-with-full-channel");
+    for encoding_stage in [false, true] {
+        if dir.is_dir() {
+            fs::remove_dir_all(dir).await.unwrap();
+        }
+        fs::create_dir(dir).await.unwrap();
+
+        // The logger's tasks do not run before this task yields.
+        let cfg = Config::default().with_channel_capacity(4).with_encoding_stage(encoding_stage);
+        let log = Logger::new(dir, cfg).await.unwrap();
+        let mut n = 0u32;
+        while log.try_add(n).is_ok() {
+            n += 1
+        }
+        assert!(matches!(log.try_add(n), Err(LogError::Full)));
+        drop(log.guard(Duration::from_secs(5)));
+
+        // The channel closes before the last entries are written, but the
+        // directory stays locked until the writer is done.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while matches!(EntryWriter::open(dir, Config::default()).await, Err(WriteError::Locked(_))) {
+                sleep(Duration::from_millis(10)).await
+            }
+        })
+        .await
+        .unwrap();
+        let entries = LogReader::new(dir, BlockInfo::zero())
+            .into_stream()
+            .map_ok(|(_, e)| minicbor::decode::<u32>(&e).unwrap())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!((0 .. n).collect::<Vec<_>>(), entries)
+    }
+}
+
+#[tokio::test]
+async fn filter_and_sample() {
+    let dir = Path::new("/tmp/logs-test-filter-and-sample");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let log = Logger::new(dir, Config::default())
+        .await
+        .unwrap()
+        .with_filter(|m: &String| !m.contains("secret"))
+        .with_sampling(NonZeroU32::new(3).unwrap(), |m| m.starts_with("debug"));
+    log.add("info".to_string()).await.unwrap();
+    log.add("info secret".to_string()).await.unwrap();
+    for i in 0 .. 10 {
+        log.add(format!("debug {i}")).await.unwrap()
+    }
+    assert_eq!(7, log.health().filtered());
+    log.close().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    let mut messages = Vec::new();
+    while let Some((e, _)) = r.next_entry().await.unwrap() {
+        messages.push(minicbor::decode::<String>(&e).unwrap())
+    }
+    assert_eq!(["info", "debug 0", "debug 3", "debug 6", "debug 9"][..], messages)
+}
+
+#[tokio::test]
+async fn transform_entries() {
+    struct Redact;
+
+    impl Transform for Redact {
+        fn transform<'a>(&self, entry: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+            let s: String = minicbor::decode(entry).ok()?;
+            if s == "drop" {
+                return None
+            }
+            if s.contains("password") {
+                return Some(Cow::Owned(minicbor::to_vec("<redacted>").unwrap()))
+            }
+            Some(Cow::Borrowed(entry))
+        }
+    }
+
+    let dir = Path::new("/tmp/logs-test-transform-entries");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let log = Logger::new(dir, Config::default().with_transform(Redact)).await.unwrap();
+    log.add("hello".to_string()).await.unwrap();
+    log.add("password=hunter2".to_string()).await.unwrap();
+    log.add("drop".to_string()).await.unwrap();
+    log.add_raw(minicbor::to_vec("raw password").unwrap().into()).await.unwrap();
+    log.close().await.unwrap();
+    assert_eq!(1, log.health().filtered());
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    let mut messages = Vec::new();
+    while let Some((e, _)) = r.next_entry().await.unwrap() {
+        messages.push(minicbor::decode::<String>(&e).unwrap())
+    }
+    assert_eq!(["hello", "<redacted>", "<redacted>"][..], messages)
+}
+
+#[tokio::test]
+async fn route_by_topic() {
+    let dir = Path::new("/tmp/logs-test-route-by-topic");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    let set = LogSet::open(dir).await.unwrap();
+    let router = Router::with_topic_fn(|v: &(String, String)| v.0.as_str())
+        .with_stream(&set.stream("audit").await.unwrap(), Config::default())
+        .await
+        .unwrap()
+        .with_stream(&set.stream("debug").await.unwrap(), Config::default())
+        .await
+        .unwrap();
+
+    let entry = |t: &str, m: &str| (t.to_string(), m.to_string());
+    router.add(entry("audit", "login")).await.unwrap();
+    router.add(entry("debug", "x = 1")).await.unwrap();
+    router.add(entry("audit", "logout")).await.unwrap();
+    assert!(matches!(router.add(entry("metrics", "cpu")).await, Err(LogError::NoRoute(t)) if t == "metrics"));
+    router.close().await.unwrap();
+
+    for (stream, expected) in [("audit", &["login", "logout"][..]), ("debug", &["x = 1"][..])] {
+        let mut r = EntryReader::open(dir.join(stream), BlockInfo::zero().with_number(1)).await.unwrap();
+        let mut messages = Vec::new();
+        while let Some((e, _)) = r.next_entry().await.unwrap() {
+            let (t, m): (String, String) = minicbor::decode(&e).unwrap();
+            assert_eq!(stream, t);
+            messages.push(m)
+        }
+        assert_eq!(expected, messages)
+    }
+}
+
+#[tokio::test]
+async fn update_logger_config() {
+    let dir = Path::new("/tmp/logs-test-update-logger-config");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default()
+        .with_max_entry_len(2000)
+        .with_max_block_len(2048)
+        .with_quota(8192);
+    let log = Logger::new(dir, cfg.clone()).await.unwrap();
+    while log.add(ByteVec::from(vec![0; 1000])).await.is_ok() {
+        log.sync().await.unwrap();
+        sleep(Duration::from_millis(10)).await
+    }
+    assert!(log.health().is_refusing());
+
+    // The block length must still be within the quota.
+    assert!(log.update_config(cfg.clone().with_quota(1024)).await.is_err());
+
+    log.update_config(cfg.with_quota(32768)).await.unwrap();
+    sleep(Duration::from_millis(1500)).await;
+    assert!(log.health().is_healthy());
+    log.add(ByteVec::from(vec![0; 1000])).await.unwrap();
+    log.close().await.unwrap()
+}
+
+#[cfg(feature = "blake3")]
+#[tokio::test]
+async fn entry_digests() {
+    use bogger::{DigestKind, ReadError};
+
+    let dir = Path::new("/tmp/logs-test-entry-digests");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_chunking(true).with_max_entry_len(4).with_digest(Some(DigestKind::Blake3));
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    w.append(b"first").await.unwrap();
+    w.append(b"2nd").await.unwrap();
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert_eq!(b"first", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(Some(DigestKind::Blake3.compute(b"first").unwrap()), r.digest());
+    assert_eq!(b"2nd", &r.next_entry().await.unwrap().unwrap().0[..]);
+
+    // Flip a bit of the last digest.
+    let path = dir.join("block.1");
+    let mut bytes = fs::read(&path).await.unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    fs::write(&path, bytes).await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert!(r.next_entry().await.unwrap().is_some());
+    assert!(matches!(r.next_entry().await, Err(ReadError::Digest(_))));
+    assert_eq!(BlockStatus::Corrupt { entries: 1 }, verify_block(dir, BlockNum::from(1)).await.unwrap())
+}
+
+#[tokio::test]
+async fn skip_corrupt_entries() {
+    let dir = Path::new("/tmp/logs-test-skip-corrupt-entries");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    for e in [&b"first"[..], b"second", b"third"] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    // Damage the second entry, which starts after the header (8 bytes)
+    // and the first entry (2 + 5 + 4 bytes).
+    let path = dir.join("block.1");
+    let mut bytes = fs::read(&path).await.unwrap();
+    bytes[21] ^= 0xFF;
+    fs::write(&path, bytes).await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    assert_eq!(b"first", &r.next_entry().await.unwrap().unwrap().0[..]);
+    let at = r.block_info();
+    match r.next_entry().await {
+        Err(e) => assert_eq!(Some(at), e.position()),
+        Ok(_)  => panic!("corrupt entry not detected")
+    }
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap().with_skip_corrupt(true);
+    assert_eq!(b"first", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(b"third", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn skip_long_corrupt_run() {
+    let dir = Path::new("/tmp/logs-test-skip-long-corrupt-run");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    // The corrupt entry is longer than what is scanned in memory at once and
+    // the entry after it does not fit into the rest of the first window.
+    let mut rng = rand::thread_rng();
+    let large: Vec<u8> = (0 .. 100 * 1024).map(|_| rng.gen()).collect();
+    let after: Vec<u8> = (0 .. 40 * 1024).map(|_| rng.gen()).collect();
+    let cfg = Config::default().with_max_entry_len(128 * 1024).with_max_block_len(1024 * 1024);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    for e in [&b"first"[..], &large, &after, b"last"] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap();
+    r.next_entry().await.unwrap();
+    let from = r.block_info().offset() as usize;
+    r.next_entry().await.unwrap();
+    let to = r.block_info().offset() as usize;
+
+    // Keep the length (4 bytes) so that the entry is not taken as being
+    // partially written.
+    let path = dir.join("block.1");
+    let mut bytes = fs::read(&path).await.unwrap();
+    rng.fill(&mut bytes[from + 4 .. to]);
+    fs::write(&path, bytes).await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(1)).await.unwrap().with_skip_corrupt(true);
+    assert_eq!(b"first", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(after, r.next_entry().await.unwrap().unwrap().0);
+    assert_eq!(b"last", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn entry_metadata() {
+    use bogger::Metadata;
+
+    let dir = Path::new("/tmp/logs-test-entry-metadata");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let meta = Metadata::new().with("tenant", "acme").with("severity", "warn");
+
+    let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    assert!(matches!(w.append_with_metadata(b"x", &meta).await, Err(WriteError::NoMetadata)));
+    drop(w);
+
+    let cfg = Config::default().with_chunking(true).with_max_entry_len(4).with_trailer(true).with_entry_metadata(true);
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    w.append_with_metadata(b"first", &meta).await.unwrap();
+    w.append(b"2nd").await.unwrap();
+    w.sync().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(2)).await.unwrap();
+    assert_eq!(b"first", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(Some(&meta), r.metadata());
+    assert_eq!(Some("acme"), r.metadata().and_then(|m| m.get("tenant")));
+    assert_eq!(b"2nd", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(Some(&Metadata::new()), r.metadata());
+    assert!(r.next_entry().await.unwrap().is_none());
+    drop(w);
+
+    // Metadata is refused up front, without failing other entries.
+    let log = Logger::new(dir, Config::default()).await.unwrap();
+    log.add(1u8).await.unwrap();
+    assert!(matches!(log.add_with_metadata(2u8, meta.clone()).await, Err(LogError::NoMetadata)));
+    log.add_with_metadata(3u8, Metadata::new()).await.unwrap();
+    log.close().await.unwrap();
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(3)).await.unwrap();
+    assert_eq!(minicbor::to_vec(1u8).unwrap(), r.next_entry().await.unwrap().unwrap().0);
+    assert_eq!(minicbor::to_vec(3u8).unwrap(), r.next_entry().await.unwrap().unwrap().0);
+
+    let log = Logger::new(dir, Config::default().with_entry_metadata(true)).await.unwrap();
+    log.add_with_metadata(1u8, meta.clone()).await.unwrap();
+    log.close().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(4)).await.unwrap();
+    assert!(r.next_entry().await.unwrap().is_some());
+    assert_eq!(Some(&meta), r.metadata());
+
+    // Metadata longer than the maximum entry length is refused up front
+    // as well, without failing the entries batched with it.
+    let small = Metadata::new().with("k", "v");
+    let large = Metadata::new().with("k", "v".repeat(16));
+    let log = Logger::new(dir, Config::default().with_entry_metadata(true).with_max_entry_len(16)).await.unwrap();
+    log.add_with_metadata(5u8, small.clone()).await.unwrap();
+    assert!(matches!(log.add_with_metadata(6u8, large).await, Err(LogError::MetadataSize)));
+    log.add_with_metadata(7u8, small.clone()).await.unwrap();
+    log.close().await.unwrap();
+
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(5)).await.unwrap();
+    assert_eq!(minicbor::to_vec(5u8).unwrap(), r.next_entry().await.unwrap().unwrap().0);
+    assert_eq!(Some(&small), r.metadata());
+    assert_eq!(minicbor::to_vec(7u8).unwrap(), r.next_entry().await.unwrap().unwrap().0);
+    assert_eq!(Some(&small), r.metadata());
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn entry_schemas() {
+    use bogger::{Metadata, SchemaError, Schemas};
+
+    let dir = Path::new("/tmp/logs-test-entry-schemas");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    w.append(&minicbor::to_vec(7u8).unwrap()).await.unwrap();
+    assert!(matches!(w.append_with_schema(b"x", None, 1).await, Err(WriteError::NoSchema)));
+    w.sync().await.unwrap();
+    drop(w);
+
+    // Chunks must leave room for the schema ID in the first frame.
+    let cfg = Config::default()
+        .with_chunking(true)
+        .with_max_entry_len(4)
+        .with_entry_metadata(true)
+        .with_schema(Some(1));
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    w.append(&minicbor::to_vec("seven").unwrap()).await.unwrap();
+    w.append_with_schema(&minicbor::to_vec(8u8).unwrap(), Some(&Metadata::new().with("k", "v")), 300).await.unwrap();
+    w.append_with_schema(b"", None, 2).await.unwrap();
+    w.sync().await.unwrap();
+
+    let schemas = Schemas::<String>::new()
+        .with_default(|b| minicbor::decode::<u8>(b).map(|n| n.to_string()))
+        .with_cbor::<String>(1)
+        .with_schema(300, |b| minicbor::decode::<u8>(b).map(|n| format!("#{n}")));
+    let mut r = LogReader::new(dir, BlockInfo::zero().with_number(1));
+    let mut found = Vec::new();
+    while let Some((_, b)) = r.next_entry().await.unwrap() {
+        found.push((r.schema(), schemas.decode(r.schema(), &b)))
+    }
+    assert_eq!(4, found.len());
+    assert!(matches!(&found[0], (None, Ok(s)) if s == "7"));
+    assert!(matches!(&found[1], (Some(1), Ok(s)) if s == "seven"));
+    assert!(matches!(&found[2], (Some(300), Ok(s)) if s == "#8"));
+    assert!(matches!(&found[3], (Some(2), Err(SchemaError::Unknown(Some(2))))));
+    drop(w);
+
+    assert!(matches!(verify_block(dir, BlockNum::from(2)).await.unwrap(), BlockStatus::Valid { entries: 3 }));
+
+    #[cfg(feature = "mmap")]
+    {
+        let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(2)).await.unwrap();
+        let mut m = bogger::MmapEntryReader::open(dir, BlockInfo::zero().with_number(2)).await.unwrap();
+        while let Some(e) = r.next_entry().await.unwrap() {
+            assert_eq!(Some(e), m.next_entry().await.unwrap());
+            assert_eq!(r.schema(), m.schema())
+        }
+    }
+
+    // Unchunked entries leave room for the schema ID. An entry of exactly
+    // the maximum length is refused without failing the batch it is in.
+    let cfg = Config::default().with_max_entry_len(16).with_schema(Some(300));
+    let mut w = EntryWriter::open(dir, cfg.clone()).await.unwrap();
+    assert!(matches!(w.append(&[0; 16]).await, Err(WriteError::EntrySize)));
+    w.append(&[0; 14]).await.unwrap();
+    drop(w);
+
+    let log = Logger::new(dir, cfg).await.unwrap();
+    log.add(ByteVec::from(vec![1])).await.unwrap();
+    log.add(ByteVec::from(vec![0; 15])).await.unwrap();
+    log.add(ByteVec::from(vec![2])).await.unwrap();
+    log.close().await.unwrap();
+    assert_eq!(1, log.health().dropped());
+
+    let mut r = LogReader::new(dir, BlockInfo::zero().with_number(4));
+    assert_eq!(minicbor::to_vec(ByteVec::from(vec![1])).unwrap(), r.next_entry().await.unwrap().unwrap().1);
+    assert_eq!(Some(300), r.schema());
+    assert_eq!(minicbor::to_vec(ByteVec::from(vec![2])).unwrap(), r.next_entry().await.unwrap().unwrap().1);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+#[tokio::test]
+async fn query_time_range() {
+    use bogger::{LogRecord, Query, QueryError, query};
+    use std::time::SystemTime;
+
+    let dir = Path::new("/tmp/logs-test-query-time-range");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let base = SystemTime::now() - Duration::from_secs(3600);
+    let at = |s| base + Duration::from_secs(s);
+    for block in [[0, 10], [20, 30], [40, 50]] {
+        let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+        for s in block {
+            let r = LogRecord::info("test", s.to_string()).with_timestamp(at(s));
+            w.append(&minicbor::to_vec(r).unwrap()).await.unwrap()
+        }
+        w.sync().await.unwrap()
+    }
+    let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    w.append(b"not a log record").await.unwrap();
+    w.sync().await.unwrap();
+    std::fs::File::options().write(true).open(dir.join("block.1")).unwrap().set_modified(at(12)).unwrap();
+
+    let messages = |q: Query| q.into_stream().map_ok(|(_, r)| r.message().to_string()).try_collect::<Vec<_>>();
+
+    // The first block has been modified before the range.
+    let q = Query::new(dir, at(15) .. at(45));
+    assert_eq!(vec![BlockNum::from(2), BlockNum::from(3), BlockNum::from(4)], q.blocks().await.unwrap());
+    assert!(matches!(messages(q).await, Err(QueryError::Decode(..))));
+
+    // Sorted entries are not read beyond the range.
+    let q = Query::new(dir, at(5) .. at(45)).with_sorted(true);
+    assert_eq!(["10", "20", "30", "40"], &messages(q).await.unwrap()[..]);
+    fs::remove_file(dir.join("block.4")).await.unwrap();
+
+    let r = query(dir, at(0), at(15)).try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!(2, r.len());
+    assert_eq!(BlockInfo::zero().with_number(1).with_offset(8u8), r[0].0)
+}
+
+#[tokio::test]
+async fn lock_directory() {
+    let dir = Path::new("/tmp/logs-test-lock-directory");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    assert!(matches!(EntryWriter::open(dir, Config::default()).await, Err(WriteError::Locked(_))));
+    // Writers of other block names may share the directory.
+    let names = BlockNames::new().with_prefix("other.");
+    assert!(EntryWriter::open(dir, Config::default().with_block_names(names)).await.is_ok());
+    drop(w);
+    assert!(EntryWriter::open(dir, Config::default()).await.is_ok())
+}
+
+#[tokio::test]
+async fn reject_invalid_config() {
+    assert!(Config::default().build().is_ok());
+    assert_eq!(Some(ConfigError::ZeroBuffer), Config::default().with_max_buffer_len(0).validate().err());
+    assert_eq!(Some(ConfigError::BlockTooSmall(8)), Config::default().with_max_block_len(8).validate().err());
+    let cfg = Config::default().with_max_block_len(512);
+    assert_eq!(Some(ConfigError::EntryTooLarge { entry: 1024, block: 512 }), cfg.validate().err());
+    let cfg = Config::default().with_quota(1024);
+    assert!(matches!(cfg.validate(), Err(ConfigError::QuotaTooSmall { .. })));
+
+    let cfg = Config::default().with_channel_capacity(0);
+    assert!(matches!(Logger::<u8>::new_in(MemoryStore::new(), cfg).await, Err(LogError::Write(WriteError::Config(_)))))
+}
+
+#[tokio::test]
+async fn log_to_memory_store() {
+    let store = MemoryStore::new();
+    let cfg = Config::default().with_max_block_len(256).with_max_entry_len(64).with_max_batch_len(8).with_trailer(true);
+    let log = Logger::new_in(store.clone(), cfg).await.unwrap();
+    for i in 0 .. 100u32 {
+        log.add(i).await.unwrap()
+    }
+    log.close().await.unwrap();
+    assert!(store.list().await.unwrap().len() > 1);
+
+    let entries = LogReader::new_in(store.clone(), BlockInfo::zero())
+        .into_stream()
+        .map_ok(|(_, e)| minicbor::decode::<u32>(&e).unwrap())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert_eq!((0 .. 100).collect::<Vec<_>>(), entries);
+
+    // Old blocks are removed from the store to stay within the quota.
+    let cfg = Config::default()
+        .with_max_block_len(256)
+        .with_max_entry_len(64)
+        .with_quota(1024)
+        .with_quota_policy(QuotaPolicy::DropOldest);
+    let mut w = EntryWriter::open_in(store.clone(), cfg).await.unwrap();
+    for _ in 0 .. 100 {
+        w.append(&[0; 32]).await.unwrap()
+    }
+    w.sync().await.unwrap();
+    let blocks = store.list().await.unwrap();
+    assert!(blocks.iter().map(|b| b.1).sum::<u64>() <= 1024);
+    assert!(store.get(BlockNum::from(1)).is_none())
+}
+
+#[tokio::test]
+async fn write_and_read_byte_buffers() {
+    let cfg = Config::default().with_trailer(true).with_chunking(true).with_max_entry_len(16);
+    let entries: [&[u8]; 3] = [b"first", b"second", b"third"];
+    let mut w = EntryWriter::from_writer(Vec::new(), cfg).await.unwrap();
+    for e in entries {
+        w.append(e).await.unwrap()
+    }
+    w.append(&[7; 40]).await.unwrap();
+    let bytes = w.finish().await.unwrap();
+
+    let start = BlockInfo::zero().with_number(1);
+    let mut r = EntryReader::from_reader(std::io::Cursor::new(bytes), start).await.unwrap();
+    for e in entries {
+        assert_eq!(e, r.next_entry().await.unwrap().unwrap().0)
+    }
+    assert_eq!([7; 40][..], r.next_entry().await.unwrap().unwrap().0);
+    assert!(r.next_entry().await.unwrap().is_none());
+    assert_eq!(Some(4), r.trailer().map(|t| t.entries()))
+}
+
+#[tokio::test]
+async fn log_with_custom_writer() {
+    let (w, mut r) = tokio::io::duplex(1024);
+    let reader = tokio::spawn(async move {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes).await.unwrap();
+        bytes
+    });
+    let cfg = Config::default().with_max_entry_len(64).with_max_batch_len(8);
+    let log = Logger::new_with_writer(EntryWriter::from_writer(w, cfg).await.unwrap());
+    for i in 0 .. 100u32 {
+        log.add(i).await.unwrap()
+    }
+    log.close().await.unwrap();
+    drop(log);
+
+    let bytes = reader.await.unwrap();
+    let start = BlockInfo::zero().with_number(1);
+    let mut r = EntryReader::from_reader(std::io::Cursor::new(bytes), start).await.unwrap();
+    let mut entries = Vec::new();
+    while let Some((e, _)) = r.next_entry().await.unwrap() {
+        entries.push(minicbor::decode::<u32>(&e).unwrap())
+    }
+    assert_eq!((0 .. 100).collect::<Vec<_>>(), entries)
+}
+
+#[tokio::test]
+async fn batch_entries_into_single_writes() {
+    // Writes of one entry, 8 entries at most, and the rest.
+    let cfg = Config::default().with_max_batch_len(8);
+    let writes = batch_writes(cfg, 21).await;
+    let f = writes[0];
+    assert_eq!([f, 8 * f, 8 * f, 4 * f][..], writes);
+
+    // Entries have 11 bytes, so 3 of them exceed 30 bytes.
+    let cfg = Config::default().with_max_batch_bytes(30);
+    let writes = batch_writes(cfg, 21).await;
+    let f = writes[0];
+    assert_eq!([f, 3 * f, 3 * f, 3 * f, 3 * f, 3 * f, 3 * f, 2 * f][..], writes)
+}
+
+#[tokio::test]
+async fn resume_partial_writes() {
+    use bogger::Metadata;
+
+    // Metadata, chunks and the trailer make for many parts per write.
+    let cfg = Config::default()
+        .with_max_buffer_len(1)
+        .with_max_entry_len(4)
+        .with_chunking(true)
+        .with_trailer(true)
+        .with_entry_metadata(true);
+    let entries = [&b"first"[..], b"second entry", b"", b"third"];
+    let meta = Metadata::new().with("k", "v");
+    let mut w = EntryWriter::from_writer(TrickleSink::default(), cfg).await.unwrap();
+    w.append_with_metadata(entries[0], &meta).await.unwrap();
+    w.append_batch(entries[1 ..].iter().copied()).await.unwrap();
+    let sink = w.finish().await.unwrap();
+    assert!(sink.calls > sink.bytes.len() / 3);
+
+    let start = BlockInfo::zero().with_number(1);
+    let mut r = EntryReader::from_reader(std::io::Cursor::new(sink.bytes), start).await.unwrap();
+    assert_eq!(entries[0], &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert_eq!(Some(&meta), r.metadata());
+    for e in &entries[1 ..] {
+        assert_eq!(*e, &r.next_entry().await.unwrap().unwrap().0[..])
+    }
+    assert!(r.next_entry().await.unwrap().is_none());
+    assert_eq!(Some(4), r.trailer().map(|t| t.entries()))
+}
+
+/// A sink which accepts at most 3 bytes per write.
+#[derive(Default)]
+struct TrickleSink {
+    bytes: Vec<u8>,
+    calls: usize
+}
+
+impl tokio::io::AsyncWrite for TrickleSink {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored(cx, &[io::IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, _: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        self.calls += 1;
+        let mut n = 0;
+        for b in bufs {
+            let k = b.len().min(3 - n);
+            self.bytes.extend_from_slice(&b[.. k]);
+            n += k;
+            if n == 3 {
+                break
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Log `n` entries and return the lengths of the writes after the header.
+///
+/// Writes are held up after the first entry until all others are queued.
+async fn batch_writes(cfg: Config, n: usize) -> Vec<usize> {
+    let sink = GatedSink::default();
+    let writer = EntryWriter::from_writer(sink.clone(), cfg.with_max_buffer_len(1)).await.unwrap();
+    sink.set_open(false);
+    sink.writes.lock().unwrap().clear();
+    let log = Logger::new_with_writer(writer);
+    log.add(ByteVec::from(vec![0; 10])).await.unwrap();
+    timeout(Duration::from_secs(5), async {
+        while sink.waker.lock().unwrap().is_none() {
+            sleep(Duration::from_millis(10)).await
+        }
+    })
+    .await
+    .unwrap();
+    for _ in 1 .. n {
+        log.add(ByteVec::from(vec![0; 10])).await.unwrap()
+    }
+    sink.set_open(true);
+    log.close().await.unwrap();
+    let writes = sink.writes.lock().unwrap().clone();
+    writes
+}
+
+/// A sink which records the length of its writes and holds them up while
+/// closed.
+#[derive(Clone, Default)]
+struct GatedSink {
+    closed: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<Waker>>>,
+    writes: Arc<Mutex<Vec<usize>>>
+}
+
+impl GatedSink {
+    fn set_open(&self, open: bool) {
+        self.closed.store(!open, Ordering::SeqCst);
+        if open {
+            if let Some(w) = self.waker.lock().unwrap().take() {
+                w.wake()
+            }
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for GatedSink {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored(cx, &[io::IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        if self.closed.load(Ordering::SeqCst) {
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            return Poll::Pending
+        }
+        let n = bufs.iter().map(|b| b.len()).sum();
+        self.writes.lock().unwrap().push(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A word encoded as its index in a dictionary given as context.
+struct Word(&'static str);
+
+impl minicbor::Encode<Vec<&'static str>> for Word {
+    fn encode<W: minicbor::encode::Write>
+        ( &self
+        , e: &mut minicbor::Encoder<W>
+        , dict: &mut Vec<&'static str>
+        ) -> Result<(), minicbor::encode::Error<W::Error>>
+    {
+        let i = dict.iter().position(|w| *w == self.0).ok_or_else(|| minicbor::encode::Error::message("unknown word"))?;
+        e.u32(i as u32)?.ok()
+    }
+}
+
+#[tokio::test]
+async fn log_with_context() {
+    for (i, stage) in [false, true].into_iter().enumerate() {
+        let dir = format!("/tmp/logs-test-log-with-context-{i}");
+        let dir = Path::new(&dir);
+        if dir.is_dir() {
+            fs::remove_dir_all(dir).await.unwrap();
+        }
+        fs::create_dir(dir).await.unwrap();
+
+        let cfg = Config::default().with_encoding_stage(stage);
+        let log = Logger::new_with_context(dir, cfg, vec!["zero", "one", "two"]).await.unwrap();
+        for w in ["two", "zero", "unknown", "one"] {
+            log.add(Word(w)).await.unwrap()
+        }
+        log.close().await.unwrap();
+        assert_eq!(1, log.health().dropped());
+
+        let entries = LogReader::new(dir, BlockInfo::zero())
+            .into_stream()
+            .map_ok(|(_, e)| minicbor::decode::<u32>(&e).unwrap())
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(vec![2, 0, 1], entries)
+    }
+}
+
+#[tokio::test]
+async fn log_raw_entries() {
+    let dir = Path::new("/tmp/logs-test-log-raw-entries");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let cfg = Config::default().with_max_entry_len(16);
+    let log = Logger::new(dir, cfg).await.unwrap();
+    for i in 0 .. 10u32 {
+        if i % 2 == 0 {
+            log.add(i).await.unwrap()
+        } else {
+            log.add_raw(minicbor::to_vec(i).unwrap().into()).await.unwrap()
+        }
+    }
+    log.add_raw(minicbor::to_vec([0u8; 32]).unwrap().into()).await.unwrap();
+    log.close().await.unwrap();
+    assert_eq!(1, log.health().dropped());
+
+    let entries = LogReader::new(dir, BlockInfo::zero())
+        .into_stream()
+        .map_ok(|(_, e)| minicbor::decode::<u32>(&e).unwrap())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert_eq!((0 .. 10).collect::<Vec<_>>(), entries)
+}
+
+#[tokio::test]
+async fn stream_log_entries() {
+    let dir = Path::new("/tmp/logs-test-stream-log-entries");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    // Two entries per block.
+    let entries = [&b"first"[..], b"second", b"third", b"fourth", b"fifth"];
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(32).with_max_entry_len(8)).await.unwrap();
+    for e in entries {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let mut r = LogReader::new(dir, BlockInfo::zero());
+    let mut expected = Vec::new();
+    while let Some((pos, e)) = r.next_entry().await.unwrap() {
+        expected.push((pos, e))
+    }
+    assert_eq!(3, expected.last().unwrap().0.number().value());
+
+    // The stream ends at the last entry, although the writer may append more.
+    let found = LogReader::new(dir, BlockInfo::zero()).into_stream().try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!(expected, found);
+    assert_eq!(entries[..], found.iter().map(|(_, e)| &e[..]).collect::<Vec<_>>());
+
+    // Streams start at any position.
+    let found = LogReader::new(dir, expected[2].0).into_stream().try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!(expected[2 ..], found);
+
+    w.append(b"sixth").await.unwrap();
+    w.sync().await.unwrap();
+    let found = LogReader::new(dir, expected[4].0).into_stream().try_collect::<Vec<_>>().await.unwrap();
+    assert_eq!([&b"fifth"[..], b"sixth"][..], found.iter().map(|(_, e)| &e[..]).collect::<Vec<_>>())
+}
+
+#[tokio::test]
+async fn read_entries_ahead() {
+    let dir = Path::new("/tmp/logs-test-read-entries-ahead");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default()).await.unwrap();
+    for i in 0 .. 100u32 {
+        w.append(&i.to_be_bytes()).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let start = BlockInfo::zero().with_number(1);
+    let mut r = EntryReader::open(dir, start).await.unwrap().read_ahead(8);
+    for i in 0 .. 100u32 {
+        let (e, _) = r.next_entry().await.unwrap().unwrap();
+        assert_eq!(i.to_be_bytes(), &e[..])
+    }
+    assert!(r.next_entry().await.unwrap().is_none());
+    let end = r.block_info();
+    assert_eq!(start.with_offset(8 + 100 * 10u64), end);
+
+    // Entries appended later are read once the reader is asked again.
+    w.append(b"more").await.unwrap();
+    w.sync().await.unwrap();
+    assert_eq!(b"more", &r.next_entry().await.unwrap().unwrap().0[..]);
+    assert!(r.next_entry().await.unwrap().is_none())
+}
+
+/// Write random entries with random settings and read them back.
+#[test]
+fn roundtrip_random_entries() {
+    fn prop(entries: Vec<Vec<u8>>, block_len: u16, chunking: bool, trailer: bool) -> TestResult {
+        let max = 128;
+        if !chunking && entries.iter().any(|e| e.len() > max) {
+            return TestResult::discard()
+        }
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let actual = rt.block_on(async {
+            let dir = Path::new("/tmp/logs-test-roundtrip-random-entries");
+            if dir.is_dir() {
+                fs::remove_dir_all(dir).await.unwrap();
+            }
+            fs::create_dir(dir).await.unwrap();
+            let cfg = Config::default()
+                .with_max_block_len(u64::from(block_len).max(max as u64))
+                .with_max_entry_len(max as u32)
+                .with_chunking(chunking)
+                .with_trailer(trailer);
+            let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+            for e in &entries {
+                w.append(e).await.unwrap()
+            }
+            w.sync().await.unwrap();
+            LogReader::new(dir, BlockInfo::zero())
+                .into_stream()
+                .map_ok(|(_, e)| e.to_vec())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+        });
+        TestResult::from_bool(entries == actual)
+    }
+    QuickCheck::new().tests(50).quickcheck(prop as fn(Vec<Vec<u8>>, u16, bool, bool) -> TestResult)
+}
+
+/// Reading a damaged block must neither panic nor loop forever.
+#[test]
+fn read_damaged_blocks() {
+    fn prop(damage: Vec<(u16, u8)>, truncate: u16, chunking: bool, metadata: bool) -> bool {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let dir = Path::new("/tmp/logs-test-read-damaged-blocks");
+            if dir.is_dir() {
+                fs::remove_dir_all(dir).await.unwrap();
+            }
+            fs::create_dir(dir).await.unwrap();
+            let cfg = Config::default()
+                .with_max_entry_len(16)
+                .with_chunking(chunking)
+                .with_entry_metadata(metadata)
+                .with_trailer(true);
+            let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+            for i in 0 .. 20u8 {
+                w.append(&vec![i; usize::from(i % 6) * 3]).await.unwrap()
+            }
+            w.sync().await.unwrap();
+            let path = dir.join("block.1");
+            let mut bytes = fs::read(&path).await.unwrap();
+            for (at, x) in damage {
+                let at = 8 + usize::from(at) % (bytes.len() - 8);
+                bytes[at] ^= x
+            }
+            bytes.truncate(8 + usize::from(truncate) % (bytes.len() - 7));
+            fs::write(&path, &bytes).await.unwrap();
+            for skip in [false, true] {
+                let start = BlockInfo::zero().with_number(1);
+                let mut r = EntryReader::open(dir, start).await.unwrap().with_skip_corrupt(skip);
+                for _ in 0 ..= bytes.len() {
+                    match r.next_entry().await {
+                        Ok(Some(_)) => continue,
+                        Ok(None) | Err(_) => break
+                    }
+                }
+            }
+            true
+        })
+    }
+    QuickCheck::new().tests(50).quickcheck(prop as fn(Vec<(u16, u8)>, u16, bool, bool) -> bool)
+}
+
+/// Entries appended before a write fault are never lost and no damaged
+/// entry is ever read back.
+#[cfg(feature = "testing")]
+#[tokio::test]
+async fn recover_from_write_faults() {
+    use bogger::testing::Faults;
+
+    let dir = Path::new("/tmp/logs-test-recover-from-write-faults");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+    let faults = Faults::new(42)
+        .with_short_writes(0.3)
+        .with_interrupts(0.1)
+        .with_no_space(0.05);
+    let cfg = Config::default()
+        .with_max_buffer_len(64)
+        .with_max_block_len(512)
+        .with_max_entry_len(64)
+        .with_trailer(true)
+        .with_faults(faults.clone());
+    let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+    let mut appended = Vec::new();
+    for i in 0 .. 300u16 {
+        let entry = i.to_be_bytes().repeat(usize::from(i % 7) + 1);
+        if w.append(&entry).await.is_ok() {
+            appended.push(entry)
+        }
+        if i % 10 == 0 {
+            let _ = w.sync().await;
+        }
+    }
+    while w.sync().await.is_err() {}
+    assert!(appended.len() < 300);
+
+    let actual = LogReader::new(dir, BlockInfo::zero())
+        .into_stream()
+        .map_ok(|(_, e)| e.to_vec())
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert_eq!(appended, actual)
+}
+
+#[tokio::test]
+async fn compact_small_blocks() {
+    use bogger::{CompactError, CompactOptions, compact_blocks_with};
+
+    let dir = Path::new("/tmp/logs-test-compact-small-blocks");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(16).with_max_entry_len(8)).await.unwrap();
+    for i in 0 .. 8 {
+        w.append(format!("e{i}").as_bytes()).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let blocks = list_blocks(dir).await.unwrap();
+    assert_eq!(8, blocks.len());
+    let len = blocks[0].size();
+
+    // A reader which has opened a block keeps reading its old contents.
+    let mut r = EntryReader::open(dir, BlockInfo::zero().with_number(2)).await.unwrap();
+
+    // Blocks from number 8 on have not been forwarded yet.
+    let opts = CompactOptions::new().with_max_block_len(3 * len);
+    let s = compact_blocks_with(dir, BlockNum::from(8), &opts).await.unwrap();
+    assert_eq!([BlockNum::from(3), BlockNum::from(6)], s.merged());
+    assert_eq!([1, 2, 4, 5].map(BlockNum::from), s.removed());
+
+    let blocks = list_blocks(dir).await.unwrap();
+    assert_eq!(vec![3, 6, 7, 8], blocks.iter().map(|b| b.number().value()).collect::<Vec<_>>());
+    assert!(matches!(verify_block(dir, BlockNum::from(3)).await.unwrap(), BlockStatus::Valid { entries: 3 }));
+    assert_eq!(b"e1", &r.next_entry().await.unwrap().unwrap().0[..]);
+
+    // Readers of removed blocks continue with the merged block.
+    let mut r = LogReader::new(dir, BlockInfo::zero().with_number(1));
+    let mut found = Vec::new();
+    while let Some((pos, e)) = r.next_entry().await.unwrap() {
+        found.push((pos.number().value(), String::from_utf8(e.to_vec()).unwrap()))
+    }
+    let expected = [3, 3, 3, 6, 6, 6, 7, 8].into_iter().zip(0 ..).map(|(n, i)| (n, format!("e{i}")));
+    assert_eq!(expected.collect::<Vec<_>>(), found);
+
+    // Only one compaction runs at a time.
+    let lock = std::fs::File::create(dir.join("block.compact")).unwrap();
+    lock.try_lock().unwrap();
+    assert!(matches!(compact_blocks_with(dir, BlockNum::from(8), &opts).await, Err(CompactError::Locked(_))));
+    drop(lock);
+
+    // Merged blocks may be merged again.
+    let s = compact_blocks_with(dir, BlockNum::from(8), &opts).await.unwrap();
+    assert_eq!([BlockNum::from(7)], s.merged());
+    assert_eq!([BlockNum::from(6)], s.removed())
+}
+
+#[tokio::test]
+async fn migrate_block_format() {
+    use bogger::{MigrateError, MigrateOptions, migrate_blocks_with};
+
+    let dir = Path::new("/tmp/logs-test-migrate-block-format");
+    let target = Path::new("/tmp/logs-test-migrate-block-format-target");
+    for d in [dir, target] {
+        if d.is_dir() {
+            fs::remove_dir_all(d).await.unwrap();
+        }
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(24).with_max_entry_len(8)).await.unwrap();
+    for e in [&b"first"[..], b"second", b"third"] {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    // No writer may append while blocks are migrated.
+    let opts = MigrateOptions::new()
+        .with_config(Config::default().with_trailer(true))
+        .with_min_version(2)
+        .with_force(true);
+    assert!(matches!(migrate_blocks_with(dir, &opts).await, Err(MigrateError::Locked(_))));
+    drop(w);
+
+    let before = list_blocks(dir).await.unwrap();
+    let s = migrate_blocks_with(dir, &opts).await.unwrap();
+    assert_eq!(before.len(), s.migrated().len());
+
+    let after = list_blocks(dir).await.unwrap();
+    for (b, a) in before.iter().zip(&after) {
+        assert_eq!(b.number(), a.number());
+        assert_eq!(b.modified(), a.modified());
+        assert!(matches!(verify_block(dir, a.number()).await.unwrap(), BlockStatus::Complete { .. }));
+        let mut file = fs::File::open(a.path()).await.unwrap();
+        assert_eq!(2, file.read_u64().await.unwrap() >> 16 & 0xFF)
+    }
+    let mut r = LogReader::new(dir, BlockInfo::zero());
+    let mut found = Vec::new();
+    while let Some((_, e)) = r.next_entry().await.unwrap() {
+        found.push(e.to_vec())
+    }
+    assert_eq!(vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()], found);
+
+    // Migrated blocks are left alone.
+    let s = migrate_blocks_with(dir, &opts).await.unwrap();
+    assert!(s.migrated().is_empty());
+    assert_eq!(before.len(), s.skipped().len());
+
+    // Migrating into another directory keeps the original blocks.
+    let opts = MigrateOptions::new()
+        .with_config(Config::default().with_entry_metadata(true))
+        .with_target_dir(Some(target))
+        .with_force(true);
+    let s = migrate_blocks_with(dir, &opts).await.unwrap();
+    assert_eq!(before.len(), s.migrated().len());
+    let mut r = LogReader::new(target, BlockInfo::zero());
+    let mut found = Vec::new();
+    while let Some((_, e)) = r.next_entry().await.unwrap() {
+        found.push((e.to_vec(), r.metadata().is_some()))
+    }
+    assert_eq!(3, found.len());
+    assert!(found.iter().all(|(_, m)| *m));
+    assert!(matches!(verify_block(dir, after[0].number()).await.unwrap(), BlockStatus::Complete { .. }))
+}
+
+#[tokio::test]
+async fn downgrade_for_older_readers() {
+    use bogger::Metadata;
+
+    let dir = Path::new("/tmp/logs-test-downgrade-for-older-readers");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    // Entry metadata requires version 3 of the block format.
+    let mut w = EntryWriter::open(dir, Config::default().with_entry_metadata(true)).await.unwrap();
+    w.append_with_metadata(b"zeroth", &Metadata::new().with("k", "v")).await.unwrap();
+    w.sync().await.unwrap();
+    drop(w);
+
+    // Once the configuration is reverted, blocks are written in version 1.
+    let entries = [&b"first"[..], b"second", b"third"];
+    let mut w = EntryWriter::open(dir, Config::default().with_max_block_len(32).with_max_entry_len(16)).await.unwrap();
+    for e in entries {
+        w.append(e).await.unwrap()
+    }
+    w.sync().await.unwrap();
+
+    let blocks = list_blocks(dir).await.unwrap();
+    assert_eq!(b"block\x03", &fs::read(blocks[0].path()).await.unwrap()[.. 6]);
+    let mut found = Vec::new();
+    for b in &blocks[1 ..] {
+        found.extend(read_version_1(&fs::read(b.path()).await.unwrap()))
+    }
+    assert_eq!(entries[..], found);
+
+    let mut r = LogReader::new(dir, BlockInfo::zero());
+    let mut found = Vec::new();
+    while let Some((_, e)) = r.next_entry().await.unwrap() {
+        found.push(e.to_vec())
+    }
+    assert_eq!([&b"zeroth"[..], b"first", b"second", b"third"][..], found)
+}
+
+/// Read the entries of a block as readers which only know version 1 do.
+fn read_version_1(mut bytes: &[u8]) -> Vec<Vec<u8>> {
+    let crc32c = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+    assert_eq!(b"block\x01", &bytes[.. 6]);
+    bytes = &bytes[8 ..];
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        let len = usize::from(u16::from_be_bytes([bytes[0], bytes[1]]));
+        let (entry, rest) = bytes[2 ..].split_at(len);
+        assert_eq!(crc32c.checksum(entry).to_be_bytes(), rest[.. 4]);
+        entries.push(entry.to_vec());
+        bytes = &rest[4 ..]
+    }
+    entries
+}
+
+#[tokio::test]
+async fn mixed_header_versions() {
+    use bogger::{Metadata, ReadError};
+
+    let dir = Path::new("/tmp/logs-test-mixed-header-versions");
+    if dir.is_dir() {
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+    fs::create_dir(dir).await.unwrap();
+
+    // Versions 1, 2 and 3, as written by writers configured across upgrades.
+    let configs = [
+        Config::default(),
+        Config::default().with_max_entry_len(64 * 1024).with_trailer(true),
+        Config::default().with_entry_metadata(true).with_chunking(true).with_max_entry_len(4)
+    ];
+    for (i, cfg) in configs.into_iter().enumerate() {
+        let mut w = EntryWriter::open(dir, cfg).await.unwrap();
+        w.append_with_metadata(format!("entry {i}").as_bytes(), &Metadata::new()).await.unwrap();
+        w.finish().await.unwrap();
+    }
+    for (n, v) in [(1, 1), (2, 2), (3, 3)] {
+        let mut file = fs::File::open(dir.join(format!("block.{n}"))).await.unwrap();
+        assert_eq!(v, file.read_u64().await.unwrap() >> 16 & 0xFF)
+    }
+
+    // A block with flags from a future format.
+    let mut header = u64::from_be_bytes(*b"block\x03\x00\x00");
+    header |= 0x40;
+    fs::write(dir.join("block.4"), header.to_be_bytes()).await.unwrap();
+
+    let mut r = LogReader::new(dir, BlockInfo::zero());
+    for i in 0 .. 3 {
+        let (pos, e) = r.next_entry().await.unwrap().unwrap();
+        assert_eq!(i + 1, pos.number().value());
+        assert_eq!(format!("entry {i}").as_bytes(), &e[..])
+    }
+    assert!(matches!(r.next_entry().await, Err(ReadError::Flags(3, 0x40))))
+}